@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use super::{Object, RelocKind, SectionId};
+
+// ELF64 for x86-64, little endian. Relocatable objects get the usual
+// sections; executables are static with one segment for code and one for
+// data, and need no dynamic loader.
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_64: u64 = 1;
+const R_X86_64_PLT32: u64 = 4;
+const R_X86_64_32S: u64 = 11;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const HEADER_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+// Executables are loaded at the traditional non-PIE address
+const BASE_ADDR: u64 = 0x400000;
+const PAGE: u64 = 0x1000;
+
+#[derive(Default)]
+struct Buffer {
+    bytes: Vec<u8>
+}
+
+impl Buffer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn align(&mut self, align: usize) {
+        while !self.bytes.len().is_multiple_of(align) {
+            self.bytes.push(0);
+        }
+    }
+
+    fn header(&mut self, kind: u16, entry: u64, phnum: u16, shoff: u64, shnum: u16) {
+        self.bytes.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0,
+                                       0, 0, 0, 0, 0, 0, 0, 0]);
+        self.u16(kind);
+        self.u16(EM_X86_64);
+        self.u32(1);
+        self.u64(entry);
+        self.u64(if phnum > 0 { HEADER_SIZE as u64 } else { 0 });
+        self.u64(shoff);
+        self.u32(0);
+        self.u16(HEADER_SIZE as u16);
+        self.u16(if phnum > 0 { PHDR_SIZE as u16 } else { 0 });
+        self.u16(phnum);
+        self.u16(if shnum > 0 { SHDR_SIZE as u16 } else { 0 });
+        self.u16(shnum);
+        // .shstrtab is always the last section
+        self.u16(if shnum > 0 { shnum - 1 } else { 0 });
+    }
+}
+
+// A string table, remembering where each string went
+#[derive(Default)]
+struct Strings {
+    bytes: Vec<u8>,
+    offsets: HashMap<String, u32>
+}
+
+impl Strings {
+    fn new() -> Strings {
+        Strings { bytes: vec![0], offsets: HashMap::new() }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        if let Some(&offset) = self.offsets.get(name) {
+            return offset;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(name.to_string(), offset);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64
+}
+
+fn section_index(id: SectionId) -> u16 {
+    match id {
+        SectionId::Text => 1,
+        SectionId::Data => 2,
+    }
+}
+
+// A relocatable object, to be linked by the system linker
+pub fn write_object(object: &Object) -> Vec<u8> {
+    // symbol table: null, the two section symbols, locals, then globals
+    let mut strtab = Strings::new();
+    let mut symtab = Buffer::default();
+    let mut indices = HashMap::new();
+    let mut count = 0;
+    {
+        let mut symbol = |name: Option<&str>, bind: u8, kind: u8, shndx: u16, value: u64| {
+            let name_offset = name.map_or(0, |n| strtab.add(n));
+            symtab.u32(name_offset);
+            symtab.u8(bind << 4 | kind);
+            symtab.u8(0);
+            symtab.u16(shndx);
+            symtab.u64(value);
+            symtab.u64(0);
+            if let Some(name) = name {
+                indices.insert(name.to_string(), count);
+            }
+            count += 1;
+        };
+        symbol(None, STB_LOCAL, STT_NOTYPE, 0, 0);
+        symbol(None, STB_LOCAL, STT_SECTION, 1, 0);
+        symbol(None, STB_LOCAL, STT_SECTION, 2, 0);
+        for sym in object.symbols.iter().filter(|sym| !sym.global) {
+            symbol(Some(&sym.name), STB_LOCAL, STT_NOTYPE, section_index(sym.section), sym.offset as u64);
+        }
+    }
+    let first_global = count;
+    {
+        let mut symbol = |name: &str, shndx: u16, value: u64| {
+            let name_offset = strtab.add(name);
+            symtab.u32(name_offset);
+            symtab.u8(STB_GLOBAL << 4 | STT_NOTYPE);
+            symtab.u8(0);
+            symtab.u16(shndx);
+            symtab.u64(value);
+            symtab.u64(0);
+            indices.insert(name.to_string(), count);
+            count += 1;
+        };
+        for sym in object.symbols.iter().filter(|sym| sym.global) {
+            symbol(&sym.name, section_index(sym.section), sym.offset as u64);
+        }
+        // everything referenced but not defined comes from elsewhere
+        let mut undefined: Vec<&str> = Vec::new();
+        for reloc in object.text.relocs.iter().chain(object.data.relocs.iter()) {
+            if object.symbol(&reloc.symbol).is_none() && !undefined.contains(&&*reloc.symbol) {
+                undefined.push(&reloc.symbol);
+            }
+        }
+        for name in undefined {
+            symbol(name, 0, 0);
+        }
+    }
+
+    let rela = |id: SectionId| -> Vec<u8> {
+        let mut buf = Buffer::default();
+        for reloc in &object.section(id).relocs {
+            let kind = match reloc.kind {
+                RelocKind::Abs64 => R_X86_64_64,
+                RelocKind::Abs32S => R_X86_64_32S,
+                RelocKind::Plt32 => R_X86_64_PLT32,
+            };
+            buf.u64(reloc.offset as u64);
+            buf.u64((indices[&reloc.symbol] as u64) << 32 | kind);
+            buf.u64(reloc.addend as u64);
+        }
+        buf.bytes
+    };
+    let rela_text = rela(SectionId::Text);
+    let rela_data = rela(SectionId::Data);
+
+    let mut shstrtab = Strings::new();
+    let mut out = Buffer::default();
+    out.bytes.resize(HEADER_SIZE, 0);
+    let mut headers = Vec::new();
+    {
+        let mut section = |out: &mut Buffer, name: &str, kind: u32, flags: u64, bytes: &[u8],
+                           link: u32, info: u32, align: u64, entsize: u64| {
+            out.align(align as usize);
+            let offset = out.bytes.len();
+            out.bytes.extend_from_slice(bytes);
+            headers.push(SectionHeader {
+                name: shstrtab.add(name),
+                kind,
+                flags,
+                offset,
+                size: bytes.len(),
+                link,
+                info,
+                align,
+                entsize
+            });
+        };
+        section(&mut out, ".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, &object.text.bytes, 0, 0, 16, 0);
+        section(&mut out, ".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, &object.data.bytes, 0, 0, 8, 0);
+        section(&mut out, ".rela.text", SHT_RELA, SHF_INFO_LINK, &rela_text, 5, 1, 8, RELA_SIZE as u64);
+        section(&mut out, ".rela.data", SHT_RELA, SHF_INFO_LINK, &rela_data, 5, 2, 8, RELA_SIZE as u64);
+        section(&mut out, ".symtab", SHT_SYMTAB, 0, &symtab.bytes, 6, first_global, 8, SYM_SIZE as u64);
+        section(&mut out, ".strtab", SHT_STRTAB, 0, &strtab.bytes, 0, 0, 1, 0);
+        // the stack doesn't need to be executable
+        section(&mut out, ".note.GNU-stack", SHT_PROGBITS, 0, &[], 0, 0, 1, 0);
+        let name = shstrtab.add(".shstrtab");
+        let bytes = shstrtab.bytes.clone();
+        let offset = out.bytes.len();
+        out.bytes.extend_from_slice(&bytes);
+        headers.push(SectionHeader {
+            name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset,
+            size: bytes.len(),
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0
+        });
+    }
+
+    out.align(8);
+    let shoff = out.bytes.len();
+    out.bytes.resize(shoff + SHDR_SIZE, 0);
+    for header in &headers {
+        out.u32(header.name);
+        out.u32(header.kind);
+        out.u64(header.flags);
+        out.u64(0);
+        out.u64(header.offset as u64);
+        out.u64(header.size as u64);
+        out.u32(header.link);
+        out.u32(header.info);
+        out.u64(header.align);
+        out.u64(header.entsize);
+    }
+
+    let mut header = Buffer::default();
+    header.header(ET_REL, 0, 0, shoff as u64, headers.len() as u16 + 1);
+    out.bytes[..HEADER_SIZE].copy_from_slice(&header.bytes);
+    out.bytes
+}
+
+// A static executable starting at `entry`. Every symbol must be defined.
+pub fn write_executable(object: &Object, entry: &str) -> Vec<u8> {
+    let text_offset = HEADER_SIZE + 2 * PHDR_SIZE;
+    let text_end = text_offset + object.text.bytes.len();
+    let data_offset = (text_end + 15) & !15;
+    let text_addr = BASE_ADDR + text_offset as u64;
+    // data gets its own pages, at the same offset within the page as in the file
+    let data_addr = ((BASE_ADDR + text_end as u64 + PAGE - 1) & !(PAGE - 1))
+        + data_offset as u64 % PAGE;
+
+    let address = |name: &str| -> u64 {
+        match object.symbol(name) {
+            Some(sym) => match sym.section {
+                SectionId::Text => text_addr + sym.offset as u64,
+                SectionId::Data => data_addr + sym.offset as u64,
+            },
+            None => panic!("Internal error: undefined symbol {}", name),
+        }
+    };
+
+    let relocate = |id: SectionId, base: u64| -> Vec<u8> {
+        let mut bytes = object.section(id).bytes.clone();
+        for reloc in &object.section(id).relocs {
+            let value = (address(&reloc.symbol) as i64).wrapping_add(reloc.addend);
+            let field = &mut bytes[reloc.offset..];
+            match reloc.kind {
+                RelocKind::Abs64 => field[..8].copy_from_slice(&value.to_le_bytes()),
+                RelocKind::Abs32S => field[..4].copy_from_slice(&(value as i32).to_le_bytes()),
+                RelocKind::Plt32 => {
+                    let relative = value - (base + reloc.offset as u64) as i64;
+                    field[..4].copy_from_slice(&(relative as i32).to_le_bytes());
+                },
+            }
+        }
+        bytes
+    };
+    let text = relocate(SectionId::Text, text_addr);
+    let data = relocate(SectionId::Data, data_addr);
+
+    let mut out = Buffer::default();
+    out.header(ET_EXEC, address(entry), 2, 0, 0);
+    let segments = [(0, BASE_ADDR, text_end, PF_R | PF_X),
+                    (data_offset, data_addr, data.len(), PF_R | PF_W)];
+    for &(offset, addr, size, flags) in &segments {
+        out.u32(PT_LOAD);
+        out.u32(flags);
+        out.u64(offset as u64);
+        out.u64(addr);
+        out.u64(addr);
+        out.u64(size as u64);
+        out.u64(size as u64);
+        out.u64(PAGE);
+    }
+    out.bytes.extend_from_slice(&text);
+    out.align(16);
+    out.bytes.extend_from_slice(&data);
+    out.bytes
+}
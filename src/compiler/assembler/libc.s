@@ -0,0 +1,528 @@
+# The few C library functions the runtime helpers use, written directly
+# against Linux system calls so a program can be linked into a static
+# executable without any toolchain. Only what emooji needs is supported:
+# printf and sprintf understand %lu and %s with a single argument, strtol
+# only reads base 10, and malloc never gives memory back.
+#
+# A FILE is
+#    0: file descriptor
+#    8: character pushed back by ungetc, or -1
+#   16: number of buffered output bytes
+#   24: next open FILE, so exit can flush them all
+#   32: 4096 byte output buffer
+
+.data
+stdin_file_: .quad 0, -1, 0, 0
+		.zero 4096
+stdout_file_: .quad 1, -1, 0, stderr_file_
+		.zero 4096
+stderr_file_: .quad 2, -1, 0, 0
+		.zero 4096
+stdin: .quad stdin_file_
+stdout: .quad stdout_file_
+stderr: .quad stderr_file_
+open_files_: .quad stdout_file_
+heap_next_: .quad 0
+heap_end_: .quad 0
+rand_state_: .quad 1
+
+.text
+.global _start
+_start:
+		movq (%rsp), %rdi
+		leaq 8(%rsp), %rsi
+		andq $-16, %rsp
+		call main
+		movq %rax, %rdi
+		call exit
+
+# exit(status): flush every open file and exit
+exit:
+		movq %rdi, %rbx
+		movq open_files_, %r12
+exit_flush_:
+		cmpq $0, %r12
+		je exit_done_
+		movq %r12, %rdi
+		call flush_
+		movq 24(%r12), %r12
+		jmp exit_flush_
+exit_done_:
+		movq %rbx, %rdi
+		movq $60, %rax
+		syscall
+
+# flush_(file): write out the buffer, 0 on success and -1 on failure
+flush_:
+		movq 16(%rdi), %rdx
+		leaq 32(%rdi), %rsi
+		movq (%rdi), %r8
+		movq %rdi, %r9
+		movq $0, 16(%rdi)
+flush_loop_:
+		cmpq $0, %rdx
+		jle flush_done_
+		movq %r8, %rdi
+		movq $1, %rax
+		syscall
+		cmpq $0, %rax
+		jle flush_failed_
+		addq %rax, %rsi
+		subq %rax, %rdx
+		jmp flush_loop_
+flush_done_:
+		movq $0, %rax
+		ret
+flush_failed_:
+		movq $-1, %rax
+		ret
+
+# fputc(c, file): buffer c, flushing when full (or at a newline on stderr)
+fputc:
+		movq 16(%rsi), %rax
+		cmpq $4096, %rax
+		jl fputc_store_
+		push %rdi
+		push %rsi
+		movq %rsi, %rdi
+		call flush_
+		pop %rsi
+		pop %rdi
+		movq 16(%rsi), %rax
+fputc_store_:
+		movb %dil, 32(%rsi,%rax)
+		addq $1, %rax
+		movq %rax, 16(%rsi)
+		cmpq $2, (%rsi)
+		jne fputc_done_
+		cmpl $10, %edi
+		jne fputc_done_
+		push %rdi
+		movq %rsi, %rdi
+		call flush_
+		pop %rdi
+fputc_done_:
+		movzbl %dil, %eax
+		ret
+
+# putchar(c)
+putchar:
+		movq $stdout_file_, %rsi
+		jmp fputc
+
+# fputs(s, file): 0 on success
+fputs:
+		push %rbx
+		push %r12
+		movq %rdi, %rbx
+		movq %rsi, %r12
+fputs_loop_:
+		movzbl (%rbx), %edi
+		cmpl $0, %edi
+		je fputs_done_
+		movq %r12, %rsi
+		call fputc
+		addq $1, %rbx
+		jmp fputs_loop_
+fputs_done_:
+		movq $0, %rax
+		pop %r12
+		pop %rbx
+		ret
+
+# printf(format, value)
+printf:
+		movq %rsi, %rdx
+		movq %rdi, %rsi
+		movq $0, %rdi
+		jmp format_
+
+# sprintf(buffer, format, value)
+sprintf:
+		jmp format_
+
+# format_(buffer, format, value): writes to buffer, or stdout if it is 0
+format_:
+		push %rbx
+		push %r12
+		push %r13
+		push %r14
+		subq $40, %rsp
+		movq %rdi, %rbx
+		movq %rsi, %r12
+		movq %rdx, %r13
+format_loop_:
+		movzbl (%r12), %edi
+		cmpl $0, %edi
+		je format_done_
+		addq $1, %r12
+		cmpl $37, %edi
+		jne format_literal_
+		movzbl (%r12), %eax
+		cmpl $115, %eax
+		je format_string_
+		cmpl $108, %eax
+		je format_number_
+format_literal_:
+		call format_put_
+		jmp format_loop_
+format_string_:
+		addq $1, %r12
+		movq %r13, %r14
+format_copy_:
+		movzbl (%r14), %edi
+		cmpl $0, %edi
+		je format_loop_
+		call format_put_
+		addq $1, %r14
+		jmp format_copy_
+format_number_:
+		# skip "lu", then produce the digits backwards
+		addq $2, %r12
+		leaq 32(%rsp), %r14
+		movb $0, (%r14)
+		movq %r13, %rax
+		movq $10, %rcx
+format_digit_:
+		xorq %rdx, %rdx
+		divq %rcx
+		addq $48, %rdx
+		subq $1, %r14
+		movb %dl, (%r14)
+		cmpq $0, %rax
+		jne format_digit_
+		jmp format_copy_
+format_done_:
+		cmpq $0, %rbx
+		je format_return_
+		movb $0, (%rbx)
+format_return_:
+		movq $0, %rax
+		addq $40, %rsp
+		pop %r14
+		pop %r13
+		pop %r12
+		pop %rbx
+		ret
+
+# format_put_: character %edi to the destination in %rbx, advancing it
+format_put_:
+		cmpq $0, %rbx
+		je format_put_stdout_
+		movb %dil, (%rbx)
+		addq $1, %rbx
+		ret
+format_put_stdout_:
+		movq $stdout_file_, %rsi
+		jmp fputc
+
+# malloc(size): bump allocation from the program break
+malloc:
+		addq $15, %rdi
+		andq $-16, %rdi
+		movq heap_next_, %rax
+		cmpq $0, %rax
+		jne malloc_have_heap_
+		push %rdi
+		movq $0, %rdi
+		movq $12, %rax
+		syscall
+		pop %rdi
+		movq %rax, heap_next_
+		movq %rax, heap_end_
+malloc_have_heap_:
+		movq %rax, %rsi
+		addq %rdi, %rax
+		cmpq heap_end_, %rax
+		jbe malloc_fits_
+		# grow by a megabyte more than needed
+		push %rsi
+		push %rax
+		leaq 1048576(%rax), %rdi
+		movq $12, %rax
+		syscall
+		pop %rdx
+		pop %rsi
+		cmpq %rdx, %rax
+		jb malloc_failed_
+		movq %rax, heap_end_
+		movq %rdx, %rax
+malloc_fits_:
+		movq %rax, heap_next_
+		movq %rsi, %rax
+		ret
+malloc_failed_:
+		movq $0, %rax
+		ret
+
+# realloc(pointer, size): a new block with the old contents copied over.
+# Blocks don't record their size, but the new one starts where the heap
+# ended, so everything between the old block and it can be copied safely.
+realloc:
+		push %rdi
+		push %rsi
+		movq %rsi, %rdi
+		call malloc
+		pop %rcx
+		pop %rsi
+		cmpq $0, %rax
+		je realloc_done_
+		cmpq $0, %rsi
+		je realloc_done_
+		movq %rax, %rdx
+		subq %rsi, %rdx
+		cmpq %rcx, %rdx
+		jae realloc_copy_
+		movq %rdx, %rcx
+realloc_copy_:
+		movq %rax, %rdi
+realloc_loop_:
+		cmpq $0, %rcx
+		je realloc_done_
+		movzbl (%rsi), %edx
+		movb %dl, (%rdi)
+		addq $1, %rsi
+		addq $1, %rdi
+		subq $1, %rcx
+		jmp realloc_loop_
+realloc_done_:
+		ret
+
+# free(pointer): memory is never given back
+free:
+		ret
+
+# strlen(s)
+strlen:
+		movq %rdi, %rax
+strlen_loop_:
+		cmpb $0, (%rax)
+		je strlen_done_
+		addq $1, %rax
+		jmp strlen_loop_
+strlen_done_:
+		subq %rdi, %rax
+		ret
+
+# strcmp(a, b)
+strcmp:
+		movzbl (%rdi), %eax
+		movzbl (%rsi), %ecx
+		cmpl %ecx, %eax
+		jne strcmp_done_
+		cmpl $0, %eax
+		je strcmp_done_
+		addq $1, %rdi
+		addq $1, %rsi
+		jmp strcmp
+strcmp_done_:
+		subl %ecx, %eax
+		ret
+
+# strcpy(dst, src): returns dst
+strcpy:
+		movq %rdi, %rax
+strcpy_loop_:
+		movzbl (%rsi), %ecx
+		movb %cl, (%rdi)
+		addq $1, %rdi
+		addq $1, %rsi
+		cmpl $0, %ecx
+		jne strcpy_loop_
+		ret
+
+# strcat(dst, src): returns dst
+strcat:
+		push %rdi
+		push %rsi
+		call strlen
+		pop %rsi
+		movq (%rsp), %rdi
+		addq %rax, %rdi
+		call strcpy
+		pop %rax
+		ret
+
+# strtol(s, end, base): leading spaces, an optional sign and decimal digits
+strtol:
+		movq $0, %rax
+		movq $0, %r8
+strtol_space_:
+		movzbl (%rdi), %ecx
+		cmpl $32, %ecx
+		je strtol_skip_
+		cmpl $9, %ecx
+		jb strtol_sign_
+		cmpl $13, %ecx
+		ja strtol_sign_
+strtol_skip_:
+		addq $1, %rdi
+		jmp strtol_space_
+strtol_sign_:
+		cmpl $45, %ecx
+		jne strtol_plus_
+		movq $1, %r8
+		addq $1, %rdi
+		jmp strtol_digits_
+strtol_plus_:
+		cmpl $43, %ecx
+		jne strtol_digits_
+		addq $1, %rdi
+strtol_digits_:
+		movzbl (%rdi), %ecx
+		subl $48, %ecx
+		cmpl $9, %ecx
+		ja strtol_done_
+		imulq $10, %rax, %rax
+		addq %rcx, %rax
+		addq $1, %rdi
+		jmp strtol_digits_
+strtol_done_:
+		cmpq $0, %r8
+		je strtol_return_
+		negq %rax
+strtol_return_:
+		ret
+
+# fopen(path, mode): mode starts with r, w or a
+fopen:
+		movzbl (%rsi), %eax
+		movq $0, %rsi
+		cmpl $114, %eax
+		je fopen_open_
+		# O_WRONLY | O_CREAT | O_TRUNC
+		movq $577, %rsi
+		cmpl $119, %eax
+		je fopen_open_
+		# O_WRONLY | O_CREAT | O_APPEND
+		movq $1089, %rsi
+fopen_open_:
+		movq $420, %rdx
+		movq $2, %rax
+		syscall
+		cmpq $0, %rax
+		jl fopen_failed_
+		push %rax
+		movq $4128, %rdi
+		call malloc
+		pop %rcx
+		movq %rcx, (%rax)
+		movq $-1, 8(%rax)
+		movq $0, 16(%rax)
+		movq open_files_, %rcx
+		movq %rcx, 24(%rax)
+		movq %rax, open_files_
+		ret
+fopen_failed_:
+		movq $0, %rax
+		ret
+
+# fclose(file): 0 on success
+fclose:
+		push %rdi
+		call flush_
+		pop %rdi
+		push %rax
+		movq (%rdi), %rdi
+		movq $3, %rax
+		syscall
+		pop %rcx
+		orq %rcx, %rax
+		cmpq $0, %rax
+		je fclose_done_
+		movq $-1, %rax
+fclose_done_:
+		ret
+
+# fgetc(file): next byte, or -1 at the end
+fgetc:
+		movq 8(%rdi), %rax
+		cmpq $-1, %rax
+		je fgetc_read_
+		movq $-1, 8(%rdi)
+		ret
+fgetc_read_:
+		push %rdi
+		subq $8, %rsp
+		# show any pending prompt before waiting for input
+		cmpq $0, (%rdi)
+		jne fgetc_syscall_
+		movq $stdout_file_, %rdi
+		call flush_
+		movq 8(%rsp), %rdi
+fgetc_syscall_:
+		movq (%rdi), %rdi
+		movq %rsp, %rsi
+		movq $1, %rdx
+		movq $0, %rax
+		syscall
+		cmpq $1, %rax
+		jne fgetc_eof_
+		movzbl (%rsp), %eax
+		addq $16, %rsp
+		ret
+fgetc_eof_:
+		movq $-1, %rax
+		addq $16, %rsp
+		ret
+
+# ungetc(c, file)
+ungetc:
+		movslq %edi, %rax
+		movq %rax, 8(%rsi)
+		ret
+
+# fgets(buffer, size, file): a line including its newline, 0 at the end
+fgets:
+		push %rbx
+		push %r12
+		push %r13
+		push %r14
+		movq %rdi, %rbx
+		movq %rdi, %r12
+		leaq -1(%rsi), %r13
+		movq %rdx, %r14
+fgets_loop_:
+		cmpq $0, %r13
+		jle fgets_done_
+		movq %r14, %rdi
+		call fgetc
+		cmpl $-1, %eax
+		je fgets_done_
+		movb %al, (%r12)
+		addq $1, %r12
+		subq $1, %r13
+		cmpl $10, %eax
+		jne fgets_loop_
+fgets_done_:
+		movb $0, (%r12)
+		movq %rbx, %rax
+		cmpq %rbx, %r12
+		jne fgets_return_
+		movq $0, %rax
+fgets_return_:
+		pop %r14
+		pop %r13
+		pop %r12
+		pop %rbx
+		ret
+
+# srand(seed), rand(): a 64 bit linear congruential generator
+srand:
+		movq %rdi, rand_state_
+		ret
+rand:
+		movq rand_state_, %rax
+		movq $6364136223846793005, %rcx
+		imulq %rcx, %rax
+		movq $1442695040888963407, %rcx
+		addq %rcx, %rax
+		movq %rax, rand_state_
+		shrq $33, %rax
+		ret
+
+# time(t)
+time:
+		movq $201, %rax
+		syscall
+		ret
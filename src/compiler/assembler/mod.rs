@@ -0,0 +1,1086 @@
+mod elf;
+use std::collections::HashMap;
+pub use self::elf::write_object;
+pub use self::elf::write_executable;
+
+// Assembles the AT&T syntax the code generator writes into x86-64 machine
+// code, so objects and executables can be produced without binutils. Only the
+// instructions and directives emooji itself uses are understood; anything
+// else is an internal error.
+//
+// Every branch and every symbolic operand is encoded with a 32 bit field, so
+// instruction sizes never depend on where symbols end up and one pass is
+// enough. Branches to labels in the same section are filled in at the end,
+// everything else is left as a relocation.
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SectionId {
+    Text,
+    Data
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RelocKind {
+    // 64 bit absolute address
+    Abs64,
+    // 32 bit absolute address, sign-extended by the CPU
+    Abs32S,
+    // 32 bit offset from the end of the field, for branches
+    Plt32
+}
+
+#[derive(Debug, Clone)]
+pub struct Reloc {
+    pub offset: usize,
+    pub kind: RelocKind,
+    pub symbol: String,
+    pub addend: i64
+}
+
+#[derive(Debug, Default)]
+pub struct Section {
+    pub bytes: Vec<u8>,
+    pub relocs: Vec<Reloc>
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub section: SectionId,
+    pub offset: usize,
+    pub global: bool
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub text: Section,
+    pub data: Section,
+    // defined symbols, in order of definition
+    pub symbols: Vec<Symbol>
+}
+
+impl Object {
+    pub fn section(&self, id: SectionId) -> &Section {
+        match id {
+            SectionId::Text => &self.text,
+            SectionId::Data => &self.data,
+        }
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|sym| sym.name == name)
+    }
+}
+
+// A number, a symbol, or a symbol plus a number
+#[derive(Debug, PartialEq, Clone)]
+struct Value {
+    symbol: Option<String>,
+    offset: i64
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Reg {
+    num: u8,
+    // in bytes
+    size: u8
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Mem {
+    base: Option<u8>,
+    index: Option<(u8, u8)>,
+    disp: Value
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Operand {
+    Reg(Reg),
+    Imm(Value),
+    Mem(Mem),
+    // *operand, for indirect calls and jumps
+    Indirect(Box<Operand>)
+}
+
+const REGS_64: [&str; 16] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+                             "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+const REGS_32: [&str; 16] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+                             "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+const REGS_16: [&str; 16] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+                             "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"];
+const REGS_8: [&str; 16] = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil",
+                            "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b"];
+
+// Condition codes, in encoding order, with their aliases
+const CONDITIONS: [&[&str]; 16] = [
+    &["o"], &["no"], &["b", "c", "nae"], &["ae", "nb", "nc"], &["e", "z"], &["ne", "nz"],
+    &["be", "na"], &["a", "nbe"], &["s"], &["ns"], &["p", "pe"], &["np", "po"],
+    &["l", "nge"], &["ge", "nl"], &["le", "ng"], &["g", "nle"],
+];
+
+fn condition_code(name: &str) -> Option<u8> {
+    CONDITIONS.iter().position(|names| names.contains(&name)).map(|cc| cc as u8)
+}
+
+fn parse_reg(name: &str) -> Option<Reg> {
+    let tables: [(&[&str; 16], u8); 4] = [(&REGS_64, 8), (&REGS_32, 4), (&REGS_16, 2), (&REGS_8, 1)];
+    for &(table, size) in &tables {
+        if let Some(num) = table.iter().position(|reg| *reg == name) {
+            return Some(Reg { num: num as u8, size });
+        }
+    }
+    None
+}
+
+fn fits_i8(value: i64) -> bool {
+    value >= i8::MIN as i64 && value <= i8::MAX as i64
+}
+
+fn fits_i32(value: i64) -> bool {
+    value >= i32::MIN as i64 && value <= i32::MAX as i64
+}
+
+// Splits on commas that are not inside parentheses or quotes
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            '\'' | '"' => quoted = !quoted,
+            '\\' if quoted => {
+                current.push(ch);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                continue;
+            },
+            ',' if depth == 0 && !quoted => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => {}
+        }
+        current.push(ch);
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+// Removes a trailing comment, leaving '#' inside quotes alone
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = None;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (ch, quoted) {
+            ('\\', Some(_)) => escaped = true,
+            ('"', None) | ('\'', None) => quoted = Some(ch),
+            (c, Some(q)) if c == q => quoted = None,
+            ('#', None) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        let ch = if ch == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(other) => other,
+                None => '\\',
+            }
+        } else {
+            ch
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+    }
+    bytes
+}
+
+pub struct Assembler {
+    text: Section,
+    data: Section,
+    section: SectionId,
+    labels: HashMap<String, (SectionId, usize)>,
+    symbols: Vec<Symbol>,
+    globals: Vec<String>,
+    line: usize
+}
+
+// Assembles `code` into an object
+pub fn assemble(code: &str) -> Object {
+    let mut asm = Assembler::new();
+    for (i, line) in code.lines().enumerate() {
+        asm.line = i + 1;
+        asm.line_of_code(line);
+    }
+    asm.finish()
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            text: Section::default(),
+            data: Section::default(),
+            section: SectionId::Text,
+            labels: HashMap::new(),
+            symbols: Vec::new(),
+            globals: Vec::new(),
+            line: 0
+        }
+    }
+
+    fn error(&self, message: &str) -> ! {
+        panic!("Internal error: cannot assemble line {}: {}", self.line, message);
+    }
+
+    fn current(&mut self) -> &mut Section {
+        match self.section {
+            SectionId::Text => &mut self.text,
+            SectionId::Data => &mut self.data,
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.current().bytes.extend_from_slice(bytes);
+    }
+
+    fn offset(&mut self) -> usize {
+        self.current().bytes.len()
+    }
+
+    fn line_of_code(&mut self, line: &str) {
+        let mut line = strip_comment(line).trim();
+
+        // any number of labels may start a line
+        while let Some(colon) = line.find(':') {
+            let name = line[..colon].trim();
+            if name.is_empty() || name.contains(char::is_whitespace) || name.contains('"') {
+                break;
+            }
+            self.define(name);
+            line = line[colon + 1..].trim();
+        }
+        if line.is_empty() {
+            return;
+        }
+
+        let (mnemonic, rest) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], line[space..].trim()),
+            None => (line, ""),
+        };
+        if mnemonic.starts_with('.') {
+            self.directive(mnemonic, rest);
+        } else {
+            let operands: Vec<Operand> = split_operands(rest).iter()
+                .map(|op| self.operand(op))
+                .collect();
+            if self.section != SectionId::Text {
+                self.error("instruction outside of .text");
+            }
+            self.instruction(mnemonic, &operands);
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if self.labels.contains_key(name) {
+            self.error(&format!("label {} defined twice", name));
+        }
+        let section = self.section;
+        let offset = self.offset();
+        self.labels.insert(name.to_string(), (section, offset));
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            section,
+            offset,
+            global: false
+        });
+    }
+
+    fn directive(&mut self, name: &str, rest: &str) {
+        match name {
+            ".text" => self.section = SectionId::Text,
+            ".data" => self.section = SectionId::Data,
+            ".section" => {
+                self.section = match split_operands(rest).first().map(|s| s.as_str()) {
+                    Some(".text") => SectionId::Text,
+                    Some(".data") => SectionId::Data,
+                    // nothing goes in any other section, e.g. .note.GNU-stack
+                    _ => self.section,
+                };
+            },
+            ".global" | ".globl" => self.globals.push(rest.to_string()),
+            // undefined symbols are external anyway
+            ".extern" => {},
+            ".quad" => {
+                for op in split_operands(rest) {
+                    let value = self.value(&op);
+                    self.data_value(value, 8);
+                }
+            },
+            ".long" => {
+                for op in split_operands(rest) {
+                    let value = self.value(&op);
+                    self.data_value(value, 4);
+                }
+            },
+            ".byte" => {
+                for op in split_operands(rest) {
+                    let value = self.value(&op);
+                    self.data_value(value, 1);
+                }
+            },
+            ".string" | ".asciz" | ".ascii" => {
+                if !rest.starts_with('"') || !rest.ends_with('"') || rest.len() < 2 {
+                    self.error("expected a quoted string");
+                }
+                let mut bytes = unescape(&rest[1..rest.len() - 1]);
+                if name != ".ascii" {
+                    bytes.push(0);
+                }
+                self.emit(&bytes);
+            },
+            ".zero" | ".skip" => {
+                let count = self.value(rest);
+                if count.symbol.is_some() || count.offset < 0 {
+                    self.error("bad size");
+                }
+                let zeros = vec![0; count.offset as usize];
+                self.emit(&zeros);
+            },
+            ".align" | ".p2align" | ".balign" => {
+                let value = self.value(&split_operands(rest)[0]).offset as usize;
+                let align = if name == ".p2align" { 1 << value } else { value };
+                while !self.offset().is_multiple_of(align) {
+                    let fill = if self.section == SectionId::Text { 0x90 } else { 0 };
+                    self.emit(&[fill]);
+                }
+            },
+            // debug information is not carried over
+            ".file" | ".loc" | ".type" | ".size" | ".ident" | ".cfi_startproc" |
+            ".cfi_endproc" => {},
+            _ => self.error(&format!("unknown directive {}", name)),
+        }
+    }
+
+    fn data_value(&mut self, value: Value, size: usize) {
+        if let Some(symbol) = value.symbol {
+            if size != 8 {
+                self.error("symbols in data must be .quad");
+            }
+            let offset = self.offset();
+            self.current().relocs.push(Reloc {
+                offset,
+                kind: RelocKind::Abs64,
+                symbol,
+                addend: value.offset
+            });
+            self.emit(&[0; 8]);
+        } else {
+            self.emit(&value.offset.to_le_bytes()[..size]);
+        }
+    }
+
+    // A number, a character literal, or a symbol with an optional offset
+    fn value(&self, text: &str) -> Value {
+        let text = text.trim();
+        if text.starts_with('\'') {
+            let bytes = unescape(text.trim_matches('\''));
+            if bytes.len() != 1 {
+                self.error(&format!("bad character {}", text));
+            }
+            return Value { symbol: None, offset: bytes[0] as i64 };
+        }
+        if let Some(number) = self.number(text) {
+            return Value { symbol: None, offset: number };
+        }
+
+        // symbol+offset or symbol-offset
+        let split = text.char_indices().skip(1).find(|&(_, ch)| ch == '+' || ch == '-');
+        if let Some((i, _)) = split {
+            if let Some(number) = self.number(&text[i..]) {
+                return Value { symbol: Some(text[..i].to_string()), offset: number };
+            }
+        }
+        if text.is_empty() || text.contains(|ch: char| "$%(),".contains(ch) || ch.is_whitespace()) {
+            self.error(&format!("bad value {}", text));
+        }
+        Value { symbol: Some(text.to_string()), offset: 0 }
+    }
+
+    fn number(&self, text: &str) -> Option<i64> {
+        let (negative, digits) = if let Some(digits) = text.strip_prefix('-') {
+            (true, digits)
+        } else if let Some(digits) = text.strip_prefix('+') {
+            (false, digits)
+        } else {
+            (false, text)
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).ok()
+        } else if !digits.is_empty() && digits.chars().all(|ch| ch.is_ascii_digit()) {
+            digits.parse::<u64>().ok()
+        } else {
+            None
+        };
+        value.map(|v| if negative { (v as i64).wrapping_neg() } else { v as i64 })
+    }
+
+    fn operand(&self, text: &str) -> Operand {
+        if let Some(target) = text.strip_prefix('*') {
+            return Operand::Indirect(Box::new(self.operand(target)));
+        }
+        if let Some(value) = text.strip_prefix('$') {
+            return Operand::Imm(self.value(value));
+        }
+        if let Some(name) = text.strip_prefix('%') {
+            return match parse_reg(name) {
+                Some(reg) => Operand::Reg(reg),
+                None => self.error(&format!("unknown register {}", text)),
+            };
+        }
+
+        // disp(base,index,scale), any part optional
+        let (disp, inner) = match text.find('(') {
+            Some(open) => {
+                if !text.ends_with(')') {
+                    self.error(&format!("bad memory operand {}", text));
+                }
+                (&text[..open], Some(&text[open + 1..text.len() - 1]))
+            },
+            None => (text, None),
+        };
+        let disp = if disp.trim().is_empty() {
+            Value { symbol: None, offset: 0 }
+        } else {
+            self.value(disp)
+        };
+        let mut mem = Mem { base: None, index: None, disp };
+        if let Some(inner) = inner {
+            let parts: Vec<&str> = inner.split(',').map(|s| s.trim()).collect();
+            let reg = |part: &str| -> u8 {
+                match parse_reg(part.trim_start_matches('%')) {
+                    Some(Reg { num, size: 8 }) => num,
+                    _ => self.error(&format!("bad address register {}", part)),
+                }
+            };
+            if !parts[0].is_empty() {
+                mem.base = Some(reg(parts[0]));
+            }
+            if parts.len() > 1 {
+                let scale = match parts.get(2).map(|s| s.parse::<u8>()) {
+                    None => 1,
+                    Some(Ok(scale)) if [1, 2, 4, 8].contains(&scale) => scale,
+                    _ => self.error(&format!("bad scale in {}", text)),
+                };
+                let index = reg(parts[1]);
+                if index == 4 {
+                    self.error("%rsp cannot be an index");
+                }
+                mem.index = Some((index, scale));
+            }
+        }
+        Operand::Mem(mem)
+    }
+
+    // The operand size implied by the suffix or by the register operands
+    fn size(&self, suffix: Option<u8>, operands: &[Operand]) -> u8 {
+        if let Some(size) = suffix {
+            return size;
+        }
+        for op in operands {
+            if let Operand::Reg(reg) = *op {
+                return reg.size;
+            }
+        }
+        self.error("operand size is ambiguous");
+    }
+
+    // Writes an instruction with a ModRM byte. `reg` is the register or
+    // opcode extension in the reg field, `rm` the register or memory operand.
+    fn modrm(&mut self, size: u8, opcode: &[u8], reg: u8, rm: &Operand, rex_w: bool) {
+        self.modrm_rex(size, opcode, reg, rm, rex_w, false);
+    }
+
+    // Same as modrm, with the register `reg` in the reg field
+    fn modrm_reg(&mut self, size: u8, opcode: &[u8], reg: u8, rm: &Operand, rex_w: bool) {
+        // spl, bpl, sil and dil only exist with a REX prefix
+        let byte_reg = size == 1 && (4..8).contains(&reg);
+        self.modrm_rex(size, opcode, reg, rm, rex_w, byte_reg);
+    }
+
+    fn modrm_rex(&mut self, size: u8, opcode: &[u8], reg: u8, rm: &Operand, rex_w: bool, force_rex: bool) {
+        let mut rex = if force_rex { 0x40 } else { 0 };
+        if rex_w {
+            rex |= 0x48;
+        }
+        if reg >= 8 {
+            rex |= 0x44;
+        }
+        match *rm {
+            Operand::Reg(r) => {
+                if r.num >= 8 {
+                    rex |= 0x41;
+                }
+                // spl, bpl, sil and dil only exist with a REX prefix
+                if size == 1 && r.num >= 4 && r.num < 8 {
+                    rex |= 0x40;
+                }
+            },
+            Operand::Mem(ref m) => {
+                if m.base.is_some_and(|b| b >= 8) {
+                    rex |= 0x41;
+                }
+                if m.index.is_some_and(|(i, _)| i >= 8) {
+                    rex |= 0x42;
+                }
+            },
+            _ => self.error("expected a register or memory operand"),
+        }
+        if size == 2 {
+            self.emit(&[0x66]);
+        }
+        if rex != 0 {
+            self.emit(&[rex]);
+        }
+        self.emit(opcode);
+
+        let reg = reg & 7;
+        match *rm {
+            Operand::Reg(r) => self.emit(&[0xc0 | reg << 3 | (r.num & 7)]),
+            Operand::Mem(ref m) => self.memory(reg, m),
+            _ => unreachable!(),
+        }
+    }
+
+    fn memory(&mut self, reg: u8, m: &Mem) {
+        let symbolic = m.disp.symbol.is_some();
+        let disp = m.disp.offset;
+        match (m.base, m.index) {
+            (None, None) => {
+                // absolute address through a SIB byte with no base or index
+                self.emit(&[reg << 3 | 0x04, 0x25]);
+                self.disp32(&m.disp);
+            },
+            (None, Some((index, scale))) => {
+                self.emit(&[reg << 3 | 0x04, scale_bits(scale) | (index & 7) << 3 | 0x05]);
+                self.disp32(&m.disp);
+            },
+            (Some(base), index) => {
+                // %rbp and %r13 always need a displacement
+                let mode = if symbolic || !fits_i8(disp) {
+                    0x80
+                } else if disp == 0 && base & 7 != 5 {
+                    0x00
+                } else {
+                    0x40
+                };
+                match index {
+                    Some((index, scale)) => {
+                        self.emit(&[mode | reg << 3 | 0x04,
+                                    scale_bits(scale) | (index & 7) << 3 | (base & 7)]);
+                    },
+                    // %rsp and %r12 need a SIB byte
+                    None if base & 7 == 4 => self.emit(&[mode | reg << 3 | 0x04, 0x24]),
+                    None => self.emit(&[mode | reg << 3 | (base & 7)]),
+                }
+                match mode {
+                    0x40 => self.emit(&[disp as u8]),
+                    0x80 => self.disp32(&m.disp),
+                    _ => {}
+                }
+            },
+        }
+    }
+
+    fn disp32(&mut self, value: &Value) {
+        if let Some(ref symbol) = value.symbol {
+            let offset = self.offset();
+            self.current().relocs.push(Reloc {
+                offset,
+                kind: RelocKind::Abs32S,
+                symbol: symbol.clone(),
+                addend: value.offset
+            });
+            self.emit(&[0; 4]);
+        } else {
+            if !fits_i32(value.offset) {
+                self.error("displacement does not fit in 32 bits");
+            }
+            self.imm(value.offset, 4);
+        }
+    }
+
+    fn imm(&mut self, value: i64, size: u8) {
+        self.emit(&value.to_le_bytes()[..size as usize]);
+    }
+
+    // An immediate of `size` bytes (at most 4, sign-extended by the CPU)
+    fn imm_value(&mut self, value: &Value, size: u8) {
+        if value.symbol.is_some() {
+            if size != 4 {
+                self.error("symbolic immediate must be 32 bits");
+            }
+            self.disp32(value);
+        } else {
+            self.imm(value.offset, size);
+        }
+    }
+
+    fn branch(&mut self, opcode: &[u8], target: &Operand) {
+        let value = match *target {
+            Operand::Mem(Mem { base: None, index: None, ref disp }) if disp.symbol.is_some() => disp.clone(),
+            _ => self.error("expected a label"),
+        };
+        self.emit(opcode);
+        let offset = self.offset();
+        self.current().relocs.push(Reloc {
+            offset,
+            kind: RelocKind::Plt32,
+            symbol: value.symbol.unwrap(),
+            addend: value.offset - 4
+        });
+        self.emit(&[0; 4]);
+    }
+
+    fn instruction(&mut self, mnemonic: &str, ops: &[Operand]) {
+        let (base, suffix) = split_suffix(mnemonic);
+        let expect = |count: usize| {
+            if ops.len() != count {
+                panic!("Internal error: cannot assemble line {}: {} takes {} operands",
+                    self.line, mnemonic, count);
+            }
+        };
+
+        match base {
+            "ret" => self.emit(&[0xc3]),
+            "leave" => self.emit(&[0xc9]),
+            "syscall" => self.emit(&[0x0f, 0x05]),
+            "nop" => self.emit(&[0x90]),
+            "cqto" | "cqo" => self.emit(&[0x48, 0x99]),
+            "cltq" | "cdqe" => self.emit(&[0x48, 0x98]),
+            "push" => {
+                expect(1);
+                match ops[0] {
+                    Operand::Reg(r) => self.short_reg(0x50, r.num),
+                    Operand::Imm(ref v) if v.symbol.is_none() && fits_i8(v.offset) => {
+                        self.emit(&[0x6a]);
+                        self.imm(v.offset, 1);
+                    },
+                    Operand::Imm(ref v) => {
+                        self.emit(&[0x68]);
+                        self.imm_value(v, 4);
+                    },
+                    ref mem => self.modrm(8, &[0xff], 6, mem, false),
+                }
+            },
+            "pop" => {
+                expect(1);
+                match ops[0] {
+                    Operand::Reg(r) => self.short_reg(0x58, r.num),
+                    ref mem => self.modrm(8, &[0x8f], 0, mem, false),
+                }
+            },
+            "call" | "jmp" => {
+                expect(1);
+                let (direct, ext) = if base == "call" { (0xe8, 2) } else { (0xe9, 4) };
+                match ops[0] {
+                    Operand::Indirect(ref target) => self.modrm(8, &[0xff], ext, target, false),
+                    ref target => self.branch(&[direct], target),
+                }
+            },
+            "mov" => {
+                expect(2);
+                let size = self.size(suffix, ops);
+                self.mov(size, &ops[0], &ops[1]);
+            },
+            "movabs" => {
+                expect(2);
+                match (&ops[0], &ops[1]) {
+                    (Operand::Imm(v), Operand::Reg(r)) if v.symbol.is_none() => {
+                        self.rex_b(true, r.num);
+                        self.emit(&[0xb8 + (r.num & 7)]);
+                        self.imm(v.offset, 8);
+                    },
+                    _ => self.error("movabs needs a number and a register"),
+                }
+            },
+            "movzbq" | "movzbl" | "movzwq" | "movzwl" | "movsbq" | "movsbl" | "movswq" => {
+                expect(2);
+                let dst = self.reg(&ops[1]);
+                let opcode: &[u8] = match &base[..5] {
+                    "movzb" => &[0x0f, 0xb6],
+                    "movzw" => &[0x0f, 0xb7],
+                    "movsb" => &[0x0f, 0xbe],
+                    _ => &[0x0f, 0xbf],
+                };
+                let src_size = if base.as_bytes()[4] == b'b' { 1 } else { 2 };
+                self.modrm(src_size, opcode, dst.num, &ops[0], mnemonic.ends_with('q'));
+            },
+            "movslq" => {
+                expect(2);
+                let dst = self.reg(&ops[1]);
+                self.modrm(4, &[0x63], dst.num, &ops[0], true);
+            },
+            "lea" => {
+                expect(2);
+                let dst = self.reg(&ops[1]);
+                if let Operand::Mem(_) = ops[0] {} else {
+                    self.error("lea needs a memory operand");
+                }
+                self.modrm(dst.size, &[0x8d], dst.num, &ops[0], dst.size == 8);
+            },
+            "add" | "or" | "adc" | "sbb" | "and" | "sub" | "xor" | "cmp" => {
+                expect(2);
+                let op = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"]
+                    .iter().position(|name| *name == base).unwrap() as u8;
+                let size = self.size(suffix, ops);
+                self.alu(size, op, &ops[0], &ops[1]);
+            },
+            "test" => {
+                expect(2);
+                let size = self.size(suffix, ops);
+                let byte = (size == 1) as u8;
+                match (&ops[0], &ops[1]) {
+                    (Operand::Imm(v), rm) => {
+                        self.modrm(size, &[0xf7 - byte], 0, rm, size == 8);
+                        self.imm_value(v, size.min(4));
+                    },
+                    (Operand::Reg(r), rm) => self.modrm_reg(size, &[0x85 - byte], r.num, rm, size == 8),
+                    _ => self.error("bad test operands"),
+                }
+            },
+            "not" | "neg" | "mul" | "div" | "idiv" | "inc" | "dec" => {
+                expect(1);
+                let size = self.size(suffix, ops);
+                let byte = (size == 1) as u8;
+                let (opcode, ext) = match base {
+                    "not" => (0xf7, 2),
+                    "neg" => (0xf7, 3),
+                    "mul" => (0xf7, 4),
+                    "div" => (0xf7, 6),
+                    "idiv" => (0xf7, 7),
+                    "inc" => (0xff, 0),
+                    _ => (0xff, 1),
+                };
+                self.modrm(size, &[opcode - byte], ext, &ops[0], size == 8);
+            },
+            "imul" => {
+                let size = self.size(suffix, ops);
+                match ops.len() {
+                    1 => self.modrm(size, &[0xf7], 5, &ops[0], size == 8),
+                    2 => {
+                        let dst = self.reg(&ops[1]);
+                        if let Operand::Imm(ref v) = ops[0] {
+                            self.imul_imm(size, v, &ops[1], dst.num);
+                        } else {
+                            self.modrm(size, &[0x0f, 0xaf], dst.num, &ops[0], size == 8);
+                        }
+                    },
+                    3 => {
+                        let dst = self.reg(&ops[2]);
+                        match ops[0] {
+                            Operand::Imm(ref v) => self.imul_imm(size, v, &ops[1], dst.num),
+                            _ => self.error("imul with three operands needs an immediate"),
+                        }
+                    },
+                    _ => self.error("imul takes one to three operands"),
+                }
+            },
+            "shl" | "sal" | "shr" | "sar" => {
+                let size = self.size(suffix, &ops[ops.len() - 1..]);
+                let ext = match base {
+                    "shr" => 5,
+                    "sar" => 7,
+                    _ => 4,
+                };
+                match ops.len() {
+                    1 => self.modrm(size, &[0xd1], ext, &ops[0], size == 8),
+                    2 => match ops[0] {
+                        Operand::Imm(ref v) if v.symbol.is_none() => {
+                            self.modrm(size, &[0xc1], ext, &ops[1], size == 8);
+                            self.imm(v.offset, 1);
+                        },
+                        Operand::Reg(Reg { num: 1, size: 1 }) => {
+                            self.modrm(size, &[0xd3], ext, &ops[1], size == 8);
+                        },
+                        _ => self.error("shift count must be a number or %cl"),
+                    },
+                    _ => self.error("shifts take one or two operands"),
+                }
+            },
+            _ => {
+                if let Some(cc) = base.strip_prefix('j').and_then(condition_code) {
+                    expect(1);
+                    return self.branch(&[0x0f, 0x80 + cc], &ops[0]);
+                }
+                if let Some(cc) = base.strip_prefix("set").and_then(condition_code) {
+                    expect(1);
+                    return self.modrm(1, &[0x0f, 0x90 + cc], 0, &ops[0], false);
+                }
+                if let Some(cc) = base.strip_prefix("cmov").and_then(condition_code) {
+                    expect(2);
+                    let size = self.size(suffix, ops);
+                    let dst = self.reg(&ops[1]);
+                    return self.modrm(size, &[0x0f, 0x40 + cc], dst.num, &ops[0], size == 8);
+                }
+                self.error(&format!("unknown instruction {}", mnemonic));
+            },
+        }
+    }
+
+    fn reg(&self, op: &Operand) -> Reg {
+        match *op {
+            Operand::Reg(r) => r,
+            _ => self.error("expected a register"),
+        }
+    }
+
+    fn rex_b(&mut self, w: bool, num: u8) {
+        let mut rex = if w { 0x48 } else { 0 };
+        if num >= 8 {
+            rex |= 0x41;
+        }
+        if rex != 0 {
+            self.emit(&[rex]);
+        }
+    }
+
+    // push and pop encode the register in the opcode
+    fn short_reg(&mut self, opcode: u8, num: u8) {
+        self.rex_b(false, num);
+        self.emit(&[opcode + (num & 7)]);
+    }
+
+    fn imul_imm(&mut self, size: u8, v: &Value, src: &Operand, dst: u8) {
+        if v.symbol.is_none() && fits_i8(v.offset) {
+            self.modrm(size, &[0x6b], dst, src, size == 8);
+            self.imm(v.offset, 1);
+        } else {
+            self.modrm(size, &[0x69], dst, src, size == 8);
+            self.imm_value(v, 4);
+        }
+    }
+
+    fn mov(&mut self, size: u8, src: &Operand, dst: &Operand) {
+        let byte = (size == 1) as u8;
+        let w = size == 8;
+        match (src, dst) {
+            (Operand::Reg(r), rm) => self.modrm_reg(size, &[0x89 - byte], r.num, rm, w),
+            (Operand::Mem(_), Operand::Reg(r)) => self.modrm_reg(size, &[0x8b - byte], r.num, src, w),
+            (Operand::Imm(v), Operand::Reg(r)) => {
+                let number = v.offset;
+                if size == 8 && v.symbol.is_none() && !fits_i32(number) {
+                    if number >= 0 && number <= u32::MAX as i64 {
+                        // writing a 32 bit register clears the upper half
+                        self.rex_b(false, r.num);
+                        self.emit(&[0xb8 + (r.num & 7)]);
+                        self.imm(number, 4);
+                    } else {
+                        self.rex_b(true, r.num);
+                        self.emit(&[0xb8 + (r.num & 7)]);
+                        self.imm(number, 8);
+                    }
+                } else if size == 8 {
+                    self.modrm(size, &[0xc7], 0, dst, true);
+                    self.imm_value(v, 4);
+                } else if size == 1 {
+                    self.modrm(size, &[0xc6], 0, dst, false);
+                    self.imm(number, 1);
+                } else {
+                    if size == 2 {
+                        self.emit(&[0x66]);
+                    }
+                    self.rex_b(false, r.num);
+                    self.emit(&[0xb8 + (r.num & 7)]);
+                    self.imm_value(v, size);
+                }
+            },
+            (Operand::Imm(v), Operand::Mem(_)) => {
+                self.modrm(size, &[0xc7 - byte], 0, dst, w);
+                self.imm_value(v, size.min(4));
+            },
+            _ => self.error("bad mov operands"),
+        }
+    }
+
+    fn alu(&mut self, size: u8, op: u8, src: &Operand, dst: &Operand) {
+        let byte = (size == 1) as u8;
+        let w = size == 8;
+        match (src, dst) {
+            (Operand::Imm(v), rm) => {
+                if size == 1 {
+                    self.modrm(size, &[0x80], op, rm, false);
+                    self.imm(v.offset, 1);
+                } else if v.symbol.is_none() && fits_i8(v.offset) {
+                    self.modrm(size, &[0x83], op, rm, w);
+                    self.imm(v.offset, 1);
+                } else {
+                    self.modrm(size, &[0x81], op, rm, w);
+                    self.imm_value(v, size.min(4));
+                }
+            },
+            (Operand::Reg(r), rm) => self.modrm_reg(size, &[op * 8 + 1 - byte], r.num, rm, w),
+            (Operand::Mem(_), Operand::Reg(r)) => self.modrm_reg(size, &[op * 8 + 3 - byte], r.num, src, w),
+            _ => self.error("bad operands"),
+        }
+    }
+
+    fn finish(mut self) -> Object {
+        for name in &self.globals {
+            // a global that is never defined is just an external
+            if let Some(sym) = self.symbols.iter_mut().find(|sym| sym.name == *name) {
+                sym.global = true;
+            }
+        }
+
+        // branches within .text don't need the linker
+        let labels = &self.labels;
+        let text = &mut self.text;
+        let mut relocs = Vec::new();
+        for reloc in text.relocs.drain(..) {
+            match labels.get(&reloc.symbol) {
+                Some(&(SectionId::Text, target)) if reloc.kind == RelocKind::Plt32 => {
+                    let value = target as i64 + reloc.addend - reloc.offset as i64;
+                    text.bytes[reloc.offset..reloc.offset + 4].copy_from_slice(&(value as i32).to_le_bytes());
+                },
+                _ => relocs.push(reloc),
+            }
+        }
+        text.relocs = relocs;
+
+        Object {
+            text: self.text,
+            data: self.data,
+            symbols: self.symbols
+        }
+    }
+}
+
+fn scale_bits(scale: u8) -> u8 {
+    match scale {
+        1 => 0x00,
+        2 => 0x40,
+        4 => 0x80,
+        _ => 0xc0,
+    }
+}
+
+// Separates an operand size suffix from the mnemonic, if it has one
+fn split_suffix(mnemonic: &str) -> (&str, Option<u8>) {
+    const BASES: [&str; 28] = ["ret", "leave", "syscall", "nop", "push", "pop", "call", "jmp",
+                               "mov", "movabs", "lea", "add", "or", "adc", "sbb", "and", "sub",
+                               "xor", "cmp", "test", "not", "neg", "mul", "div", "idiv", "inc",
+                               "dec", "imul"];
+    const SHIFTS: [&str; 4] = ["shl", "sal", "shr", "sar"];
+    if BASES.contains(&mnemonic) || SHIFTS.contains(&mnemonic) {
+        return (mnemonic, None);
+    }
+    if mnemonic.len() > 1 {
+        let (base, suffix) = mnemonic.split_at(mnemonic.len() - 1);
+        let size = match suffix {
+            "q" => Some(8),
+            "l" => Some(4),
+            "w" => Some(2),
+            "b" => Some(1),
+            _ => None,
+        };
+        if size.is_some() && (BASES.contains(&base) || SHIFTS.contains(&base)) {
+            return (base, size);
+        }
+    }
+    (mnemonic, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(line: &str) -> Vec<u8> {
+        assemble(&format!(".text\n{}\n", line)).text.bytes
+    }
+
+    // expected encodings are the ones GNU as produces
+    #[test]
+    fn encodes_like_gas() {
+        assert_eq!(bytes("movq %rax, %rdi"), [0x48, 0x89, 0xc7]);
+        assert_eq!(bytes("movq $10, %rax"), [0x48, 0xc7, 0xc0, 0x0a, 0, 0, 0]);
+        assert_eq!(bytes("movq -8(%rbp), %rdx"), [0x48, 0x8b, 0x55, 0xf8]);
+        assert_eq!(bytes("movq %rdi, -8(%rbp)"), [0x48, 0x89, 0x7d, 0xf8]);
+        assert_eq!(bytes("movq 8(%rax,%rdi,8), %rax"), [0x48, 0x8b, 0x44, 0xf8, 0x08]);
+        assert_eq!(bytes("movb $0, -1(%rdi,%rax)"), [0xc6, 0x44, 0x07, 0xff, 0x00]);
+        assert_eq!(bytes("cmpb $10, -1(%rdi,%rax)"), [0x80, 0x7c, 0x07, 0xff, 0x0a]);
+        assert_eq!(bytes("pop %r15"), [0x41, 0x5f]);
+        assert_eq!(bytes("push %rbp"), [0x55]);
+        assert_eq!(bytes("addq %r15, %rax"), [0x4c, 0x01, 0xf8]);
+        assert_eq!(bytes("subq $16, %rsp"), [0x48, 0x83, 0xec, 0x10]);
+        assert_eq!(bytes("andq $-16, %rsp"), [0x48, 0x83, 0xe4, 0xf0]);
+        assert_eq!(bytes("cmpl $-1, %eax"), [0x83, 0xf8, 0xff]);
+        assert_eq!(bytes("movq $16000, %rdi"), [0x48, 0xc7, 0xc7, 0x80, 0x3e, 0, 0]);
+        assert_eq!(bytes("mul %r15"), [0x49, 0xf7, 0xe7]);
+        assert_eq!(bytes("divq -8(%rbp)"), [0x48, 0xf7, 0x75, 0xf8]);
+        assert_eq!(bytes("sete %al"), [0x0f, 0x94, 0xc0]);
+        assert_eq!(bytes("movzbq %al, %rax"), [0x48, 0x0f, 0xb6, 0xc0]);
+        assert_eq!(bytes("movslq %eax, %rax"), [0x48, 0x63, 0xc0]);
+        assert_eq!(bytes("leaq 1(%rax), %rdi"), [0x48, 0x8d, 0x78, 0x01]);
+        assert_eq!(bytes("movl %eax, %edi"), [0x89, 0xc7]);
+        assert_eq!(bytes("movq (%rsp), %rdi"), [0x48, 0x8b, 0x3c, 0x24]);
+        assert_eq!(bytes("movq 0(%r13), %rax"), [0x49, 0x8b, 0x45, 0x00]);
+        assert_eq!(bytes("movb (%rsi), %dil"), [0x40, 0x8a, 0x3e]);
+        assert_eq!(bytes("movq $4294967295, %rax"), [0xb8, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(bytes("movq $-2, %rax"), [0x48, 0xc7, 0xc0, 0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(bytes("movq $9223372036854775807, %rcx"),
+            [0x48, 0xb9, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+        assert_eq!(bytes("imulq $10, %rax, %rax"), [0x48, 0x6b, 0xc0, 0x0a]);
+        assert_eq!(bytes("shlq $3, %rax"), [0x48, 0xc1, 0xe0, 0x03]);
+        assert_eq!(bytes("leave\nret\nsyscall"), [0xc9, 0xc3, 0x0f, 0x05]);
+    }
+
+    #[test]
+    fn local_branches_are_resolved() {
+        let object = assemble(".text\nstart:\n\tjmp end\n\tje start\nend:\n\tcall start\n");
+        assert_eq!(object.text.bytes, [0xe9, 0x06, 0, 0, 0,
+                                       0x0f, 0x84, 0xf5, 0xff, 0xff, 0xff,
+                                       0xe8, 0xf0, 0xff, 0xff, 0xff]);
+        assert!(object.text.relocs.is_empty());
+    }
+
+    #[test]
+    fn symbols_become_relocations() {
+        let object = assemble(".data\n\t😀: .quad 0\n\tp: .quad 😀\n\
+                               .text\n.global main\nmain:\n\tmovq $😀, %rax\n\
+                               \tmovq 😀, %rax\n\tcall *😀\n\tcall printf\n");
+        let kinds: Vec<(RelocKind, &str, i64)> = object.text.relocs.iter()
+            .map(|r| (r.kind, r.symbol.as_str(), r.addend)).collect();
+        assert_eq!(kinds, [(RelocKind::Abs32S, "😀", 0), (RelocKind::Abs32S, "😀", 0),
+                           (RelocKind::Abs32S, "😀", 0), (RelocKind::Plt32, "printf", -4)]);
+        assert_eq!(object.data.relocs[0].kind, RelocKind::Abs64);
+        assert_eq!(object.data.bytes.len(), 16);
+        assert!(object.symbol("main").unwrap().global);
+        assert!(!object.symbol("😀").unwrap().global);
+    }
+
+    #[test]
+    fn strings_and_bytes() {
+        let object = assemble(".data\n\ts: .string \"a#\\n\" # comment\n\tb: .byte '%', 'l', 0\n");
+        assert_eq!(object.data.bytes, [b'a', b'#', b'\n', 0, b'%', b'l', 0]);
+    }
+}
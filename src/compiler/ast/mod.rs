@@ -0,0 +1,171 @@
+use super::tokenizer::VarType;
+
+// Every node remembers the position of its first token so errors and labels
+// can refer back to the source.
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    // filled in by the checker
+    pub globals: Vec<(String, VarType)>,
+    pub statements: Vec<Stmt>
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub pos: usize,
+    // just past its last token
+    pub end: usize
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    Declare(VarType, String),
+    Assign(String, Expr),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    Block(Vec<Stmt>),
+    // arguments, and whether to end the line
+    Print(Vec<Expr>, bool),
+    Function(Function),
+    Return(Option<Expr>),
+    Exit(Expr),
+    // calls whose result is thrown away
+    Expr(Expr)
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub id: String,
+    pub ret_type: VarType,
+    pub params: Vec<(String, VarType)>,
+    // variables declared anywhere in the body, filled in by the checker
+    pub locals: Vec<(String, VarType)>,
+    pub body: Box<Stmt>
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    // filled in by the checker, NONE until then
+    pub var_type: VarType,
+    pub pos: usize
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Int(u64),
+    Bool(bool),
+    Str(String),
+    Var(String),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Call(String, Vec<Expr>),
+    Builtin(Builtin, Vec<Expr>),
+    // literal segments around the placeholders, and one argument per placeholder
+    Format(Vec<String>, Vec<VarType>, Vec<Expr>),
+    // inserted by the checker wherever a value changes type
+    Convert(Box<Expr>)
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Builtin {
+    Rand,
+    ReadStr,
+    ReadInt,
+    Eof,
+    ArgCount,
+    Arg,
+    Parse,
+    Open,
+    ReadLine,
+    Write,
+    Close
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, pos: usize) -> Expr {
+        Expr {
+            kind,
+            var_type: VarType::NONE,
+            pos
+        }
+    }
+}
+
+impl Function {
+    // The positions of the statements that call the function itself as the
+    // last thing it does, like opt::tail finds in the IR, so that backends
+    // without it can jump back to the start instead
+    pub fn tail_calls(&self) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.find_tail_calls(&self.body, true, &mut found);
+        found
+    }
+
+    fn find_tail_calls(&self, stmt: &Stmt, last: bool, found: &mut Vec<usize>) {
+        let calls_itself = |expr: &Expr| match expr.kind {
+            ExprKind::Call(ref id, _) => *id == self.id,
+            _ => false,
+        };
+        match stmt.kind {
+            StmtKind::Return(Some(ref value)) if calls_itself(value) => found.push(stmt.pos),
+            // without a value to return, nothing happens after the call
+            StmtKind::Expr(ref expr) if last && self.ret_type == VarType::NONE && calls_itself(expr) => {
+                found.push(stmt.pos);
+            },
+            StmtKind::Block(ref statements) => {
+                for (i, s) in statements.iter().enumerate() {
+                    let returns_next = match statements.get(i + 1) {
+                        Some(next) => matches!(next.kind, StmtKind::Return(None)),
+                        None => last,
+                    };
+                    self.find_tail_calls(s, returns_next, found);
+                }
+            },
+            StmtKind::If(_, ref then, ref otherwise) => {
+                self.find_tail_calls(then, last, found);
+                if let Some(ref s) = *otherwise {
+                    self.find_tail_calls(s, last, found);
+                }
+            },
+            StmtKind::While(_, ref body) => self.find_tail_calls(body, false, found),
+            _ => {},
+        }
+    }
+}
+
+impl Builtin {
+    // argument types and result type
+    pub fn signature(&self, arg_count: usize) -> (Vec<VarType>, VarType) {
+        match *self {
+            Builtin::Rand => (vec![VarType::INT], VarType::INT),
+            Builtin::ReadStr => (vec![], VarType::STR),
+            Builtin::ReadInt => (vec![], VarType::INT),
+            // stdin unless given a file
+            Builtin::Eof => {
+                if arg_count == 0 {
+                    (vec![], VarType::BOOL)
+                } else {
+                    (vec![VarType::File], VarType::BOOL)
+                }
+            },
+            Builtin::ArgCount => (vec![], VarType::INT),
+            Builtin::Arg => (vec![VarType::INT], VarType::STR),
+            Builtin::Parse => (vec![VarType::STR], VarType::INT),
+            Builtin::Open => (vec![VarType::STR, VarType::STR], VarType::File),
+            Builtin::ReadLine => (vec![VarType::File], VarType::STR),
+            Builtin::Write => (vec![VarType::File, VarType::STR], VarType::BOOL),
+            Builtin::Close => (vec![VarType::File], VarType::BOOL),
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use super::super::tokenizer::VarType;
+use super::super::ast::{self, *};
+use super::{Op, Module, Function, BUILTINS, KIND_NUMBER, KIND_STRING};
+
+// Turns a checked program into bytecode. The main program comes first and
+// ends with Halt, followed by the bodies of the functions.
+struct Compiler<'a> {
+    code: Vec<Op>,
+    strings: Vec<Vec<u8>>,
+    globals: HashMap<String, u32>,
+    functions: HashMap<String, u32>,
+    function_table: Vec<Function>,
+    // functions whose body still has to be compiled
+    pending: Vec<&'a ast::Function>,
+    // slots of the function being compiled, if any
+    locals: Option<HashMap<String, u32>>
+}
+
+fn kind(var_type: VarType) -> u8 {
+    if var_type == VarType::STR {
+        KIND_STRING
+    } else {
+        KIND_NUMBER
+    }
+}
+
+// Finds every function definition, wherever it is in the main program
+fn collect_functions<'a>(stmt: &'a Stmt, functions: &mut Vec<&'a ast::Function>) {
+    match stmt.kind {
+        StmtKind::Function(ref func) => functions.push(func),
+        StmtKind::Block(ref statements) => {
+            for s in statements {
+                collect_functions(s, functions);
+            }
+        },
+        StmtKind::If(_, ref then, ref otherwise) => {
+            collect_functions(then, functions);
+            if let Some(ref s) = *otherwise {
+                collect_functions(s, functions);
+            }
+        },
+        StmtKind::While(_, ref body) => collect_functions(body, functions),
+        _ => {}
+    }
+}
+
+pub fn compile(program: &Program) -> Module {
+    let mut compiler = Compiler {
+        code: Vec::new(),
+        strings: Vec::new(),
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+        function_table: Vec::new(),
+        pending: Vec::new(),
+        locals: None
+    };
+
+    let mut globals = Vec::new();
+    for &(ref id, var_type) in &program.globals {
+        compiler.globals.insert(id.clone(), globals.len() as u32);
+        globals.push(kind(var_type));
+    }
+    // calls can come before the definition, so every function gets its
+    // number up front
+    let mut functions = Vec::new();
+    for stmt in &program.statements {
+        collect_functions(stmt, &mut functions);
+    }
+    for func in functions {
+        compiler.declare_function(func);
+    }
+
+    for stmt in &program.statements {
+        compiler.statement(stmt);
+    }
+    compiler.code.push(Op::Halt);
+    for func in compiler.pending.split_off(0) {
+        compiler.function(func);
+    }
+
+    Module {
+        strings: compiler.strings,
+        globals,
+        functions: compiler.function_table,
+        code: compiler.code
+    }
+}
+
+impl<'a> Compiler<'a> {
+    fn string(&mut self, value: &[u8]) -> u32 {
+        match self.strings.iter().position(|s| s[..] == *value) {
+            Some(index) => index as u32,
+            None => {
+                self.strings.push(value.to_vec());
+                (self.strings.len() - 1) as u32
+            },
+        }
+    }
+
+    fn declare_function(&mut self, func: &'a ast::Function) {
+        // every parameter and local gets a slot, parameters first
+        let mut slots = Vec::new();
+        let mut seen = Vec::new();
+        for &(ref id, var_type) in func.params.iter().chain(func.locals.iter()) {
+            if !seen.contains(id) {
+                seen.push(id.clone());
+                slots.push(kind(var_type));
+            }
+        }
+        let name = self.string(func.id.as_bytes());
+        self.functions.insert(func.id.clone(), self.function_table.len() as u32);
+        self.function_table.push(Function {
+            name,
+            entry: 0,
+            params: func.params.len() as u32,
+            slots
+        });
+        self.pending.push(func);
+    }
+
+    // Where the next instruction goes
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    // Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: u32) {
+        let target = self.here();
+        self.code[at as usize] = match self.code[at as usize] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfZero(_) => Op::JumpIfZero(target),
+            op => panic!("Internal error: patching {:?}", op),
+        };
+    }
+
+    fn store(&mut self, id: &str) {
+        if let Some(ref locals) = self.locals {
+            if let Some(&slot) = locals.get(id) {
+                self.code.push(Op::StoreLocal(slot));
+                return;
+            }
+        }
+        let global = self.globals[id];
+        self.code.push(Op::Store(global));
+    }
+
+    fn load(&mut self, id: &str) {
+        if let Some(ref locals) = self.locals {
+            if let Some(&slot) = locals.get(id) {
+                self.code.push(Op::LoadLocal(slot));
+                return;
+            }
+        }
+        let global = self.globals[id];
+        self.code.push(Op::Load(global));
+    }
+
+    fn print_str(&mut self, text: &str) {
+        let index = self.string(text.as_bytes());
+        self.code.push(Op::Str(index));
+        self.code.push(Op::Print(2));
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match stmt.kind {
+            StmtKind::Declare(..) => {},
+            StmtKind::Assign(ref id, ref value) => {
+                self.expression(value);
+                self.store(id);
+            },
+            StmtKind::If(ref cond, ref then, ref otherwise) => {
+                self.expression(cond);
+                let skip_then = self.here();
+                self.code.push(Op::JumpIfZero(0));
+                self.statement(then);
+                match *otherwise {
+                    Some(ref s) => {
+                        let skip_else = self.here();
+                        self.code.push(Op::Jump(0));
+                        self.patch(skip_then);
+                        self.statement(s);
+                        self.patch(skip_else);
+                    },
+                    None => self.patch(skip_then),
+                }
+            },
+            StmtKind::While(ref cond, ref body) => {
+                let start = self.here();
+                self.expression(cond);
+                let exit = self.here();
+                self.code.push(Op::JumpIfZero(0));
+                self.statement(body);
+                self.code.push(Op::Jump(start));
+                self.patch(exit);
+            },
+            StmtKind::Block(ref statements) => {
+                for s in statements {
+                    self.statement(s);
+                }
+            },
+            StmtKind::Print(ref args, newline) => {
+                for (i, arg) in args.iter().enumerate() {
+                    // arguments are separated by a space
+                    if i > 0 {
+                        self.print_str(" ");
+                    }
+                    self.expression(arg);
+                    let kind = match arg.var_type {
+                        VarType::STR => 2,
+                        VarType::BOOL => 1,
+                        _ => 0,
+                    };
+                    self.code.push(Op::Print(kind));
+                }
+                if newline {
+                    self.print_str("\n");
+                }
+            },
+            // functions can be called from the start, like in native code
+            StmtKind::Function(_) => {},
+            StmtKind::Return(ref value) => {
+                match *value {
+                    Some(ref v) => self.expression(v),
+                    None => self.code.push(Op::Int(0)),
+                }
+                self.code.push(Op::Return);
+            },
+            StmtKind::Exit(ref status) => {
+                self.expression(status);
+                self.code.push(Op::Exit);
+            },
+            StmtKind::Expr(ref expr) => {
+                // result is ignored when used as a statement
+                self.expression(expr);
+                self.code.push(Op::Pop);
+            },
+        }
+    }
+
+    fn function(&mut self, func: &'a ast::Function) {
+        let index = self.functions[&func.id];
+        let mut locals = HashMap::new();
+        for (id, _) in func.params.iter().chain(func.locals.iter()) {
+            let slot = locals.len() as u32;
+            locals.entry(id.clone()).or_insert(slot);
+        }
+        self.function_table[index as usize].entry = self.here();
+
+        self.locals = Some(locals);
+        self.statement(&func.body);
+        self.locals = None;
+
+        // falling off the end returns the default value
+        if func.ret_type == VarType::STR {
+            let empty = self.string(b"");
+            self.code.push(Op::Str(empty));
+        } else {
+            self.code.push(Op::Int(0));
+        }
+        self.code.push(Op::Return);
+    }
+
+    fn expression(&mut self, expr: &'a Expr) {
+        match expr.kind {
+            ExprKind::Int(value) => self.code.push(Op::Int(value)),
+            ExprKind::Bool(value) => self.code.push(Op::Int(value as u64)),
+            ExprKind::Str(ref value) => {
+                let index = self.string(value.as_bytes());
+                self.code.push(Op::Str(index));
+            },
+            ExprKind::Var(ref id) => self.load(id),
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+                let op = match (op, expr.var_type) {
+                    (BinOp::Add, VarType::STR) => Op::Concat,
+                    // ➕ on booleans is "or" and ✖️ is "and"
+                    (BinOp::Add, VarType::BOOL) => Op::Or,
+                    (BinOp::Mul, VarType::BOOL) => Op::And,
+                    (BinOp::Add, _) => Op::Add,
+                    (BinOp::Sub, _) => Op::Sub,
+                    (BinOp::Mul, _) => Op::Mul,
+                    (BinOp::Div, _) => Op::Div,
+                    (BinOp::Eq, _) if lhs.var_type == VarType::STR => Op::StrEq,
+                    (BinOp::Eq, _) => Op::Eq,
+                };
+                self.code.push(op);
+            },
+            ExprKind::Not(ref operand) => {
+                self.expression(operand);
+                self.code.push(Op::Not);
+            },
+            ExprKind::Call(ref id, ref args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let index = self.functions[id];
+                self.code.push(Op::Call(index));
+            },
+            ExprKind::Builtin(builtin, ref args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let number = BUILTINS.iter().position(|&b| b == builtin).unwrap();
+                self.code.push(Op::Builtin(number as u8, args.len() as u8));
+            },
+            ExprKind::Format(ref segments, _, ref args) => {
+                // arguments were converted to strings by the checker
+                let first = self.string(segments[0].as_bytes());
+                self.code.push(Op::Str(first));
+                for (i, arg) in args.iter().enumerate() {
+                    self.expression(arg);
+                    self.code.push(Op::Concat);
+                    if !segments[i + 1].is_empty() {
+                        let segment = self.string(segments[i + 1].as_bytes());
+                        self.code.push(Op::Str(segment));
+                        self.code.push(Op::Concat);
+                    }
+                }
+            },
+            ExprKind::Convert(ref inner) => {
+                self.expression(inner);
+                match (inner.var_type, expr.var_type) {
+                    (VarType::INT, VarType::STR) => self.code.push(Op::IntToStr),
+                    (VarType::BOOL, VarType::STR) => self.code.push(Op::BoolToStr),
+                    // booleans are always 0 or 1
+                    (_, VarType::BOOL) => self.code.push(Op::ToBool),
+                    _ => {},
+                }
+            },
+        }
+    }
+}
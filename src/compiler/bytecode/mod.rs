@@ -0,0 +1,524 @@
+mod compile;
+mod vm;
+pub use self::compile::compile;
+pub use self::vm::Vm;
+use super::ast::Builtin;
+
+// A portable stack-based bytecode. Instructions pop their operands off a value
+// stack and push their result, where a value is a number or a string, with
+// booleans and files being numbers like in the compiled code. Function calls
+// get their own frame of local slots.
+//
+// Serialized, a module is a sequence of little-endian fields:
+//
+//   magic       4 bytes, "MOOB"
+//   version     u16, currently 1
+//   strings     u32 count, then for each: u32 length and that many bytes
+//   globals     u32 count, then for each: u8 kind (0 number, 1 string)
+//   functions   u32 count, then for each:
+//                 u32 name (string index), u32 entry (instruction index),
+//                 u32 parameter count, u32 slot count and a u8 kind per slot.
+//                 The parameters are the first slots.
+//   code        u32 count, then for each instruction: a u8 opcode followed by
+//               its operand, if any (see Op)
+//
+// Execution starts at instruction 0. The main program's code comes first and
+// each function's code runs from its entry up to the next function's, so
+// functions are listed in the order of their code. Every piece of code ends
+// by jumping, returning or stopping, and only jumps within itself or uses
+// slots of its own function. Variables start out as 0 or the empty string
+// depending on their kind.
+
+pub const MAGIC: &[u8] = b"MOOB";
+pub const VERSION: u16 = 1;
+
+// Instructions, with their opcode
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Op {
+    // 0x00: stop the program with status 0
+    Halt,
+    // 0x01 u64: push a number
+    Int(u64),
+    // 0x02 u32: push a string constant
+    Str(u32),
+    // 0x03 u32, 0x04 u32: push or pop a global
+    Load(u32),
+    Store(u32),
+    // 0x05 u32, 0x06 u32: push or pop a slot of the current frame
+    LoadLocal(u32),
+    StoreLocal(u32),
+    // 0x10-0x18: pop the right operand, then the left one, push the result.
+    // Arithmetic wraps around, Eq compares numbers and StrEq strings.
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Or,
+    And,
+    Concat,
+    Eq,
+    StrEq,
+    // 0x19-0x1c: replace the top of the stack
+    Not,
+    ToBool,
+    IntToStr,
+    BoolToStr,
+    // 0x20 u32, 0x21 u32: jump to an instruction, always or if popping 0
+    Jump(u32),
+    JumpIfZero(u32),
+    // 0x23 u32: call a function, popping its arguments (the last one first)
+    Call(u32),
+    // 0x24: leave the function, handing the popped value to the caller
+    Return,
+    // 0x30 u8 u8: call builtin number n (see BUILTINS) with this many arguments
+    Builtin(u8, u8),
+    // 0x31 u8: pop and print a value as 0 a number, 1 a boolean or 2 a string
+    Print(u8),
+    // 0x32: throw the top value away
+    Pop,
+    // 0x33: stop the program with the popped status
+    Exit
+}
+
+// Builtins by number
+pub const BUILTINS: [Builtin; 11] = [
+    Builtin::Rand,
+    Builtin::ReadStr,
+    Builtin::ReadInt,
+    Builtin::Eof,
+    Builtin::ArgCount,
+    Builtin::Arg,
+    Builtin::Parse,
+    Builtin::Open,
+    Builtin::ReadLine,
+    Builtin::Write,
+    Builtin::Close
+];
+
+pub const KIND_NUMBER: u8 = 0;
+pub const KIND_STRING: u8 = 1;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: u32,
+    pub entry: u32,
+    pub params: u32,
+    // kind of every slot, parameters first
+    pub slots: Vec<u8>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module {
+    pub strings: Vec<Vec<u8>>,
+    pub globals: Vec<u8>,
+    pub functions: Vec<Function>,
+    pub code: Vec<Op>
+}
+
+// Reads the fields of a serialized module, failing rather than reading past
+// its end
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < count {
+            return Err(String::from("bytecode ends too early"));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    // A count of items that each take at least `item_size` bytes
+    fn count(&mut self, item_size: usize) -> Result<usize, String> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(item_size) > self.bytes.len() - self.pos {
+            return Err(String::from("bytecode ends too early"));
+        }
+        Ok(count)
+    }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+impl Op {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let (opcode, operand) = match *self {
+            Op::Halt => (0x00, None),
+            Op::Int(value) => {
+                bytes.push(0x01);
+                bytes.extend_from_slice(&value.to_le_bytes());
+                return;
+            },
+            Op::Str(index) => (0x02, Some(index)),
+            Op::Load(index) => (0x03, Some(index)),
+            Op::Store(index) => (0x04, Some(index)),
+            Op::LoadLocal(slot) => (0x05, Some(slot)),
+            Op::StoreLocal(slot) => (0x06, Some(slot)),
+            Op::Add => (0x10, None),
+            Op::Sub => (0x11, None),
+            Op::Mul => (0x12, None),
+            Op::Div => (0x13, None),
+            Op::Or => (0x14, None),
+            Op::And => (0x15, None),
+            Op::Concat => (0x16, None),
+            Op::Eq => (0x17, None),
+            Op::StrEq => (0x18, None),
+            Op::Not => (0x19, None),
+            Op::ToBool => (0x1a, None),
+            Op::IntToStr => (0x1b, None),
+            Op::BoolToStr => (0x1c, None),
+            Op::Jump(target) => (0x20, Some(target)),
+            Op::JumpIfZero(target) => (0x21, Some(target)),
+            Op::Call(func) => (0x23, Some(func)),
+            Op::Return => (0x24, None),
+            Op::Builtin(builtin, args) => {
+                bytes.extend_from_slice(&[0x30, builtin, args]);
+                return;
+            },
+            Op::Print(kind) => {
+                bytes.extend_from_slice(&[0x31, kind]);
+                return;
+            },
+            Op::Pop => (0x32, None),
+            Op::Exit => (0x33, None),
+        };
+        bytes.push(opcode);
+        if let Some(operand) = operand {
+            push_u32(bytes, operand);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Op, String> {
+        let op = match reader.u8()? {
+            0x00 => Op::Halt,
+            0x01 => Op::Int(reader.u64()?),
+            0x02 => Op::Str(reader.u32()?),
+            0x03 => Op::Load(reader.u32()?),
+            0x04 => Op::Store(reader.u32()?),
+            0x05 => Op::LoadLocal(reader.u32()?),
+            0x06 => Op::StoreLocal(reader.u32()?),
+            0x10 => Op::Add,
+            0x11 => Op::Sub,
+            0x12 => Op::Mul,
+            0x13 => Op::Div,
+            0x14 => Op::Or,
+            0x15 => Op::And,
+            0x16 => Op::Concat,
+            0x17 => Op::Eq,
+            0x18 => Op::StrEq,
+            0x19 => Op::Not,
+            0x1a => Op::ToBool,
+            0x1b => Op::IntToStr,
+            0x1c => Op::BoolToStr,
+            0x20 => Op::Jump(reader.u32()?),
+            0x21 => Op::JumpIfZero(reader.u32()?),
+            0x23 => Op::Call(reader.u32()?),
+            0x24 => Op::Return,
+            0x30 => {
+                let builtin = reader.u8()?;
+                Op::Builtin(builtin, reader.u8()?)
+            },
+            0x31 => Op::Print(reader.u8()?),
+            0x32 => Op::Pop,
+            0x33 => Op::Exit,
+            opcode => return Err(format!("unknown opcode {:#04x}", opcode)),
+        };
+        Ok(op)
+    }
+}
+
+impl Module {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        push_u32(&mut bytes, self.strings.len() as u32);
+        for string in &self.strings {
+            push_u32(&mut bytes, string.len() as u32);
+            bytes.extend_from_slice(string);
+        }
+        push_u32(&mut bytes, self.globals.len() as u32);
+        bytes.extend_from_slice(&self.globals);
+        push_u32(&mut bytes, self.functions.len() as u32);
+        for func in &self.functions {
+            push_u32(&mut bytes, func.name);
+            push_u32(&mut bytes, func.entry);
+            push_u32(&mut bytes, func.params);
+            push_u32(&mut bytes, func.slots.len() as u32);
+            bytes.extend_from_slice(&func.slots);
+        }
+        push_u32(&mut bytes, self.code.len() as u32);
+        for op in &self.code {
+            op.write(&mut bytes);
+        }
+        bytes
+    }
+
+    // Reads a serialized module, making sure everything it refers to exists
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(String::from("not emooji bytecode"));
+        }
+        let mut reader = Reader { bytes, pos: MAGIC.len() };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("bytecode version {} is not supported", version));
+        }
+
+        let mut strings = Vec::new();
+        for _ in 0..reader.count(4)? {
+            let len = reader.u32()? as usize;
+            strings.push(reader.take(len)?.to_vec());
+        }
+        let count = reader.count(1)?;
+        let globals = reader.take(count)?.to_vec();
+        let mut functions = Vec::new();
+        for _ in 0..reader.count(16)? {
+            let name = reader.u32()?;
+            let entry = reader.u32()?;
+            let params = reader.u32()?;
+            let count = reader.count(1)?;
+            functions.push(Function {
+                name,
+                entry,
+                params,
+                slots: reader.take(count)?.to_vec()
+            });
+        }
+        let mut code = Vec::new();
+        for _ in 0..reader.count(1)? {
+            code.push(Op::read(&mut reader)?);
+        }
+        if reader.pos != bytes.len() {
+            return Err(String::from("unexpected bytes after the code"));
+        }
+
+        let module = Module {
+            strings,
+            globals,
+            functions,
+            code
+        };
+        module.validate()?;
+        Ok(module)
+    }
+
+    // Everything an instruction refers to has to exist and belong to the
+    // code it is part of, so the VM never looks past the end of a table or
+    // runs from one function into the next
+    fn validate(&self) -> Result<(), String> {
+        let kinds_ok = |kinds: &[u8]| kinds.iter().all(|&kind| kind == KIND_NUMBER || kind == KIND_STRING);
+        if !kinds_ok(&self.globals) {
+            return Err(String::from("unknown global kind"));
+        }
+        if self.code.is_empty() {
+            return Err(String::from("no code"));
+        }
+        let mut starts = vec![0];
+        for func in &self.functions {
+            if func.name as usize >= self.strings.len() || func.entry as usize >= self.code.len()
+                || func.entry as usize <= *starts.last().unwrap()
+                || func.params as usize > func.slots.len() || !kinds_ok(&func.slots) {
+                return Err(format!("bad function {:?}", func));
+            }
+            starts.push(func.entry as usize);
+        }
+
+        for (k, &start) in starts.iter().enumerate() {
+            let end = starts.get(k + 1).cloned().unwrap_or(self.code.len());
+            // the main program has no slots and nothing to return to
+            let slots = if k == 0 { 0 } else { self.functions[k - 1].slots.len() };
+            for (i, op) in self.code.iter().enumerate().take(end).skip(start) {
+                let ok = match *op {
+                    Op::Str(index) => (index as usize) < self.strings.len(),
+                    Op::Load(index) | Op::Store(index) => (index as usize) < self.globals.len(),
+                    Op::LoadLocal(slot) | Op::StoreLocal(slot) => (slot as usize) < slots,
+                    Op::Jump(target) | Op::JumpIfZero(target) => (start..end).contains(&(target as usize)),
+                    Op::Call(func) => (func as usize) < self.functions.len(),
+                    Op::Return => k > 0,
+                    Op::Builtin(builtin, args) => (builtin as usize) < BUILTINS.len()
+                        && BUILTINS[builtin as usize].signature(args as usize).0.len() == args as usize,
+                    Op::Print(kind) => kind <= 2,
+                    _ => true,
+                };
+                if !ok {
+                    return Err(format!("bad instruction {}: {:?}", i, op));
+                }
+            }
+            match self.code[end - 1] {
+                Op::Jump(_) | Op::Return | Op::Halt | Op::Exit => {},
+                _ => return Err(format!("the code from instruction {} runs into what follows", start)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tokenizer::Tokenizer;
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+
+    fn compile_str(program: &str) -> Module {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        compile(&ast)
+    }
+
+    #[test]
+    fn serialization_round_trips() {
+        let module = compile_str("🆕🔤👽🔚 👽⬅️💬👾💬🔚 \
+            🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+            📄📞🦊🌜5️⃣🌛◾👽➕👍◾🗣🌜0️⃣🌛↔️👽🔚 🚪🌜1️⃣🌛🔚");
+        let bytes = module.to_bytes();
+        assert!(bytes.starts_with(b"MOOB\x01\x00"));
+        assert_eq!(Module::from_bytes(&bytes), Ok(module));
+    }
+
+    #[test]
+    fn layout_matches_the_documentation() {
+        let module = Module {
+            strings: vec![b"ab".to_vec()],
+            globals: vec![KIND_STRING],
+            functions: vec![Function { name: 0, entry: 1, params: 1, slots: vec![KIND_NUMBER] }],
+            code: vec![Op::Int(7), Op::Builtin(5, 1), Op::Halt]
+        };
+        let mut expected = b"MOOB\x01\x00".to_vec();
+        expected.extend_from_slice(b"\x01\x00\x00\x00\x02\x00\x00\x00ab");
+        expected.extend_from_slice(b"\x01\x00\x00\x00\x01");
+        expected.extend_from_slice(b"\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\
+                                     \x01\x00\x00\x00\x01\x00\x00\x00\x00");
+        expected.extend_from_slice(b"\x03\x00\x00\x00\x01\x07\x00\x00\x00\x00\x00\x00\x00\
+                                     \x30\x05\x01\x00");
+        assert_eq!(module.to_bytes(), expected);
+    }
+
+    #[test]
+    fn bad_bytecode_is_rejected() {
+        let bytes = compile_str("📄1️⃣🔚").to_bytes();
+        assert!(Module::from_bytes(b"\x7fELF").is_err());
+        assert!(Module::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(Module::from_bytes(&newer).is_err());
+
+        let mut module = Module::from_bytes(&bytes).unwrap();
+        module.code.insert(0, Op::Jump(1000));
+        assert!(Module::from_bytes(&module.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn operands_must_belong_to_their_code() {
+        let valid = compile_str("🤪🔢🦊🌜🔢🐾🌛 🌘 ↩️🐾➕1️⃣🔚 🌒 📄📞🦊🌜2️⃣🌛🔚");
+        assert_eq!(Module::from_bytes(&valid.to_bytes()), Ok(valid.clone()));
+        let entry = valid.functions[0].entry as usize;
+        let broken = |change: &dyn Fn(&mut Module)| {
+            let mut module = valid.clone();
+            change(&mut module);
+            Module::from_bytes(&module.to_bytes())
+        };
+        // a slot the function doesn't have, or any slot in the main program
+        assert!(broken(&|m| m.code[entry] = Op::LoadLocal(1)).is_err());
+        assert!(broken(&|m| m.code[0] = Op::LoadLocal(0)).is_err());
+        // jumping or returning out of the main program, or running into a function
+        assert!(broken(&|m| m.code[0] = Op::Jump(entry as u32)).is_err());
+        assert!(broken(&|m| m.code[0] = Op::Return).is_err());
+        assert!(broken(&|m| m.code[entry - 1] = Op::Pop).is_err());
+        // builtins need their number of arguments
+        assert!(broken(&|m| m.code[0] = Op::Builtin(11, 0)).is_err());
+        assert!(broken(&|m| m.code[0] = Op::Builtin(0, 2)).is_err());
+        assert!(broken(&|m| m.code[0] = Op::Builtin(3, 2)).is_err());
+        assert!(broken(&|m| m.functions[0].entry = 0).is_err());
+    }
+
+    fn run_module(module: &Module) -> Result<i32, String> {
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        Vm::new(&[], &mut &b""[..], &mut stdout, &mut stderr).run(module)
+    }
+
+    #[test]
+    fn values_of_the_wrong_kind_are_errors() {
+        let module = Module {
+            strings: vec![b"ab".to_vec()],
+            globals: vec![],
+            functions: vec![],
+            code: vec![Op::Str(0), Op::Int(1), Op::Add, Op::Halt]
+        };
+        assert!(run_module(&Module::from_bytes(&module.to_bytes()).unwrap()).is_err());
+        let module = Module { code: vec![Op::Pop, Op::Halt], ..module };
+        assert!(run_module(&Module::from_bytes(&module.to_bytes()).unwrap()).is_err());
+        let module = Module { code: vec![Op::Int(1), Op::Builtin(6, 1), Op::Halt], ..module };
+        assert!(run_module(&Module::from_bytes(&module.to_bytes()).unwrap()).is_err());
+    }
+
+    // whether a module must stop: jumps only go forward and functions only
+    // call the ones defined before them, so a corrupted one can't loop forever
+    fn finishes(module: &Module) -> bool {
+        module.code.iter().enumerate().all(|(at, op)| {
+            let caller = module.functions.iter().filter(|f| f.entry as usize <= at).count();
+            match *op {
+                Op::Jump(target) | Op::JumpIfZero(target) => target as usize > at,
+                Op::Call(func) => caller == 0 || (func as usize) + 1 < caller,
+                _ => true,
+            }
+        })
+    }
+
+    #[test]
+    fn corrupt_bytecode_never_panics() {
+        let bytes = compile_str("🆕🔤👽🔚 👽⬅️💬👾💬🔚 \
+            🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️2️⃣🔚 🌒 \
+            🤪🔢🐝🌜🔢🐾🌛 🌘 🆕🔢🐸🔚 🐸⬅️📞🦊🌜🐾➖1️⃣🌛🔚 ↩️🐸➕🐾🔚 🌒 \
+            📄📞🐝🌜5️⃣🌛◾👽➕👍◾🎲🌜9️⃣🌛◾🔄🌜👽🌛🔚 🚪🌜1️⃣🌛🔚").to_bytes();
+        let mut seed: u64 = 1;
+        let mut random = |limit: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % limit
+        };
+        assert!(finishes(&Module::from_bytes(&bytes).unwrap()));
+        let mut ran = 0;
+        for _ in 0..3000 {
+            let mut corrupt = bytes.clone();
+            for _ in 0..2 {
+                let at = random(corrupt.len());
+                corrupt[at] = random(256) as u8;
+            }
+            if let Ok(module) = Module::from_bytes(&corrupt) {
+                if finishes(&module) {
+                    let _ = run_module(&module);
+                    ran += 1;
+                }
+            }
+        }
+        assert!(ran > 200, "only {} corrupted modules ran", ran);
+    }
+}
@@ -0,0 +1,236 @@
+use std::io::{BufRead, Write};
+use super::super::tokenizer::VarType;
+use super::super::machine::{Machine, Value, Stop, string, convert};
+use super::{Op, Module, BUILTINS, KIND_STRING};
+
+// Runs bytecode modules. Calls don't recurse on the Rust stack, so programs
+// can go as deep as memory allows.
+pub struct Vm<'a> {
+    machine: Machine<'a>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    globals: Vec<Value>
+}
+
+// Why execution stopped early: the program stopped, or the bytecode does
+// something validation can't rule out up front, like popping an empty stack
+enum Fault {
+    Stop(Stop),
+    Invalid(String)
+}
+
+impl From<Stop> for Fault {
+    fn from(stop: Stop) -> Fault {
+        Fault::Stop(stop)
+    }
+}
+
+fn invalid<T>(why: &str) -> Result<T, Fault> {
+    Err(Fault::Invalid(String::from(why)))
+}
+
+struct Frame {
+    // where to continue in the caller
+    ret: usize,
+    slots: Vec<Value>
+}
+
+fn default(kind: u8) -> Value {
+    if kind == KIND_STRING {
+        Value::default(VarType::STR)
+    } else {
+        Value::default(VarType::INT)
+    }
+}
+
+impl<'a> Vm<'a> {
+    // `args` are the program's arguments, not including the program name
+    pub fn new(args: &'a [String], input: &'a mut dyn BufRead, output: &'a mut dyn Write,
+               errors: &'a mut dyn Write) -> Vm<'a> {
+        Vm {
+            machine: Machine::new(args, input, output, errors),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: Vec::new()
+        }
+    }
+
+    // Runs a validated module, returning its exit status, or why the
+    // bytecode turned out to be invalid after all
+    pub fn run(mut self, module: &Module) -> Result<i32, String> {
+        self.globals = module.globals.iter().map(|&kind| default(kind)).collect();
+        let result = match self.execute(module) {
+            Ok(()) => Ok(0),
+            Err(Fault::Stop(Stop::Exit(status))) | Err(Fault::Stop(Stop::Error(status))) => Ok(status),
+            Err(Fault::Invalid(why)) => Err(why),
+        };
+        self.machine.flush();
+        result
+    }
+
+    fn pop(&mut self) -> Result<Value, Fault> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => invalid("pop from an empty stack"),
+        }
+    }
+
+    fn pop_int(&mut self) -> Result<u64, Fault> {
+        match self.pop()? {
+            Value::Int(value) => Ok(value),
+            Value::Str(_) => invalid("expected a number, found a string"),
+        }
+    }
+
+    fn pop_str(&mut self) -> Result<Value, Fault> {
+        match self.pop()? {
+            Value::Int(_) => invalid("expected a string, found a number"),
+            value => Ok(value),
+        }
+    }
+
+    // The top `count` values, the last one on top
+    fn pop_many(&mut self, count: usize) -> Result<Vec<Value>, Fault> {
+        if self.stack.len() < count {
+            return invalid("missing arguments");
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    fn slot(&mut self, slot: u32) -> Result<&mut Value, Fault> {
+        let frame = match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => return invalid("local slot used outside of a function"),
+        };
+        match frame.slots.get_mut(slot as usize) {
+            Some(value) => Ok(value),
+            None => invalid("no such local slot"),
+        }
+    }
+
+    fn execute(&mut self, module: &Module) -> Result<(), Fault> {
+        let mut pc = 0;
+        loop {
+            let op = match module.code.get(pc) {
+                Some(&op) => op,
+                None => return invalid("ran past the end of the code"),
+            };
+            pc += 1;
+            match op {
+                Op::Halt => return Ok(()),
+                Op::Int(value) => self.stack.push(Value::Int(value)),
+                Op::Str(index) => self.stack.push(string(&module.strings[index as usize])),
+                Op::Load(index) => {
+                    let value = self.globals[index as usize].clone();
+                    self.stack.push(value);
+                },
+                Op::Store(index) => self.globals[index as usize] = self.pop()?,
+                Op::LoadLocal(slot) => {
+                    let value = self.slot(slot)?.clone();
+                    self.stack.push(value);
+                },
+                Op::StoreLocal(slot) => {
+                    let value = self.pop()?;
+                    *self.slot(slot)? = value;
+                },
+                Op::Concat | Op::StrEq => {
+                    let right = self.pop_str()?;
+                    let left = self.pop_str()?;
+                    let value = if op == Op::Concat {
+                        let mut text = left.bytes().to_vec();
+                        text.extend_from_slice(right.bytes());
+                        string(&text)
+                    } else {
+                        Value::Int((left.bytes() == right.bytes()) as u64)
+                    };
+                    self.stack.push(value);
+                },
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Or | Op::And | Op::Eq => {
+                    let right = self.pop_int()?;
+                    let left = self.pop_int()?;
+                    let value = match op {
+                        Op::Add => left.wrapping_add(right),
+                        Op::Sub => left.wrapping_sub(right),
+                        Op::Mul => left.wrapping_mul(right),
+                        Op::Div => {
+                            if right == 0 {
+                                return Err(Fault::Stop(self.machine.runtime_error("division by zero", 4)));
+                            }
+                            left / right
+                        },
+                        Op::Or => left | right,
+                        Op::And => left & right,
+                        _ => (left == right) as u64,
+                    };
+                    self.stack.push(Value::Int(value));
+                },
+                Op::Not => {
+                    let value = self.pop_int()?;
+                    self.stack.push(Value::Int((value == 0) as u64));
+                },
+                Op::ToBool | Op::IntToStr | Op::BoolToStr => {
+                    let (from, to) = match op {
+                        Op::ToBool => (VarType::INT, VarType::BOOL),
+                        Op::IntToStr => (VarType::INT, VarType::STR),
+                        _ => (VarType::BOOL, VarType::STR),
+                    };
+                    let value = self.pop_int()?;
+                    self.stack.push(convert(Value::Int(value), from, to));
+                },
+                Op::Jump(target) => pc = target as usize,
+                Op::JumpIfZero(target) => {
+                    if self.pop_int()? == 0 {
+                        pc = target as usize;
+                    }
+                },
+                Op::Call(index) => {
+                    let func = &module.functions[index as usize];
+                    let params = func.params as usize;
+                    let mut slots = self.pop_many(params)?;
+                    slots.extend(func.slots[params..].iter().map(|&kind| default(kind)));
+                    self.frames.push(Frame { ret: pc, slots });
+                    pc = func.entry as usize;
+                },
+                Op::Return => {
+                    match self.frames.pop() {
+                        Some(frame) => pc = frame.ret,
+                        None => return invalid("return outside of a function"),
+                    }
+                },
+                Op::Builtin(builtin, argc) => {
+                    let builtin = BUILTINS[builtin as usize];
+                    let args = self.pop_many(argc as usize)?;
+                    let (params, _) = builtin.signature(args.len());
+                    for (arg, &param) in args.iter().zip(&params) {
+                        if let (&Value::Str(_), false) | (&Value::Int(_), true) = (arg, param == VarType::STR) {
+                            return invalid("builtin argument of the wrong kind");
+                        }
+                    }
+                    let value = self.machine.builtin(builtin, args)?;
+                    self.stack.push(value);
+                },
+                Op::Print(kind) => {
+                    let var_type = match kind {
+                        2 => VarType::STR,
+                        1 => VarType::BOOL,
+                        _ => VarType::INT,
+                    };
+                    let value = if var_type == VarType::STR {
+                        self.pop_str()?
+                    } else {
+                        Value::Int(self.pop_int()?)
+                    };
+                    self.machine.print(&value, var_type);
+                },
+                Op::Pop => {
+                    self.pop()?;
+                },
+                Op::Exit => {
+                    // only the low byte reaches the parent process
+                    let status = self.pop_int()?;
+                    return Err(Fault::Stop(Stop::Exit((status & 0xff) as i32)));
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+/* emooji runtime for programs compiled to C.
+ *
+ * Numbers and booleans are uint64_t, strings are NUL-terminated and never
+ * freed, files are FILE pointers where NULL means "no file". Everything
+ * behaves like the runtime of the x86-64 backend, including the messages and
+ * exit statuses of runtime errors:
+ *   3 - argument index out of bounds
+ *   4 - division by zero
+ *
+ * Emoji are spelled as octal escapes so any C99 compiler accepts the file,
+ * whatever its source character set. */
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define EM_TRUE "\360\237\221\215"
+#define EM_FALSE "\360\237\221\216"
+#define EM_MODE_READ "\360\237\223\226"
+#define EM_MODE_WRITE "\360\237\223\235"
+#define EM_MODE_APPEND "\360\237\223\216"
+#define EM_ERROR "\360\237\232\250 Runtime error: "
+
+/* line buffers start this big and double until the line fits */
+#define EM_LINE_SIZE 4096
+
+static int em_argc;
+static char **em_argv;
+
+static void em_init(int argc, char **argv) {
+    em_argc = argc;
+    em_argv = argv;
+    srand((unsigned) time(NULL));
+}
+
+/* only the low byte reaches the parent process */
+static void em_exit(uint64_t status) {
+    exit((int) (status & 0xff));
+}
+
+static void em_error(const char *message, int status) {
+    fputs(EM_ERROR, stderr);
+    fputs(message, stderr);
+    fputc('\n', stderr);
+    exit(status);
+}
+
+static uint64_t em_div(uint64_t left, uint64_t right) {
+    if (right == 0) {
+        em_error("division by zero", 4);
+    }
+    return left / right;
+}
+
+/* a random number below limit */
+static uint64_t em_rand(uint64_t limit) {
+    if (limit == 0) {
+        em_error("division by zero", 4);
+    }
+    return (uint64_t) rand() % limit;
+}
+
+static const char *em_concat(const char *left, const char *right) {
+    size_t length = strlen(left);
+    char *result = malloc(length + strlen(right) + 1);
+    strcpy(result, left);
+    strcpy(result + length, right);
+    return result;
+}
+
+static uint64_t em_str_eq(const char *left, const char *right) {
+    return strcmp(left, right) == 0;
+}
+
+static const char *em_int_str(uint64_t value) {
+    char *result = malloc(21);
+    sprintf(result, "%" PRIu64, value);
+    return result;
+}
+
+static const char *em_bool_str(uint64_t value) {
+    return value != 0 ? EM_TRUE : EM_FALSE;
+}
+
+static void em_print_int(uint64_t value) {
+    printf("%" PRIu64, value);
+}
+
+static void em_print_str(const char *value) {
+    fputs(value, stdout);
+}
+
+static void em_print_bool(uint64_t value) {
+    em_print_str(em_bool_str(value));
+}
+
+static void em_print_char(char ch) {
+    putchar(ch);
+}
+
+/* a line without its newline, or the empty string at the end of the file */
+static const char *em_read_line(FILE *file) {
+    char *line;
+    size_t size = EM_LINE_SIZE;
+    size_t length = 0;
+    if (file == NULL) {
+        return "";
+    }
+    line = malloc(size);
+    while (fgets(line + length, (int) (size - length), file) != NULL) {
+        length += strlen(line + length);
+        if (length > 0 && line[length - 1] == '\n') {
+            line[length - 1] = '\0';
+            return line;
+        }
+        if (length < size - 1) {
+            return line;
+        }
+        size *= 2;
+        line = realloc(line, size);
+    }
+    if (length == 0) {
+        free(line);
+        return "";
+    }
+    return line;
+}
+
+static const char *em_read_str(void) {
+    return em_read_line(stdin);
+}
+
+/* 0 if the string doesn't start with a number */
+static uint64_t em_parse(const char *text) {
+    return (uint64_t) strtoll(text, NULL, 10);
+}
+
+static uint64_t em_read_int(void) {
+    return em_parse(em_read_str());
+}
+
+/* peeks one character ahead */
+static uint64_t em_file_eof(FILE *file) {
+    int ch;
+    if (file == NULL) {
+        return 1;
+    }
+    ch = fgetc(file);
+    if (ch == EOF) {
+        return 1;
+    }
+    ungetc(ch, file);
+    return 0;
+}
+
+static uint64_t em_eof(void) {
+    return em_file_eof(stdin);
+}
+
+/* the program's arguments, not counting its name */
+static uint64_t em_arg_count(void) {
+    return (uint64_t) em_argc - 1;
+}
+
+static const char *em_arg(uint64_t index) {
+    if (index >= em_arg_count()) {
+        em_error("argument index out of bounds", 3);
+    }
+    return em_argv[index + 1];
+}
+
+/* NULL if the mode is unknown or the file can't be opened */
+static FILE *em_open(const char *path, const char *mode) {
+    if (strcmp(mode, EM_MODE_READ) == 0) {
+        return fopen(path, "r");
+    }
+    if (strcmp(mode, EM_MODE_WRITE) == 0) {
+        return fopen(path, "w");
+    }
+    if (strcmp(mode, EM_MODE_APPEND) == 0) {
+        return fopen(path, "a");
+    }
+    return NULL;
+}
+
+/* writes the string and a newline, 1 on success */
+static uint64_t em_write(FILE *file, const char *text) {
+    if (file == NULL || fputs(text, file) < 0 || fputc('\n', file) == EOF) {
+        return 0;
+    }
+    return 1;
+}
+
+static uint64_t em_close(FILE *file) {
+    return file != NULL && fclose(file) == 0;
+}
@@ -0,0 +1,414 @@
+use std::mem;
+use super::tokenizer::VarType;
+use super::ast::*;
+
+// C99 code generation, for building with any C compiler on any architecture.
+// The runtime in emooji.h is pasted at the top so the output stands alone.
+//
+// C leaves the order in which operands and arguments are evaluated open, so
+// every value that reads or changes state goes into a temporary first. What
+// is left in expressions are temporaries and literals, combined with
+// operators that have no side effects.
+pub struct CGen {
+    content: String,
+    // prototypes of the functions, so any code can call them
+    decls: String,
+    functions: String,
+    indent: usize,
+    temps: usize,
+    // the parameters of the function being generated, and where it calls
+    // itself as the last thing it does, which jumps back to its start
+    params: Vec<String>,
+    tail_calls: Vec<usize>
+}
+
+const RUNTIME: &str = include_str!("emooji.h");
+
+// Emoji aren't valid in C identifiers, so names are spelled as code points
+fn name(prefix: &str, id: &str) -> String {
+    let mut name = String::from(prefix);
+    for ch in id.chars() {
+        name.push_str(&format!("_{:x}", ch as u32));
+    }
+    name
+}
+
+fn var_name(id: &str) -> String {
+    name("v", id)
+}
+
+fn func_name(id: &str) -> String {
+    name("f", id)
+}
+
+// The C type of `var_type`, ready to be followed by a name
+fn c_type(var_type: VarType) -> &'static str {
+    match var_type {
+        VarType::STR => "const char *",
+        VarType::File => "FILE *",
+        VarType::NONE => "void ",
+        _ => "uint64_t ",
+    }
+}
+
+fn default_value(var_type: VarType) -> &'static str {
+    match var_type {
+        VarType::STR => "\"\"",
+        VarType::File => "NULL",
+        _ => "0",
+    }
+}
+
+// A C string literal with everything but plain ASCII escaped
+fn literal(value: &str) -> String {
+    let mut content = String::from("\"");
+    for &byte in value.as_bytes() {
+        match byte {
+            // '?' could start a trigraph
+            b'"' | b'\\' | b'?' => content.push_str(&format!("\\{}", byte as char)),
+            b' '..=b'~' => content.push(byte as char),
+            _ => content.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    content.push('"');
+    content
+}
+
+fn params(func: &Function) -> String {
+    if func.params.is_empty() {
+        return String::from("void");
+    }
+    let params: Vec<String> = func.params.iter()
+        .map(|&(ref id, var_type)| format!("{}{}", c_type(var_type), var_name(id)))
+        .collect();
+    params.join(", ")
+}
+
+impl CGen {
+    pub fn new() -> CGen {
+        CGen {
+            content: String::new(),
+            decls: String::new(),
+            functions: String::new(),
+            indent: 1,
+            temps: 0,
+            params: Vec::new(),
+            tail_calls: Vec::new()
+        }
+    }
+
+    // Generates the complete C file for a checked program
+    pub fn gen(mut self, program: &Program) -> String {
+        self.write_line("em_init(argc, argv);");
+        for stmt in &program.statements {
+            self.statement(stmt);
+        }
+        self.write_line("return 0;");
+        let main = mem::take(&mut self.content);
+
+        self.content.push_str("/* Generated by emooji */\n\n");
+        self.content.push_str(RUNTIME);
+        self.content.push('\n');
+        for &(ref id, var_type) in &program.globals {
+            self.content.push_str(&format!("static {}{} = {};\n", c_type(var_type),
+                var_name(id), default_value(var_type)));
+        }
+        let decls = mem::take(&mut self.decls);
+        self.content.push_str(&decls);
+        let functions = mem::take(&mut self.functions);
+        self.content.push_str(&functions);
+        self.content.push_str("\nint main(int argc, char **argv) {\n");
+        self.content.push_str(&main);
+        self.content.push_str("}\n");
+        self.content
+    }
+
+    fn write_line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.content.push_str("    ");
+        }
+        self.content.push_str(line);
+        self.content.push('\n');
+    }
+
+    // Evaluates `value` into a new temporary, so it happens right here
+    fn temp(&mut self, var_type: VarType, value: &str) -> String {
+        self.temps += 1;
+        let temp = format!("t{}", self.temps);
+        self.write_line(&format!("{}{} = {};", c_type(var_type), temp, value));
+        temp
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt.kind {
+            StmtKind::Declare(..) => {},
+            StmtKind::Assign(ref id, ref value) => {
+                let value = self.expression(value);
+                self.write_line(&format!("{} = {};", var_name(id), value));
+            },
+            StmtKind::If(ref cond, ref then, ref otherwise) => {
+                let cond = self.expression(cond);
+                self.write_line(&format!("if ({}) {{", cond));
+                self.indent += 1;
+                self.statement(then);
+                self.indent -= 1;
+                if let Some(ref s) = *otherwise {
+                    self.write_line("} else {");
+                    self.indent += 1;
+                    self.statement(s);
+                    self.indent -= 1;
+                }
+                self.write_line("}");
+            },
+            StmtKind::While(ref cond, ref body) => {
+                // the condition may need statements of its own
+                self.write_line("for (;;) {");
+                self.indent += 1;
+                let cond = self.expression(cond);
+                self.write_line(&format!("if (!{}) break;", cond));
+                self.statement(body);
+                self.indent -= 1;
+                self.write_line("}");
+            },
+            StmtKind::Block(ref statements) => {
+                for s in statements {
+                    self.statement(s);
+                }
+            },
+            StmtKind::Print(ref args, newline) => {
+                for (i, arg) in args.iter().enumerate() {
+                    // arguments are separated by a space
+                    if i > 0 {
+                        self.write_line("em_print_char(' ');");
+                    }
+                    let value = self.expression(arg);
+                    let line = match arg.var_type {
+                        VarType::STR => format!("em_print_str({});", value),
+                        VarType::BOOL => format!("em_print_bool({});", value),
+                        // the x86-64 backend prints the pointer
+                        VarType::File => format!("em_print_int((uint64_t) (uintptr_t) {});", value),
+                        _ => format!("em_print_int({});", value),
+                    };
+                    self.write_line(&line);
+                }
+                if newline {
+                    self.write_line("em_print_char('\\n');");
+                }
+            },
+            StmtKind::Function(ref func) => {
+                // it can be called before this point, like in native code
+                self.decls.push_str(&format!("static {}{}({});\n", c_type(func.ret_type),
+                    func_name(&func.id), params(func)));
+                self.function(func);
+            },
+            StmtKind::Return(Some(ref call)) | StmtKind::Expr(ref call) if self.tail_calls.contains(&stmt.pos) => {
+                let args: Vec<String> = match call.kind {
+                    ExprKind::Call(_, ref args) => args.iter().map(|arg| self.expression(arg)).collect(),
+                    _ => unreachable!(),
+                };
+                // the arguments are temporaries and literals, so assigning
+                // one parameter doesn't change the others
+                for (param, arg) in self.params.clone().iter().zip(args) {
+                    self.write_line(&format!("{} = {};", param, arg));
+                }
+                self.write_line("goto start;");
+            },
+            StmtKind::Return(ref value) => {
+                match *value {
+                    Some(ref v) => {
+                        let value = self.expression(v);
+                        self.write_line(&format!("return {};", value));
+                    },
+                    None => self.write_line("return;"),
+                }
+            },
+            StmtKind::Exit(ref status) => {
+                let status = self.expression(status);
+                self.write_line(&format!("em_exit({});", status));
+            },
+            StmtKind::Expr(ref expr) => {
+                // result is ignored when used as a statement
+                self.expression(expr);
+            },
+        }
+    }
+
+    fn function(&mut self, func: &Function) {
+        let caller = mem::take(&mut self.content);
+        let caller_indent = mem::replace(&mut self.indent, 1);
+        let caller_params = mem::replace(&mut self.params,
+            func.params.iter().map(|param| var_name(&param.0)).collect());
+        let caller_tail_calls = mem::replace(&mut self.tail_calls, func.tail_calls());
+        if !self.tail_calls.is_empty() {
+            // locals start over too
+            self.write_line("start: ;");
+        }
+
+        let mut seen: Vec<&String> = func.params.iter().map(|param| &param.0).collect();
+        for &(ref id, var_type) in &func.locals {
+            if !seen.contains(&id) {
+                seen.push(id);
+                self.write_line(&format!("{}{} = {};", c_type(var_type), var_name(id),
+                    default_value(var_type)));
+            }
+        }
+        self.statement(&func.body);
+        // falling off the end returns the default value
+        if func.ret_type != VarType::NONE {
+            self.write_line(&format!("return {};", default_value(func.ret_type)));
+        }
+
+        let code = mem::replace(&mut self.content, caller);
+        self.indent = caller_indent;
+        self.params = caller_params;
+        self.tail_calls = caller_tail_calls;
+        self.functions.push_str(&format!("\nstatic {}{}({}) {{\n{}}}\n",
+            c_type(func.ret_type), func_name(&func.id), params(func), code));
+    }
+
+    // Evaluates `expr`, returning a C expression for its value that can be
+    // used at any later point. Calls without a value return nothing.
+    fn expression(&mut self, expr: &Expr) -> String {
+        match expr.kind {
+            ExprKind::Int(value) => format!("UINT64_C({})", value),
+            ExprKind::Bool(value) => format!("{}", value as u64),
+            ExprKind::Str(ref value) => literal(value),
+            // the variable may change before the value is used
+            ExprKind::Var(ref id) => self.temp(expr.var_type, &var_name(id)),
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                let left = self.expression(lhs);
+                let right = self.expression(rhs);
+                match (op, expr.var_type) {
+                    (BinOp::Add, VarType::STR) => {
+                        self.temp(VarType::STR, &format!("em_concat({}, {})", left, right))
+                    },
+                    // ➕ on booleans is "or" and ✖️ is "and"
+                    (BinOp::Add, VarType::BOOL) => format!("({} | {})", left, right),
+                    (BinOp::Mul, VarType::BOOL) => format!("({} & {})", left, right),
+                    (BinOp::Add, _) => format!("({} + {})", left, right),
+                    (BinOp::Sub, _) => format!("({} - {})", left, right),
+                    (BinOp::Mul, _) => format!("({} * {})", left, right),
+                    (BinOp::Div, _) => {
+                        self.temp(VarType::INT, &format!("em_div({}, {})", left, right))
+                    },
+                    (BinOp::Eq, _) if lhs.var_type == VarType::STR => {
+                        format!("em_str_eq({}, {})", left, right)
+                    },
+                    (BinOp::Eq, _) => format!("(uint64_t) ({} == {})", left, right),
+                }
+            },
+            ExprKind::Not(ref operand) => {
+                let value = self.expression(operand);
+                format!("(uint64_t) ({} == 0)", value)
+            },
+            ExprKind::Call(ref id, ref args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.expression(arg)).collect();
+                let call = format!("{}({})", func_name(id), args.join(", "));
+                if expr.var_type == VarType::NONE {
+                    self.write_line(&format!("{};", call));
+                    String::new()
+                } else {
+                    self.temp(expr.var_type, &call)
+                }
+            },
+            ExprKind::Builtin(builtin, ref args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.expression(arg)).collect();
+                let helper = match builtin {
+                    Builtin::Rand => "em_rand",
+                    Builtin::ReadStr => "em_read_str",
+                    Builtin::ReadInt => "em_read_int",
+                    Builtin::Eof => {
+                        if args.is_empty() {
+                            "em_eof"
+                        } else {
+                            "em_file_eof"
+                        }
+                    },
+                    Builtin::ArgCount => "em_arg_count",
+                    Builtin::Arg => "em_arg",
+                    Builtin::Parse => "em_parse",
+                    Builtin::Open => "em_open",
+                    Builtin::ReadLine => "em_read_line",
+                    Builtin::Write => "em_write",
+                    Builtin::Close => "em_close",
+                };
+                self.temp(expr.var_type, &format!("{}({})", helper, args.join(", ")))
+            },
+            ExprKind::Format(ref segments, _, ref args) => {
+                // arguments were converted to strings by the checker
+                let mut text = literal(&segments[0]);
+                for (i, arg) in args.iter().enumerate() {
+                    let value = self.expression(arg);
+                    text = self.temp(VarType::STR, &format!("em_concat({}, {})", text, value));
+                    if !segments[i + 1].is_empty() {
+                        let segment = literal(&segments[i + 1]);
+                        text = self.temp(VarType::STR, &format!("em_concat({}, {})", text, segment));
+                    }
+                }
+                text
+            },
+            ExprKind::Convert(ref inner) => {
+                let value = self.expression(inner);
+                match (inner.var_type, expr.var_type) {
+                    (VarType::INT, VarType::STR) => {
+                        self.temp(VarType::STR, &format!("em_int_str({})", value))
+                    },
+                    (VarType::BOOL, VarType::STR) => format!("em_bool_str({})", value),
+                    (VarType::File, VarType::BOOL) => format!("(uint64_t) ({} != NULL)", value),
+                    // booleans are always 0 or 1
+                    (_, VarType::BOOL) => format!("(uint64_t) ({} != 0)", value),
+                    _ => value,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CGen, literal, name};
+    use super::super::tokenizer::Tokenizer;
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+
+    fn gen_str(program: &str) -> String {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        CGen::new().gen(&ast)
+    }
+
+    #[test]
+    fn names_and_literals_are_ascii() {
+        assert_eq!(name("v", "😀"), "v_1f600");
+        assert_eq!(name("f", "❤️"), "f_2764_fe0f");
+        assert_eq!(literal("🐶 \"??\""), "\"\\360\\237\\220\\266 \\\"\\?\\?\\\"\"");
+    }
+
+    #[test]
+    fn operands_are_evaluated_in_order() {
+        let code = gen_str("🆕🔢😀🔚 📄📨🌜🌛➖😀➗️📨🌜🌛🔚");
+        let main = &code[code.find("int main").unwrap()..];
+        assert!(main.contains("    uint64_t t1 = em_read_int();\n\
+                               \x20   uint64_t t2 = v_1f600;\n\
+                               \x20   uint64_t t3 = em_read_int();\n\
+                               \x20   uint64_t t4 = em_div(t2, t3);\n\
+                               \x20   em_print_int((t1 - t4));\n"), "{}", main);
+    }
+
+    #[test]
+    fn functions_are_called_directly() {
+        let code = gen_str("📄📞🐝🌜1️⃣🌛🔚 🤪🔤🐝🌜🔢🐾🌛 🌘 ↩️🐾➕💬🐝💬🔚 🌒");
+        assert!(code.contains("static const char *f_1f41d(uint64_t v_1f43e);\n"));
+        assert!(code.contains("\nstatic const char *f_1f41d(uint64_t v_1f43e) {\n"));
+        assert!(code.contains(" = f_1f41d(UINT64_C(1));\n"), "{}", code);
+        assert!(code.ends_with("    return 0;\n}\n"));
+    }
+
+    #[test]
+    fn tail_calls_jump_back() {
+        let code = gen_str("🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️0️⃣🔚 ↩️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 📄📞🦊🌜3️⃣🌛🔚");
+        assert!(code.contains("static uint64_t f_1f98a(uint64_t v_1f43e) {\n    start: ;\n"), "{}", code);
+        assert!(code.contains("    v_1f43e = (t2 - UINT64_C(1));\n    goto start;\n"), "{}", code);
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::mem;
+use super::tokenizer::VarType;
+use super::tokenizer::Token;
+use super::ast::*;
+
+// Resolves names, works out the type of every expression and inserts
+// Convert nodes wherever a value is used as a different type.
+pub struct Checker<'a> {
+    tokens: &'a [Token],
+    scope: Scope,
+    // locals and return type of the function being checked, if any
+    locals: Option<HashMap<String, VarType>>,
+    ret_type: VarType
+}
+
+// The global variables and functions declared so far. The REPL keeps them
+// between inputs, so each one can use what the earlier ones declared.
+#[derive(Clone, Default)]
+pub struct Scope {
+    vars: HashMap<String, VarType>,
+    funcs: HashMap<String, (Vec<VarType>, VarType)>
+}
+
+pub fn type_name(var_type: VarType) -> &'static str {
+    match var_type {
+        VarType::BOOL => "bool",
+        VarType::INT => "int",
+        VarType::STR => "string",
+        VarType::File => "file",
+        VarType::NONE => "nothing",
+    }
+}
+
+// Gathers the variables declared in `stmt` into `decls`. Functions keep their
+// own declarations as locals.
+fn collect_decls(stmt: &mut Stmt, decls: &mut Vec<(String, VarType)>) {
+    match stmt.kind {
+        StmtKind::Declare(var_type, ref id) if !decls.contains(&(id.clone(), var_type)) => {
+            decls.push((id.clone(), var_type));
+        },
+        StmtKind::Function(ref mut func) => {
+            let mut locals = Vec::new();
+            collect_decls(&mut func.body, &mut locals);
+            func.locals = locals;
+        },
+        StmtKind::Block(ref mut statements) => {
+            for s in statements {
+                collect_decls(s, decls);
+            }
+        },
+        StmtKind::If(_, ref mut then, ref mut otherwise) => {
+            collect_decls(then, decls);
+            if let Some(ref mut s) = *otherwise {
+                collect_decls(s, decls);
+            }
+        },
+        StmtKind::While(_, ref mut body) => collect_decls(body, decls),
+        _ => {}
+    }
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(tokens: &'a [Token]) -> Checker<'a> {
+        Checker::with_scope(tokens, Scope::default())
+    }
+
+    // A checker that already knows the declarations in `scope`
+    pub fn with_scope(tokens: &'a [Token], scope: Scope) -> Checker<'a> {
+        Checker {
+            tokens,
+            scope,
+            locals: None,
+            ret_type: VarType::NONE
+        }
+    }
+
+    // Everything declared so far, including by the checked program
+    pub fn into_scope(self) -> Scope {
+        self.scope
+    }
+
+    pub fn check(&mut self, program: &mut Program) {
+        // globals and functions can be used before their declaration
+        let mut globals = Vec::new();
+        for stmt in &mut program.statements {
+            collect_decls(stmt, &mut globals);
+        }
+        for &(ref id, var_type) in &globals {
+            self.declare_global(id, var_type);
+        }
+        program.globals = globals;
+        for stmt in &program.statements {
+            self.collect_funcs(stmt);
+        }
+
+        for stmt in &mut program.statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn debug_str(&self, pos: usize) -> String {
+        let mut content = format!("token at pos {}: ", pos);
+        let mut count = 0;
+        while count < 5 && pos+count < self.tokens.len(){
+            content.push_str(&self.tokens[pos + count].to_string());
+            count += 1;
+        }
+        content
+    }
+
+    fn declare_global(&mut self, id: &str, var_type: VarType) {
+        if let Some(&existing) = self.scope.vars.get(id) {
+            if existing != var_type {
+                panic!("Variable {} declared as both {} and {}", id,
+                    type_name(existing), type_name(var_type));
+            }
+        }
+        self.scope.vars.insert(id.to_string(), var_type);
+    }
+
+    fn collect_funcs(&mut self, stmt: &Stmt) {
+        match stmt.kind {
+            StmtKind::Function(ref func) => {
+                if self.scope.funcs.contains_key(&func.id) || self.scope.vars.contains_key(&func.id) {
+                    panic!("Name already in use: {}", self.debug_str(stmt.pos));
+                }
+                let args = func.params.iter().map(|&(_, vt)| vt).collect();
+                self.scope.funcs.insert(func.id.clone(), (args, func.ret_type));
+            },
+            StmtKind::Block(ref statements) => {
+                for s in statements {
+                    self.collect_funcs(s);
+                }
+            },
+            StmtKind::If(_, ref then, ref otherwise) => {
+                self.collect_funcs(then);
+                if let Some(ref s) = *otherwise {
+                    self.collect_funcs(s);
+                }
+            },
+            StmtKind::While(_, ref body) => self.collect_funcs(body),
+            _ => {}
+        }
+    }
+
+    fn lookup(&self, id: &str, pos: usize) -> VarType {
+        if let Some(ref locals) = self.locals {
+            if let Some(var_type) = locals.get(id) {
+                return *var_type;
+            }
+        }
+        match self.scope.vars.get(id) {
+            Some(var_type) => *var_type,
+            None => panic!("Variable or function never declared: {}", self.debug_str(pos)),
+        }
+    }
+
+    // Checks `expr` and makes sure it ends up with type `to`
+    fn coerce(&mut self, expr: &mut Expr, to: VarType) {
+        self.expression(expr);
+        self.convert(expr, to);
+    }
+
+    // Same as coerce, for an expression that has already been checked
+    fn convert(&self, expr: &mut Expr, to: VarType) {
+        let from = expr.var_type;
+        if from == VarType::NONE {
+            panic!("Function does not return a value: {}", self.debug_str(expr.pos));
+        }
+        if !Token::can_convert_to(from, to) {
+            panic!("Mismatched types: expected {}, found {}: {}", type_name(to),
+                type_name(from), self.debug_str(expr.pos));
+        }
+        if from != to {
+            let pos = expr.pos;
+            let inner = mem::replace(expr, Expr::new(ExprKind::Int(0), pos));
+            *expr = Expr {
+                kind: ExprKind::Convert(Box::new(inner)),
+                var_type: to,
+                pos
+            };
+        }
+    }
+
+    fn condition(&mut self, expr: &mut Expr) {
+        let vt = self.expression(expr);
+        if vt == VarType::NONE || !Token::can_convert_to(vt, VarType::BOOL) {
+            panic!("Condition must evaluate to boolean: {}", self.debug_str(expr.pos));
+        }
+        self.convert(expr, VarType::BOOL);
+    }
+
+    fn statement(&mut self, stmt: &mut Stmt) {
+        let pos = stmt.pos;
+        match stmt.kind {
+            StmtKind::Declare(..) => {},
+            StmtKind::Assign(ref id, ref mut value) => {
+                let var_type = self.lookup(id, pos);
+                self.coerce(value, var_type);
+            },
+            StmtKind::If(ref mut cond, ref mut then, ref mut otherwise) => {
+                self.condition(cond);
+                self.statement(then);
+                if let Some(ref mut s) = *otherwise {
+                    self.statement(s);
+                }
+            },
+            StmtKind::While(ref mut cond, ref mut body) => {
+                self.condition(cond);
+                self.statement(body);
+            },
+            StmtKind::Block(ref mut statements) => {
+                for s in statements {
+                    self.statement(s);
+                }
+            },
+            StmtKind::Print(ref mut args, _) => {
+                for arg in args {
+                    if self.expression(arg) == VarType::NONE {
+                        panic!("Function does not return a value: {}", self.debug_str(arg.pos));
+                    }
+                }
+            },
+            StmtKind::Function(ref mut func) => {
+                let mut locals = HashMap::new();
+                for &(ref id, var_type) in func.params.iter().chain(func.locals.iter()) {
+                    if self.scope.vars.contains_key(id) {
+                        panic!("Argument cannot share name with global variable: {}",
+                            self.debug_str(pos));
+                    }
+                    if let Some(existing) = locals.insert(id.clone(), var_type) {
+                        if existing != var_type {
+                            panic!("Variable {} declared as both {} and {}", id,
+                                type_name(existing), type_name(var_type));
+                        }
+                    }
+                }
+                self.locals = Some(locals);
+                self.ret_type = func.ret_type;
+                self.statement(&mut func.body);
+                self.locals = None;
+                self.ret_type = VarType::NONE;
+            },
+            StmtKind::Return(ref mut value) => {
+                if self.locals.is_none() {
+                    panic!("Cannot return outside of a function: {}", self.debug_str(pos));
+                }
+                let ret_type = self.ret_type;
+                match *value {
+                    Some(ref mut v) => {
+                        if ret_type == VarType::NONE {
+                            panic!("Function has no return type: {}", self.debug_str(pos));
+                        }
+                        self.coerce(v, ret_type);
+                    },
+                    None => {
+                        if ret_type != VarType::NONE {
+                            panic!("Must return a {}: {}", type_name(ret_type),
+                                self.debug_str(pos));
+                        }
+                    }
+                }
+            },
+            StmtKind::Exit(ref mut status) => {
+                self.coerce(status, VarType::INT);
+            },
+            StmtKind::Expr(ref mut expr) => {
+                self.expression(expr);
+            },
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expr) -> VarType {
+        let pos = expr.pos;
+        let var_type = match expr.kind {
+            ExprKind::Int(_) => VarType::INT,
+            ExprKind::Bool(_) => VarType::BOOL,
+            ExprKind::Str(_) => VarType::STR,
+            ExprKind::Var(ref id) => {
+                if self.scope.funcs.contains_key(id) {
+                    panic!("Functions must be called with 📞: {}", self.debug_str(pos));
+                }
+                self.lookup(id, pos)
+            },
+            ExprKind::Binary(op, ref mut lhs, ref mut rhs) => {
+                let vt1 = self.expression(lhs);
+                let vt2 = self.expression(rhs);
+                if vt1 == VarType::NONE || vt2 == VarType::NONE {
+                    panic!("Function does not return a value: {}", self.debug_str(pos));
+                }
+                if vt1 == VarType::File || vt2 == VarType::File {
+                    panic!("Arithmetic not defined for files: {}", self.debug_str(pos));
+                }
+                match op {
+                    BinOp::Eq => {
+                        if vt1 != vt2 {
+                            panic!("Cannot check equality of mismatched types: {}",
+                                self.debug_str(pos));
+                        }
+                        VarType::BOOL
+                    },
+                    BinOp::Add => {
+                        if vt1 == VarType::STR || vt2 == VarType::STR {
+                            // concatenation, converting the other side to a string
+                            self.convert(lhs, VarType::STR);
+                            self.convert(rhs, VarType::STR);
+                            VarType::STR
+                        } else if vt1 == VarType::INT || vt2 == VarType::INT {
+                            self.convert(lhs, VarType::INT);
+                            self.convert(rhs, VarType::INT);
+                            VarType::INT
+                        } else {
+                            VarType::BOOL
+                        }
+                    },
+                    BinOp::Mul => {
+                        if vt1 == VarType::STR || vt2 == VarType::STR {
+                            panic!("Multiplication not defined for strings: {}",
+                                self.debug_str(pos));
+                        } else if vt1 == VarType::INT || vt2 == VarType::INT {
+                            self.convert(lhs, VarType::INT);
+                            self.convert(rhs, VarType::INT);
+                            VarType::INT
+                        } else {
+                            VarType::BOOL
+                        }
+                    },
+                    BinOp::Sub | BinOp::Div => {
+                        if vt1 == VarType::STR || vt2 == VarType::STR {
+                            panic!("{} not defined for strings: {}",
+                                if op == BinOp::Sub { "Subtraction" } else { "Division" },
+                                self.debug_str(pos));
+                        }
+                        self.convert(lhs, VarType::INT);
+                        self.convert(rhs, VarType::INT);
+                        VarType::INT
+                    },
+                }
+            },
+            ExprKind::Not(ref mut operand) => {
+                self.coerce(operand, VarType::BOOL);
+                VarType::BOOL
+            },
+            ExprKind::Call(ref id, ref mut args) => {
+                let (arg_types, ret_type) = match self.scope.funcs.get(id) {
+                    Some(sig) => sig.clone(),
+                    None => panic!("Cannot call undeclared function: {}", self.debug_str(pos)),
+                };
+                if args.len() != arg_types.len() {
+                    panic!("Expected {} arguments, found {}: {}", arg_types.len(), args.len(),
+                        self.debug_str(pos));
+                }
+                for (arg, arg_type) in args.iter_mut().zip(arg_types) {
+                    self.coerce(arg, arg_type);
+                }
+                ret_type
+            },
+            ExprKind::Builtin(builtin, ref mut args) => {
+                let (arg_types, ret_type) = builtin.signature(args.len());
+                if args.len() != arg_types.len() {
+                    panic!("Expected {} arguments, found {}: {}", arg_types.len(), args.len(),
+                        self.debug_str(pos));
+                }
+                for (arg, arg_type) in args.iter_mut().zip(arg_types) {
+                    self.coerce(arg, arg_type);
+                }
+                ret_type
+            },
+            ExprKind::Format(_, ref placeholders, ref mut args) => {
+                for (arg, placeholder) in args.iter_mut().zip(placeholders) {
+                    let vt = self.expression(arg);
+                    let matches = match *placeholder {
+                        VarType::STR => vt == VarType::STR,
+                        _ => vt == VarType::INT || vt == VarType::BOOL,
+                    };
+                    if !matches {
+                        panic!("Mismatched types: expected {}, found {}: {}",
+                            type_name(*placeholder), type_name(vt), self.debug_str(arg.pos));
+                    }
+                    // interpret the value as the placeholder's type, then spell it out
+                    self.convert(arg, *placeholder);
+                    self.convert(arg, VarType::STR);
+                }
+                VarType::STR
+            },
+            ExprKind::Convert(_) => expr.var_type,
+        };
+        expr.var_type = var_type;
+        var_type
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checker;
+    use super::super::tokenizer::{Tokenizer, VarType};
+    use super::super::parser::Parser;
+    use super::super::ast::*;
+
+    fn check(program: &str) -> Program {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        ast
+    }
+
+    fn printed(program: &Program) -> &Expr {
+        match program.statements.last().unwrap().kind {
+            StmtKind::Print(ref args, _) => &args[0],
+            ref kind => panic!("not a print: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parenthesized_expression_has_inner_type() {
+        let program = check("📄🌜💬😀💬🌛🔚");
+        assert_eq!(printed(&program).var_type, VarType::STR);
+    }
+
+    #[test]
+    fn adding_a_string_converts_the_number() {
+        let program = check("🆕🔤🐒🔚 📄3️⃣➕🐒🔚");
+        let expr = printed(&program);
+        assert_eq!(expr.var_type, VarType::STR);
+        match expr.kind {
+            ExprKind::Binary(BinOp::Add, ref lhs, ref rhs) => {
+                assert_eq!(lhs.var_type, VarType::STR);
+                match lhs.kind {
+                    ExprKind::Convert(ref inner) => assert_eq!(inner.var_type, VarType::INT),
+                    ref kind => panic!("expected a conversion, found {:?}", kind),
+                }
+                assert_eq!(rhs.var_type, VarType::STR);
+            },
+            ref kind => panic!("expected an addition, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn comparison_is_boolean() {
+        let program = check("📄1️⃣➕2️⃣↔️3️⃣🔚");
+        assert_eq!(printed(&program).var_type, VarType::BOOL);
+    }
+
+    #[test]
+    fn builtin_call_has_result_type() {
+        let program = check("📄🗣🌜0️⃣🌛🔚");
+        assert_eq!(printed(&program).var_type, VarType::STR);
+    }
+
+    #[test]
+    fn call_has_return_type() {
+        let program = check("🤪☯️😇🌜🔢😀🌛 ↩️😀↔️1️⃣🔚 📄📞😇🌜1️⃣🌛🔚");
+        assert_eq!(printed(&program).var_type, VarType::BOOL);
+    }
+
+    #[test]
+    #[should_panic(expected = "Function does not return a value")]
+    fn printing_a_void_call_is_an_error() {
+        check("🤪😇🌜🌛 📄1️⃣🔚 📄📞😇🌜🌛🔚");
+    }
+}
@@ -0,0 +1,254 @@
+mod runtime;
+use super::{Target, Var, ascii_symbol};
+use super::super::tokenizer::VarType;
+use super::super::ast::BinOp;
+
+// AArch64 (ARM64) for Linux, in GNU assembler syntax. The accumulator is x0,
+// the left operand of a binary operator is in x2 unless it has a register of
+// its own, and x9 and x10 are scratch. Values are allocated x19 to x28, which
+// calls preserve, and x11 to x15. The stack pointer has to stay 16 byte
+// aligned, so every push takes 16 bytes.
+//
+// Symbols are ASCII names derived from the emoji, since not every assembler
+// takes emoji, and globals are reached PC-relative so the result links into
+// position independent executables.
+#[derive(Copy, Clone)]
+pub struct Aarch64;
+
+// Loads `value` into `reg` 16 bits at a time, skipping zero parts
+fn mov_imm(reg: &str, value: u64) -> String {
+    let mut content = format!("\t\tmov {}, #{}\n", reg, value & 0xffff);
+    for shift in [16, 32, 48].iter() {
+        let part = (value >> shift) & 0xffff;
+        if part != 0 {
+            content.push_str(&format!("\t\tmovk {}, #{}, lsl #{}\n", reg, part, shift));
+        }
+    }
+    content
+}
+
+// A load or store of `reg` at `offset` from the frame pointer. Immediate
+// offsets only reach 256 bytes below it, further slots need the offset in a
+// register.
+fn slot(store: bool, reg: &str, offset: i64) -> String {
+    if offset >= -256 {
+        let op = if store { "stur" } else { "ldur" };
+        format!("\t\t{} {}, [x29, #{}]\n", op, reg, offset)
+    } else {
+        let op = if store { "str" } else { "ldr" };
+        format!("{}\t\t{} {}, [x29, x10]\n", mov_imm("x10", offset as u64), op, reg)
+    }
+}
+
+// The address of `label` in `reg`
+fn address(reg: &str, label: &str) -> String {
+    format!("\t\tadrp {}, {}\n\
+             \t\tadd {}, {}, :lo12:{}\n", reg, label, reg, reg, label)
+}
+
+// A load or store of `reg` at an 8 byte aligned data symbol
+fn global(store: bool, reg: &str, symbol: &str) -> String {
+    let op = if store { "str" } else { "ldr" };
+    format!("\t\tadrp x9, {}\n\
+             \t\t{} {}, [x9, :lo12:{}]\n", symbol, op, reg, symbol)
+}
+
+impl Target for Aarch64 {
+    fn main_prologue(&self, frame_size: usize) -> String {
+        let mut content = String::from("\n\n.text\n\
+                                        .global main\n\
+                                        main:\n");
+        content.push_str(&self.enter(frame_size));
+        content.push_str(&global(true, "x0", "argc_"));
+        content.push_str(&global(true, "x1", "argv_"));
+        content.push_str("\t\tmov x0, #0\n\
+                          \t\tbl time\n\
+                          \t\tbl srand\n");
+        content
+    }
+
+    fn main_epilogue(&self) -> String {
+        format!("\t\tmov x0, #0\n{}", self.leave())
+    }
+
+    fn runtime(&self) -> String {
+        self.gen_runtime()
+    }
+
+    fn runtime_data(&self) -> String {
+        self.gen_runtime_data()
+    }
+
+    fn symbol(&self, id: &str) -> String {
+        ascii_symbol(id)
+    }
+
+    fn data_quad(&self, name: &str, value: &str) -> String {
+        format!("\t\t.balign 8\n\
+                 \t\t{}: .quad {}\n", name, value)
+    }
+
+    fn data_string(&self, name: &str, value: &str) -> String {
+        format!("\t\t{}: .string \"{}\"\n", name, value)
+    }
+
+    fn comment(&self, text: &str) -> String {
+        format!("// {}\n", text)
+    }
+
+    fn load_int(&self, value: u64) -> String {
+        mov_imm("x0", value)
+    }
+
+    fn load_address(&self, label: &str) -> String {
+        address("x0", label)
+    }
+
+    fn load(&self, var: Var) -> String {
+        match var {
+            Var::Global(symbol) => global(false, "x0", symbol),
+            Var::Local(offset) => slot(false, "x0", offset),
+            Var::Reg(reg) => format!("\t\tmov x0, {}\n", reg),
+        }
+    }
+
+    fn store(&self, var: Var) -> String {
+        match var {
+            Var::Global(symbol) => global(true, "x0", symbol),
+            Var::Local(offset) => slot(true, "x0", offset),
+            Var::Reg(reg) => format!("\t\tmov {}, x0\n", reg),
+        }
+    }
+
+    fn push(&self) -> String {
+        String::from("\t\tstr x0, [sp, #-16]!\n")
+    }
+
+    fn pop(&self) -> String {
+        String::from("\t\tldr x0, [sp], #16\n")
+    }
+
+    fn pop_arg(&self, index: usize) -> String {
+        format!("\t\tldr x{}, [sp], #16\n", index)
+    }
+
+    fn saved_registers(&self) -> &'static [&'static str] {
+        &["x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28"]
+    }
+
+    fn temporary_registers(&self) -> &'static [&'static str] {
+        &["x11", "x12", "x13", "x14", "x15"]
+    }
+
+    fn operand_register(&self) -> &'static str {
+        "x2"
+    }
+
+    fn save(&self, reg: &str, offset: i64) -> String {
+        slot(true, reg, offset)
+    }
+
+    fn restore(&self, reg: &str, offset: i64) -> String {
+        slot(false, reg, offset)
+    }
+
+    fn jump(&self, label: &str) -> String {
+        format!("\t\tb {}\n", label)
+    }
+
+    fn jump_if_zero(&self, label: &str) -> String {
+        format!("\t\tcbz x0, {}\n", label)
+    }
+
+    fn call(&self, name: &str) -> String {
+        format!("\t\tbl {}\n", name)
+    }
+
+    // the accumulator is already the first argument
+    fn call_with(&self, name: &str) -> String {
+        self.call(name)
+    }
+
+    fn call_var(&self, var: Var) -> String {
+        let load = match var {
+            Var::Global(symbol) => global(false, "x9", symbol),
+            Var::Local(offset) => slot(false, "x9", offset),
+            Var::Reg(reg) => return format!("\t\tblr {}\n", reg),
+        };
+        format!("{}\t\tblr x9\n", load)
+    }
+
+    fn print_char(&self, ch: char) -> String {
+        format!("\t\tmov x0, #{}\n\
+                 \t\tbl print_char_\n", ch as u32)
+    }
+
+    fn binary(&self, op: BinOp, lhs: &str, operand_type: VarType, result_type: VarType) -> String {
+        match op {
+            BinOp::Add => match result_type {
+                VarType::STR => format!("\t\tmov x1, x0\n\
+                                         \t\tmov x0, {}\n\
+                                         \t\tbl concat_\n", lhs),
+                // ➕ on booleans is "or"
+                VarType::BOOL => format!("\t\torr x0, {}, x0\n", lhs),
+                _ => format!("\t\tadd x0, {}, x0\n", lhs),
+            },
+            BinOp::Sub => format!("\t\tsub x0, {}, x0\n", lhs),
+            BinOp::Mul => match result_type {
+                // ✖️ on booleans is "and"
+                VarType::BOOL => format!("\t\tand x0, {}, x0\n", lhs),
+                _ => format!("\t\tmul x0, {}, x0\n", lhs),
+            },
+            BinOp::Div => format!("\t\tcbz x0, div_zero_error_\n\
+                                   \t\tudiv x0, {}, x0\n", lhs),
+            BinOp::Eq => match operand_type {
+                VarType::STR => format!("\t\tmov x1, x0\n\
+                                         \t\tmov x0, {}\n\
+                                         \t\tbl str_eq_\n", lhs),
+                _ => format!("\t\tcmp {}, x0\n\
+                              \t\tcset x0, eq\n", lhs),
+            },
+        }
+    }
+
+    fn not(&self) -> String {
+        String::from("\t\tcmp x0, #0\n\
+                      \t\tcset x0, eq\n")
+    }
+
+    fn to_bool(&self) -> String {
+        String::from("\t\tcmp x0, #0\n\
+                      \t\tcset x0, ne\n")
+    }
+
+    fn enter(&self, frame_size: usize) -> String {
+        let mut content = String::from("\t\tstp x29, x30, [sp, #-16]!\n\
+                                        \t\tmov x29, sp\n");
+        if frame_size > 0xfff {
+            content.push_str(&mov_imm("x9", frame_size as u64));
+            content.push_str("\t\tsub sp, sp, x9\n");
+        } else if frame_size > 0 {
+            content.push_str(&format!("\t\tsub sp, sp, #{}\n", frame_size));
+        }
+        content
+    }
+
+    fn store_arg(&self, index: usize, var: Var) -> String {
+        match var {
+            Var::Reg(reg) => format!("\t\tmov {}, x{}\n", reg, index),
+            Var::Local(offset) => slot(true, &format!("x{}", index), offset),
+            Var::Global(_) => panic!("Parameters are never global"),
+        }
+    }
+
+    fn leave(&self) -> String {
+        String::from("\t\tmov sp, x29\n\
+                      \t\tldp x29, x30, [sp], #16\n\
+                      \t\tret\n")
+    }
+
+    // x29
+    fn dwarf_frame_register(&self) -> u8 {
+        29
+    }
+}
@@ -0,0 +1,355 @@
+use super::Aarch64;
+
+impl Aarch64 {
+    // Constants used by the runtime helpers
+    pub fn gen_runtime_data(&self) -> String {
+        String::from("\t\t.balign 8\n\
+                      \t\targc_: .quad 0\n\
+                      \t\targv_: .quad 0\n\
+                      \t\tFormat_ints: .string \"%lu\"\n\
+                      \t\tFormat_strings: .string \"%s\"\n\
+                      \t\tTrue_string: .string \"👍\"\n\
+                      \t\tFalse_string: .string \"👎\"\n\
+                      \t\tEmpty_string: .string \"\"\n\
+                      \t\tBounds_error: .string \"🚨 Runtime error: argument index out of bounds\\n\"\n\
+                      \t\tDiv_zero_error: .string \"🚨 Runtime error: division by zero\\n\"\n\
+                      \t\tMode_read: .string \"📖\"\n\
+                      \t\tMode_write: .string \"📝\"\n\
+                      \t\tMode_append: .string \"📎\"\n\
+                      \t\tFopen_read: .string \"r\"\n\
+                      \t\tFopen_write: .string \"w\"\n\
+                      \t\tFopen_append: .string \"a\"\n")
+    }
+
+    // Runtime helpers called from generated code. The stack is always
+    // aligned, so they call the C library directly. C's stdin and stderr are
+    // reached through the GOT.
+    //
+    // Runtime errors print a message to stderr and exit with their own code:
+    //   3 - argument index out of bounds
+    //   4 - division by zero
+    pub fn gen_runtime(&self) -> String {
+        let mut content = String::new();
+        // exit_: flush output and exit with status x0
+        content.push_str("exit_:\n\
+                          \t\tbl exit\n");
+
+        content.push_str("bounds_error_:\n\
+                          \t\tadrp x0, Bounds_error\n\
+                          \t\tadd x0, x0, :lo12:Bounds_error\n\
+                          \t\tadrp x9, :got:stderr\n\
+                          \t\tldr x9, [x9, :got_lo12:stderr]\n\
+                          \t\tldr x1, [x9]\n\
+                          \t\tbl fputs\n\
+                          \t\tmov x0, #3\n\
+                          \t\tbl exit\n");
+
+        content.push_str("div_zero_error_:\n\
+                          \t\tadrp x0, Div_zero_error\n\
+                          \t\tadd x0, x0, :lo12:Div_zero_error\n\
+                          \t\tadrp x9, :got:stderr\n\
+                          \t\tldr x9, [x9, :got_lo12:stderr]\n\
+                          \t\tldr x1, [x9]\n\
+                          \t\tbl fputs\n\
+                          \t\tmov x0, #4\n\
+                          \t\tbl exit\n");
+
+        // read_line_: read a line from file x0 without its trailing newline,
+        // or the empty string at end of file. The buffer doubles until the
+        // whole line fits, and every call gets a fresh one so strings can be
+        // kept.
+        content.push_str("read_line_:\n\
+                          \t\tstp x29, x30, [sp, #-48]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tcbz x0, read_line_empty_\n\
+                          \t\tstr x0, [x29, #16]\n\
+                          \t\tmov x0, #4096\n\
+                          \t\tstr x0, [x29, #32]\n\
+                          \t\tstr xzr, [x29, #40]\n\
+                          \t\tbl malloc\n\
+                          \t\tstr x0, [x29, #24]\n\
+                          read_line_more_:\n\
+                          \t\tldp x0, x1, [x29, #24]\n\
+                          \t\tldr x3, [x29, #40]\n\
+                          \t\tadd x0, x0, x3\n\
+                          \t\tsub x1, x1, x3\n\
+                          \t\tldr x2, [x29, #16]\n\
+                          \t\tbl fgets\n\
+                          \t\tcbz x0, read_line_end_\n\
+                          \t\tbl strlen\n\
+                          \t\tldr x3, [x29, #40]\n\
+                          \t\tadd x3, x3, x0\n\
+                          \t\tstr x3, [x29, #40]\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tcbz x3, read_line_done_\n\
+                          \t\tadd x2, x0, x3\n\
+                          \t\tldurb w4, [x2, #-1]\n\
+                          \t\tcmp w4, #10\n\
+                          \t\tb.eq read_line_newline_\n\
+                          \t\tldr x1, [x29, #32]\n\
+                          \t\tsub x4, x1, #1\n\
+                          \t\tcmp x3, x4\n\
+                          \t\tb.ne read_line_done_\n\
+                          \t\tlsl x1, x1, #1\n\
+                          \t\tstr x1, [x29, #32]\n\
+                          \t\tbl realloc\n\
+                          \t\tstr x0, [x29, #24]\n\
+                          \t\tb read_line_more_\n\
+                          read_line_newline_:\n\
+                          \t\tsturb wzr, [x2, #-1]\n\
+                          read_line_done_:\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tldp x29, x30, [sp], #48\n\
+                          \t\tret\n\
+                          read_line_end_:\n\
+                          \t\tldr x3, [x29, #40]\n\
+                          \t\tcbnz x3, read_line_done_\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tbl free\n\
+                          read_line_empty_:\n\
+                          \t\tadrp x0, Empty_string\n\
+                          \t\tadd x0, x0, :lo12:Empty_string\n\
+                          \t\tldp x29, x30, [sp], #48\n\
+                          \t\tret\n");
+
+        // read_str_: read a line from stdin
+        content.push_str("read_str_:\n\
+                          \t\tadrp x9, :got:stdin\n\
+                          \t\tldr x9, [x9, :got_lo12:stdin]\n\
+                          \t\tldr x0, [x9]\n\
+                          \t\tb read_line_\n");
+
+        // read_int_: read a line from stdin and parse it as a number
+        content.push_str("read_int_:\n\
+                          \t\tstp x29, x30, [sp, #-16]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tbl read_str_\n\
+                          \t\tbl parse_int_\n\
+                          \t\tldp x29, x30, [sp], #16\n\
+                          \t\tret\n");
+
+        // parse_int_: string in x0 to number, 0 if it doesn't start with digits
+        content.push_str("parse_int_:\n\
+                          \t\tmov x1, #0\n\
+                          \t\tmov x2, #10\n\
+                          \t\tb strtol\n");
+
+        // concat_: new string with x1 appended to x0
+        content.push_str("concat_:\n\
+                          \t\tstp x29, x30, [sp, #-48]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tstp x0, x1, [x29, #16]\n\
+                          \t\tbl strlen\n\
+                          \t\tstr x0, [x29, #32]\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tbl strlen\n\
+                          \t\tldr x1, [x29, #32]\n\
+                          \t\tadd x0, x0, x1\n\
+                          \t\tadd x0, x0, #1\n\
+                          \t\tbl malloc\n\
+                          \t\tstr x0, [x29, #32]\n\
+                          \t\tldr x1, [x29, #16]\n\
+                          \t\tbl strcpy\n\
+                          \t\tldr x0, [x29, #32]\n\
+                          \t\tldr x1, [x29, #24]\n\
+                          \t\tbl strcat\n\
+                          \t\tldr x0, [x29, #32]\n\
+                          \t\tldp x29, x30, [sp], #48\n\
+                          \t\tret\n");
+
+        // int_str_: number x0 as a new string
+        content.push_str("int_str_:\n\
+                          \t\tstp x29, x30, [sp, #-32]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tstr x0, [x29, #16]\n\
+                          \t\tmov x0, #21\n\
+                          \t\tbl malloc\n\
+                          \t\tstr x0, [x29, #24]\n\
+                          \t\tadrp x1, Format_ints\n\
+                          \t\tadd x1, x1, :lo12:Format_ints\n\
+                          \t\tldr x2, [x29, #16]\n\
+                          \t\tbl sprintf\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n");
+
+        // bool_str_: 👍 or 👎 for boolean x0
+        content.push_str("bool_str_:\n\
+                          \t\tadrp x1, True_string\n\
+                          \t\tadd x1, x1, :lo12:True_string\n\
+                          \t\tadrp x2, False_string\n\
+                          \t\tadd x2, x2, :lo12:False_string\n\
+                          \t\tcmp x0, #0\n\
+                          \t\tcsel x0, x1, x2, ne\n\
+                          \t\tret\n");
+
+        // print_int_, print_str_, print_bool_, print_char_: print x0 without a newline
+        content.push_str("print_int_:\n\
+                          \t\tmov x1, x0\n\
+                          \t\tadrp x0, Format_ints\n\
+                          \t\tadd x0, x0, :lo12:Format_ints\n\
+                          \t\tb printf\n");
+
+        content.push_str("print_str_:\n\
+                          \t\tmov x1, x0\n\
+                          \t\tadrp x0, Format_strings\n\
+                          \t\tadd x0, x0, :lo12:Format_strings\n\
+                          \t\tb printf\n");
+
+        content.push_str("print_bool_:\n\
+                          \t\tstp x29, x30, [sp, #-16]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tbl bool_str_\n\
+                          \t\tldp x29, x30, [sp], #16\n\
+                          \t\tb print_str_\n");
+
+        content.push_str("print_char_:\n\
+                          \t\tb putchar\n");
+
+        // arg_count_: number of command-line arguments, not counting the program
+        content.push_str("arg_count_:\n\
+                          \t\tadrp x9, argc_\n\
+                          \t\tldr x0, [x9, :lo12:argc_]\n\
+                          \t\tsub x0, x0, #1\n\
+                          \t\tret\n");
+
+        // arg_: argument x0 (0 is the first one after the program name)
+        content.push_str("arg_:\n\
+                          \t\tadrp x9, argc_\n\
+                          \t\tldr x1, [x9, :lo12:argc_]\n\
+                          \t\tsub x1, x1, #1\n\
+                          \t\tcmp x0, x1\n\
+                          \t\tb.hs bounds_error_\n\
+                          \t\tadrp x9, argv_\n\
+                          \t\tldr x1, [x9, :lo12:argv_]\n\
+                          \t\tadd x1, x1, x0, lsl #3\n\
+                          \t\tldr x0, [x1, #8]\n\
+                          \t\tret\n");
+
+        // file_eof_: 1 if file x0 has no more input, peeking one character ahead
+        content.push_str("file_eof_:\n\
+                          \t\tstp x29, x30, [sp, #-32]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tcbz x0, file_eof_true_\n\
+                          \t\tstr x0, [x29, #16]\n\
+                          \t\tbl fgetc\n\
+                          \t\tcmn w0, #1\n\
+                          \t\tb.eq file_eof_true_\n\
+                          \t\tldr x1, [x29, #16]\n\
+                          \t\tbl ungetc\n\
+                          \t\tmov x0, #0\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n\
+                          file_eof_true_:\n\
+                          \t\tmov x0, #1\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n");
+
+        // eof_: 1 if stdin has no more input
+        content.push_str("eof_:\n\
+                          \t\tadrp x9, :got:stdin\n\
+                          \t\tldr x9, [x9, :got_lo12:stdin]\n\
+                          \t\tldr x0, [x9]\n\
+                          \t\tb file_eof_\n");
+
+        // file_open_: open path x0 with mode x1 (💬📖💬 read, 💬📝💬 write,
+        // 💬📎💬 append). Returns 0 if the mode is unknown or fopen fails.
+        content.push_str("file_open_:\n\
+                          \t\tstp x29, x30, [sp, #-32]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tstp x0, x1, [x29, #16]\n\
+                          \t\tmov x0, x1\n\
+                          \t\tadrp x1, Mode_read\n\
+                          \t\tadd x1, x1, :lo12:Mode_read\n\
+                          \t\tbl strcmp\n\
+                          \t\tadrp x1, Fopen_read\n\
+                          \t\tadd x1, x1, :lo12:Fopen_read\n\
+                          \t\tcbz w0, file_open_mode_\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tadrp x1, Mode_write\n\
+                          \t\tadd x1, x1, :lo12:Mode_write\n\
+                          \t\tbl strcmp\n\
+                          \t\tadrp x1, Fopen_write\n\
+                          \t\tadd x1, x1, :lo12:Fopen_write\n\
+                          \t\tcbz w0, file_open_mode_\n\
+                          \t\tldr x0, [x29, #24]\n\
+                          \t\tadrp x1, Mode_append\n\
+                          \t\tadd x1, x1, :lo12:Mode_append\n\
+                          \t\tbl strcmp\n\
+                          \t\tadrp x1, Fopen_append\n\
+                          \t\tadd x1, x1, :lo12:Fopen_append\n\
+                          \t\tcbz w0, file_open_mode_\n\
+                          \t\tmov x0, #0\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n\
+                          file_open_mode_:\n\
+                          \t\tldr x0, [x29, #16]\n\
+                          \t\tbl fopen\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n");
+
+        // file_write_: write string x1 and a newline to file x0, 1 on success
+        content.push_str("file_write_:\n\
+                          \t\tstp x29, x30, [sp, #-32]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tcbz x0, file_write_failed_\n\
+                          \t\tstr x0, [x29, #16]\n\
+                          \t\tmov x9, x0\n\
+                          \t\tmov x0, x1\n\
+                          \t\tmov x1, x9\n\
+                          \t\tbl fputs\n\
+                          \t\ttbnz w0, #31, file_write_failed_\n\
+                          \t\tmov w0, #10\n\
+                          \t\tldr x1, [x29, #16]\n\
+                          \t\tbl fputc\n\
+                          \t\tcmn w0, #1\n\
+                          \t\tb.eq file_write_failed_\n\
+                          \t\tmov x0, #1\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n\
+                          file_write_failed_:\n\
+                          \t\tmov x0, #0\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n");
+
+        // file_close_: close file x0, 1 on success
+        content.push_str("file_close_:\n\
+                          \t\tstp x29, x30, [sp, #-16]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tcbz x0, file_close_failed_\n\
+                          \t\tbl fclose\n\
+                          \t\tcbnz w0, file_close_failed_\n\
+                          \t\tmov x0, #1\n\
+                          \t\tldp x29, x30, [sp], #16\n\
+                          \t\tret\n\
+                          file_close_failed_:\n\
+                          \t\tmov x0, #0\n\
+                          \t\tldp x29, x30, [sp], #16\n\
+                          \t\tret\n");
+
+        // str_eq_: 1 if strings x0 and x1 have the same contents
+        content.push_str("str_eq_:\n\
+                          \t\tstp x29, x30, [sp, #-16]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tbl strcmp\n\
+                          \t\tcmp w0, #0\n\
+                          \t\tcset x0, eq\n\
+                          \t\tldp x29, x30, [sp], #16\n\
+                          \t\tret\n");
+
+        // rand_: random number below x0
+        content.push_str("rand_:\n\
+                          \t\tstp x29, x30, [sp, #-32]!\n\
+                          \t\tmov x29, sp\n\
+                          \t\tcbz x0, div_zero_error_\n\
+                          \t\tstr x0, [x29, #16]\n\
+                          \t\tbl rand\n\
+                          \t\tsxtw x0, w0\n\
+                          \t\tldr x1, [x29, #16]\n\
+                          \t\tudiv x2, x0, x1\n\
+                          \t\tmsub x0, x2, x1, x0\n\
+                          \t\tldp x29, x30, [sp], #32\n\
+                          \t\tret\n");
+        content
+    }
+}
@@ -0,0 +1,184 @@
+use super::super::tokenizer::VarType;
+use super::super::checker::type_name;
+
+// Debug information in DWARF 4, for -g. The assembler turns .file and .loc
+// directives into the line table, which maps the code back to the lines of
+// the .moo file; the entries here describe the functions and where their
+// variables are, so debuggers can print them by name.
+pub struct Debug {
+    file: String,
+    // line and column of every token
+    positions: Vec<(usize, usize)>,
+    // what the last .loc said
+    last: (usize, usize),
+    functions: Vec<Subprogram>
+}
+
+// A function of the program and the variables in its frame, parameters first
+pub struct Subprogram {
+    pub name: String,
+    pub label: String,
+    pub params: usize,
+    pub locals: Vec<(String, VarType, i64)>
+}
+
+// A variable in the data section: its name, symbol and type
+pub type Global = (String, String, VarType);
+
+const TYPES: [VarType; 4] = [VarType::INT, VarType::BOOL, VarType::STR, VarType::File];
+
+impl Debug {
+    pub fn new(file: &str, positions: Vec<(usize, usize)>) -> Debug {
+        Debug {
+            file: file.to_string(),
+            positions,
+            last: (0, 0),
+            functions: Vec::new()
+        }
+    }
+
+    // Names the source file, at the start of the assembly
+    pub fn file(&self) -> String {
+        format!("\t\t.file 1 \"{}\"\n", escape(&self.file))
+    }
+
+    // Gives the code that follows the line and column of token `pos`, if
+    // that isn't where the code before it came from
+    pub fn locate(&mut self, pos: usize) -> String {
+        let position = self.positions.get(pos).cloned().unwrap_or(self.last);
+        if position == self.last {
+            return String::new();
+        }
+        self.last = position;
+        format!("\t\t.loc 1 {} {}\n", position.0, position.1)
+    }
+
+    pub fn add_function(&mut self, function: Subprogram) {
+        self.functions.push(function);
+    }
+
+    // The .debug_abbrev and .debug_info sections, with the frame pointer
+    // (DWARF register `frame_register`) as the base of the variables in
+    // frames. Each function's code ends at end_label of its label.
+    pub fn sections(&self, frame_register: u8, globals: &[Global]) -> String {
+        let mut content = String::from(ABBREVIATIONS);
+        content.push_str("\t\t.section .debug_line,\"\",@progbits\n\
+                          .Ldebug_line0:\n\
+                          \t\t.section .debug_info,\"\",@progbits\n\
+                          .Ldebug_info0:\n\
+                          \t\t.long .Ldebug_info_end - .Ldebug_info_start\n\
+                          .Ldebug_info_start:\n\
+                          \t\t.short 4\n\
+                          \t\t.long .Ldebug_abbrev0\n\
+                          \t\t.byte 8\n");
+        // the compilation unit, DW_LANG_C99 so debuggers parse expressions
+        // with the names of variables in them
+        let first = self.functions.first().map_or("main", |function| &function.label);
+        let last = self.functions.last().map_or("main", |function| &function.label);
+        content.push_str(&format!("\t\t.uleb128 1\n\
+                                   \t\t.string \"{}\"\n\
+                                   \t\t.string \"emooji\"\n\
+                                   \t\t.byte 0xc\n\
+                                   \t\t.long .Ldebug_line0\n\
+                                   \t\t.quad {}\n\
+                                   \t\t.quad {}\n",
+                                  escape(&self.file), first, end_label(last)));
+        for &var_type in TYPES.iter() {
+            content.push_str(&format!(".Ldebug_type_{}:\n", type_name(var_type)));
+            content.push_str(&match var_type {
+                // strings are pointers to their bytes
+                VarType::STR => String::from("\t\t.uleb128 3\n\
+                                              \t\t.byte 8\n\
+                                              \t\t.long .Ldebug_type_char - .Ldebug_info0\n\
+                                              .Ldebug_type_char:\n\
+                                              \t\t.uleb128 2\n\
+                                              \t\t.string \"char\"\n\
+                                              \t\t.byte 0x8\n\
+                                              \t\t.byte 1\n"),
+                _ => {
+                    // DW_ATE_unsigned, DW_ATE_boolean and DW_ATE_address
+                    let encoding = match var_type {
+                        VarType::BOOL => 0x2,
+                        VarType::File => 0x1,
+                        _ => 0x7,
+                    };
+                    format!("\t\t.uleb128 2\n\
+                             \t\t.string \"{}\"\n\
+                             \t\t.byte {:#x}\n\
+                             \t\t.byte 8\n", type_name(var_type), encoding)
+                },
+            });
+        }
+        for &(ref name, ref symbol, var_type) in globals {
+            // DW_OP_addr
+            content.push_str(&format!("\t\t.uleb128 4\n\
+                                       \t\t.string \"{}\"\n\
+                                       \t\t.long .Ldebug_type_{} - .Ldebug_info0\n\
+                                       \t\t.uleb128 9\n\
+                                       \t\t.byte 0x3\n\
+                                       \t\t.quad {}\n", escape(name), type_name(var_type), symbol));
+        }
+        for function in &self.functions {
+            // DW_OP_breg of the frame pointer, plus 0
+            content.push_str(&format!("\t\t.uleb128 5\n\
+                                       \t\t.string \"{}\"\n\
+                                       \t\t.quad {}\n\
+                                       \t\t.quad {}\n\
+                                       \t\t.uleb128 2\n\
+                                       \t\t.byte {:#x}, 0\n",
+                                      escape(&function.name), function.label, end_label(&function.label),
+                                      0x70 + frame_register));
+            for (i, &(ref name, var_type, offset)) in function.locals.iter().enumerate() {
+                // DW_OP_fbreg
+                let mut location = vec![0x91];
+                location.extend(sleb128(offset));
+                let bytes: Vec<String> = location.iter().map(|byte| format!("{:#x}", byte)).collect();
+                content.push_str(&format!("\t\t.uleb128 {}\n\
+                                           \t\t.string \"{}\"\n\
+                                           \t\t.long .Ldebug_type_{} - .Ldebug_info0\n\
+                                           \t\t.uleb128 {}\n\
+                                           \t\t.byte {}\n",
+                                          if i < function.params { 6 } else { 4 }, escape(name),
+                                          type_name(var_type), location.len(), bytes.join(", ")));
+            }
+            content.push_str("\t\t.byte 0\n");
+        }
+        content.push_str("\t\t.byte 0\n\
+                          .Ldebug_info_end:\n");
+        content
+    }
+}
+
+// The label after the code of the function at `label`
+pub fn end_label(label: &str) -> String {
+    format!(".L{}_end", label)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn sleb128(mut value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// The kinds of entries: tag, whether it has children, then its attributes
+// and their forms
+const ABBREVIATIONS: &str = "\t\t.section .debug_abbrev,\"\",@progbits\n\
+.Ldebug_abbrev0:\n\
+\t\t.byte 1, 0x11, 1, 0x03, 0x08, 0x25, 0x08, 0x13, 0x0b, 0x10, 0x17, 0x11, 0x01, 0x12, 0x01, 0, 0\n\
+\t\t.byte 2, 0x24, 0, 0x03, 0x08, 0x3e, 0x0b, 0x0b, 0x0b, 0, 0\n\
+\t\t.byte 3, 0x0f, 0, 0x0b, 0x0b, 0x49, 0x13, 0, 0\n\
+\t\t.byte 4, 0x34, 0, 0x03, 0x08, 0x49, 0x13, 0x02, 0x18, 0, 0\n\
+\t\t.byte 5, 0x2e, 1, 0x03, 0x08, 0x11, 0x01, 0x12, 0x01, 0x40, 0x18, 0, 0\n\
+\t\t.byte 6, 0x05, 0, 0x03, 0x08, 0x49, 0x13, 0x02, 0x18, 0, 0\n\
+\t\t.byte 0\n";
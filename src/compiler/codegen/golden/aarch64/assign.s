@@ -0,0 +1,18 @@
+// v2: int = add v0, v1
+		mov x0, #1
+		mov x2, x0
+		mov x0, #2
+		add x0, x2, x0
+		mov x11, x0
+// store 😀, v2
+		mov x0, x11
+		adrp x9, v_1f600
+		str x0, [x9, :lo12:v_1f600]
+// store 👽, v3
+		adrp x0, str_0
+		add x0, x0, :lo12:str_0
+		adrp x9, v_1f47d
+		str x0, [x9, :lo12:v_1f47d]
+// ret
+.data
+		str_0: .string "👾"
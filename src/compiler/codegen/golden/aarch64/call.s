@@ -0,0 +1,39 @@
+// define 🐺
+		adrp x0, func_0
+		add x0, x0, :lo12:func_0
+		adrp x9, v_1f43a
+		str x0, [x9, :lo12:v_1f43a]
+// v0: int = call 🐺()
+		bl func_0
+		mov x11, x0
+// v3: file = open(v1, v2)
+		adrp x0, str_0
+		add x0, x0, :lo12:str_0
+		str x0, [sp, #-16]!
+		adrp x0, str_1
+		add x0, x0, :lo12:str_1
+		str x0, [sp, #-16]!
+		ldr x1, [sp], #16
+		ldr x0, [sp], #16
+		bl file_open_
+		mov x11, x0
+// v4: bool = close(v3)
+		mov x0, x11
+		str x0, [sp, #-16]!
+		ldr x0, [sp], #16
+		bl file_close_
+		mov x11, x0
+// ret
+func_0:
+		stp x29, x30, [sp, #-16]!
+		mov x29, sp
+// ret v0
+		mov x0, #7
+		mov sp, x29
+		ldp x29, x30, [sp], #16
+		ret
+.data
+		str_0: .string "🐸"
+		str_1: .string "📖"
+		.balign 8
+		v_1f43a: .quad 0
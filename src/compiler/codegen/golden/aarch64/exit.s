@@ -0,0 +1,18 @@
+// v0: string = readstr()
+		bl read_str_
+		mov x11, x0
+// v2: bool = eq v0, v1
+		adrp x0, str_0
+		add x0, x0, :lo12:str_0
+		mov x1, x0
+		mov x0, x11
+		bl str_eq_
+		mov x11, x0
+// v3: int = convert v2
+		mov x0, x11
+		mov x11, x0
+// exit v3
+		mov x0, x11
+		bl exit_
+.data
+		str_0: .string "🐸"
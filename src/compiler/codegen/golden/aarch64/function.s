@@ -0,0 +1,46 @@
+// define 🦊
+		adrp x0, func_0
+		add x0, x0, :lo12:func_0
+		adrp x9, v_1f98a
+		str x0, [x9, :lo12:v_1f98a]
+// v2: string = call 🦊(v0, v1)
+		mov x0, #3
+		str x0, [sp, #-16]!
+		adrp x0, str_0
+		add x0, x0, :lo12:str_0
+		str x0, [sp, #-16]!
+		ldr x1, [sp], #16
+		ldr x0, [sp], #16
+		bl func_0
+		mov x11, x0
+// print v2
+		mov x0, x11
+		bl print_str_
+// print '\n'
+		mov x0, #10
+		bl print_char_
+// ret
+func_0:
+		stp x29, x30, [sp, #-16]!
+		mov x29, sp
+// v0: int = param 0
+		mov x11, x0
+// v1: string = param 1
+		mov x11, x1
+// v4: string = add v1, v3
+		adrp x0, str_1
+		add x0, x0, :lo12:str_1
+		mov x1, x0
+		mov x0, x11
+		bl concat_
+		mov x11, x0
+// ret v4
+		mov x0, x11
+		mov sp, x29
+		ldp x29, x30, [sp], #16
+		ret
+.data
+		str_0: .string "🐸"
+		str_1: .string "🐾"
+		.balign 8
+		v_1f98a: .quad 0
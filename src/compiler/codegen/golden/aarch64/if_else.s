@@ -0,0 +1,31 @@
+// v0: int = load 😀
+		adrp x9, v_1f600
+		ldr x0, [x9, :lo12:v_1f600]
+		mov x11, x0
+// v2: bool = eq v0, v1
+		mov x0, #0
+		cmp x11, x0
+		cset x0, eq
+		mov x11, x0
+// branch v2, b1, b2
+		mov x0, x11
+		cbz x0, main_b2
+main_b1:
+// print v3
+		mov x0, #1
+		bl print_int_
+// print '\n'
+		mov x0, #10
+		bl print_char_
+// jump b3
+		b main_b3
+main_b2:
+// print v4
+		mov x0, #2
+		bl print_int_
+// print '\n'
+		mov x0, #10
+		bl print_char_
+// jump b3
+main_b3:
+// ret
@@ -0,0 +1,54 @@
+// print v0
+		adrp x0, str_0
+		add x0, x0, :lo12:str_0
+		bl print_str_
+// print ' '
+		mov x0, #32
+		bl print_char_
+// v3: int = div v1, v2
+		mov x0, #4
+		mov x2, x0
+		mov x0, #2
+		cbz x0, div_zero_error_
+		udiv x0, x2, x0
+		mov x11, x0
+// print v3
+		mov x0, x11
+		bl print_int_
+// print ' '
+		mov x0, #32
+		bl print_char_
+// print v4
+		mov x0, #1
+		bl print_bool_
+// v7: string = convert v6
+		mov x0, #7
+		bl int_str_
+		mov x11, x0
+// v8: string = add v5, v7
+		adrp x0, str_1
+		add x0, x0, :lo12:str_1
+		mov x2, x0
+		mov x0, x11
+		mov x1, x0
+		mov x0, x2
+		bl concat_
+		mov x11, x0
+// v10: string = add v8, v9
+		adrp x0, str_2
+		add x0, x0, :lo12:str_2
+		mov x1, x0
+		mov x0, x11
+		bl concat_
+		mov x11, x0
+// print v10
+		mov x0, x11
+		bl print_str_
+// print '\n'
+		mov x0, #10
+		bl print_char_
+// ret
+.data
+		str_0: .string "🐶"
+		str_1: .string ""
+		str_2: .string "🐱"
@@ -0,0 +1,36 @@
+// jump b1
+main_b1:
+// v0: int = load 😀
+		adrp x9, v_1f600
+		ldr x0, [x9, :lo12:v_1f600]
+		mov x11, x0
+// v2: bool = eq v0, v1
+		mov x0, #3
+		cmp x11, x0
+		cset x0, eq
+		mov x11, x0
+// v3: bool = not v2
+		mov x0, x11
+		cmp x0, #0
+		cset x0, eq
+		mov x11, x0
+// branch v3, b2, b3
+		mov x0, x11
+		cbz x0, main_b3
+main_b2:
+// v4: int = load 😀
+		adrp x9, v_1f600
+		ldr x0, [x9, :lo12:v_1f600]
+		mov x11, x0
+// v6: int = add v4, v5
+		mov x0, #1
+		add x0, x11, x0
+		mov x11, x0
+// store 😀, v6
+		mov x0, x11
+		adrp x9, v_1f600
+		str x0, [x9, :lo12:v_1f600]
+// jump b1
+		b main_b1
+main_b3:
+// ret
@@ -0,0 +1,17 @@
+# v2: int = add v0, v1
+		li a0, 1
+		mv t1, a0
+		li a0, 2
+		add a0, t1, a0
+		mv t2, a0
+# store 😀, v2
+		mv a0, t2
+		lla t0, v_1f600
+		sd a0, 0(t0)
+# store 👽, v3
+		lla a0, str_0
+		lla t0, v_1f47d
+		sd a0, 0(t0)
+# ret
+.data
+		str_0: .string "👾"
@@ -0,0 +1,46 @@
+# define 🐺
+		lla a0, func_0
+		lla t0, v_1f43a
+		sd a0, 0(t0)
+# v0: int = call 🐺()
+		call func_0
+		mv t2, a0
+# v3: file = open(v1, v2)
+		lla a0, str_0
+		addi sp, sp, -16
+		sd a0, 0(sp)
+		lla a0, str_1
+		addi sp, sp, -16
+		sd a0, 0(sp)
+		ld a1, 0(sp)
+		addi sp, sp, 16
+		ld a0, 0(sp)
+		addi sp, sp, 16
+		call file_open_
+		mv t2, a0
+# v4: bool = close(v3)
+		mv a0, t2
+		addi sp, sp, -16
+		sd a0, 0(sp)
+		ld a0, 0(sp)
+		addi sp, sp, 16
+		call file_close_
+		mv t2, a0
+# ret
+func_0:
+		addi sp, sp, -16
+		sd ra, 8(sp)
+		sd s0, 0(sp)
+		mv s0, sp
+# ret v0
+		li a0, 7
+		mv sp, s0
+		ld ra, 8(sp)
+		ld s0, 0(sp)
+		addi sp, sp, 16
+		ret
+.data
+		str_0: .string "🐸"
+		str_1: .string "📖"
+		.balign 8
+		v_1f43a: .quad 0
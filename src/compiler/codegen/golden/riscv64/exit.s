@@ -0,0 +1,17 @@
+# v0: string = readstr()
+		call read_str_
+		mv t2, a0
+# v2: bool = eq v0, v1
+		lla a0, str_0
+		mv a1, a0
+		mv a0, t2
+		call str_eq_
+		mv t2, a0
+# v3: int = convert v2
+		mv a0, t2
+		mv t2, a0
+# exit v3
+		mv a0, t2
+		call exit_
+.data
+		str_0: .string "🐸"
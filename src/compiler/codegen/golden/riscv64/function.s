@@ -0,0 +1,51 @@
+# define 🦊
+		lla a0, func_0
+		lla t0, v_1f98a
+		sd a0, 0(t0)
+# v2: string = call 🦊(v0, v1)
+		li a0, 3
+		addi sp, sp, -16
+		sd a0, 0(sp)
+		lla a0, str_0
+		addi sp, sp, -16
+		sd a0, 0(sp)
+		ld a1, 0(sp)
+		addi sp, sp, 16
+		ld a0, 0(sp)
+		addi sp, sp, 16
+		call func_0
+		mv t2, a0
+# print v2
+		mv a0, t2
+		call print_str_
+# print '\n'
+		li a0, 10
+		call print_char_
+# ret
+func_0:
+		addi sp, sp, -16
+		sd ra, 8(sp)
+		sd s0, 0(sp)
+		mv s0, sp
+# v0: int = param 0
+		mv t2, a0
+# v1: string = param 1
+		mv t2, a1
+# v4: string = add v1, v3
+		lla a0, str_1
+		mv a1, a0
+		mv a0, t2
+		call concat_
+		mv t2, a0
+# ret v4
+		mv a0, t2
+		mv sp, s0
+		ld ra, 8(sp)
+		ld s0, 0(sp)
+		addi sp, sp, 16
+		ret
+.data
+		str_0: .string "🐸"
+		str_1: .string "🐾"
+		.balign 8
+		v_1f98a: .quad 0
@@ -0,0 +1,33 @@
+# v0: int = load 😀
+		lla t0, v_1f600
+		ld a0, 0(t0)
+		mv t2, a0
+# v2: bool = eq v0, v1
+		li a0, 0
+		xor a0, t2, a0
+		seqz a0, a0
+		mv t2, a0
+# branch v2, b1, b2
+		mv a0, t2
+		bnez a0, 1f
+		j main_b2
+1:
+main_b1:
+# print v3
+		li a0, 1
+		call print_int_
+# print '\n'
+		li a0, 10
+		call print_char_
+# jump b3
+		j main_b3
+main_b2:
+# print v4
+		li a0, 2
+		call print_int_
+# print '\n'
+		li a0, 10
+		call print_char_
+# jump b3
+main_b3:
+# ret
@@ -0,0 +1,53 @@
+# print v0
+		lla a0, str_0
+		call print_str_
+# print ' '
+		li a0, 32
+		call print_char_
+# v3: int = div v1, v2
+		li a0, 4
+		mv t1, a0
+		li a0, 2
+		bnez a0, 1f
+		j div_zero_error_
+1:
+		divu a0, t1, a0
+		mv t2, a0
+# print v3
+		mv a0, t2
+		call print_int_
+# print ' '
+		li a0, 32
+		call print_char_
+# print v4
+		li a0, 1
+		call print_bool_
+# v7: string = convert v6
+		li a0, 7
+		call int_str_
+		mv t2, a0
+# v8: string = add v5, v7
+		lla a0, str_1
+		mv t1, a0
+		mv a0, t2
+		mv a1, a0
+		mv a0, t1
+		call concat_
+		mv t2, a0
+# v10: string = add v8, v9
+		lla a0, str_2
+		mv a1, a0
+		mv a0, t2
+		call concat_
+		mv t2, a0
+# print v10
+		mv a0, t2
+		call print_str_
+# print '\n'
+		li a0, 10
+		call print_char_
+# ret
+.data
+		str_0: .string "🐶"
+		str_1: .string ""
+		str_2: .string "🐱"
@@ -0,0 +1,37 @@
+# jump b1
+main_b1:
+# v0: int = load 😀
+		lla t0, v_1f600
+		ld a0, 0(t0)
+		mv t2, a0
+# v2: bool = eq v0, v1
+		li a0, 3
+		xor a0, t2, a0
+		seqz a0, a0
+		mv t2, a0
+# v3: bool = not v2
+		mv a0, t2
+		seqz a0, a0
+		mv t2, a0
+# branch v3, b2, b3
+		mv a0, t2
+		bnez a0, 1f
+		j main_b3
+1:
+main_b2:
+# v4: int = load 😀
+		lla t0, v_1f600
+		ld a0, 0(t0)
+		mv t2, a0
+# v6: int = add v4, v5
+		li a0, 1
+		add a0, t2, a0
+		mv t2, a0
+# store 😀, v6
+		mv a0, t2
+		lla t0, v_1f600
+		sd a0, 0(t0)
+# jump b1
+		j main_b1
+main_b3:
+# ret
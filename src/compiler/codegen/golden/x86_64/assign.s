@@ -0,0 +1,15 @@
+# v2: int = add v0, v1
+		movq $1, %rax
+		movq %rax, %rcx
+		movq $2, %rax
+		addq %rcx, %rax
+		movq %rax, %r10
+# store 😀, v2
+		movq %r10, %rax
+		movq %rax, 😀
+# store 👽, v3
+		movq $str_0, %rax
+		movq %rax, 👽
+# ret
+.data
+		str_0: .string "👾"
@@ -0,0 +1,33 @@
+# define 🐺
+		lea func_0, %rax
+		movq %rax, 🐺
+# v0: int = call 🐺()
+		call func_0
+		movq %rax, %r10
+# v3: file = open(v1, v2)
+		movq $str_0, %rax
+		push %rax
+		movq $str_1, %rax
+		push %rax
+		pop %rsi
+		pop %rdi
+		call file_open_
+		movq %rax, %r10
+# v4: bool = close(v3)
+		movq %r10, %rax
+		push %rax
+		pop %rdi
+		call file_close_
+		movq %rax, %r10
+# ret
+func_0:
+		push %rbp
+		movq %rsp, %rbp
+# ret v0
+		movq $7, %rax
+		leave
+		ret
+.data
+		str_0: .string "🐸"
+		str_1: .string "📖"
+		🐺: .quad 0
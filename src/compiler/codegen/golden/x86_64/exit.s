@@ -0,0 +1,18 @@
+# v0: string = readstr()
+		call read_str_
+		movq %rax, %r10
+# v2: bool = eq v0, v1
+		movq $str_0, %rax
+		movq %rax, %rsi
+		movq %r10, %rdi
+		call str_eq_
+		movq %rax, %r10
+# v3: int = convert v2
+		movq %r10, %rax
+		movq %rax, %r10
+# exit v3
+		movq %r10, %rax
+		movq %rax, %rdi
+		call exit_
+.data
+		str_0: .string "🐸"
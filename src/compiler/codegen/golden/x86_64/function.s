@@ -0,0 +1,41 @@
+# define 🦊
+		lea func_0, %rax
+		movq %rax, 🦊
+# v2: string = call 🦊(v0, v1)
+		movq $3, %rax
+		push %rax
+		movq $str_0, %rax
+		push %rax
+		pop %rsi
+		pop %rdi
+		call func_0
+		movq %rax, %r10
+# print v2
+		movq %r10, %rax
+		movq %rax, %rdi
+		call print_str_
+# print '\n'
+		movq $10, %rdi
+		call print_char_
+# ret
+func_0:
+		push %rbp
+		movq %rsp, %rbp
+# v0: int = param 0
+		movq %rdi, %r10
+# v1: string = param 1
+		movq %rsi, %r10
+# v4: string = add v1, v3
+		movq $str_1, %rax
+		movq %rax, %rsi
+		movq %r10, %rdi
+		call concat_
+		movq %rax, %r10
+# ret v4
+		movq %r10, %rax
+		leave
+		ret
+.data
+		str_0: .string "🐸"
+		str_1: .string "🐾"
+		🦊: .quad 0
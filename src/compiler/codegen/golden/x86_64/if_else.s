@@ -0,0 +1,34 @@
+# v0: int = load 😀
+		movq 😀, %rax
+		movq %rax, %r10
+# v2: bool = eq v0, v1
+		movq $0, %rax
+		subq %r10, %rax
+		sete %al
+		movzbq %al, %rax
+		movq %rax, %r10
+# branch v2, b1, b2
+		movq %r10, %rax
+		cmp $0, %rax
+		je main_b2
+main_b1:
+# print v3
+		movq $1, %rax
+		movq %rax, %rdi
+		call print_int_
+# print '\n'
+		movq $10, %rdi
+		call print_char_
+# jump b3
+		jmp main_b3
+main_b2:
+# print v4
+		movq $2, %rax
+		movq %rax, %rdi
+		call print_int_
+# print '\n'
+		movq $10, %rdi
+		call print_char_
+# jump b3
+main_b3:
+# ret
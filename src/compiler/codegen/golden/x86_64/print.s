@@ -0,0 +1,60 @@
+# print v0
+		movq $str_0, %rax
+		movq %rax, %rdi
+		call print_str_
+# print ' '
+		movq $32, %rdi
+		call print_char_
+# v3: int = div v1, v2
+		movq $4, %rax
+		movq %rax, %rcx
+		movq $2, %rax
+		movq %rax, %r15
+		movq %rcx, %rax
+		cmpq $0, %r15
+		je div_zero_error_
+		xorq %rdx, %rdx
+		divq %r15
+		movq %rax, %r10
+# print v3
+		movq %r10, %rax
+		movq %rax, %rdi
+		call print_int_
+# print ' '
+		movq $32, %rdi
+		call print_char_
+# print v4
+		movq $1, %rax
+		movq %rax, %rdi
+		call print_bool_
+# v7: string = convert v6
+		movq $7, %rax
+		movq %rax, %rdi
+		call int_str_
+		movq %rax, %r10
+# v8: string = add v5, v7
+		movq $str_1, %rax
+		movq %rax, %rcx
+		movq %r10, %rax
+		movq %rax, %rsi
+		movq %rcx, %rdi
+		call concat_
+		movq %rax, %r10
+# v10: string = add v8, v9
+		movq $str_2, %rax
+		movq %rax, %rsi
+		movq %r10, %rdi
+		call concat_
+		movq %rax, %r10
+# print v10
+		movq %r10, %rax
+		movq %rax, %rdi
+		call print_str_
+# print '\n'
+		movq $10, %rdi
+		call print_char_
+# ret
+.data
+		str_0: .string "🐶"
+		str_1: .string ""
+		str_2: .string "🐱"
@@ -0,0 +1,36 @@
+# jump b1
+main_b1:
+# v0: int = load 😀
+		movq 😀, %rax
+		movq %rax, %r10
+# v2: bool = eq v0, v1
+		movq $3, %rax
+		subq %r10, %rax
+		sete %al
+		movzbq %al, %rax
+		movq %rax, %r10
+# v3: bool = not v2
+		movq %r10, %rax
+		cmpq $0, %rax
+		sete %al
+		movzbq %al, %rax
+		movq %rax, %r10
+# branch v3, b2, b3
+		movq %r10, %rax
+		cmp $0, %rax
+		je main_b3
+main_b2:
+# v4: int = load 😀
+		movq 😀, %rax
+		movq %rax, %r10
+# v6: int = add v4, v5
+		movq $1, %rax
+		addq %r10, %rax
+		movq %rax, %r10
+# store 😀, v6
+		movq %r10, %rax
+		movq %rax, 😀
+# jump b1
+		jmp main_b1
+main_b3:
+# ret
@@ -0,0 +1,772 @@
+mod x86_64;
+mod aarch64;
+mod riscv64;
+mod regalloc;
+mod dwarf;
+use std::collections::HashMap;
+use std::mem;
+use super::tokenizer::VarType;
+use super::ast::{BinOp, Builtin};
+use super::ir::{Module, Function, Inst, Op, Term, Value, BlockId};
+use self::dwarf::{Debug, Subprogram};
+pub use self::x86_64::X86_64;
+pub use self::aarch64::Aarch64;
+pub use self::riscv64::Riscv64;
+
+// Where a variable lives
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Var<'a> {
+    // a symbol in the data section, named by Target::symbol
+    Global(&'a str),
+    // a slot in the current function's frame, at this offset from the frame
+    // pointer
+    Local(i64),
+    // a register the value was allocated to
+    Reg(&'a str)
+}
+
+// The instructions of one architecture. Code generation lowers programs the
+// same way everywhere: every instruction computes its result in an
+// accumulator register, from where it goes to the register or frame slot
+// the value was allocated. Each method returns the assembly for one step.
+pub trait Target {
+    // Everything before the program's first statement in main, which gets
+    // argc and argv like any C main and saves them for the runtime, and sets
+    // up a frame with `frame_size` bytes of slots
+    fn main_prologue(&self, frame_size: usize) -> String;
+    // Returns 0 from main
+    fn main_epilogue(&self) -> String;
+    // Runtime helpers called from generated code, and the data they use
+    fn runtime(&self) -> String;
+    fn runtime_data(&self) -> String;
+
+    // Assembly name of the variable or function `id`
+    fn symbol(&self, id: &str) -> String;
+    // A 64 bit value in the data section, a number or a label's address
+    fn data_quad(&self, name: &str, value: &str) -> String;
+    fn data_string(&self, name: &str, value: &str) -> String;
+    fn comment(&self, text: &str) -> String;
+    fn label(&self, name: &str) -> String {
+        format!("{}:\n", name)
+    }
+
+    fn load_int(&self, value: u64) -> String;
+    fn load_address(&self, label: &str) -> String;
+    // The address of code, which some assemblers want spelled differently
+    fn load_code_address(&self, label: &str) -> String {
+        self.load_address(label)
+    }
+    fn load(&self, var: Var) -> String;
+    fn store(&self, var: Var) -> String;
+    fn push(&self) -> String;
+    // Pops into the accumulator
+    fn pop(&self) -> String;
+    // Pops into the register for argument `index` of a call
+    fn pop_arg(&self, index: usize) -> String;
+
+    // Registers values can be allocated: ones calls preserve, which
+    // functions using them save in their frame, and ones calls may change
+    fn saved_registers(&self) -> &'static [&'static str];
+    fn temporary_registers(&self) -> &'static [&'static str];
+    // Holds the left operand of a binary operator that isn't in a register
+    fn operand_register(&self) -> &'static str;
+    // Saves a register in a slot of the frame, and restores it
+    fn save(&self, reg: &str, offset: i64) -> String;
+    fn restore(&self, reg: &str, offset: i64) -> String;
+
+    fn jump(&self, label: &str) -> String;
+    fn jump_if_zero(&self, label: &str) -> String;
+    fn call(&self, name: &str) -> String;
+    // Calls `name` with the accumulator as its only argument
+    fn call_with(&self, name: &str) -> String;
+    // Calls the function whose address is in `var`
+    fn call_var(&self, var: Var) -> String;
+    fn print_char(&self, ch: char) -> String;
+
+    // Combines the left operand in register `lhs` with the right one in the
+    // accumulator
+    fn binary(&self, op: BinOp, lhs: &str, operand_type: VarType, result_type: VarType) -> String;
+    fn not(&self) -> String;
+    // Turns any number into a boolean 0 or 1
+    fn to_bool(&self) -> String;
+
+    // Sets up a function's frame, with `frame_size` bytes of slots
+    fn enter(&self, frame_size: usize) -> String;
+    // Moves argument `index` of the call to where the parameter lives
+    fn store_arg(&self, index: usize, var: Var) -> String;
+    // Returns the accumulator from the current function
+    fn leave(&self) -> String;
+
+    // The DWARF number of the frame pointer register
+    fn dwarf_frame_register(&self) -> u8;
+}
+
+// A symbol for `id` made of ASCII only, for assemblers that don't take emoji:
+// "v" followed by the code point of every character in hex
+fn ascii_symbol(id: &str) -> String {
+    let mut name = String::from("v");
+    for ch in id.chars() {
+        name.push_str(&format!("_{:x}", ch as u32));
+    }
+    name
+}
+
+// Code generation for native targets, the same for every architecture. Every
+// value of the IR that isn't a constant gets a register, or a slot in its
+// function's frame when there are too few; instructions load their operands
+// into the accumulator, compute, and store the result.
+pub struct CodeGen<T: Target> {
+    target: T,
+    content: String,
+    data: String,
+    // string literals so far, numbering their labels
+    strings: usize,
+    // where each value of the function being generated is found
+    places: Vec<Place>,
+    // the saved registers the function uses, and their slots
+    saved: Vec<(&'static str, i64)>,
+    // false keeps every value in the frame
+    registers: bool,
+    // slots of the function's variables that stay in its frame
+    locals: HashMap<String, i64>,
+    // whether this is a piece of a program, whose main doesn't return
+    fragment: bool,
+    // what -g adds to the assembly
+    debug: Option<Debug>
+}
+
+#[derive(PartialEq)]
+enum Place {
+    Int(u64),
+    Label(String),
+    Slot(i64),
+    Reg(&'static str)
+}
+
+fn block_label(func: &Function, block: BlockId) -> String {
+    format!("{}_b{}", func.label, block.0)
+}
+
+impl<T: Target> CodeGen<T> {
+    pub fn new(target: T) -> CodeGen<T> {
+        CodeGen {
+            target,
+            content: String::new(),
+            data: String::new(),
+            strings: 0,
+            places: Vec::new(),
+            saved: Vec::new(),
+            registers: true,
+            locals: HashMap::new(),
+            fragment: false,
+            debug: None
+        }
+    }
+
+    // Adds line information and DWARF entries for debuggers, where `file` is
+    // the source and `positions` the line and column of every token
+    pub fn with_debug_info(mut self, file: &str, positions: Vec<(usize, usize)>) -> CodeGen<T> {
+        self.debug = Some(Debug::new(file, positions));
+        self
+    }
+
+    // Code as it was before register allocation, to compare against
+    #[cfg(test)]
+    pub fn without_registers(mut self) -> CodeGen<T> {
+        self.registers = false;
+        self
+    }
+
+    // Generates the complete assembly file for a module
+    pub fn gen(mut self, module: &Module) -> String {
+        let main = &module.functions[0];
+        let frame_size = self.frame(main);
+        self.locate(main.pos);
+        let prologue = self.target.main_prologue(frame_size) + &self.saves();
+        self.write(&prologue);
+        self.blocks(module, main);
+        self.end_function(main);
+        for func in &module.functions[1..] {
+            self.function(module, func);
+        }
+        let runtime = self.target.runtime();
+        self.write(&runtime);
+        let code = mem::take(&mut self.content);
+
+        // data section, including literals gathered while generating code
+        self.write(".data\n");
+        let runtime_data = self.target.runtime_data();
+        self.write(&runtime_data);
+        for &(ref id, var_type) in &module.globals {
+            // strings are pointers, initially to the empty string
+            let value = if var_type == VarType::STR { "Empty_string" } else { "0" };
+            let quad = self.target.data_quad(&self.target.symbol(id), value);
+            self.write(&quad);
+        }
+        let data = mem::take(&mut self.data);
+        self.write(&data);
+        self.write(&code);
+        if let Some(ref debug) = self.debug {
+            let globals: Vec<_> = module.globals.iter()
+                .map(|&(ref id, var_type)| (id.clone(), self.target.symbol(id), var_type))
+                .collect();
+            self.content = debug.file() + &self.content + &debug.sections(self.target.dwarf_frame_register(), &globals);
+        }
+        self.content
+    }
+
+    // Code for a piece of a program, from ir::lower_fragment, followed by
+    // the data it uses, to show what it compiles to
+    pub fn gen_fragment(mut self, module: &Module) -> String {
+        self.fragment = true;
+        let main = &module.functions[0];
+        self.frame(main);
+        self.blocks(module, main);
+        for func in &module.functions[1..] {
+            self.function(module, func);
+        }
+        self.function_pointers(module);
+        if !self.data.is_empty() {
+            let data = mem::take(&mut self.data);
+            self.write(".data\n");
+            self.write(&data);
+        }
+        self.content
+    }
+
+    fn write(&mut self, data: &str) {
+        self.content.push_str(data);
+    }
+
+    fn write_data(&mut self, data: &str) {
+        self.data.push_str(data);
+    }
+
+    // The variables later REPL fragments call the functions through
+    fn function_pointers(&mut self, module: &Module) {
+        for func in &module.functions[1..] {
+            let quad = self.target.data_quad(&self.target.symbol(&func.id), "0");
+            self.write_data(&quad);
+        }
+    }
+
+    // Decides where the values of `func` are, returning the size of the
+    // slots in its frame
+    fn frame(&mut self, func: &Function) -> usize {
+        let registers = if self.registers {
+            regalloc::allocate(func, self.target.saved_registers(), self.target.temporary_registers())
+        } else {
+            vec![None; func.types.len()]
+        };
+        self.places = func.types.iter().map(|_| Place::Int(0)).collect();
+        let mut slots = 0;
+        for block in &func.blocks {
+            for inst in &block.insts {
+                let value = match inst.value {
+                    Some(value) => value,
+                    None => continue,
+                };
+                self.places[value.0] = match inst.op {
+                    Op::Const(value) => Place::Int(value),
+                    Op::Str(ref value) => {
+                        // string literals live in the data section
+                        let label = format!("str_{}", self.strings);
+                        self.strings += 1;
+                        let string = self.target.data_string(&label, value);
+                        self.write_data(&string);
+                        Place::Label(label)
+                    },
+                    _ => match registers[value.0] {
+                        Some(reg) => Place::Reg(reg),
+                        None => {
+                            slots += 1;
+                            Place::Slot(-8 * slots)
+                        },
+                    },
+                };
+            }
+        }
+        self.locals.clear();
+        for local in &func.locals {
+            slots += 1;
+            self.locals.insert(local.0.clone(), -8 * slots);
+        }
+        self.saved.clear();
+        for &reg in self.target.saved_registers() {
+            if registers.contains(&Some(reg)) {
+                slots += 1;
+                self.saved.push((reg, -8 * slots));
+            }
+        }
+        (slots as usize + slots as usize % 2) * 8
+    }
+
+    // Saves the registers the function uses that its caller expects kept
+    fn saves(&self) -> String {
+        self.saved.iter().map(|&(reg, offset)| self.target.save(reg, offset)).collect()
+    }
+
+    fn restores(&self) -> String {
+        self.saved.iter().map(|&(reg, offset)| self.target.restore(reg, offset)).collect()
+    }
+
+    fn function(&mut self, module: &Module, func: &Function) {
+        let frame_size = self.frame(func);
+        self.locate(func.pos);
+        let code = self.target.label(&func.label) + &self.target.enter(frame_size) + &self.saves();
+        self.write(&code);
+        self.blocks(module, func);
+        self.end_function(func);
+    }
+
+    // Tells the assembler the code that follows is from the line of token
+    // `pos`, with -g
+    fn locate(&mut self, pos: usize) {
+        let code = match self.debug {
+            Some(ref mut debug) => debug.locate(pos),
+            None => return,
+        };
+        self.write(&code);
+    }
+
+    // Marks where the code of `func` ends, for the debug information about
+    // it and its variables
+    fn end_function(&mut self, func: &Function) {
+        if self.debug.is_none() {
+            return;
+        }
+        let function = Subprogram {
+            name: func.id.clone(),
+            label: func.label.clone(),
+            params: func.params.len(),
+            locals: func.locals.iter().map(|&(ref id, var_type)| (id.clone(), var_type, self.locals[id])).collect()
+        };
+        self.debug.as_mut().unwrap().add_function(function);
+        let code = self.target.label(&dwarf::end_label(&func.label));
+        self.write(&code);
+    }
+
+    // Where a variable kept in memory is: in the frame if the function has
+    // it, else at `symbol` in the data section
+    fn memory<'a>(&self, id: &str, symbol: &'a str) -> Var<'a> {
+        match self.locals.get(id) {
+            Some(&offset) => Var::Local(offset),
+            None => Var::Global(symbol),
+        }
+    }
+
+    fn blocks(&mut self, module: &Module, func: &Function) {
+        for (i, block) in func.blocks.iter().enumerate() {
+            if i > 0 {
+                let code = self.target.label(&block_label(func, BlockId(i)));
+                self.write(&code);
+            }
+            for inst in &block.insts {
+                self.inst(module, func, inst);
+            }
+            self.term(func, BlockId(i), &block.term);
+        }
+    }
+
+    // Where a value that isn't a constant lives
+    fn var(&self, value: Value) -> Var<'static> {
+        match self.places[value.0] {
+            Place::Slot(offset) => Var::Local(offset),
+            Place::Reg(reg) => Var::Reg(reg),
+            _ => panic!("Constant {} has no slot or register", value),
+        }
+    }
+
+    fn load(&self, value: Value) -> String {
+        match self.places[value.0] {
+            Place::Int(n) => self.target.load_int(n),
+            Place::Label(ref label) => self.target.load_address(label),
+            _ => self.target.load(self.var(value)),
+        }
+    }
+
+    fn store(&self, value: Value) -> String {
+        self.target.store(self.var(value))
+    }
+
+    fn inst(&mut self, module: &Module, func: &Function, inst: &Inst) {
+        if !matches!(inst.op, Op::Const(_) | Op::Str(_) | Op::Phi(_)) {
+            self.locate(inst.pos);
+        }
+        let mut code = match inst.op {
+            // constants are loaded where they are used, and phis are set by
+            // the blocks before
+            Op::Const(_) | Op::Str(_) | Op::Phi(_) => return,
+            Op::Param(index) => {
+                // straight from the argument register
+                let code = self.target.comment(&func.inst_str(inst))
+                    + &self.target.store_arg(index, self.var(inst.value.unwrap()));
+                self.write(&code);
+                return;
+            },
+            Op::Load(ref id) => self.target.load(self.memory(id, &self.target.symbol(id))),
+            Op::Store(ref id, value) => self.load(value) + &self.target.store(self.memory(id, &self.target.symbol(id))),
+            Op::Binary(op, lhs, rhs) => {
+                let result_type = func.types[inst.value.unwrap().0];
+                let (mut code, reg) = match self.places[lhs.0] {
+                    Place::Reg(reg) => (String::new(), reg),
+                    _ => {
+                        let reg = self.target.operand_register();
+                        (self.load(lhs) + &self.target.store(Var::Reg(reg)), reg)
+                    },
+                };
+                code.push_str(&self.load(rhs));
+                code + &self.target.binary(op, reg, func.types[lhs.0], result_type)
+            },
+            Op::Not(value) => self.load(value) + &self.target.not(),
+            Op::Convert(value) => {
+                let conversion = match (func.types[value.0], func.types[inst.value.unwrap().0]) {
+                    (VarType::INT, VarType::STR) => self.target.call_with("int_str_"),
+                    (VarType::BOOL, VarType::STR) => self.target.call_with("bool_str_"),
+                    // booleans are always 0 or 1
+                    (_, VarType::BOOL) => self.target.to_bool(),
+                    _ => String::new(),
+                };
+                self.load(value) + &conversion
+            },
+            Op::Call(ref id, ref args) => {
+                // functions of the module are called directly, others that
+                // the REPL defined earlier through their variable
+                let call = match module.functions.iter().find(|func| func.id == *id) {
+                    Some(callee) => self.target.call(&callee.label),
+                    None => self.target.call_var(Var::Global(&self.target.symbol(id))),
+                };
+                self.call_args(args) + &call
+            },
+            Op::Builtin(builtin, ref args) => {
+                let helper = match builtin {
+                    Builtin::Rand => "rand_",
+                    Builtin::ReadStr => "read_str_",
+                    Builtin::ReadInt => "read_int_",
+                    Builtin::Eof => {
+                        if args.is_empty() {
+                            "eof_"
+                        } else {
+                            "file_eof_"
+                        }
+                    },
+                    Builtin::ArgCount => "arg_count_",
+                    Builtin::Arg => "arg_",
+                    Builtin::Parse => "parse_int_",
+                    Builtin::Open => "file_open_",
+                    Builtin::ReadLine => "read_line_",
+                    Builtin::Write => "file_write_",
+                    Builtin::Close => "file_close_",
+                };
+                self.call_args(args) + &self.target.call(helper)
+            },
+            Op::Print(value) => {
+                let helper = match func.types[value.0] {
+                    VarType::STR => "print_str_",
+                    VarType::BOOL => "print_bool_",
+                    _ => "print_int_",
+                };
+                self.load(value) + &self.target.call_with(helper)
+            },
+            Op::PrintChar(ch) => self.target.print_char(ch),
+            // only later REPL fragments need to find the function
+            Op::Define(..) if !self.fragment => String::new(),
+            Op::Define(ref id, index) => {
+                self.target.load_code_address(&module.functions[index].label)
+                    + &self.target.store(Var::Global(&self.target.symbol(id)))
+            },
+        };
+        if let Some(value) = inst.value {
+            code.push_str(&self.store(value));
+        }
+        let comment = self.target.comment(&func.inst_str(inst));
+        self.write(&comment);
+        self.write(&code);
+    }
+
+    // Evaluates arguments into the argument registers
+    fn call_args(&self, args: &[Value]) -> String {
+        let mut code = String::new();
+        for &arg in args {
+            code.push_str(&self.load(arg));
+            code.push_str(&self.target.push());
+        }
+        for i in (0..args.len()).rev() {
+            code.push_str(&self.target.pop_arg(i));
+        }
+        code
+    }
+
+    // Sets the phis of `to` to their values coming from `from`
+    fn phi_copies(&self, func: &Function, from: BlockId, to: BlockId) -> String {
+        let mut copies = Vec::new();
+        for inst in &func.blocks[to.0].insts {
+            if let Op::Phi(ref incoming) = inst.op {
+                let phi = inst.value.unwrap();
+                for &(pred, value) in incoming {
+                    if pred == from && self.places[value.0] != self.places[phi.0] {
+                        copies.push((phi, value));
+                    }
+                }
+            }
+        }
+        let mut code = String::new();
+        // when a copy overwrites what another one reads, they have to be
+        // done all at once
+        let parallel = copies.iter().any(|&(_, value)| {
+            copies.iter().any(|&(phi, _)| self.places[phi.0] == self.places[value.0])
+        });
+        if parallel {
+            for &(_, value) in &copies {
+                code.push_str(&self.load(value));
+                code.push_str(&self.target.push());
+            }
+            for &(phi, _) in copies.iter().rev() {
+                code.push_str(&self.target.pop());
+                code.push_str(&self.store(phi));
+            }
+        } else {
+            for &(phi, value) in &copies {
+                code.push_str(&self.load(value));
+                code.push_str(&self.store(phi));
+            }
+        }
+        code
+    }
+
+    fn term(&mut self, func: &Function, block: BlockId, term: &Term) {
+        let next = BlockId(block.0 + 1);
+        let mut code = self.target.comment(&term.to_string());
+        match *term {
+            Term::Jump(target) => {
+                code.push_str(&self.phi_copies(func, block, target));
+                if target != next {
+                    code.push_str(&self.target.jump(&block_label(func, target)));
+                }
+            },
+            Term::Branch(cond, then, otherwise) => {
+                code.push_str(&self.load(cond));
+                let then_copies = self.phi_copies(func, block, then);
+                let else_copies = self.phi_copies(func, block, otherwise);
+                if else_copies.is_empty() {
+                    code.push_str(&self.target.jump_if_zero(&block_label(func, otherwise)));
+                    code.push_str(&then_copies);
+                    if then != next {
+                        code.push_str(&self.target.jump(&block_label(func, then)));
+                    }
+                } else {
+                    // the copies for the edge to `otherwise` get their own label
+                    let edge = format!("{}_b{}_b{}", func.label, block.0, otherwise.0);
+                    code.push_str(&self.target.jump_if_zero(&edge));
+                    code.push_str(&then_copies);
+                    code.push_str(&self.target.jump(&block_label(func, then)));
+                    code.push_str(&self.target.label(&edge));
+                    code.push_str(&else_copies);
+                    if otherwise != next {
+                        code.push_str(&self.target.jump(&block_label(func, otherwise)));
+                    }
+                }
+            },
+            Term::Return(value) => {
+                if func.label == "main" {
+                    if !self.fragment {
+                        code.push_str(&self.restores());
+                        code.push_str(&self.target.main_epilogue());
+                    }
+                } else {
+                    if let Some(value) = value {
+                        code.push_str(&self.load(value));
+                    }
+                    code.push_str(&self.restores());
+                    code.push_str(&self.target.leave());
+                }
+            },
+            Term::Exit(status) => {
+                code.push_str(&self.load(status));
+                code.push_str(&self.target.call_with("exit_"));
+            },
+        }
+        self.write(&code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use super::{CodeGen, Target, X86_64, Aarch64, Riscv64};
+    use super::super::tokenizer::Tokenizer;
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+    use super::super::ast::Program;
+    use super::super::ir;
+
+    // A program for every kind of statement
+    const STATEMENTS: [(&str, &str); 7] = [
+        ("assign", "🆕🔢😀🔚 🆕🔤👽🔚 😀⬅️1️⃣➕2️⃣🔚 👽⬅️💬👾💬🔚"),
+        ("if_else", "🆕🔢😀🔚 ❓😀↔️0️⃣ 📄1️⃣🔚 ❌ 📄2️⃣🔚"),
+        ("while", "🆕🔢😀🔚 🔁🚫🌜😀↔️3️⃣🌛 🌘 😀⬅️😀➕1️⃣🔚 🌒"),
+        ("print", "🖨️💬🐶💬◾4️⃣➗️2️⃣◾👍🔚 📄🖼🌜💬🔢🐱💬◾7️⃣🌛🔚"),
+        ("function", "🤪🔤🦊🌜🔢🐾◾🔤🐛🌛 🌘 🆕☯️🐜🔚 ↩️🐛➕💬🐾💬🔚 🌒 📄📞🦊🌜3️⃣◾💬🐸💬🌛🔚"),
+        ("call", "🤪🔢🐺🌜🌛 🌘 ↩️7️⃣🔚 🌒 📞🐺🌜🌛🔚 🔒🌜📂🌜💬🐸💬◾💬📖💬🌛🌛🔚"),
+        ("exit", "🚪🌜📥🌜🌛↔️💬🐸💬🌛🔚"),
+    ];
+
+    // Arithmetic-heavy programs for comparing the code with and without
+    // register allocation
+    const BENCHMARKS: [(&str, &str); 3] = [
+        ("collatz", include_str!("../../../bench/collatz.moo")),
+        ("primes", include_str!("../../../bench/primes.moo")),
+        ("polynomial", include_str!("../../../bench/polynomial.moo")),
+    ];
+
+    fn check(program: &str) -> Program {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        ast
+    }
+
+    // Compares the code for each statement with the files in golden/`dir`,
+    // or rewrites them when UPDATE_GOLDEN is set
+    fn matches_golden<T: Target + Copy>(target: T, dir: &str) {
+        let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/compiler/codegen/golden").join(dir);
+        for &(name, program) in STATEMENTS.iter() {
+            let code = CodeGen::new(target).gen_fragment(&ir::lower_fragment(&check(program)));
+            let path = golden.join(format!("{}.s", name));
+            if env::var_os("UPDATE_GOLDEN").is_some() {
+                fs::create_dir_all(&golden).unwrap();
+                fs::write(&path, &code).unwrap();
+            } else {
+                let expected = fs::read_to_string(&path).unwrap();
+                assert!(code == expected, "{} differs from {}:\n{}", name, path.display(), code);
+            }
+        }
+    }
+
+    // Assembles complete programs with llvm-mc, when it is installed
+    fn assembles<T: Target + Copy>(target: T, triple: &str, features: &str) {
+        let dir = env::temp_dir().join("emooji_tests");
+        fs::create_dir_all(&dir).unwrap();
+        for &(name, program) in STATEMENTS.iter().chain(BENCHMARKS.iter()) {
+            let path = dir.join(format!("{}_{}.s", triple, name));
+            fs::write(&path, CodeGen::new(target).gen(&ir::lower(&check(program)))).unwrap();
+            let status = Command::new("llvm-mc").arg(format!("-triple={}", triple))
+                .arg(format!("-mattr={}", features))
+                .args(["-filetype=obj", "-o", "/dev/null"]).arg(&path).status();
+            match status {
+                Ok(status) => assert!(status.success(), "{} does not assemble", path.display()),
+                Err(_) => return,
+            }
+        }
+    }
+
+    #[test]
+    fn x86_64_matches_golden() {
+        matches_golden(X86_64, "x86_64");
+    }
+
+    #[test]
+    fn aarch64_matches_golden() {
+        matches_golden(Aarch64, "aarch64");
+        assembles(Aarch64, "aarch64-linux-gnu", "");
+    }
+
+    #[test]
+    fn riscv64_matches_golden() {
+        matches_golden(Riscv64, "riscv64");
+        assembles(Riscv64, "riscv64-linux-gnu", "+m,+a,+f,+d,+c");
+    }
+
+    #[test]
+    fn aarch64_immediates_and_far_slots() {
+        let code = CodeGen::new(Aarch64).gen_fragment(&ir::lower_fragment(&check("📄1️⃣8️⃣4️⃣4️⃣6️⃣7️⃣4️⃣4️⃣0️⃣7️⃣3️⃣7️⃣0️⃣9️⃣5️⃣5️⃣1️⃣6️⃣1️⃣5️⃣🔚")));
+        assert!(code.starts_with("// print v0\n\t\tmov x0, #65535\n\t\tmovk x0, #65535, lsl #16\n\
+                                  \t\tmovk x0, #65535, lsl #32\n\t\tmovk x0, #65535, lsl #48\n"));
+        assert_eq!(super::Target::load(&Aarch64, super::Var::Local(-264)),
+                   "\t\tmov x10, #65272\n\t\tmovk x10, #65535, lsl #16\n\t\tmovk x10, #65535, lsl #32\n\
+                    \t\tmovk x10, #65535, lsl #48\n\t\tldr x0, [x29, x10]\n");
+    }
+
+    #[test]
+    fn riscv64_immediates_and_far_slots() {
+        let code = CodeGen::new(Riscv64).gen_fragment(&ir::lower_fragment(&check("📄1️⃣8️⃣4️⃣4️⃣6️⃣7️⃣4️⃣4️⃣0️⃣7️⃣3️⃣7️⃣0️⃣9️⃣5️⃣5️⃣1️⃣6️⃣1️⃣5️⃣🔚")));
+        assert!(code.starts_with("# print v0\n\t\tli a0, -1\n"));
+        assert_eq!(super::Target::load(&Riscv64, super::Var::Local(-4096)),
+                   "\t\tli t0, -4096\n\t\tadd t0, s0, t0\n\t\tld a0, 0(t0)\n");
+        assert_eq!(super::Target::enter(&Riscv64, 4096),
+                   "\t\taddi sp, sp, -16\n\t\tsd ra, 8(sp)\n\t\tsd s0, 0(sp)\n\t\tmv s0, sp\n\
+                    \t\tli t0, 4096\n\t\tsub sp, sp, t0\n");
+    }
+
+    #[test]
+    fn debug_info_locates_code_and_variables() {
+        fn debug<T: Target + Copy>(target: T, triple: &str, features: Option<&str>, frame_register: &str) {
+            let program = "🆕🔢😀🔚\n🤪🔢🦊🌜🔢🐾🌛 🌘\n    ↩️🐾➕1️⃣🔚\n🌒\n😀⬅️📞🦊🌜2️⃣🌛🔚\n📄😀🔚";
+            let tokens = Tokenizer::new(String::from(program)).tokenize();
+            let mut ast = Parser::new(&tokens).parse();
+            Checker::new(&tokens).check(&mut ast);
+            let positions = tokens.iter().map(|token| (token.line, token.column)).collect();
+            let code = CodeGen::new(target).with_debug_info("/tmp/d\"ebug.moo", positions).gen(&ir::lower_debug(&ast));
+            assert!(code.starts_with("\t\t.file 1 \"/tmp/d\\\"ebug.moo\"\n"));
+            // the return, the assignment and the print
+            for line in &["\t\t.loc 1 3 ", "\t\t.loc 1 5 1\n", "\t\t.loc 1 6 1\n"] {
+                assert!(code.contains(line), "no {} in\n{}", line.trim(), code);
+            }
+            assert!(code.contains("\t\t.string \"😀\"\n\t\t.long .Ldebug_type_int - .Ldebug_info0\n\
+                                   \t\t.uleb128 9\n\t\t.byte 0x3\n"));
+            assert!(code.contains(&format!("\t\t.uleb128 2\n\t\t.byte {}, 0\n", frame_register)));
+            assert!(code.contains("\t\t.uleb128 6\n\t\t.string \"🐾\"\n"));
+
+            // llvm-mc takes no emoji symbols, which only x86-64 code has
+            let features = match features {
+                Some(features) => features,
+                None => return,
+            };
+            let path = env::temp_dir().join("emooji_tests").join(format!("{}_debug.s", triple));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, code).unwrap();
+            let status = Command::new("llvm-mc").arg(format!("-triple={}", triple))
+                .arg(format!("-mattr={}", features))
+                .args(["-filetype=obj", "-o", "/dev/null"]).arg(&path).status();
+            if let Ok(status) = status {
+                assert!(status.success(), "{} does not assemble", path.display());
+            }
+        }
+        debug(X86_64, "x86_64-linux-gnu", None, "0x76");
+        debug(Aarch64, "aarch64-linux-gnu", Some(""), "0x8d");
+        debug(Riscv64, "riscv64-linux-gnu", Some("+m,+a,+f,+d,+c"), "0x78");
+    }
+
+    // The instructions in `code`, leaving out labels, directives and data
+    fn instructions(code: &str) -> usize {
+        code.lines().filter(|line| {
+            let word = line.split_whitespace().next().unwrap_or("");
+            line.starts_with('\t') && !word.starts_with('.') && !word.ends_with(':')
+        }).count()
+    }
+
+    // Static instruction counts of the IR code generator with every value on
+    // the stack and with registers allocated, not instructions executed. Run
+    // with --nocapture (make bench) to see the table
+    #[test]
+    fn register_allocation_benchmark() {
+        fn count<T: Target + Copy>(target: T, arch: &str, name: &str, program: &str) {
+            let module = ir::lower(&check(program));
+            let runtime = instructions(&target.runtime());
+            let before = instructions(&CodeGen::new(target).without_registers().gen(&module)) - runtime;
+            let after = instructions(&CodeGen::new(target).gen(&module)) - runtime;
+            println!("{:<12}{:<10}{:>8}{:>8}{:>7}%", name, arch, before, after,
+                     100 - after * 100 / before);
+            assert!(after * 10 < before * 9, "{} on {}: {} instructions, {} before", name, arch, after, before);
+        }
+        println!("\n{:<12}{:<10}{:>8}{:>8}{:>8}", "program", "target", "stack", "regs", "saved");
+        for &(name, program) in BENCHMARKS.iter() {
+            count(X86_64, "x86_64", name, program);
+            count(Aarch64, "aarch64", name, program);
+            count(Riscv64, "riscv64", name, program);
+        }
+    }
+}
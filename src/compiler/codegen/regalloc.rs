@@ -0,0 +1,209 @@
+use super::super::tokenizer::VarType;
+use super::super::ir::{Function, Op, Value};
+
+// Linear scan register allocation, after "Linear Scan Register Allocation"
+// by Poletto and Sarkar. Instructions are numbered in block order, every
+// value gets one interval from its definition to the last point it is live,
+// and walking the intervals by start, each takes a register no interval still
+// running holds. When none is left, whichever interval ends last goes to a
+// slot in the frame instead.
+//
+// Calls may change `temporaries`, so only values that aren't live across a
+// call get those; the others need one of the `saved` registers, which calls
+// preserve. Constants aren't allocated, they are loaded where they are used.
+// Returns the register of every value, None for the rest.
+pub fn allocate(func: &Function, saved: &[&'static str], temporaries: &[&'static str])
+                -> Vec<Option<&'static str>> {
+    let mut intervals = intervals(func);
+    intervals.sort_by_key(|interval| interval.start);
+    let registers: Vec<&'static str> = temporaries.iter().chain(saved.iter()).cloned().collect();
+    let mut free = vec![true; registers.len()];
+    let mut result = vec![None; func.types.len()];
+    // the intervals holding a register, and its index
+    let mut active: Vec<(Interval, usize)> = Vec::new();
+
+    for interval in intervals {
+        // an interval ending where this one starts is done reading its value
+        active.retain(|&(ref other, reg)| {
+            if other.end <= interval.start {
+                free[reg] = true;
+                false
+            } else {
+                true
+            }
+        });
+        let crosses_call = interval.crosses_call;
+        let allowed = |reg: usize| reg >= temporaries.len() || !crosses_call;
+        if let Some(reg) = (0..registers.len()).find(|&reg| free[reg] && allowed(reg)) {
+            free[reg] = false;
+            result[interval.value.0] = Some(registers[reg]);
+            active.push((interval, reg));
+            continue;
+        }
+        // spill whichever ends last, this one or one holding a register it
+        // could have
+        let victim = active.iter().enumerate()
+            .filter(|&(_, &(_, reg))| allowed(reg))
+            .max_by_key(|&(_, active)| active.0.end)
+            .map(|(i, _)| i);
+        if let Some(i) = victim {
+            if active[i].0.end > interval.end {
+                let (spilled, reg) = active.swap_remove(i);
+                result[spilled.value.0] = None;
+                result[interval.value.0] = Some(registers[reg]);
+                active.push((interval, reg));
+            }
+        }
+    }
+    result
+}
+
+#[derive(Debug)]
+struct Interval {
+    value: Value,
+    start: usize,
+    end: usize,
+    crosses_call: bool
+}
+
+// Whether the code for `op` calls a function, which may change temporaries
+fn calls(func: &Function, op: &Op, result_type: VarType) -> bool {
+    match *op {
+        Op::Call(..) | Op::Builtin(..) | Op::Print(_) | Op::PrintChar(_) => true,
+        // int_str_ and bool_str_
+        Op::Convert(_) => result_type == VarType::STR,
+        // concat_ and str_eq_
+        Op::Binary(_, lhs, _) => func.types[lhs.0] == VarType::STR,
+        _ => false,
+    }
+}
+
+// The live interval of every value that isn't a constant. Each block starts
+// with a position for its phis, then one for every other instruction and one
+// for the terminator, which is also where the phis of its successors read
+// their operands.
+fn intervals(func: &Function) -> Vec<Interval> {
+    let count = func.blocks.len();
+    let mut block_start = vec![0; count];
+    let mut block_end = vec![0; count];
+    let mut def = vec![None; func.types.len()];
+    let mut last_use = vec![0; func.types.len()];
+    let mut call_positions = Vec::new();
+    let mut pos = 0;
+    for (i, block) in func.blocks.iter().enumerate() {
+        block_start[i] = pos;
+        for inst in &block.insts {
+            if let Op::Phi(_) = inst.op {
+                def[inst.value.unwrap().0] = Some(block_start[i]);
+                continue;
+            }
+            pos += 1;
+            for value in inst.op.operands() {
+                last_use[value.0] = pos;
+            }
+            let result_type = inst.value.map_or(VarType::NONE, |value| func.types[value.0]);
+            if calls(func, &inst.op, result_type) {
+                call_positions.push(pos);
+            }
+            match (inst.value, &inst.op) {
+                (_, &Op::Const(_)) | (_, &Op::Str(_)) | (None, _) => {},
+                (Some(value), _) => def[value.0] = Some(pos),
+            }
+        }
+        pos += 1;
+        block_end[i] = pos;
+        for value in block.term.operands() {
+            last_use[value.0] = pos;
+        }
+        pos += 1;
+    }
+
+    // phi operands are read at the end of the block they come from
+    let mut phi_uses = vec![Vec::new(); count];
+    for block in &func.blocks {
+        for inst in &block.insts {
+            if let Op::Phi(ref incoming) = inst.op {
+                for &(pred, value) in incoming {
+                    phi_uses[pred.0].push(value);
+                    last_use[value.0] = last_use[value.0].max(block_end[pred.0]);
+                }
+            }
+        }
+    }
+
+    let (live_in, live_out) = liveness(func, &phi_uses);
+    let mut intervals = Vec::new();
+    for (index, &def) in def.iter().enumerate() {
+        let start = match def {
+            Some(start) => start,
+            None => continue,
+        };
+        let value = Value(index);
+        let mut interval = Interval { value, start, end: last_use[index].max(start), crosses_call: false };
+        for block in 0..count {
+            if live_in[block][index] {
+                interval.start = interval.start.min(block_start[block]);
+                interval.end = interval.end.max(block_start[block]);
+            }
+            if live_out[block][index] {
+                interval.end = interval.end.max(block_end[block]);
+            }
+        }
+        interval.crosses_call = call_positions.iter().any(|&call| interval.start < call && call < interval.end);
+        intervals.push(interval);
+    }
+    intervals
+}
+
+// The values live at the start and at the end of every block
+fn liveness(func: &Function, phi_uses: &[Vec<Value>]) -> (Vec<Vec<bool>>, Vec<Vec<bool>>) {
+    let count = func.blocks.len();
+    let values = func.types.len();
+    // values read before being defined in the block, and defined in it
+    let mut uses = vec![vec![false; values]; count];
+    let mut defs = vec![vec![false; values]; count];
+    for (i, block) in func.blocks.iter().enumerate() {
+        for inst in &block.insts {
+            if !matches!(inst.op, Op::Phi(_)) {
+                for value in inst.op.operands() {
+                    if !defs[i][value.0] {
+                        uses[i][value.0] = true;
+                    }
+                }
+            }
+            if let Some(value) = inst.value {
+                defs[i][value.0] = true;
+            }
+        }
+        for value in block.term.operands() {
+            if !defs[i][value.0] {
+                uses[i][value.0] = true;
+            }
+        }
+    }
+
+    let mut live_in = vec![vec![false; values]; count];
+    let mut live_out = vec![vec![false; values]; count];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..count).rev() {
+            let mut out = vec![false; values];
+            for succ in func.blocks[i].term.successors() {
+                for value in 0..values {
+                    out[value] |= live_in[succ.0][value];
+                }
+            }
+            for value in &phi_uses[i] {
+                out[value.0] = true;
+            }
+            let live: Vec<bool> = (0..values).map(|value| uses[i][value] || (out[value] && !defs[i][value])).collect();
+            if out != live_out[i] || live != live_in[i] {
+                live_out[i] = out;
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    (live_in, live_out)
+}
@@ -0,0 +1,249 @@
+mod runtime;
+use super::{Target, Var, ascii_symbol};
+use super::super::tokenizer::VarType;
+use super::super::ast::BinOp;
+
+// RISC-V 64 (RV64GC) for Linux, in GNU assembler syntax. The accumulator is
+// a0, the left operand of a binary operator is in t1 unless it has a
+// register of its own, t0 is scratch and s0 is the frame pointer. Values are
+// allocated s1 to s11, which calls preserve, and t2 to t6. The stack pointer
+// stays 16 byte aligned, so every push takes 16 bytes.
+//
+// Symbols are ASCII names derived from the emoji, like on AArch64, and
+// addresses are PC-relative so the result links into position independent
+// executables.
+#[derive(Copy, Clone)]
+pub struct Riscv64;
+
+// Conditional branches only reach 4 KiB, so branches to labels that may be
+// further away jump over an unconditional jump instead
+fn branch_if_zero(reg: &str, label: &str) -> String {
+    format!("\t\tbnez {}, 1f\n\
+             \t\tj {}\n\
+             1:\n", reg, label)
+}
+
+// A load or store of `reg` at `offset` from the frame pointer. Immediate
+// offsets reach 2 KiB, further slots need the address computed in t0.
+fn slot(store: bool, reg: &str, offset: i64) -> String {
+    let op = if store { "sd" } else { "ld" };
+    if offset >= -2048 {
+        format!("\t\t{} {}, {}(s0)\n", op, reg, offset)
+    } else {
+        format!("\t\tli t0, {}\n\
+                 \t\tadd t0, s0, t0\n\
+                 \t\t{} {}, 0(t0)\n", offset, op, reg)
+    }
+}
+
+// A load or store of `reg` at a data symbol
+fn global(store: bool, reg: &str, symbol: &str) -> String {
+    let op = if store { "sd" } else { "ld" };
+    format!("\t\tlla t0, {}\n\
+             \t\t{} {}, 0(t0)\n", symbol, op, reg)
+}
+
+impl Target for Riscv64 {
+    fn main_prologue(&self, frame_size: usize) -> String {
+        let mut content = String::from("\n\n.text\n\
+                                        .global main\n\
+                                        main:\n");
+        content.push_str(&self.enter(frame_size));
+        content.push_str(&global(true, "a0", "argc_"));
+        content.push_str(&global(true, "a1", "argv_"));
+        content.push_str("\t\tli a0, 0\n\
+                          \t\tcall time\n\
+                          \t\tcall srand\n");
+        content
+    }
+
+    fn main_epilogue(&self) -> String {
+        format!("\t\tli a0, 0\n{}", self.leave())
+    }
+
+    fn runtime(&self) -> String {
+        self.gen_runtime()
+    }
+
+    fn runtime_data(&self) -> String {
+        self.gen_runtime_data()
+    }
+
+    fn symbol(&self, id: &str) -> String {
+        ascii_symbol(id)
+    }
+
+    fn data_quad(&self, name: &str, value: &str) -> String {
+        format!("\t\t.balign 8\n\
+                 \t\t{}: .quad {}\n", name, value)
+    }
+
+    fn data_string(&self, name: &str, value: &str) -> String {
+        format!("\t\t{}: .string \"{}\"\n", name, value)
+    }
+
+    fn comment(&self, text: &str) -> String {
+        format!("# {}\n", text)
+    }
+
+    // li takes signed immediates, which wrap to the same 64 bits
+    fn load_int(&self, value: u64) -> String {
+        format!("\t\tli a0, {}\n", value as i64)
+    }
+
+    fn load_address(&self, label: &str) -> String {
+        format!("\t\tlla a0, {}\n", label)
+    }
+
+    fn load(&self, var: Var) -> String {
+        match var {
+            Var::Global(symbol) => global(false, "a0", symbol),
+            Var::Local(offset) => slot(false, "a0", offset),
+            Var::Reg(reg) => format!("\t\tmv a0, {}\n", reg),
+        }
+    }
+
+    fn store(&self, var: Var) -> String {
+        match var {
+            Var::Global(symbol) => global(true, "a0", symbol),
+            Var::Local(offset) => slot(true, "a0", offset),
+            Var::Reg(reg) => format!("\t\tmv {}, a0\n", reg),
+        }
+    }
+
+    fn push(&self) -> String {
+        String::from("\t\taddi sp, sp, -16\n\
+                      \t\tsd a0, 0(sp)\n")
+    }
+
+    fn pop(&self) -> String {
+        String::from("\t\tld a0, 0(sp)\n\
+                      \t\taddi sp, sp, 16\n")
+    }
+
+    fn pop_arg(&self, index: usize) -> String {
+        format!("\t\tld a{}, 0(sp)\n\
+                 \t\taddi sp, sp, 16\n", index)
+    }
+
+    fn saved_registers(&self) -> &'static [&'static str] {
+        &["s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11"]
+    }
+
+    fn temporary_registers(&self) -> &'static [&'static str] {
+        &["t2", "t3", "t4", "t5", "t6"]
+    }
+
+    fn operand_register(&self) -> &'static str {
+        "t1"
+    }
+
+    fn save(&self, reg: &str, offset: i64) -> String {
+        slot(true, reg, offset)
+    }
+
+    fn restore(&self, reg: &str, offset: i64) -> String {
+        slot(false, reg, offset)
+    }
+
+    fn jump(&self, label: &str) -> String {
+        format!("\t\tj {}\n", label)
+    }
+
+    fn jump_if_zero(&self, label: &str) -> String {
+        branch_if_zero("a0", label)
+    }
+
+    fn call(&self, name: &str) -> String {
+        format!("\t\tcall {}\n", name)
+    }
+
+    // the accumulator is already the first argument
+    fn call_with(&self, name: &str) -> String {
+        self.call(name)
+    }
+
+    fn call_var(&self, var: Var) -> String {
+        let load = match var {
+            Var::Global(symbol) => global(false, "t0", symbol),
+            Var::Local(offset) => slot(false, "t0", offset),
+            Var::Reg(reg) => return format!("\t\tjalr {}\n", reg),
+        };
+        format!("{}\t\tjalr t0\n", load)
+    }
+
+    fn print_char(&self, ch: char) -> String {
+        format!("\t\tli a0, {}\n\
+                 \t\tcall print_char_\n", ch as u32)
+    }
+
+    fn binary(&self, op: BinOp, lhs: &str, operand_type: VarType, result_type: VarType) -> String {
+        match op {
+            BinOp::Add => match result_type {
+                VarType::STR => format!("\t\tmv a1, a0\n\
+                                         \t\tmv a0, {}\n\
+                                         \t\tcall concat_\n", lhs),
+                // ➕ on booleans is "or"
+                VarType::BOOL => format!("\t\tor a0, {}, a0\n", lhs),
+                _ => format!("\t\tadd a0, {}, a0\n", lhs),
+            },
+            BinOp::Sub => format!("\t\tsub a0, {}, a0\n", lhs),
+            BinOp::Mul => match result_type {
+                // ✖️ on booleans is "and"
+                VarType::BOOL => format!("\t\tand a0, {}, a0\n", lhs),
+                _ => format!("\t\tmul a0, {}, a0\n", lhs),
+            },
+            BinOp::Div => format!("{}\t\tdivu a0, {}, a0\n", branch_if_zero("a0", "div_zero_error_"), lhs),
+            BinOp::Eq => match operand_type {
+                VarType::STR => format!("\t\tmv a1, a0\n\
+                                         \t\tmv a0, {}\n\
+                                         \t\tcall str_eq_\n", lhs),
+                _ => format!("\t\txor a0, {}, a0\n\
+                              \t\tseqz a0, a0\n", lhs),
+            },
+        }
+    }
+
+    fn not(&self) -> String {
+        String::from("\t\tseqz a0, a0\n")
+    }
+
+    fn to_bool(&self) -> String {
+        String::from("\t\tsnez a0, a0\n")
+    }
+
+    fn enter(&self, frame_size: usize) -> String {
+        let mut content = String::from("\t\taddi sp, sp, -16\n\
+                                        \t\tsd ra, 8(sp)\n\
+                                        \t\tsd s0, 0(sp)\n\
+                                        \t\tmv s0, sp\n");
+        if frame_size > 2048 {
+            content.push_str(&format!("\t\tli t0, {}\n\
+                                       \t\tsub sp, sp, t0\n", frame_size));
+        } else if frame_size > 0 {
+            content.push_str(&format!("\t\taddi sp, sp, -{}\n", frame_size));
+        }
+        content
+    }
+
+    fn store_arg(&self, index: usize, var: Var) -> String {
+        match var {
+            Var::Reg(reg) => format!("\t\tmv {}, a{}\n", reg, index),
+            Var::Local(offset) => slot(true, &format!("a{}", index), offset),
+            Var::Global(_) => panic!("Parameters are never global"),
+        }
+    }
+
+    fn leave(&self) -> String {
+        String::from("\t\tmv sp, s0\n\
+                      \t\tld ra, 8(sp)\n\
+                      \t\tld s0, 0(sp)\n\
+                      \t\taddi sp, sp, 16\n\
+                      \t\tret\n")
+    }
+
+    // s0
+    fn dwarf_frame_register(&self) -> u8 {
+        8
+    }
+}
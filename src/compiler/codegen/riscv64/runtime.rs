@@ -0,0 +1,358 @@
+use super::Riscv64;
+
+// Loads the C stream `name` (stdin or stderr) into `reg` through the GOT.
+// `label` names the auipc for the %pcrel_lo that pairs with it.
+fn stream(reg: &str, name: &str, label: &str) -> String {
+    format!("{}:\n\
+             \t\tauipc {}, %got_pcrel_hi({})\n\
+             \t\tld {}, %pcrel_lo({})({})\n\
+             \t\tld {}, 0({})\n", label, reg, name, reg, label, reg, reg, reg)
+}
+
+impl Riscv64 {
+    // Constants used by the runtime helpers
+    pub fn gen_runtime_data(&self) -> String {
+        String::from("\t\t.balign 8\n\
+                      \t\targc_: .quad 0\n\
+                      \t\targv_: .quad 0\n\
+                      \t\tFormat_ints: .string \"%lu\"\n\
+                      \t\tFormat_strings: .string \"%s\"\n\
+                      \t\tTrue_string: .string \"👍\"\n\
+                      \t\tFalse_string: .string \"👎\"\n\
+                      \t\tEmpty_string: .string \"\"\n\
+                      \t\tBounds_error: .string \"🚨 Runtime error: argument index out of bounds\\n\"\n\
+                      \t\tDiv_zero_error: .string \"🚨 Runtime error: division by zero\\n\"\n\
+                      \t\tMode_read: .string \"📖\"\n\
+                      \t\tMode_write: .string \"📝\"\n\
+                      \t\tMode_append: .string \"📎\"\n\
+                      \t\tFopen_read: .string \"r\"\n\
+                      \t\tFopen_write: .string \"w\"\n\
+                      \t\tFopen_append: .string \"a\"\n")
+    }
+
+    // Runtime helpers called from generated code. The stack is always
+    // aligned, so they call the C library directly. Helpers that make calls
+    // keep ra and their own values in a small frame.
+    //
+    // Runtime errors print a message to stderr and exit with their own code:
+    //   3 - argument index out of bounds
+    //   4 - division by zero
+    pub fn gen_runtime(&self) -> String {
+        let mut content = String::new();
+        // exit_: flush output and exit with status a0
+        content.push_str("exit_:\n\
+                          \t\tcall exit\n");
+
+        content.push_str("bounds_error_:\n\
+                          \t\tlla a0, Bounds_error\n");
+        content.push_str(&stream("a1", "stderr", "bounds_error_stderr_"));
+        content.push_str("\t\tcall fputs\n\
+                          \t\tli a0, 3\n\
+                          \t\tcall exit\n");
+
+        content.push_str("div_zero_error_:\n\
+                          \t\tlla a0, Div_zero_error\n");
+        content.push_str(&stream("a1", "stderr", "div_zero_error_stderr_"));
+        content.push_str("\t\tcall fputs\n\
+                          \t\tli a0, 4\n\
+                          \t\tcall exit\n");
+
+        // read_line_: read a line from file a0 without its trailing newline,
+        // or the empty string at end of file. The buffer doubles until the
+        // whole line fits, and every call gets a fresh one so strings can be
+        // kept.
+        content.push_str("read_line_:\n\
+                          \t\taddi sp, sp, -48\n\
+                          \t\tsd ra, 40(sp)\n\
+                          \t\tbeqz a0, read_line_empty_\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tli a0, 4096\n\
+                          \t\tsd a0, 16(sp)\n\
+                          \t\tsd zero, 24(sp)\n\
+                          \t\tcall malloc\n\
+                          \t\tsd a0, 8(sp)\n\
+                          read_line_more_:\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tld a1, 16(sp)\n\
+                          \t\tld a3, 24(sp)\n\
+                          \t\tadd a0, a0, a3\n\
+                          \t\tsub a1, a1, a3\n\
+                          \t\tld a2, 0(sp)\n\
+                          \t\tcall fgets\n\
+                          \t\tbeqz a0, read_line_end_\n\
+                          \t\tcall strlen\n\
+                          \t\tld a3, 24(sp)\n\
+                          \t\tadd a3, a3, a0\n\
+                          \t\tsd a3, 24(sp)\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tbeqz a3, read_line_done_\n\
+                          \t\tadd a2, a0, a3\n\
+                          \t\tlbu a4, -1(a2)\n\
+                          \t\tli a5, 10\n\
+                          \t\tbeq a4, a5, read_line_newline_\n\
+                          \t\tld a1, 16(sp)\n\
+                          \t\taddi a4, a1, -1\n\
+                          \t\tbne a3, a4, read_line_done_\n\
+                          \t\tslli a1, a1, 1\n\
+                          \t\tsd a1, 16(sp)\n\
+                          \t\tcall realloc\n\
+                          \t\tsd a0, 8(sp)\n\
+                          \t\tj read_line_more_\n\
+                          read_line_newline_:\n\
+                          \t\tsb zero, -1(a2)\n\
+                          read_line_done_:\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tld ra, 40(sp)\n\
+                          \t\taddi sp, sp, 48\n\
+                          \t\tret\n\
+                          read_line_end_:\n\
+                          \t\tld a3, 24(sp)\n\
+                          \t\tbnez a3, read_line_done_\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tcall free\n\
+                          read_line_empty_:\n\
+                          \t\tlla a0, Empty_string\n\
+                          \t\tld ra, 40(sp)\n\
+                          \t\taddi sp, sp, 48\n\
+                          \t\tret\n");
+
+        // read_str_: read a line from stdin
+        content.push_str("read_str_:\n");
+        content.push_str(&stream("a0", "stdin", "read_str_stdin_"));
+        content.push_str("\t\tj read_line_\n");
+
+        // read_int_: read a line from stdin and parse it as a number
+        content.push_str("read_int_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tcall read_str_\n\
+                          \t\tcall parse_int_\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n");
+
+        // parse_int_: string in a0 to number, 0 if it doesn't start with digits
+        content.push_str("parse_int_:\n\
+                          \t\tli a1, 0\n\
+                          \t\tli a2, 10\n\
+                          \t\ttail strtol\n");
+
+        // concat_: new string with a1 appended to a0
+        content.push_str("concat_:\n\
+                          \t\taddi sp, sp, -32\n\
+                          \t\tsd ra, 24(sp)\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tsd a1, 8(sp)\n\
+                          \t\tcall strlen\n\
+                          \t\tsd a0, 16(sp)\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tcall strlen\n\
+                          \t\tld a1, 16(sp)\n\
+                          \t\tadd a0, a0, a1\n\
+                          \t\taddi a0, a0, 1\n\
+                          \t\tcall malloc\n\
+                          \t\tsd a0, 16(sp)\n\
+                          \t\tld a1, 0(sp)\n\
+                          \t\tcall strcpy\n\
+                          \t\tld a0, 16(sp)\n\
+                          \t\tld a1, 8(sp)\n\
+                          \t\tcall strcat\n\
+                          \t\tld a0, 16(sp)\n\
+                          \t\tld ra, 24(sp)\n\
+                          \t\taddi sp, sp, 32\n\
+                          \t\tret\n");
+
+        // int_str_: number a0 as a new string
+        content.push_str("int_str_:\n\
+                          \t\taddi sp, sp, -32\n\
+                          \t\tsd ra, 24(sp)\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tli a0, 21\n\
+                          \t\tcall malloc\n\
+                          \t\tsd a0, 8(sp)\n\
+                          \t\tlla a1, Format_ints\n\
+                          \t\tld a2, 0(sp)\n\
+                          \t\tcall sprintf\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tld ra, 24(sp)\n\
+                          \t\taddi sp, sp, 32\n\
+                          \t\tret\n");
+
+        // bool_str_: 👍 or 👎 for boolean a0
+        content.push_str("bool_str_:\n\
+                          \t\tmv a1, a0\n\
+                          \t\tlla a0, True_string\n\
+                          \t\tbnez a1, bool_str_done_\n\
+                          \t\tlla a0, False_string\n\
+                          bool_str_done_:\n\
+                          \t\tret\n");
+
+        // print_int_, print_str_, print_bool_, print_char_: print a0 without a newline
+        content.push_str("print_int_:\n\
+                          \t\tmv a1, a0\n\
+                          \t\tlla a0, Format_ints\n\
+                          \t\ttail printf\n");
+
+        content.push_str("print_str_:\n\
+                          \t\tmv a1, a0\n\
+                          \t\tlla a0, Format_strings\n\
+                          \t\ttail printf\n");
+
+        content.push_str("print_bool_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tcall bool_str_\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tj print_str_\n");
+
+        content.push_str("print_char_:\n\
+                          \t\ttail putchar\n");
+
+        // arg_count_: number of command-line arguments, not counting the program
+        content.push_str("arg_count_:\n\
+                          \t\tlla t0, argc_\n\
+                          \t\tld a0, 0(t0)\n\
+                          \t\taddi a0, a0, -1\n\
+                          \t\tret\n");
+
+        // arg_: argument a0 (0 is the first one after the program name)
+        content.push_str("arg_:\n\
+                          \t\tlla t0, argc_\n\
+                          \t\tld a1, 0(t0)\n\
+                          \t\taddi a1, a1, -1\n\
+                          \t\tbgeu a0, a1, bounds_error_\n\
+                          \t\tlla t0, argv_\n\
+                          \t\tld a1, 0(t0)\n\
+                          \t\tslli a0, a0, 3\n\
+                          \t\tadd a1, a1, a0\n\
+                          \t\tld a0, 8(a1)\n\
+                          \t\tret\n");
+
+        // file_eof_: 1 if file a0 has no more input, peeking one character ahead
+        content.push_str("file_eof_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tbeqz a0, file_eof_true_\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tcall fgetc\n\
+                          \t\tli t0, -1\n\
+                          \t\tbeq a0, t0, file_eof_true_\n\
+                          \t\tld a1, 0(sp)\n\
+                          \t\tcall ungetc\n\
+                          \t\tli a0, 0\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n\
+                          file_eof_true_:\n\
+                          \t\tli a0, 1\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n");
+
+        // eof_: 1 if stdin has no more input
+        content.push_str("eof_:\n");
+        content.push_str(&stream("a0", "stdin", "eof_stdin_"));
+        content.push_str("\t\tj file_eof_\n");
+
+        // file_open_: open path a0 with mode a1 (💬📖💬 read, 💬📝💬 write,
+        // 💬📎💬 append). Returns 0 if the mode is unknown or fopen fails.
+        content.push_str("file_open_:\n\
+                          \t\taddi sp, sp, -32\n\
+                          \t\tsd ra, 24(sp)\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tsd a1, 8(sp)\n\
+                          \t\tmv a0, a1\n\
+                          \t\tlla a1, Mode_read\n\
+                          \t\tcall strcmp\n\
+                          \t\tlla a1, Fopen_read\n\
+                          \t\tbeqz a0, file_open_mode_\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tlla a1, Mode_write\n\
+                          \t\tcall strcmp\n\
+                          \t\tlla a1, Fopen_write\n\
+                          \t\tbeqz a0, file_open_mode_\n\
+                          \t\tld a0, 8(sp)\n\
+                          \t\tlla a1, Mode_append\n\
+                          \t\tcall strcmp\n\
+                          \t\tlla a1, Fopen_append\n\
+                          \t\tbeqz a0, file_open_mode_\n\
+                          \t\tli a0, 0\n\
+                          \t\tld ra, 24(sp)\n\
+                          \t\taddi sp, sp, 32\n\
+                          \t\tret\n\
+                          file_open_mode_:\n\
+                          \t\tld a0, 0(sp)\n\
+                          \t\tcall fopen\n\
+                          \t\tld ra, 24(sp)\n\
+                          \t\taddi sp, sp, 32\n\
+                          \t\tret\n");
+
+        // file_write_: write string a1 and a newline to file a0, 1 on success
+        content.push_str("file_write_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tbeqz a0, file_write_failed_\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tmv t0, a0\n\
+                          \t\tmv a0, a1\n\
+                          \t\tmv a1, t0\n\
+                          \t\tcall fputs\n\
+                          \t\tbltz a0, file_write_failed_\n\
+                          \t\tli a0, 10\n\
+                          \t\tld a1, 0(sp)\n\
+                          \t\tcall fputc\n\
+                          \t\tli t0, -1\n\
+                          \t\tbeq a0, t0, file_write_failed_\n\
+                          \t\tli a0, 1\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n\
+                          file_write_failed_:\n\
+                          \t\tli a0, 0\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n");
+
+        // file_close_: close file a0, 1 on success
+        content.push_str("file_close_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tbeqz a0, file_close_failed_\n\
+                          \t\tcall fclose\n\
+                          \t\tbnez a0, file_close_failed_\n\
+                          \t\tli a0, 1\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n\
+                          file_close_failed_:\n\
+                          \t\tli a0, 0\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n");
+
+        // str_eq_: 1 if strings a0 and a1 have the same contents
+        content.push_str("str_eq_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tcall strcmp\n\
+                          \t\tseqz a0, a0\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n");
+
+        // rand_: random number below a0
+        content.push_str("rand_:\n\
+                          \t\taddi sp, sp, -16\n\
+                          \t\tsd ra, 8(sp)\n\
+                          \t\tbeqz a0, div_zero_error_\n\
+                          \t\tsd a0, 0(sp)\n\
+                          \t\tcall rand\n\
+                          \t\tld a1, 0(sp)\n\
+                          \t\tremu a0, a0, a1\n\
+                          \t\tld ra, 8(sp)\n\
+                          \t\taddi sp, sp, 16\n\
+                          \t\tret\n");
+        content
+    }
+}
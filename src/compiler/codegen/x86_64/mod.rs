@@ -0,0 +1,221 @@
+mod runtime;
+use super::{Target, Var};
+use super::super::tokenizer::VarType;
+use super::super::ast::BinOp;
+
+// x86-64 in AT&T syntax. The accumulator is %rax, with %r15 used as scratch.
+// Values are allocated the registers the runtime leaves alone, %rbx and
+// %r12 to %r14, which it saves, and %r10 and %r11, which calls may change.
+// Symbols are the emoji names themselves, which the GNU assembler accepts.
+#[derive(Copy, Clone)]
+pub struct X86_64;
+
+// Where arguments go, in the order of the System V calling convention
+const ARG_REGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+fn operand(var: Var) -> String {
+    match var {
+        Var::Global(symbol) => symbol.to_string(),
+        Var::Local(offset) => format!("{}(%rbp)", offset),
+        Var::Reg(reg) => reg.to_string(),
+    }
+}
+
+impl Target for X86_64 {
+    fn main_prologue(&self, frame_size: usize) -> String {
+        let mut content = String::from("\n\n.text\n\
+                                        .global main\n");
+        content.push_str(&self.gen_runtime_externs());
+        content.push_str("main:\n");
+        content.push_str(&self.enter(frame_size));
+        content.push_str("\t\tmovq %rdi, argc_\n\
+                          \t\tmovq %rsi, argv_\n\
+                          \t\tmovq $16000, %rdi\n\
+                          \t\tcall malloc\n\
+                          \t\tmovq %rax, FuncTable\n\
+                          \t\tmovq $0, %rdi\n\
+                          \t\tcall time\n\
+                          \t\tmovq %rax, %rdi\n\
+                          \t\tcall srand\n");
+        content
+    }
+
+    fn main_epilogue(&self) -> String {
+        String::from("\t\tmovq $0, %rax\n\
+                      \t\tleave\n\
+                      \t\tretq\n")
+    }
+
+    fn runtime(&self) -> String {
+        self.gen_runtime()
+    }
+
+    fn runtime_data(&self) -> String {
+        self.gen_runtime_data()
+    }
+
+    fn symbol(&self, id: &str) -> String {
+        id.to_string()
+    }
+
+    fn data_quad(&self, name: &str, value: &str) -> String {
+        format!("\t\t{}: .quad {}\n", name, value)
+    }
+
+    fn data_string(&self, name: &str, value: &str) -> String {
+        format!("\t\t{}: .string \"{}\"\n", name, value)
+    }
+
+    fn comment(&self, text: &str) -> String {
+        format!("# {}\n", text)
+    }
+
+    fn load_int(&self, value: u64) -> String {
+        format!("\t\tmovq ${}, %rax\n", value)
+    }
+
+    fn load_address(&self, label: &str) -> String {
+        format!("\t\tmovq ${}, %rax\n", label)
+    }
+
+    fn load_code_address(&self, label: &str) -> String {
+        format!("\t\tlea {}, %rax\n", label)
+    }
+
+    fn load(&self, var: Var) -> String {
+        format!("\t\tmovq {}, %rax\n", operand(var))
+    }
+
+    fn store(&self, var: Var) -> String {
+        format!("\t\tmovq %rax, {}\n", operand(var))
+    }
+
+    fn push(&self) -> String {
+        String::from("\t\tpush %rax\n")
+    }
+
+    fn pop(&self) -> String {
+        String::from("\t\tpop %rax\n")
+    }
+
+    fn pop_arg(&self, index: usize) -> String {
+        format!("\t\tpop {}\n", ARG_REGS[index])
+    }
+
+    fn saved_registers(&self) -> &'static [&'static str] {
+        &["%rbx", "%r12", "%r13", "%r14"]
+    }
+
+    fn temporary_registers(&self) -> &'static [&'static str] {
+        &["%r10", "%r11"]
+    }
+
+    fn operand_register(&self) -> &'static str {
+        "%rcx"
+    }
+
+    fn save(&self, reg: &str, offset: i64) -> String {
+        format!("\t\tmovq {}, {}(%rbp)\n", reg, offset)
+    }
+
+    fn restore(&self, reg: &str, offset: i64) -> String {
+        format!("\t\tmovq {}(%rbp), {}\n", offset, reg)
+    }
+
+    fn jump(&self, label: &str) -> String {
+        format!("\t\tjmp {}\n", label)
+    }
+
+    fn jump_if_zero(&self, label: &str) -> String {
+        format!("\t\tcmp $0, %rax\n\
+                 \t\tje {}\n", label)
+    }
+
+    fn call(&self, name: &str) -> String {
+        format!("\t\tcall {}\n", name)
+    }
+
+    fn call_with(&self, name: &str) -> String {
+        format!("\t\tmovq %rax, %rdi\n\
+                 \t\tcall {}\n", name)
+    }
+
+    fn call_var(&self, var: Var) -> String {
+        format!("\t\tcall *{}\n", operand(var))
+    }
+
+    fn print_char(&self, ch: char) -> String {
+        format!("\t\tmovq ${}, %rdi\n\
+                 \t\tcall print_char_\n", ch as u32)
+    }
+
+    fn binary(&self, op: BinOp, lhs: &str, operand_type: VarType, result_type: VarType) -> String {
+        match op {
+            BinOp::Add => match result_type {
+                VarType::STR => format!("\t\tmovq %rax, %rsi\n\
+                                         \t\tmovq {}, %rdi\n\
+                                         \t\tcall concat_\n", lhs),
+                // ➕ on booleans is "or"
+                VarType::BOOL => format!("\t\torq {}, %rax\n", lhs),
+                _ => format!("\t\taddq {}, %rax\n", lhs),
+            },
+            BinOp::Sub => format!("\t\tmovq %rax, %r15\n\
+                                   \t\tmovq {}, %rax\n\
+                                   \t\tsubq %r15, %rax\n", lhs),
+            BinOp::Mul => match result_type {
+                // ✖️ on booleans is "and"
+                VarType::BOOL => format!("\t\tandq {}, %rax\n", lhs),
+                _ => format!("\t\tmul {}\n", lhs),
+            },
+            BinOp::Div => format!("\t\tmovq %rax, %r15\n\
+                                   \t\tmovq {}, %rax\n\
+                                   \t\tcmpq $0, %r15\n\
+                                   \t\tje div_zero_error_\n\
+                                   \t\txorq %rdx, %rdx\n\
+                                   \t\tdivq %r15\n", lhs),
+            BinOp::Eq => match operand_type {
+                VarType::STR => format!("\t\tmovq %rax, %rsi\n\
+                                         \t\tmovq {}, %rdi\n\
+                                         \t\tcall str_eq_\n", lhs),
+                _ => format!("\t\tsubq {}, %rax\n\
+                              \t\tsete %al\n\
+                              \t\tmovzbq %al, %rax\n", lhs),
+            },
+        }
+    }
+
+    fn not(&self) -> String {
+        String::from("\t\tcmpq $0, %rax\n\
+                      \t\tsete %al\n\
+                      \t\tmovzbq %al, %rax\n")
+    }
+
+    fn to_bool(&self) -> String {
+        String::from("\t\tcmpq $0, %rax\n\
+                      \t\tsetne %al\n\
+                      \t\tmovzbq %al, %rax\n")
+    }
+
+    fn enter(&self, frame_size: usize) -> String {
+        let mut content = String::from("\t\tpush %rbp\n\
+                                        \t\tmovq %rsp, %rbp\n");
+        if frame_size > 0 {
+            content.push_str(&format!("\t\tsubq ${}, %rsp\n", frame_size));
+        }
+        content
+    }
+
+    fn store_arg(&self, index: usize, var: Var) -> String {
+        format!("\t\tmovq {}, {}\n", ARG_REGS[index], operand(var))
+    }
+
+    fn leave(&self) -> String {
+        String::from("\t\tleave\n\
+                      \t\tret\n")
+    }
+
+    // %rbp
+    fn dwarf_frame_register(&self) -> u8 {
+        6
+    }
+}
@@ -0,0 +1,415 @@
+use super::X86_64;
+
+impl X86_64 {
+    // Constants used by the runtime helpers
+    pub fn gen_runtime_data(&self) -> String {
+        String::from("\t\targc_: .quad 0\n\
+                      \t\targv_: .quad 0\n\
+                      \t\tFormat_ints: .byte '%', 'l', 'u', 0\n\
+                      \t\tFormat_strings: .byte '%', 's', 0\n\
+                      \t\tTrue_string: .string \"👍\"\n\
+                      \t\tFalse_string: .string \"👎\"\n\
+                      \t\tEmpty_string: .string \"\"\n\
+                      \t\tBounds_error: .string \"🚨 Runtime error: argument index out of bounds\\n\"\n\
+                      \t\tDiv_zero_error: .string \"🚨 Runtime error: division by zero\\n\"\n\
+                      \t\tMode_read: .string \"📖\"\n\
+                      \t\tMode_write: .string \"📝\"\n\
+                      \t\tMode_append: .string \"📎\"\n\
+                      \t\tFopen_read: .string \"r\"\n\
+                      \t\tFopen_write: .string \"w\"\n\
+                      \t\tFopen_append: .string \"a\"\n\
+                      \t\tFuncTable: .quad 0\n\
+                      \t\tFuncCall: .quad 0\n")
+    }
+
+    // C library functions the runtime helpers call
+    pub fn gen_runtime_externs(&self) -> String {
+        String::from(".extern printf\n\
+                      .extern malloc\n\
+                      .extern realloc\n\
+                      .extern free\n\
+                      .extern strtol\n\
+                      .extern strlen\n\
+                      .extern strcmp\n\
+                      .extern strcpy\n\
+                      .extern strcat\n\
+                      .extern sprintf\n\
+                      .extern putchar\n\
+                      .extern fopen\n\
+                      .extern fgets\n\
+                      .extern fputc\n\
+                      .extern fclose\n\
+                      .extern fgetc\n\
+                      .extern ungetc\n\
+                      .extern stdin\n\
+                      .extern stderr\n\
+                      .extern fputs\n\
+                      .extern exit\n\
+                      .extern rand\n\
+                      .extern srand\n\
+                      .extern time\n")
+    }
+
+    // Runtime helpers called from generated code. Each one realigns the stack
+    // itself, since expression evaluation leaves %rsp at arbitrary offsets.
+    //
+    // Runtime errors print a message to stderr and exit with their own code:
+    //   3 - argument index out of bounds
+    //   4 - division by zero
+    pub fn gen_runtime(&self) -> String {
+        let mut content = String::new();
+        // exit_: flush output and exit with status %rdi
+        content.push_str("exit_:\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcall exit\n");
+
+        content.push_str("bounds_error_:\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq $Bounds_error, %rdi\n\
+                          \t\tmovq stderr, %rsi\n\
+                          \t\tcall fputs\n\
+                          \t\tmovq $3, %rdi\n\
+                          \t\tcall exit\n");
+
+        content.push_str("div_zero_error_:\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq $Div_zero_error, %rdi\n\
+                          \t\tmovq stderr, %rsi\n\
+                          \t\tcall fputs\n\
+                          \t\tmovq $4, %rdi\n\
+                          \t\tcall exit\n");
+
+        // read_line_: read a line from file %rdi without its trailing newline,
+        // or the empty string at end of file. The buffer doubles until the
+        // whole line fits, and every call gets a fresh one so strings can be
+        // kept.
+        content.push_str("read_line_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $32, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcmpq $0, %rdi\n\
+                          \t\tje read_line_empty_\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tmovq $4096, -24(%rbp)\n\
+                          \t\tmovq $0, -32(%rbp)\n\
+                          \t\tmovq $4096, %rdi\n\
+                          \t\tcall malloc\n\
+                          \t\tmovq %rax, -16(%rbp)\n\
+                          read_line_more_:\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\taddq -32(%rbp), %rdi\n\
+                          \t\tmovq -24(%rbp), %rsi\n\
+                          \t\tsubq -32(%rbp), %rsi\n\
+                          \t\tmovq -8(%rbp), %rdx\n\
+                          \t\tcall fgets\n\
+                          \t\tcmpq $0, %rax\n\
+                          \t\tje read_line_end_\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\taddq -32(%rbp), %rdi\n\
+                          \t\tcall strlen\n\
+                          \t\taddq %rax, -32(%rbp)\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\tmovq -32(%rbp), %rax\n\
+                          \t\tcmpq $0, %rax\n\
+                          \t\tje read_line_done_\n\
+                          \t\tcmpb $10, -1(%rdi,%rax)\n\
+                          \t\tje read_line_newline_\n\
+                          \t\tmovq -24(%rbp), %rcx\n\
+                          \t\tsubq $1, %rcx\n\
+                          \t\tcmpq %rcx, %rax\n\
+                          \t\tjne read_line_done_\n\
+                          \t\tshlq $1, -24(%rbp)\n\
+                          \t\tmovq -24(%rbp), %rsi\n\
+                          \t\tcall realloc\n\
+                          \t\tmovq %rax, -16(%rbp)\n\
+                          \t\tjmp read_line_more_\n\
+                          read_line_newline_:\n\
+                          \t\tmovb $0, -1(%rdi,%rax)\n\
+                          read_line_done_:\n\
+                          \t\tmovq -16(%rbp), %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n\
+                          read_line_end_:\n\
+                          \t\tcmpq $0, -32(%rbp)\n\
+                          \t\tjne read_line_done_\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\tcall free\n\
+                          read_line_empty_:\n\
+                          \t\tmovq $Empty_string, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // read_str_: read a line from stdin
+        content.push_str("read_str_:\n\
+                          \t\tmovq stdin, %rdi\n\
+                          \t\tjmp read_line_\n");
+
+        // read_int_: read a line from stdin and parse it as a number
+        content.push_str("read_int_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcall read_str_\n\
+                          \t\tmovq %rax, %rdi\n\
+                          \t\tcall parse_int_\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // parse_int_: string in %rdi to number, 0 if it doesn't start with digits
+        content.push_str("parse_int_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq $0, %rsi\n\
+                          \t\tmovq $10, %rdx\n\
+                          \t\tcall strtol\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // concat_: new string with %rsi appended to %rdi
+        content.push_str("concat_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $32, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tmovq %rsi, -16(%rbp)\n\
+                          \t\tcall strlen\n\
+                          \t\tmovq %rax, -24(%rbp)\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\tcall strlen\n\
+                          \t\taddq -24(%rbp), %rax\n\
+                          \t\tleaq 1(%rax), %rdi\n\
+                          \t\tcall malloc\n\
+                          \t\tmovq %rax, -24(%rbp)\n\
+                          \t\tmovq %rax, %rdi\n\
+                          \t\tmovq -8(%rbp), %rsi\n\
+                          \t\tcall strcpy\n\
+                          \t\tmovq -24(%rbp), %rdi\n\
+                          \t\tmovq -16(%rbp), %rsi\n\
+                          \t\tcall strcat\n\
+                          \t\tmovq -24(%rbp), %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // int_str_: number %rdi as a new string
+        content.push_str("int_str_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $16, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tmovq $21, %rdi\n\
+                          \t\tcall malloc\n\
+                          \t\tmovq %rax, -16(%rbp)\n\
+                          \t\tmovq %rax, %rdi\n\
+                          \t\tmovq $Format_ints, %rsi\n\
+                          \t\tmovq -8(%rbp), %rdx\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tcall sprintf\n\
+                          \t\tmovq -16(%rbp), %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // bool_str_: 👍 or 👎 for boolean %rdi
+        content.push_str("bool_str_:\n\
+                          \t\tmovq $True_string, %rax\n\
+                          \t\tcmpq $0, %rdi\n\
+                          \t\tjne bool_str_done_\n\
+                          \t\tmovq $False_string, %rax\n\
+                          bool_str_done_:\n\
+                          \t\tret\n");
+
+        // print_int_, print_str_, print_bool_, print_char_: print %rdi without a newline
+        content.push_str("print_int_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq %rdi, %rsi\n\
+                          \t\tmovq $Format_ints, %rdi\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tcall printf\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        content.push_str("print_str_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq %rdi, %rsi\n\
+                          \t\tmovq $Format_strings, %rdi\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tcall printf\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        content.push_str("print_bool_:\n\
+                          \t\tcall bool_str_\n\
+                          \t\tmovq %rax, %rdi\n\
+                          \t\tjmp print_str_\n");
+
+        content.push_str("print_char_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcall putchar\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // arg_count_: number of command-line arguments, not counting the program
+        content.push_str("arg_count_:\n\
+                          \t\tmovq argc_, %rax\n\
+                          \t\tsubq $1, %rax\n\
+                          \t\tret\n");
+
+        // arg_: argument %rdi (0 is the first one after the program name)
+        content.push_str("arg_:\n\
+                          \t\tmovq argc_, %rax\n\
+                          \t\tsubq $1, %rax\n\
+                          \t\tcmpq %rax, %rdi\n\
+                          \t\tjae bounds_error_\n\
+                          \t\tmovq argv_, %rax\n\
+                          \t\tmovq 8(%rax,%rdi,8), %rax\n\
+                          \t\tret\n");
+
+        // file_eof_: 1 if file %rdi has no more input, peeking one character ahead
+        content.push_str("file_eof_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $16, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcmpq $0, %rdi\n\
+                          \t\tje file_eof_true_\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tcall fgetc\n\
+                          \t\tcmpl $-1, %eax\n\
+                          \t\tje file_eof_true_\n\
+                          \t\tmovl %eax, %edi\n\
+                          \t\tmovq -8(%rbp), %rsi\n\
+                          \t\tcall ungetc\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n\
+                          file_eof_true_:\n\
+                          \t\tmovq $1, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // eof_: 1 if stdin has no more input
+        content.push_str("eof_:\n\
+                          \t\tmovq stdin, %rdi\n\
+                          \t\tjmp file_eof_\n");
+
+        // file_open_: open path %rdi with mode %rsi (💬📖💬 read, 💬📝💬 write,
+        // 💬📎💬 append). Returns 0 if the mode is unknown or fopen fails.
+        content.push_str("file_open_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $16, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tmovq %rsi, -16(%rbp)\n\
+                          \t\tmovq %rsi, %rdi\n\
+                          \t\tmovq $Mode_read, %rsi\n\
+                          \t\tcall strcmp\n\
+                          \t\tmovq $Fopen_read, %rsi\n\
+                          \t\tcmpl $0, %eax\n\
+                          \t\tje file_open_mode_\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\tmovq $Mode_write, %rsi\n\
+                          \t\tcall strcmp\n\
+                          \t\tmovq $Fopen_write, %rsi\n\
+                          \t\tcmpl $0, %eax\n\
+                          \t\tje file_open_mode_\n\
+                          \t\tmovq -16(%rbp), %rdi\n\
+                          \t\tmovq $Mode_append, %rsi\n\
+                          \t\tcall strcmp\n\
+                          \t\tmovq $Fopen_append, %rsi\n\
+                          \t\tcmpl $0, %eax\n\
+                          \t\tje file_open_mode_\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n\
+                          file_open_mode_:\n\
+                          \t\tmovq -8(%rbp), %rdi\n\
+                          \t\tcall fopen\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // file_write_: write string %rsi and a newline to file %rdi, 1 on success
+        content.push_str("file_write_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $16, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcmpq $0, %rdi\n\
+                          \t\tje file_write_failed_\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tmovq %rsi, %rdi\n\
+                          \t\tmovq -8(%rbp), %rsi\n\
+                          \t\tcall fputs\n\
+                          \t\tcmpl $0, %eax\n\
+                          \t\tjl file_write_failed_\n\
+                          \t\tmovl $10, %edi\n\
+                          \t\tmovq -8(%rbp), %rsi\n\
+                          \t\tcall fputc\n\
+                          \t\tcmpl $-1, %eax\n\
+                          \t\tje file_write_failed_\n\
+                          \t\tmovq $1, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n\
+                          file_write_failed_:\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // file_close_: close file %rdi, 1 on success
+        content.push_str("file_close_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcmpq $0, %rdi\n\
+                          \t\tje file_close_failed_\n\
+                          \t\tcall fclose\n\
+                          \t\tcmpl $0, %eax\n\
+                          \t\tjne file_close_failed_\n\
+                          \t\tmovq $1, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n\
+                          file_close_failed_:\n\
+                          \t\tmovq $0, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // str_eq_: 1 if strings %rdi and %rsi have the same contents
+        content.push_str("str_eq_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcall strcmp\n\
+                          \t\tcmpl $0, %eax\n\
+                          \t\tsete %al\n\
+                          \t\tmovzbq %al, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+
+        // rand_: random number below %rdi
+        content.push_str("rand_:\n\
+                          \t\tpush %rbp\n\
+                          \t\tmovq %rsp, %rbp\n\
+                          \t\tsubq $16, %rsp\n\
+                          \t\tandq $-16, %rsp\n\
+                          \t\tcmpq $0, %rdi\n\
+                          \t\tje div_zero_error_\n\
+                          \t\tmovq %rdi, -8(%rbp)\n\
+                          \t\tcall rand\n\
+                          \t\tmovslq %eax, %rax\n\
+                          \t\txorq %rdx, %rdx\n\
+                          \t\tdivq -8(%rbp)\n\
+                          \t\tmovq %rdx, %rax\n\
+                          \t\tleave\n\
+                          \t\tret\n");
+        content
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::slice;
+use super::tokenizer::Token;
+use super::ast::{Program, Function, Stmt, StmtKind, Expr, ExprKind, BinOp};
+
+// Code that can't change what the program does: functions never called,
+// statements after a return or exit, variables never read, and values
+// assigned but overwritten before anything reads them. It is removed from the
+// checked program, before any backend sees it, and each piece gets a warning
+// pointing at the source.
+pub fn eliminate(program: &mut Program, tokens: &[Token]) -> Vec<Warning> {
+    let mut finder = Finder {
+        tokens,
+        warnings: Vec::new()
+    };
+    finder.functions(program);
+    finder.unreachable(&mut program.statements);
+    finder.variables(program);
+    finder.dead_stores(program);
+    finder.warnings.sort_by_key(|warning| warning.start);
+    finder.warnings
+}
+
+pub struct Warning {
+    pub message: String,
+    // line and column of the first character, and of the one after the last
+    pub start: (usize, usize),
+    pub end: (usize, usize)
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}-{}:{}: warning: {}", self.start.0, self.start.1, self.end.0, self.end.1,
+               self.message)
+    }
+}
+
+struct Finder<'a> {
+    tokens: &'a [Token],
+    warnings: Vec<Warning>
+}
+
+impl<'a> Finder<'a> {
+    // Warns about the code from token `pos` up to `end`
+    fn warn(&mut self, message: String, pos: usize, end: usize) {
+        let (first, last) = (&self.tokens[pos], &self.tokens[end.max(pos + 1) - 1]);
+        self.warnings.push(Warning {
+            message,
+            start: (first.line, first.column),
+            end: (last.line, last.end_column)
+        });
+    }
+
+    // Removes the functions that main never calls, directly or through the
+    // functions it calls
+    fn functions(&mut self, program: &mut Program) {
+        let mut calls = HashMap::new();
+        for func in functions(&mut program.statements) {
+            let mut called = HashSet::new();
+            stmt_calls(&func.body, &mut called);
+            calls.insert(func.id.clone(), called);
+        }
+        let mut reached = HashSet::new();
+        for stmt in &program.statements {
+            stmt_calls(stmt, &mut reached);
+        }
+        let mut work: Vec<String> = reached.iter().cloned().collect();
+        while let Some(id) = work.pop() {
+            for callee in calls.get(&id).into_iter().flat_map(|called| called.iter()) {
+                if reached.insert(callee.clone()) {
+                    work.push(callee.clone());
+                }
+            }
+        }
+
+        rewrite(&mut program.statements, &mut |stmt| {
+            if let StmtKind::Function(ref func) = stmt.kind {
+                if !reached.contains(&func.id) {
+                    self.warn(format!("function {} is never called", func.id), stmt.pos, stmt.end);
+                    return None;
+                }
+            }
+            Some(stmt)
+        });
+    }
+
+    // Removes the statements after one that always returns or exits.
+    // Functions stay, since they can be called before their declaration.
+    fn unreachable(&mut self, statements: &mut Vec<Stmt>) {
+        for stmt in statements.iter_mut() {
+            self.unreachable_in(stmt);
+        }
+        if let Some(i) = statements.iter().position(leaves) {
+            let (kept, dead): (Vec<Stmt>, Vec<Stmt>) = statements.drain(i + 1..)
+                .partition(|stmt| matches!(stmt.kind, StmtKind::Function(_)));
+            if let (Some(first), Some(last)) = (dead.first(), dead.last()) {
+                self.warn(String::from("unreachable code"), first.pos, last.end);
+            }
+            statements.extend(kept);
+        }
+    }
+
+    fn unreachable_in(&mut self, stmt: &mut Stmt) {
+        match stmt.kind {
+            StmtKind::Block(ref mut statements) => self.unreachable(statements),
+            StmtKind::If(_, ref mut then, ref mut otherwise) => {
+                self.unreachable_in(then);
+                if let Some(ref mut s) = *otherwise {
+                    self.unreachable_in(s);
+                }
+            },
+            StmtKind::While(_, ref mut body) => self.unreachable_in(body),
+            StmtKind::Function(ref mut func) => self.unreachable_in(&mut func.body),
+            _ => {},
+        }
+    }
+
+    // Removes assignments whose value is overwritten before anything reads
+    // it, keeping the value when computing it does something
+    fn dead_stores(&mut self, program: &mut Program) {
+        self.dead_stores_in(&mut program.statements, &HashSet::new(), false);
+        for func in functions(&mut program.statements) {
+            let locals = local_names(func);
+            if let StmtKind::Block(ref mut statements) = func.body.kind {
+                self.dead_stores_in(statements, &locals, true);
+            }
+        }
+    }
+
+    // `locals` are the names local to the function the statements are in,
+    // and `top` whether they are its whole body
+    fn dead_stores_in(&mut self, statements: &mut Vec<Stmt>, locals: &HashSet<String>, top: bool) {
+        for stmt in statements.iter_mut() {
+            match stmt.kind {
+                StmtKind::Block(ref mut inner) => self.dead_stores_in(inner, locals, false),
+                StmtKind::If(_, ref mut then, ref mut otherwise) => {
+                    if let StmtKind::Block(ref mut inner) = then.kind {
+                        self.dead_stores_in(inner, locals, false);
+                    }
+                    if let Some(StmtKind::Block(ref mut inner)) = otherwise.as_mut().map(|s| &mut s.kind) {
+                        self.dead_stores_in(inner, locals, false);
+                    }
+                },
+                StmtKind::While(_, ref mut body) => {
+                    if let StmtKind::Block(ref mut inner) = body.kind {
+                        self.dead_stores_in(inner, locals, false);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let mut i = 0;
+        while i < statements.len() {
+            let dead = match statements[i].kind {
+                StmtKind::Assign(ref id, _) => overwritten(&statements[i + 1..], id, locals.contains(id), top),
+                _ => false,
+            };
+            if !dead {
+                i += 1;
+                continue;
+            }
+            let stmt = statements.remove(i);
+            if let StmtKind::Assign(id, value) = stmt.kind {
+                self.warn(format!("value assigned to {} is never read", id), stmt.pos, stmt.end);
+                if has_effects(&value) {
+                    statements.insert(i, Stmt { kind: StmtKind::Expr(value), pos: stmt.pos, end: stmt.end });
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    // Removes variables nothing reads, with their declarations and the
+    // assignments to them
+    fn variables(&mut self, program: &mut Program) {
+        let mut read = HashSet::new();
+        for stmt in &program.statements {
+            stmt_reads(stmt, &mut read);
+        }
+        for func in functions(&mut program.statements) {
+            let locals = local_names(func);
+            let mut used = HashSet::new();
+            stmt_reads(&func.body, &mut used);
+            read.extend(used.into_iter().filter(|id| !locals.contains(id)));
+        }
+        let unread: HashSet<String> = program.globals.iter().map(|global| global.0.clone())
+            .filter(|id| !read.contains(id)).collect();
+        for global in &program.globals {
+            let id = &global.0;
+            if unread.contains(id) {
+                if let Some((pos, end)) = declaration(&program.statements, id) {
+                    self.warn(format!("variable {} is never read", id), pos, end);
+                }
+            }
+        }
+        program.globals.retain(|global| !unread.contains(&global.0));
+        self.remove_vars(&mut program.statements, &unread);
+
+        for func in functions(&mut program.statements) {
+            let mut read = HashSet::new();
+            stmt_reads(&func.body, &mut read);
+            let mut dead: HashSet<String> = unread.iter().filter(|id| !local_names(func).contains(*id))
+                .cloned().collect();
+            for local in &func.locals {
+                let id = &local.0;
+                if !read.contains(id) {
+                    if let Some((pos, end)) = declaration(slice::from_ref(&*func.body), id) {
+                        self.warn(format!("variable {} is never read", id), pos, end);
+                    }
+                    dead.insert(id.clone());
+                }
+            }
+            func.locals.retain(|local| read.contains(&local.0));
+            let mut body = vec![mem::replace(&mut *func.body, empty(0, 0))];
+            self.remove_vars(&mut body, &dead);
+            *func.body = body.pop().unwrap_or_else(|| empty(0, 0));
+        }
+    }
+
+    fn remove_vars(&mut self, statements: &mut Vec<Stmt>, dead: &HashSet<String>) {
+        rewrite(statements, &mut |stmt| {
+            match stmt.kind {
+                StmtKind::Declare(_, ref id) if dead.contains(id) => None,
+                StmtKind::Assign(ref id, ref value) if dead.contains(id) => {
+                    if has_effects(value) {
+                        Some(Stmt { kind: StmtKind::Expr(value.clone()), pos: stmt.pos, end: stmt.end })
+                    } else {
+                        None
+                    }
+                },
+                _ => Some(stmt),
+            }
+        });
+    }
+}
+
+fn empty(pos: usize, end: usize) -> Stmt {
+    Stmt {
+        kind: StmtKind::Block(Vec::new()),
+        pos,
+        end
+    }
+}
+
+// Replaces every statement outside function bodies, innermost first, with
+// what `f` makes of it. One that `f` removes where a statement has to be,
+// like the body of a loop, becomes an empty block.
+fn rewrite<F: FnMut(Stmt) -> Option<Stmt>>(statements: &mut Vec<Stmt>, f: &mut F) {
+    for stmt in mem::take(statements) {
+        if let Some(stmt) = rewrite_stmt(stmt, f) {
+            statements.push(stmt);
+        }
+    }
+}
+
+fn rewrite_stmt<F: FnMut(Stmt) -> Option<Stmt>>(mut stmt: Stmt, f: &mut F) -> Option<Stmt> {
+    match stmt.kind {
+        StmtKind::Block(ref mut statements) => rewrite(statements, f),
+        StmtKind::If(_, ref mut then, ref mut otherwise) => {
+            rewrite_boxed(then, f);
+            if let Some(ref mut s) = *otherwise {
+                rewrite_boxed(s, f);
+            }
+        },
+        StmtKind::While(_, ref mut body) => rewrite_boxed(body, f),
+        _ => {},
+    }
+    f(stmt)
+}
+
+fn rewrite_boxed<F: FnMut(Stmt) -> Option<Stmt>>(stmt: &mut Box<Stmt>, f: &mut F) {
+    let (pos, end) = (stmt.pos, stmt.end);
+    let old = mem::replace(&mut **stmt, empty(pos, end));
+    if let Some(new) = rewrite_stmt(old, f) {
+        **stmt = new;
+    }
+}
+
+// Every function declared in `statements`
+fn functions(statements: &mut [Stmt]) -> Vec<&mut Function> {
+    let mut found = Vec::new();
+    for stmt in statements {
+        match stmt.kind {
+            StmtKind::Function(ref mut func) => found.push(func),
+            StmtKind::Block(ref mut inner) => found.extend(functions(inner)),
+            StmtKind::If(_, ref mut then, ref mut otherwise) => {
+                found.extend(functions(slice::from_mut(&mut **then)));
+                if let Some(ref mut s) = *otherwise {
+                    found.extend(functions(slice::from_mut(&mut **s)));
+                }
+            },
+            StmtKind::While(_, ref mut body) => found.extend(functions(slice::from_mut(&mut **body))),
+            _ => {},
+        }
+    }
+    found
+}
+
+// The parameters and locals of `func`, which hide globals of the same name
+fn local_names(func: &Function) -> HashSet<String> {
+    func.params.iter().chain(func.locals.iter()).map(|var| var.0.clone()).collect()
+}
+
+// Where `id` is first declared in `statements`, outside function bodies
+fn declaration(statements: &[Stmt], id: &str) -> Option<(usize, usize)> {
+    statements.iter().filter_map(|stmt| match stmt.kind {
+        StmtKind::Declare(_, ref declared) if declared == id => Some((stmt.pos, stmt.end)),
+        StmtKind::Block(ref inner) => declaration(inner, id),
+        StmtKind::If(_, ref then, ref otherwise) => declaration(slice::from_ref(&**then), id)
+            .or_else(|| otherwise.as_ref().and_then(|s| declaration(slice::from_ref(&**s), id))),
+        StmtKind::While(_, ref body) => declaration(slice::from_ref(&**body), id),
+        _ => None,
+    }).next()
+}
+
+// Whether control never gets past `stmt`
+fn leaves(stmt: &Stmt) -> bool {
+    match stmt.kind {
+        StmtKind::Return(_) | StmtKind::Exit(_) => true,
+        StmtKind::Block(ref statements) => statements.iter().any(leaves),
+        StmtKind::If(_, ref then, Some(ref otherwise)) => leaves(then) && leaves(otherwise),
+        _ => false,
+    }
+}
+
+// Whether control may leave the function or program inside `stmt`
+fn may_leave(stmt: &Stmt) -> bool {
+    match stmt.kind {
+        StmtKind::Return(_) | StmtKind::Exit(_) => true,
+        StmtKind::Block(ref statements) => statements.iter().any(may_leave),
+        StmtKind::If(_, ref then, Some(ref otherwise)) => may_leave(then) || may_leave(otherwise),
+        StmtKind::If(_, ref then, None) => may_leave(then),
+        StmtKind::While(_, ref body) => may_leave(body),
+        _ => false,
+    }
+}
+
+// Whether the value `id` is assigned before `rest` is overwritten before
+// anything reads it. `local` is whether it is local to the function, which
+// nothing reads once it returns, and `top` whether `rest` ends the function.
+fn overwritten(rest: &[Stmt], id: &str, local: bool, top: bool) -> bool {
+    for stmt in rest {
+        let mut read = HashSet::new();
+        stmt_reads(stmt, &mut read);
+        let mut called = HashSet::new();
+        stmt_calls(stmt, &mut called);
+        // functions may read globals
+        if read.contains(id) || (!local && !called.is_empty()) {
+            return false;
+        }
+        match stmt.kind {
+            StmtKind::Assign(ref other, _) if other == id => return true,
+            StmtKind::Return(_) => return local,
+            StmtKind::Exit(_) => return true,
+            _ if may_leave(stmt) => return false,
+            _ => {},
+        }
+    }
+    local && top
+}
+
+// Gathers the variables `stmt` reads, outside function bodies
+fn stmt_reads(stmt: &Stmt, read: &mut HashSet<String>) {
+    each_expr(stmt, &mut |expr| expr_reads(expr, read));
+}
+
+fn expr_reads(expr: &Expr, read: &mut HashSet<String>) {
+    match expr.kind {
+        ExprKind::Var(ref id) => {
+            read.insert(id.clone());
+        },
+        _ => each_operand(expr, &mut |operand| expr_reads(operand, read)),
+    }
+}
+
+// Gathers the functions `stmt` calls, outside function bodies
+fn stmt_calls(stmt: &Stmt, called: &mut HashSet<String>) {
+    each_expr(stmt, &mut |expr| expr_calls(expr, called));
+}
+
+fn expr_calls(expr: &Expr, called: &mut HashSet<String>) {
+    if let ExprKind::Call(ref id, _) = expr.kind {
+        called.insert(id.clone());
+    }
+    each_operand(expr, &mut |operand| expr_calls(operand, called));
+}
+
+// Whether evaluating `expr` does more than compute a value: calls, input and
+// output, and divisions, which stop the program when dividing by zero
+fn has_effects(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::Call(..) | ExprKind::Builtin(..) | ExprKind::Binary(BinOp::Div, _, _) => true,
+        _ => {
+            let mut effects = false;
+            each_operand(expr, &mut |operand| effects |= has_effects(operand));
+            effects
+        },
+    }
+}
+
+// Calls `f` on the expressions directly in `stmt` and the statements in it,
+// outside function bodies
+fn each_expr<F: FnMut(&Expr)>(stmt: &Stmt, f: &mut F) {
+    match stmt.kind {
+        StmtKind::Declare(..) | StmtKind::Function(_) | StmtKind::Return(None) => {},
+        StmtKind::Assign(_, ref value) | StmtKind::Return(Some(ref value)) | StmtKind::Exit(ref value) |
+        StmtKind::Expr(ref value) => f(value),
+        StmtKind::If(ref cond, ref then, ref otherwise) => {
+            f(cond);
+            each_expr(then, f);
+            if let Some(ref s) = *otherwise {
+                each_expr(s, f);
+            }
+        },
+        StmtKind::While(ref cond, ref body) => {
+            f(cond);
+            each_expr(body, f);
+        },
+        StmtKind::Block(ref statements) => {
+            for s in statements {
+                each_expr(s, f);
+            }
+        },
+        StmtKind::Print(ref args, _) => {
+            for arg in args {
+                f(arg);
+            }
+        },
+    }
+}
+
+fn each_operand<F: FnMut(&Expr)>(expr: &Expr, f: &mut F) {
+    match expr.kind {
+        ExprKind::Int(_) | ExprKind::Bool(_) | ExprKind::Str(_) | ExprKind::Var(_) => {},
+        ExprKind::Binary(_, ref lhs, ref rhs) => {
+            f(lhs);
+            f(rhs);
+        },
+        ExprKind::Not(ref inner) | ExprKind::Convert(ref inner) => f(inner),
+        ExprKind::Call(_, ref args) | ExprKind::Builtin(_, ref args) | ExprKind::Format(_, _, ref args) => {
+            for arg in args {
+                f(arg);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tokenizer::{Tokenizer, VarType};
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+
+    fn eliminated(program: &str) -> (Program, Vec<String>) {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        let warnings = eliminate(&mut ast, &tokens);
+        (ast, warnings.iter().map(|warning| warning.to_string()).collect())
+    }
+
+    #[test]
+    fn unused_code_is_removed_with_warnings() {
+        let (program, warnings) = eliminated("🆕🔢😀🔚\n🆕🔢😎🔚\n🆕🔤👽🔚\n😀⬅️3️⃣🔚\n😀⬅️4️⃣🔚\n👽⬅️📥🌜🌛🔚\n\
+                                              🤪🔢🦊🌜🔢🐾🌛 🌘\n    ↩️🐾🔚\n    📄🐾🔚\n🌒\n\
+                                              🤪🔢🐝🌜🌛 🌘 ↩️📞🦊🌜1️⃣🌛🔚 🌒\n📄😀🔚\n🚪🌜0️⃣🌛🔚\n📄😎🔚\n");
+        assert_eq!(warnings, ["2:1-2:5: warning: variable 😎 is never read",
+                              "3:1-3:5: warning: variable 👽 is never read",
+                              "4:1-4:8: warning: value assigned to 😀 is never read",
+                              "7:1-10:2: warning: function 🦊 is never called",
+                              "11:1-11:21: warning: function 🐝 is never called",
+                              "14:1-14:4: warning: unreachable code"]);
+        assert_eq!(program.globals, [(String::from("😀"), VarType::INT)]);
+        // reading the input stays
+        let kinds: Vec<String> = program.statements.iter().map(|stmt| format!("{:?}", stmt.kind)).collect();
+        assert_eq!(kinds.len(), 5, "{:?}", kinds);
+        assert!(kinds[2].starts_with("Expr(Expr { kind: Builtin(ReadStr"), "{:?}", kinds);
+    }
+
+    #[test]
+    fn code_that_matters_stays() {
+        let (program, warnings) = eliminated("🆕🔢😀🔚 🆕🔢🐶🔚 \
+            🤪🔢🦊🌜🌛 🌘 ↩️😀🔚 🌒 \
+            🤪🔢🐝🌜🔢🐾🌛 🌘 🆕🔢🐜🔚 🐜⬅️🐾➕1️⃣🔚 🐾⬅️🐜🔚 ↩️🐜🔚 🌒 \
+            😀⬅️1️⃣🔚 📄📞🦊🌜🌛🔚 😀⬅️📞🐝🌜2️⃣🌛🔚 📄😀🔚 \
+            🐶⬅️📨🌜🌛🔚 🐶⬅️0️⃣🔚 📄🐶🔚 \
+            🔁🚫🌜😀↔️0️⃣🌛 🌘 😀⬅️😀➖1️⃣🔚 ❓😀↔️1️⃣ 🚪🌜0️⃣🌛🔚 ❌ 📄😀🔚 🌒");
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].ends_with("value assigned to 🐾 is never read"), "{:?}", warnings);
+        assert!(warnings[1].ends_with("value assigned to 🐶 is never read"), "{:?}", warnings);
+        assert_eq!(program.globals.len(), 2);
+        assert_eq!(program.statements.len(), 12);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use super::tokenizer::VarType;
+use super::ast::*;
+use super::machine::{Machine, Value, Stop, Run, string, convert};
+
+// Runs a checked program by walking its syntax tree
+pub struct Interpreter<'a> {
+    machine: Machine<'a>,
+    // every function of the programs run so far, wherever it is defined
+    funcs: HashMap<String, Rc<Callee>>,
+    globals: HashMap<String, Value>,
+    // parameters and locals of the function being run, if any
+    locals: Option<HashMap<String, Value>>,
+    // the function being run, if any
+    running: Option<Rc<Callee>>
+}
+
+struct Callee {
+    func: Function,
+    // where it calls itself as the last thing it does
+    tail_calls: Vec<usize>
+}
+
+// How a statement finished
+enum Flow {
+    Next,
+    Return(Value),
+    // the function calls itself with these arguments and returns the result
+    TailCall(Vec<Value>)
+}
+
+impl<'a> Interpreter<'a> {
+    // `args` are the program's arguments, not including the program name
+    pub fn new(args: &'a [String], input: &'a mut dyn BufRead, output: &'a mut dyn Write,
+               errors: &'a mut dyn Write) -> Interpreter<'a> {
+        Interpreter {
+            machine: Machine::new(args, input, output, errors),
+            funcs: HashMap::new(),
+            globals: HashMap::new(),
+            locals: None,
+            running: None
+        }
+    }
+
+    // Runs a checked program, returning its exit status
+    pub fn run(mut self, program: &Program) -> i32 {
+        let status = match self.execute(program) {
+            Ok(()) => 0,
+            Err(Stop::Exit(status)) | Err(Stop::Error(status)) => status,
+        };
+        self.machine.flush();
+        status
+    }
+
+    // Runs a checked program, keeping its variables and functions around for
+    // whatever is executed next. Variables that already exist keep their value.
+    pub fn execute(&mut self, program: &Program) -> Result<(), Stop> {
+        for &(ref id, var_type) in &program.globals {
+            self.globals.entry(id.clone()).or_insert_with(|| Value::default(var_type));
+        }
+        // like in native code, functions can be called before their definition
+        for stmt in &program.statements {
+            self.define_functions(stmt);
+        }
+        self.statements(&program.statements).map(|_| ())
+    }
+
+    fn define_functions(&mut self, stmt: &Stmt) {
+        match stmt.kind {
+            StmtKind::Function(ref func) => {
+                let callee = Callee { func: func.clone(), tail_calls: func.tail_calls() };
+                self.funcs.insert(func.id.clone(), Rc::new(callee));
+                self.define_functions(&func.body);
+            },
+            StmtKind::Block(ref statements) => {
+                for s in statements {
+                    self.define_functions(s);
+                }
+            },
+            StmtKind::If(_, ref then, ref otherwise) => {
+                self.define_functions(then);
+                if let Some(ref s) = *otherwise {
+                    self.define_functions(s);
+                }
+            },
+            StmtKind::While(_, ref body) => self.define_functions(body),
+            _ => {},
+        }
+    }
+
+    // The streams the program reads and writes, for sharing them with the REPL
+    pub fn streams(&mut self) -> (&mut dyn BufRead, &mut dyn Write, &mut dyn Write) {
+        self.machine.streams()
+    }
+
+    // Writes out whatever output is still buffered
+    pub fn flush(&mut self) {
+        self.machine.flush();
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Run<Flow> {
+        for stmt in statements {
+            match self.statement(stmt)? {
+                Flow::Next => {},
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Run<Flow> {
+        match stmt.kind {
+            StmtKind::Declare(..) => {},
+            StmtKind::Assign(ref id, ref value) => {
+                let value = self.expression(value)?;
+                self.set_var(id, value);
+            },
+            StmtKind::If(ref cond, ref then, ref otherwise) => {
+                if self.expression(cond)?.int() != 0 {
+                    return self.statement(then);
+                }
+                if let Some(ref s) = *otherwise {
+                    return self.statement(s);
+                }
+            },
+            StmtKind::While(ref cond, ref body) => {
+                while self.expression(cond)?.int() != 0 {
+                    match self.statement(body)? {
+                        Flow::Next => {},
+                        flow => return Ok(flow),
+                    }
+                }
+            },
+            StmtKind::Block(ref statements) => return self.statements(statements),
+            StmtKind::Print(ref args, newline) => {
+                for (i, arg) in args.iter().enumerate() {
+                    // arguments are separated by a space
+                    if i > 0 {
+                        self.machine.write(b" ");
+                    }
+                    let value = self.expression(arg)?;
+                    self.machine.print(&value, arg.var_type);
+                }
+                if newline {
+                    self.machine.write(b"\n");
+                }
+            },
+            // defined before the program started
+            StmtKind::Function(_) => {},
+            StmtKind::Return(Some(ref value)) if self.tail_call(stmt) => {
+                return Ok(Flow::TailCall(self.arguments(value)?));
+            },
+            StmtKind::Expr(ref expr) if self.tail_call(stmt) => {
+                return Ok(Flow::TailCall(self.arguments(expr)?));
+            },
+            StmtKind::Return(ref value) => {
+                let value = match *value {
+                    Some(ref v) => self.expression(v)?,
+                    None => Value::Int(0),
+                };
+                return Ok(Flow::Return(value));
+            },
+            StmtKind::Exit(ref status) => {
+                // only the low byte reaches the parent process
+                let status = self.expression(status)?.int();
+                return Err(Stop::Exit((status & 0xff) as i32));
+            },
+            StmtKind::Expr(ref expr) => {
+                // result is ignored when used as a statement
+                self.expression(expr)?;
+            },
+        }
+        Ok(Flow::Next)
+    }
+
+    fn get_var(&self, id: &str) -> Value {
+        if let Some(ref locals) = self.locals {
+            if let Some(value) = locals.get(id) {
+                return value.clone();
+            }
+        }
+        match self.globals.get(id) {
+            Some(value) => value.clone(),
+            None => panic!("Internal error: unknown variable {}", id),
+        }
+    }
+
+    fn set_var(&mut self, id: &str, value: Value) {
+        if let Some(ref mut locals) = self.locals {
+            if let Some(slot) = locals.get_mut(id) {
+                *slot = value;
+                return;
+            }
+        }
+        self.globals.insert(id.to_string(), value);
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Run<Value> {
+        let value = match expr.kind {
+            ExprKind::Int(value) => Value::Int(value),
+            ExprKind::Bool(value) => Value::Int(value as u64),
+            ExprKind::Str(ref value) => string(value.as_bytes()),
+            ExprKind::Var(ref id) => self.get_var(id),
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                let left = self.expression(lhs)?;
+                let right = self.expression(rhs)?;
+                self.binary(op, left, right, lhs.var_type, expr.var_type)?
+            },
+            ExprKind::Not(ref operand) => {
+                Value::Int((self.expression(operand)?.int() == 0) as u64)
+            },
+            ExprKind::Call(ref id, _) => {
+                let values = self.arguments(expr)?;
+                self.call(id, values)?
+            },
+            ExprKind::Builtin(builtin, ref args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.expression(arg)?);
+                }
+                self.machine.builtin(builtin, values)?
+            },
+            ExprKind::Format(ref segments, _, ref args) => {
+                // arguments were converted to strings by the checker
+                let mut text = segments[0].as_bytes().to_vec();
+                for (i, arg) in args.iter().enumerate() {
+                    text.extend_from_slice(self.expression(arg)?.bytes());
+                    text.extend_from_slice(segments[i + 1].as_bytes());
+                }
+                string(&text)
+            },
+            ExprKind::Convert(ref inner) => {
+                let value = self.expression(inner)?;
+                convert(value, inner.var_type, expr.var_type)
+            },
+        };
+        Ok(value)
+    }
+
+    fn binary(&mut self, op: BinOp, left: Value, right: Value, operand_type: VarType,
+              result_type: VarType) -> Run<Value> {
+        let value = match op {
+            BinOp::Add => match result_type {
+                VarType::STR => {
+                    let mut text = left.bytes().to_vec();
+                    text.extend_from_slice(right.bytes());
+                    string(&text)
+                },
+                // ➕ on booleans is "or"
+                VarType::BOOL => Value::Int(left.int() | right.int()),
+                _ => Value::Int(left.int().wrapping_add(right.int())),
+            },
+            BinOp::Sub => Value::Int(left.int().wrapping_sub(right.int())),
+            BinOp::Mul => match result_type {
+                // ✖️ on booleans is "and"
+                VarType::BOOL => Value::Int(left.int() & right.int()),
+                _ => Value::Int(left.int().wrapping_mul(right.int())),
+            },
+            BinOp::Div => {
+                if right.int() == 0 {
+                    return Err(self.machine.runtime_error("division by zero", 4));
+                }
+                Value::Int(left.int() / right.int())
+            },
+            BinOp::Eq => match operand_type {
+                VarType::STR => Value::Int((left.bytes() == right.bytes()) as u64),
+                _ => Value::Int((left.int() == right.int()) as u64),
+            },
+        };
+        Ok(value)
+    }
+
+    // Whether `stmt` of the running function calls it as the last thing it
+    // does, which loops in call rather than going deeper
+    fn tail_call(&self, stmt: &Stmt) -> bool {
+        match self.running {
+            Some(ref callee) => callee.tail_calls.contains(&stmt.pos),
+            None => false,
+        }
+    }
+
+    // The evaluated arguments of call `expr`
+    fn arguments(&mut self, expr: &Expr) -> Run<Vec<Value>> {
+        let args = match expr.kind {
+            ExprKind::Call(_, ref args) => args,
+            _ => panic!("Internal error: expected a call"),
+        };
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.expression(arg)?);
+        }
+        Ok(values)
+    }
+
+    fn call(&mut self, id: &str, args: Vec<Value>) -> Run<Value> {
+        let callee = match self.funcs.get(id) {
+            Some(callee) => callee.clone(),
+            None => panic!("Internal error: no function {}", id),
+        };
+        let caller = self.locals.take();
+        let running = self.running.replace(callee.clone());
+        let func = &callee.func;
+        let mut args = args;
+        let flow = loop {
+            let mut locals = HashMap::new();
+            for &(ref id, var_type) in &func.locals {
+                locals.insert(id.clone(), Value::default(var_type));
+            }
+            for (param, value) in func.params.iter().zip(args) {
+                locals.insert(param.0.clone(), value);
+            }
+            self.locals = Some(locals);
+            match self.statement(&func.body) {
+                Ok(Flow::TailCall(next)) => args = next,
+                flow => break flow,
+            }
+        };
+        self.locals = caller;
+        self.running = running;
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            // falling off the end returns the default value
+            Flow::Next => Ok(Value::default(func.ret_type)),
+            Flow::TailCall(_) => unreachable!(),
+        }
+    }
+}
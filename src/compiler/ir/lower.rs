@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::slice;
+use super::super::tokenizer::VarType;
+use super::super::ast::{self, Program, Stmt, StmtKind, Expr, ExprKind, BinOp};
+use super::{Module, Function, Block, Inst, Op, Term, Value, BlockId};
+
+// Lowers a checked program. Globals that no function uses become values of
+// main, the others stay in memory.
+pub fn lower(program: &Program) -> Module {
+    let mut used = HashSet::new();
+    for stmt in &program.statements {
+        function_uses(stmt, false, &mut used);
+    }
+    let (memory, vars): (Vec<_>, Vec<_>) = program.globals.iter().cloned()
+        .partition(|global| used.contains(&global.0));
+    lower_main(program, memory, &vars, false)
+}
+
+// Lowers a piece of a program on its own, keeping every global in memory
+// since other pieces may use them
+pub fn lower_fragment(program: &Program) -> Module {
+    lower_main(program, program.globals.clone(), &[], false)
+}
+
+// Lowers a program for debugging: every variable stays in memory, globals in
+// the data section and the rest in the frame, where debuggers find them
+pub fn lower_debug(program: &Program) -> Module {
+    lower_main(program, program.globals.clone(), &[], true)
+}
+
+fn lower_main(program: &Program, memory: Vec<(String, VarType)>, vars: &[(String, VarType)],
+              debug: bool) -> Module {
+    // main goes first, once the functions it defines are done
+    let mut lowering = Lowering { functions: vec![placeholder()], debug };
+    let main = lowering.function("main", "main", &[], vars, VarType::NONE, &program.statements);
+    lowering.functions[0] = main;
+    Module {
+        globals: memory,
+        functions: lowering.functions
+    }
+}
+
+fn placeholder() -> Function {
+    Function {
+        id: String::new(),
+        label: String::new(),
+        params: Vec::new(),
+        ret_type: VarType::NONE,
+        pos: 0,
+        locals: Vec::new(),
+        blocks: Vec::new(),
+        types: Vec::new()
+    }
+}
+
+// Gathers the variables used inside function bodies in `stmt` into `used`
+fn function_uses(stmt: &Stmt, in_function: bool, used: &mut HashSet<String>) {
+    match stmt.kind {
+        StmtKind::Declare(..) => {},
+        StmtKind::Assign(ref id, ref value) => {
+            if in_function {
+                used.insert(id.clone());
+                expr_uses(value, used);
+            }
+        },
+        StmtKind::If(ref cond, ref then, ref otherwise) => {
+            if in_function {
+                expr_uses(cond, used);
+            }
+            function_uses(then, in_function, used);
+            if let Some(ref s) = *otherwise {
+                function_uses(s, in_function, used);
+            }
+        },
+        StmtKind::While(ref cond, ref body) => {
+            if in_function {
+                expr_uses(cond, used);
+            }
+            function_uses(body, in_function, used);
+        },
+        StmtKind::Block(ref statements) => {
+            for s in statements {
+                function_uses(s, in_function, used);
+            }
+        },
+        StmtKind::Print(ref args, _) => {
+            if in_function {
+                for arg in args {
+                    expr_uses(arg, used);
+                }
+            }
+        },
+        StmtKind::Function(ref func) => function_uses(&func.body, true, used),
+        StmtKind::Return(ref value) => {
+            if let Some(ref v) = *value {
+                expr_uses(v, used);
+            }
+        },
+        StmtKind::Exit(ref value) | StmtKind::Expr(ref value) => {
+            if in_function {
+                expr_uses(value, used);
+            }
+        },
+    }
+}
+
+fn expr_uses(expr: &Expr, used: &mut HashSet<String>) {
+    match expr.kind {
+        ExprKind::Int(_) | ExprKind::Bool(_) | ExprKind::Str(_) => {},
+        ExprKind::Var(ref id) => {
+            used.insert(id.clone());
+        },
+        ExprKind::Binary(_, ref lhs, ref rhs) => {
+            expr_uses(lhs, used);
+            expr_uses(rhs, used);
+        },
+        ExprKind::Not(ref inner) | ExprKind::Convert(ref inner) => expr_uses(inner, used),
+        ExprKind::Call(_, ref args) | ExprKind::Builtin(_, ref args) | ExprKind::Format(_, _, ref args) => {
+            for arg in args {
+                expr_uses(arg, used);
+            }
+        },
+    }
+}
+
+// The value a variable has before anything is assigned to it
+fn default_op(var_type: VarType) -> Op {
+    if var_type == VarType::STR {
+        Op::Str(String::new())
+    } else {
+        Op::Const(0)
+    }
+}
+
+struct Lowering {
+    functions: Vec<Function>,
+    // whether variables stay in memory, see lower_debug
+    debug: bool
+}
+
+// Builds one function. Variables are turned into values as the code is
+// lowered, following "Simple and Efficient Construction of Static Single
+// Assignment Form" by Braun et al.: reading a variable looks for its value in
+// the current block, then in the predecessors, adding phis where they merge.
+// Blocks whose predecessors aren't all known yet, loop headers, are sealed
+// later, when their phis get completed.
+struct Builder {
+    func: Function,
+    insts: Vec<Vec<Inst>>,
+    terms: Vec<Option<Term>>,
+    preds: Vec<Vec<BlockId>>,
+    sealed: Vec<bool>,
+    current: usize,
+    // types of the variables that live in values
+    vars: HashMap<String, VarType>,
+    // the value of each variable at the end of each block, where known
+    defs: HashMap<(String, usize), Value>,
+    // phis of unsealed blocks, waiting for their operands
+    incomplete: Vec<Vec<(String, Value)>>
+}
+
+impl Builder {
+    fn new(func: Function) -> Builder {
+        let mut builder = Builder {
+            func,
+            insts: Vec::new(),
+            terms: Vec::new(),
+            preds: Vec::new(),
+            sealed: Vec::new(),
+            current: 0,
+            vars: HashMap::new(),
+            defs: HashMap::new(),
+            incomplete: Vec::new()
+        };
+        let entry = builder.new_block();
+        builder.seal(entry);
+        builder
+    }
+
+    fn new_block(&mut self) -> usize {
+        self.insts.push(Vec::new());
+        self.terms.push(None);
+        self.preds.push(Vec::new());
+        self.sealed.push(false);
+        self.incomplete.push(Vec::new());
+        self.insts.len() - 1
+    }
+
+    fn new_value(&mut self, var_type: VarType) -> Value {
+        self.func.types.push(var_type);
+        Value(self.func.types.len() - 1)
+    }
+
+    // Adds an instruction to the current block, with a result unless its
+    // type is NONE
+    fn emit(&mut self, op: Op, var_type: VarType, pos: usize) -> Option<Value> {
+        let value = if var_type == VarType::NONE {
+            None
+        } else {
+            Some(self.new_value(var_type))
+        };
+        self.insts[self.current].push(Inst { value, op, pos });
+        value
+    }
+
+    fn value(&mut self, op: Op, var_type: VarType, pos: usize) -> Value {
+        self.emit(op, var_type, pos).unwrap()
+    }
+
+    fn terminate(&mut self, term: Term) {
+        for succ in term.successors() {
+            self.preds[succ.0].push(BlockId(self.current));
+        }
+        self.terms[self.current] = Some(term);
+    }
+
+    // Continues in a new block that nothing jumps to, for code after a
+    // return, which gets removed in the end
+    fn unreachable(&mut self) {
+        self.current = self.new_block();
+        self.seal(self.current);
+    }
+
+    fn write(&mut self, id: &str, block: usize, value: Value) {
+        self.defs.insert((id.to_string(), block), value);
+    }
+
+    fn read(&mut self, id: &str, block: usize, pos: usize) -> Value {
+        if let Some(&value) = self.defs.get(&(id.to_string(), block)) {
+            return value;
+        }
+        let var_type = self.vars[id];
+        let value = if !self.sealed[block] {
+            let phi = self.phi(block, var_type, pos);
+            self.incomplete[block].push((id.to_string(), phi));
+            phi
+        } else if self.preds[block].len() == 1 {
+            let pred = self.preds[block][0].0;
+            self.read(id, pred, pos)
+        } else if self.preds[block].is_empty() {
+            // only in unreachable code, where any value will do
+            let value = self.new_value(var_type);
+            self.insts[block].insert(0, Inst { value: Some(value), op: default_op(var_type), pos });
+            value
+        } else {
+            // written first to end cycles through loops
+            let phi = self.phi(block, var_type, pos);
+            self.write(id, block, phi);
+            self.add_phi_operands(id, block, phi, pos);
+            phi
+        };
+        self.write(id, block, value);
+        value
+    }
+
+    // A phi without operands at the start of `block`
+    fn phi(&mut self, block: usize, var_type: VarType, pos: usize) -> Value {
+        let value = self.new_value(var_type);
+        let index = self.insts[block].iter().take_while(|inst| is_phi(inst)).count();
+        self.insts[block].insert(index, Inst { value: Some(value), op: Op::Phi(Vec::new()), pos });
+        value
+    }
+
+    fn add_phi_operands(&mut self, id: &str, block: usize, phi: Value, pos: usize) {
+        let mut incoming = Vec::new();
+        for pred in self.preds[block].clone() {
+            incoming.push((pred, self.read(id, pred.0, pos)));
+        }
+        for inst in self.insts[block].iter_mut() {
+            if inst.value == Some(phi) {
+                inst.op = Op::Phi(incoming);
+                return;
+            }
+        }
+    }
+
+    // Completes the phis of `block`, whose predecessors are all known now
+    fn seal(&mut self, block: usize) {
+        for (id, phi) in mem::take(&mut self.incomplete[block]) {
+            let pos = self.insts[block].iter().find(|inst| inst.value == Some(phi)).unwrap().pos;
+            self.add_phi_operands(&id, block, phi, pos);
+        }
+        self.sealed[block] = true;
+    }
+
+    fn finish(mut self) -> Function {
+        let terms = mem::take(&mut self.terms);
+        for (insts, term) in self.insts.drain(..).zip(terms) {
+            self.func.blocks.push(Block { insts, term: term.unwrap() });
+        }
+        let mut func = self.func;
+        func.tidy();
+        func
+    }
+}
+
+fn is_phi(inst: &Inst) -> bool {
+    matches!(inst.op, Op::Phi(_))
+}
+
+impl Lowering {
+    // Lowers a function body, with `vars` its variables besides the parameters
+    fn function(&mut self, id: &str, label: &str, params: &[(String, VarType)],
+                vars: &[(String, VarType)], ret_type: VarType, body: &[Stmt]) -> Function {
+        let mut b = Builder::new(Function {
+            id: id.to_string(),
+            label: label.to_string(),
+            params: params.iter().map(|&(_, var_type)| var_type).collect(),
+            ret_type,
+            pos: 0,
+            locals: Vec::new(),
+            blocks: Vec::new(),
+            types: Vec::new()
+        });
+        let pos = body.first().map_or(0, |stmt| stmt.pos);
+        for (i, &(ref id, var_type)) in params.iter().enumerate() {
+            let value = b.value(Op::Param(i), var_type, pos);
+            self.define(&mut b, id, var_type, value, pos);
+        }
+        for &(ref id, var_type) in vars {
+            if !b.vars.contains_key(id) && !b.func.locals.iter().any(|local| local.0 == *id) {
+                let value = b.value(default_op(var_type), var_type, pos);
+                self.define(&mut b, id, var_type, value, pos);
+            }
+        }
+
+        for stmt in body {
+            self.statement(&mut b, stmt);
+        }
+
+        // falling off the end returns the default value
+        let value = if ret_type == VarType::NONE {
+            None
+        } else {
+            Some(b.value(default_op(ret_type), ret_type, pos))
+        };
+        b.terminate(Term::Return(value));
+        b.finish()
+    }
+
+    // Gives a variable of the function its first value
+    fn define(&self, b: &mut Builder, id: &str, var_type: VarType, value: Value, pos: usize) {
+        if self.debug {
+            b.func.locals.push((id.to_string(), var_type));
+            b.emit(Op::Store(id.to_string(), value), VarType::NONE, pos);
+        } else {
+            b.vars.insert(id.to_string(), var_type);
+            b.write(id, 0, value);
+        }
+    }
+
+    fn statement(&mut self, b: &mut Builder, stmt: &Stmt) {
+        let pos = stmt.pos;
+        match stmt.kind {
+            StmtKind::Declare(..) => {},
+            StmtKind::Assign(ref id, ref value) => {
+                let value = self.value(b, value);
+                if b.vars.contains_key(id) {
+                    let block = b.current;
+                    b.write(id, block, value);
+                } else {
+                    b.emit(Op::Store(id.clone(), value), VarType::NONE, pos);
+                }
+            },
+            StmtKind::If(ref cond, ref then, ref otherwise) => {
+                let cond = self.value(b, cond);
+                let then_block = b.new_block();
+                let else_block = otherwise.as_ref().map(|_| b.new_block());
+                let done = b.new_block();
+                b.terminate(Term::Branch(cond, BlockId(then_block), BlockId(else_block.unwrap_or(done))));
+                b.seal(then_block);
+                b.current = then_block;
+                self.statement(b, then);
+                b.terminate(Term::Jump(BlockId(done)));
+                if let (Some(else_block), Some(s)) = (else_block, otherwise.as_ref()) {
+                    b.seal(else_block);
+                    b.current = else_block;
+                    self.statement(b, s);
+                    b.terminate(Term::Jump(BlockId(done)));
+                }
+                b.seal(done);
+                b.current = done;
+            },
+            StmtKind::While(ref cond, ref body) => {
+                let header = b.new_block();
+                b.terminate(Term::Jump(BlockId(header)));
+                b.current = header;
+                let cond = self.value(b, cond);
+                let body_block = b.new_block();
+                let done = b.new_block();
+                b.terminate(Term::Branch(cond, BlockId(body_block), BlockId(done)));
+                b.seal(body_block);
+                b.current = body_block;
+                self.statement(b, body);
+                b.terminate(Term::Jump(BlockId(header)));
+                b.seal(header);
+                b.seal(done);
+                b.current = done;
+            },
+            StmtKind::Block(ref statements) => {
+                for s in statements {
+                    self.statement(b, s);
+                }
+            },
+            StmtKind::Print(ref args, newline) => {
+                for (i, arg) in args.iter().enumerate() {
+                    // arguments are separated by a space
+                    if i > 0 {
+                        b.emit(Op::PrintChar(' '), VarType::NONE, pos);
+                    }
+                    let value = self.value(b, arg);
+                    b.emit(Op::Print(value), VarType::NONE, pos);
+                }
+                if newline {
+                    b.emit(Op::PrintChar('\n'), VarType::NONE, pos);
+                }
+            },
+            StmtKind::Function(ref func) => {
+                let index = self.functions.len();
+                self.functions.push(placeholder());
+                let lowered = self.function_def(func, pos);
+                self.functions[index] = lowered;
+                b.emit(Op::Define(func.id.clone(), index), VarType::NONE, pos);
+            },
+            StmtKind::Return(ref value) => {
+                let value = value.as_ref().map(|v| self.value(b, v));
+                b.terminate(Term::Return(value));
+                b.unreachable();
+            },
+            StmtKind::Exit(ref status) => {
+                let status = self.value(b, status);
+                b.terminate(Term::Exit(status));
+                b.unreachable();
+            },
+            StmtKind::Expr(ref expr) => {
+                // result is ignored when used as a statement
+                self.expression(b, expr);
+            },
+        }
+    }
+
+    fn function_def(&mut self, func: &ast::Function, pos: usize) -> Function {
+        let mut function = self.function(&func.id, &format!("func_{}", pos), &func.params, &func.locals,
+                                         func.ret_type, slice::from_ref(&*func.body));
+        function.pos = pos;
+        function
+    }
+
+    // Lowers an expression that has a value
+    fn value(&mut self, b: &mut Builder, expr: &Expr) -> Value {
+        self.expression(b, expr).unwrap()
+    }
+
+    fn expression(&mut self, b: &mut Builder, expr: &Expr) -> Option<Value> {
+        let pos = expr.pos;
+        let op = match expr.kind {
+            ExprKind::Int(value) => Op::Const(value),
+            ExprKind::Bool(value) => Op::Const(value as u64),
+            ExprKind::Str(ref value) => Op::Str(value.clone()),
+            ExprKind::Var(ref id) => {
+                if b.vars.contains_key(id) {
+                    let block = b.current;
+                    return Some(b.read(id, block, pos));
+                }
+                Op::Load(id.clone())
+            },
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                let lhs = self.value(b, lhs);
+                let rhs = self.value(b, rhs);
+                Op::Binary(op, lhs, rhs)
+            },
+            ExprKind::Not(ref inner) => Op::Not(self.value(b, inner)),
+            ExprKind::Convert(ref inner) => Op::Convert(self.value(b, inner)),
+            ExprKind::Call(ref id, ref args) => {
+                let args = args.iter().map(|arg| self.value(b, arg)).collect();
+                Op::Call(id.clone(), args)
+            },
+            ExprKind::Builtin(builtin, ref args) => {
+                let args = args.iter().map(|arg| self.value(b, arg)).collect();
+                Op::Builtin(builtin, args)
+            },
+            ExprKind::Format(ref segments, _, ref args) => {
+                // the segments and arguments, converted to strings by the
+                // checker, concatenated in order
+                let mut value = b.value(Op::Str(segments[0].clone()), VarType::STR, pos);
+                for (i, arg) in args.iter().enumerate() {
+                    let arg = self.value(b, arg);
+                    value = b.value(Op::Binary(BinOp::Add, value, arg), VarType::STR, pos);
+                    if !segments[i + 1].is_empty() {
+                        let segment = b.value(Op::Str(segments[i + 1].clone()), VarType::STR, pos);
+                        value = b.value(Op::Binary(BinOp::Add, value, segment), VarType::STR, pos);
+                    }
+                }
+                return Some(value);
+            },
+        };
+        b.emit(op, expr.var_type, pos)
+    }
+}
@@ -0,0 +1,396 @@
+mod lower;
+mod verify;
+mod tidy;
+use std::fmt;
+use super::tokenizer::VarType;
+use super::checker::type_name;
+use super::ast::{BinOp, Builtin};
+pub use self::lower::{lower, lower_fragment, lower_debug};
+pub use self::verify::verify;
+
+// The intermediate representation between the syntax tree and the native
+// backends. Every function is a list of basic blocks in SSA form: each value
+// is defined by exactly one instruction and has a type, and where control
+// flow merges, phi instructions pick the value from the block control came
+// from. Variables of functions, and globals no function uses, only exist as
+// values; the other globals live in memory and are loaded and stored.
+
+// A value, numbered within its function
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
+pub struct Value(pub usize);
+
+// A basic block, numbered within its function
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    // globals that live in memory
+    pub globals: Vec<(String, VarType)>,
+    // main first, then the functions in the order they are defined
+    pub functions: Vec<Function>
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub id: String,
+    // assembly label of its code
+    pub label: String,
+    pub params: Vec<VarType>,
+    pub ret_type: VarType,
+    // position of the token it was declared at
+    pub pos: usize,
+    // variables kept in the frame for debuggers, parameters first. Loads and
+    // stores of their names are of these rather than globals.
+    pub locals: Vec<(String, VarType)>,
+    // the first block is the entry
+    pub blocks: Vec<Block>,
+    // the type of every value
+    pub types: Vec<VarType>
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    // phis come first
+    pub insts: Vec<Inst>,
+    pub term: Term
+}
+
+#[derive(Debug, Clone)]
+pub struct Inst {
+    // what the instruction defines, if anything
+    pub value: Option<Value>,
+    pub op: Op,
+    // position of the token it was lowered from
+    pub pos: usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Const(u64),
+    Str(String),
+    // argument `index` of the function, only in the entry block
+    Param(usize),
+    // the value coming from each predecessor
+    Phi(Vec<(BlockId, Value)>),
+    // loads and stores of globals in memory
+    Load(String),
+    Store(String, Value),
+    Binary(BinOp, Value, Value),
+    Not(Value),
+    // to the type of the result
+    Convert(Value),
+    Call(String, Vec<Value>),
+    Builtin(Builtin, Vec<Value>),
+    // printing without a newline
+    Print(Value),
+    PrintChar(char),
+    // points the function variable at function `index` of the module
+    Define(String, usize)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Jump(BlockId),
+    // to the first block if the boolean is true, else to the second
+    Branch(Value, BlockId, BlockId),
+    Return(Option<Value>),
+    Exit(Value)
+}
+
+impl Op {
+    // The values the instruction reads
+    pub fn operands(&self) -> Vec<Value> {
+        match *self {
+            Op::Const(_) | Op::Str(_) | Op::Param(_) | Op::Load(_) | Op::PrintChar(_) |
+            Op::Define(..) => vec![],
+            Op::Phi(ref incoming) => incoming.iter().map(|&(_, value)| value).collect(),
+            Op::Store(_, value) | Op::Not(value) | Op::Convert(value) | Op::Print(value) => vec![value],
+            Op::Binary(_, lhs, rhs) => vec![lhs, rhs],
+            Op::Call(_, ref args) | Op::Builtin(_, ref args) => args.clone(),
+        }
+    }
+
+    // Calls `f` on every value the instruction reads
+    pub fn map_operands<F: FnMut(Value) -> Value>(&mut self, mut f: F) {
+        match *self {
+            Op::Const(_) | Op::Str(_) | Op::Param(_) | Op::Load(_) | Op::PrintChar(_) |
+            Op::Define(..) => {},
+            Op::Phi(ref mut incoming) => {
+                for pair in incoming.iter_mut() {
+                    pair.1 = f(pair.1);
+                }
+            },
+            Op::Store(_, ref mut value) | Op::Not(ref mut value) | Op::Convert(ref mut value) |
+            Op::Print(ref mut value) => *value = f(*value),
+            Op::Binary(_, ref mut lhs, ref mut rhs) => {
+                *lhs = f(*lhs);
+                *rhs = f(*rhs);
+            },
+            Op::Call(_, ref mut args) | Op::Builtin(_, ref mut args) => {
+                for arg in args.iter_mut() {
+                    *arg = f(*arg);
+                }
+            },
+        }
+    }
+}
+
+impl Term {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match *self {
+            Term::Jump(target) => vec![target],
+            Term::Branch(_, then, otherwise) => vec![then, otherwise],
+            Term::Return(_) | Term::Exit(_) => vec![],
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Value> {
+        match *self {
+            Term::Jump(_) | Term::Return(None) => vec![],
+            Term::Branch(value, ..) | Term::Return(Some(value)) | Term::Exit(value) => vec![value],
+        }
+    }
+
+    pub fn map_operands<F: FnMut(Value) -> Value>(&mut self, mut f: F) {
+        match *self {
+            Term::Jump(_) | Term::Return(None) => {},
+            Term::Branch(ref mut value, ..) | Term::Return(Some(ref mut value)) |
+            Term::Exit(ref mut value) => *value = f(*value),
+        }
+    }
+}
+
+impl Function {
+    // The blocks control can come from, for every block
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for succ in block.term.successors() {
+                if succ.0 < preds.len() && !preds[succ.0].contains(&BlockId(i)) {
+                    preds[succ.0].push(BlockId(i));
+                }
+            }
+        }
+        preds
+    }
+
+    // The blocks reachable from the entry, each after the blocks it is
+    // reached from except through loops. Of the two targets of a branch, the
+    // first comes first.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // blocks being visited, with the successors still to visit, last
+        // first
+        let mut stack = vec![(0, self.blocks[0].term.successors())];
+        visited[0] = true;
+        while !stack.is_empty() {
+            let next = stack.last_mut().unwrap().1.pop();
+            match next {
+                Some(succ) => {
+                    if !visited[succ.0] {
+                        visited[succ.0] = true;
+                        stack.push((succ.0, self.blocks[succ.0].term.successors()));
+                    }
+                },
+                None => postorder.push(BlockId(stack.pop().unwrap().0)),
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+}
+
+fn values(values: &[Value]) -> String {
+    values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn op_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Eq => "eq",
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Op::Const(value) => write!(f, "const {}", value),
+            Op::Str(ref value) => write!(f, "str {:?}", value),
+            Op::Param(index) => write!(f, "param {}", index),
+            Op::Phi(ref incoming) => {
+                let incoming: Vec<String> = incoming.iter()
+                    .map(|&(block, value)| format!("[{}: {}]", block, value))
+                    .collect();
+                write!(f, "phi {}", incoming.join(" "))
+            },
+            Op::Load(ref id) => write!(f, "load {}", id),
+            Op::Store(ref id, value) => write!(f, "store {}, {}", id, value),
+            Op::Binary(op, lhs, rhs) => write!(f, "{} {}, {}", op_name(op), lhs, rhs),
+            Op::Not(value) => write!(f, "not {}", value),
+            Op::Convert(value) => write!(f, "convert {}", value),
+            Op::Call(ref id, ref args) => write!(f, "call {}({})", id, values(args)),
+            Op::Builtin(builtin, ref args) => {
+                write!(f, "{}({})", format!("{:?}", builtin).to_lowercase(), values(args))
+            },
+            Op::Print(value) => write!(f, "print {}", value),
+            Op::PrintChar(ch) => write!(f, "print {:?}", ch),
+            Op::Define(ref id, _) => write!(f, "define {}", id),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Jump(target) => write!(f, "jump {}", target),
+            Term::Branch(cond, then, otherwise) => write!(f, "branch {}, {}, {}", cond, then, otherwise),
+            Term::Return(Some(value)) => write!(f, "ret {}", value),
+            Term::Return(None) => write!(f, "ret"),
+            Term::Exit(value) => write!(f, "exit {}", value),
+        }
+    }
+}
+
+impl Function {
+    // One instruction as text, with the type of what it defines
+    pub fn inst_str(&self, inst: &Inst) -> String {
+        match inst.value {
+            Some(value) => format!("{}: {} = {}", value, type_name(self.types[value.0]), inst.op),
+            None => inst.op.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<&str> = self.params.iter().map(|&param| type_name(param)).collect();
+        write!(f, "fn {}({})", self.id, params.join(", "))?;
+        if self.ret_type != VarType::NONE {
+            write!(f, " -> {}", type_name(self.ret_type))?;
+        }
+        writeln!(f, " {{")?;
+        for &(ref id, var_type) in &self.locals {
+            writeln!(f, "    local {}: {}", id, type_name(var_type))?;
+        }
+        let preds = self.predecessors();
+        for (i, block) in self.blocks.iter().enumerate() {
+            if preds[i].is_empty() {
+                writeln!(f, "b{}:", i)?;
+            } else {
+                let preds: Vec<String> = preds[i].iter().map(|pred| pred.to_string()).collect();
+                writeln!(f, "b{}:  # from {}", i, preds.join(", "))?;
+            }
+            for inst in &block.insts {
+                writeln!(f, "    {}", self.inst_str(inst))?;
+            }
+            writeln!(f, "    {}", block.term)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(ref id, var_type) in &self.globals {
+            writeln!(f, "global {}: {}", id, type_name(var_type))?;
+        }
+        for func in &self.functions {
+            writeln!(f)?;
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tokenizer::Tokenizer;
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+    use super::super::ast::Program;
+
+    fn check(program: &str) -> Program {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        ast
+    }
+
+    const LOOP: &str = "🆕🔢😀🔚 🔁🚫🌜😀↔️3️⃣🌛 🌘 😀⬅️😀➕1️⃣🔚 🌒 📄😀🔚";
+
+    #[test]
+    fn variables_become_phis_where_control_merges() {
+        let module = lower(&check(LOOP));
+        assert_eq!(verify(&module), Ok(()));
+        assert!(module.globals.is_empty());
+        assert_eq!(module.to_string(), "\nfn main() {\n\
+                                        b0:\n    v0: int = const 0\n    jump b1\n\
+                                        b1:  # from b0, b2\n    v1: int = phi [b0: v0] [b2: v6]\n\
+                                        \x20   v2: int = const 3\n    v3: bool = eq v1, v2\n\
+                                        \x20   v4: bool = not v3\n    branch v4, b2, b3\n\
+                                        b2:  # from b1\n    v5: int = const 1\n    v6: int = add v1, v5\n\
+                                        \x20   jump b1\n\
+                                        b3:  # from b1\n    print v1\n    print '\\n'\n    ret\n}\n");
+
+        let module = lower(&check("🆕🔤👽🔚 ❓📥🌜🌛↔️💬🐸💬 👽⬅️💬🐶💬🔚 ❌ 👽⬅️💬🐱💬🔚 📄👽🔚"));
+        assert_eq!(verify(&module), Ok(()));
+        let text = module.to_string();
+        assert!(text.contains("b3:  # from b1, b2\n    v6: string = phi [b1: v4] [b2: v5]\n    print v6\n"), "{}", text);
+    }
+
+    #[test]
+    fn globals_used_by_functions_stay_in_memory() {
+        let module = lower(&check("🆕🔢😀🔚 🆕🔢🐶🔚 🤪🐺🌜🌛 🌘 📄😀🔚 🌒 😀⬅️🐶➕1️⃣🔚 📞🐺🌜🌛🔚"));
+        assert_eq!(verify(&module), Ok(()));
+        assert_eq!(module.globals, vec![(String::from("😀"), VarType::INT)]);
+        assert_eq!(module.functions.len(), 2);
+        assert_eq!(module.functions[1].label, "func_8");
+        let text = module.to_string();
+        assert!(text.starts_with("global 😀: int\n"), "{}", text);
+        assert!(text.contains("define 🐺\n    v1: int = const 1\n    v2: int = add v0, v1\n    store 😀, v2\n    call 🐺()\n"), "{}", text);
+
+        // the REPL lowers one piece at a time, so everything stays in memory
+        let module = lower_fragment(&check(LOOP));
+        assert_eq!(verify(&module), Ok(()));
+        assert!(module.to_string().contains("load 😀"));
+    }
+
+    #[test]
+    fn verifier_rejects_malformed_functions() {
+        let module = lower(&check(LOOP));
+        let broken = |change: &dyn Fn(&mut Function)| {
+            let mut module = module.clone();
+            change(&mut module.functions[0]);
+            verify(&module).unwrap_err()
+        };
+        assert_eq!(broken(&|func| func.blocks[0].term = Term::Return(None)), "in fn main: b1 is unreachable");
+        assert_eq!(broken(&|func| func.blocks[0].insts[0].op = Op::Not(Value(6))),
+                   "in fn main: b0: v0: int = not v6: the definition of v6 doesn't dominate its use");
+        assert_eq!(broken(&|func| func.blocks[1].insts[0].op = Op::Phi(vec![(BlockId(0), Value(0))])),
+                   "in fn main: b1: v1: int = phi [b0: v0]: phi doesn't match the predecessors");
+        assert_eq!(broken(&|func| func.blocks[2].insts[1].op = Op::Binary(BinOp::Add, Value(1), Value(3))),
+                   "in fn main: b2: v6: int = add v1, v3: v3 is bool, expected int");
+        assert_eq!(broken(&|func| func.blocks[1].term = Term::Branch(Value(1), BlockId(2), BlockId(3))),
+                   "in fn main: b1: branch v1, b2, b3: v1 is int, expected bool");
+        assert_eq!(broken(&|func| func.blocks[3].insts.push(Inst { value: Some(Value(2)), op: Op::Const(3), pos: 0 })),
+                   "in fn main: v2 is defined twice");
+    }
+}
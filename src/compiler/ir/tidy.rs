@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::mem;
+use super::{Function, Block, Op, Term, Value, BlockId};
+
+impl Function {
+    // Drops unreachable blocks, puts the others in reverse postorder, removes
+    // phis that don't merge anything and numbers the values in order. Passes
+    // that change the control flow or replace values finish with this.
+    pub fn tidy(&mut self) {
+        order_blocks(self);
+        remove_trivial_phis(self);
+        renumber(self);
+    }
+
+    // Appends blocks to their only predecessor where it jumps straight to
+    // them. The emptied blocks are left unreachable for tidy to drop.
+    pub fn merge_blocks(&mut self) {
+        let mut preds = self.predecessors();
+        for i in 0..self.blocks.len() {
+            while let Term::Jump(target) = self.blocks[i].term {
+                if target.0 == i || preds[target.0] != [BlockId(i)] {
+                    break;
+                }
+                let merged = mem::replace(&mut self.blocks[target.0],
+                                          Block { insts: Vec::new(), term: Term::Return(None) });
+                // its successors now come from this block
+                for succ in merged.term.successors() {
+                    for pred in preds[succ.0].iter_mut().filter(|pred| **pred == target) {
+                        *pred = BlockId(i);
+                    }
+                    for inst in self.blocks[succ.0].insts.iter_mut() {
+                        if let Op::Phi(ref mut incoming) = inst.op {
+                            for pair in incoming.iter_mut().filter(|pair| pair.0 == target) {
+                                pair.0 = BlockId(i);
+                            }
+                        }
+                    }
+                }
+                preds[target.0].clear();
+                self.blocks[i].insts.extend(merged.insts);
+                self.blocks[i].term = merged.term;
+            }
+        }
+    }
+}
+
+// Drops unreachable blocks and puts the others in reverse postorder
+fn order_blocks(func: &mut Function) {
+    let order = func.reverse_postorder();
+    let mut new_index = vec![None; func.blocks.len()];
+    for (i, block) in order.iter().enumerate() {
+        new_index[block.0] = Some(BlockId(i));
+    }
+    let mut blocks: Vec<Option<Block>> = mem::take(&mut func.blocks).into_iter().map(Some).collect();
+    for block in order {
+        let mut block = blocks[block.0].take().unwrap();
+        for inst in block.insts.iter_mut() {
+            if let Op::Phi(ref mut incoming) = inst.op {
+                *incoming = incoming.iter()
+                    .filter_map(|&(pred, value)| new_index[pred.0].map(|pred| (pred, value)))
+                    .collect();
+            }
+        }
+        block.term = match block.term {
+            Term::Jump(target) => Term::Jump(new_index[target.0].unwrap()),
+            Term::Branch(cond, then, otherwise) => {
+                Term::Branch(cond, new_index[then.0].unwrap(), new_index[otherwise.0].unwrap())
+            },
+            term => term,
+        };
+        func.blocks.push(block);
+    }
+}
+
+// What `value` ends up replaced with
+fn resolve(replace: &HashMap<Value, Value>, mut value: Value) -> Value {
+    while let Some(&by) = replace.get(&value) {
+        value = by;
+    }
+    value
+}
+
+// Replaces phis that merge a single value with that value
+fn remove_trivial_phis(func: &mut Function) {
+    loop {
+        let mut replace = HashMap::new();
+        for block in &func.blocks {
+            for inst in &block.insts {
+                if let Op::Phi(ref incoming) = inst.op {
+                    let phi = inst.value.unwrap();
+                    let mut merged: Vec<Value> = incoming.iter()
+                        .map(|&(_, value)| resolve(&replace, value))
+                        .filter(|&value| value != phi)
+                        .collect();
+                    merged.sort();
+                    merged.dedup();
+                    if merged.len() == 1 {
+                        replace.insert(phi, merged[0]);
+                    }
+                }
+            }
+        }
+        if replace.is_empty() {
+            return;
+        }
+        for block in func.blocks.iter_mut() {
+            block.insts.retain(|inst| !matches!(inst.value, Some(value) if replace.contains_key(&value)));
+            for inst in block.insts.iter_mut() {
+                inst.op.map_operands(|value| resolve(&replace, value));
+            }
+            block.term.map_operands(|value| resolve(&replace, value));
+        }
+    }
+}
+
+// Numbers the values in the order they are defined
+fn renumber(func: &mut Function) {
+    let mut new_value = HashMap::new();
+    let mut types = Vec::new();
+    for block in &func.blocks {
+        for inst in &block.insts {
+            if let Some(value) = inst.value {
+                new_value.insert(value, Value(types.len()));
+                types.push(func.types[value.0]);
+            }
+        }
+    }
+    for block in func.blocks.iter_mut() {
+        for inst in block.insts.iter_mut() {
+            inst.value = inst.value.map(|value| new_value[&value]);
+            inst.op.map_operands(|value| new_value[&value]);
+        }
+        block.term.map_operands(|value| new_value[&value]);
+    }
+    func.types = types;
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use super::super::tokenizer::{Token, VarType};
+use super::super::checker::type_name;
+use super::super::ast::BinOp;
+use super::{Module, Function, Inst, Op, Term, Value, BlockId};
+
+// Checks that a module is well formed: every block is reachable and ends in
+// a terminator with valid targets, every value is defined once before all of
+// its uses, phis agree with the predecessors of their block, and operands
+// have the types their instructions expect
+pub fn verify(module: &Module) -> Result<(), String> {
+    let globals: HashMap<&str, VarType> = module.globals.iter()
+        .map(|global| (global.0.as_str(), global.1))
+        .collect();
+    // globals in memory that only a fragment's earlier pieces declared,
+    // which have to keep one type
+    let mut other_globals = HashMap::new();
+    for (index, func) in module.functions.iter().enumerate() {
+        let mut verifier = Verifier {
+            module,
+            func,
+            globals: &globals,
+            other_globals: &mut other_globals,
+            is_main: index == 0,
+            defs: HashMap::new()
+        };
+        verifier.function().map_err(|message| format!("in fn {}: {}", func.id, message))?;
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    module: &'a Module,
+    func: &'a Function,
+    globals: &'a HashMap<&'a str, VarType>,
+    other_globals: &'a mut HashMap<String, VarType>,
+    is_main: bool,
+    // where each value is defined: block, and index in it
+    defs: HashMap<Value, (usize, usize)>
+}
+
+impl<'a> Verifier<'a> {
+    fn function(&mut self) -> Result<(), String> {
+        let func = self.func;
+        if func.blocks.is_empty() {
+            return Err(String::from("no blocks"));
+        }
+        for (i, block) in func.blocks.iter().enumerate() {
+            for succ in block.term.successors() {
+                if succ.0 >= func.blocks.len() {
+                    return Err(format!("b{} jumps to missing {}", i, succ));
+                }
+            }
+        }
+        let preds = func.predecessors();
+        if !preds[0].is_empty() {
+            return Err(String::from("the entry block has predecessors"));
+        }
+        let idom = dominators(func)?;
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            for (j, inst) in block.insts.iter().enumerate() {
+                if let Some(value) = inst.value {
+                    if value.0 >= func.types.len() {
+                        return Err(format!("{} has no type", value));
+                    }
+                    if self.defs.insert(value, (i, j)).is_some() {
+                        return Err(format!("{} is defined twice", value));
+                    }
+                }
+            }
+        }
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            let mut phis_done = false;
+            for (j, inst) in block.insts.iter().enumerate() {
+                let context = format!("b{}: {}", i, func.inst_str(inst));
+                if let Op::Phi(ref incoming) = inst.op {
+                    if phis_done {
+                        return Err(format!("{}: phi after other instructions", context));
+                    }
+                    let mut from: Vec<BlockId> = incoming.iter().map(|&(block, _)| block).collect();
+                    from.sort();
+                    let mut expected = preds[i].clone();
+                    expected.sort();
+                    if from != expected {
+                        return Err(format!("{}: phi doesn't match the predecessors", context));
+                    }
+                    for &(pred, value) in incoming {
+                        // available at the end of the predecessor
+                        self.available(value, pred.0, usize::MAX, &idom)
+                            .map_err(|message| format!("{}: {}", context, message))?;
+                    }
+                } else {
+                    phis_done = true;
+                    for value in inst.op.operands() {
+                        self.available(value, i, j, &idom)
+                            .map_err(|message| format!("{}: {}", context, message))?;
+                    }
+                }
+                self.inst(inst, i).map_err(|message| format!("{}: {}", context, message))?;
+            }
+            let context = format!("b{}: {}", i, block.term);
+            for value in block.term.operands() {
+                self.available(value, i, usize::MAX, &idom)
+                    .map_err(|message| format!("{}: {}", context, message))?;
+            }
+            self.term(&block.term).map_err(|message| format!("{}: {}", context, message))?;
+        }
+        Ok(())
+    }
+
+    // Checks that `value` is defined before instruction `index` of `block`
+    fn available(&self, value: Value, block: usize, index: usize, idom: &[usize]) -> Result<(), String> {
+        let &(def_block, def_index) = match self.defs.get(&value) {
+            Some(def) => def,
+            None => return Err(format!("{} is never defined", value)),
+        };
+        if def_block == block {
+            if def_index < index {
+                return Ok(());
+            }
+            return Err(format!("{} is used before its definition", value));
+        }
+        // walk up the dominator tree
+        let mut b = block;
+        while b != 0 {
+            b = idom[b];
+            if b == def_block {
+                return Ok(());
+            }
+        }
+        Err(format!("the definition of {} doesn't dominate its use", value))
+    }
+
+    fn value_type(&self, value: Value) -> VarType {
+        self.func.types[value.0]
+    }
+
+    fn expect(&self, value: Value, var_type: VarType) -> Result<(), String> {
+        let actual = self.value_type(value);
+        if actual != var_type {
+            return Err(format!("{} is {}, expected {}", value, type_name(actual), type_name(var_type)));
+        }
+        Ok(())
+    }
+
+    fn global(&mut self, id: &str, var_type: VarType) -> Result<(), String> {
+        if let Some(&(_, expected)) = self.func.locals.iter().find(|local| local.0 == id) {
+            if expected != var_type {
+                return Err(format!("local {} is {}", id, type_name(expected)));
+            }
+            return Ok(());
+        }
+        let expected = match self.globals.get(id) {
+            Some(&expected) => expected,
+            None => *self.other_globals.entry(id.to_string()).or_insert(var_type),
+        };
+        if expected != var_type {
+            return Err(format!("global {} is {}", id, type_name(expected)));
+        }
+        Ok(())
+    }
+
+    fn inst(&mut self, inst: &Inst, block: usize) -> Result<(), String> {
+        // the type of the result, NONE if there is none
+        let result = inst.value.map_or(VarType::NONE, |value| self.value_type(value));
+        let expected = match inst.op {
+            Op::Const(value) => {
+                if result == VarType::BOOL && value > 1 {
+                    return Err(String::from("booleans are 0 or 1"));
+                }
+                if result == VarType::STR || result == VarType::NONE {
+                    return Err(String::from("constants are numbers"));
+                }
+                result
+            },
+            Op::Str(_) => VarType::STR,
+            Op::Param(index) => {
+                if self.is_main || block != 0 {
+                    return Err(String::from("parameters only exist in the entry block of functions"));
+                }
+                match self.func.params.get(index) {
+                    Some(&param) => param,
+                    None => return Err(format!("there are only {} parameters", self.func.params.len())),
+                }
+            },
+            Op::Phi(ref incoming) => {
+                for &(_, value) in incoming {
+                    self.expect(value, result)?;
+                }
+                if result == VarType::NONE {
+                    return Err(String::from("phis have a value"));
+                }
+                result
+            },
+            Op::Load(ref id) => {
+                self.global(id, result)?;
+                result
+            },
+            Op::Store(ref id, value) => {
+                let var_type = self.value_type(value);
+                self.global(id, var_type)?;
+                VarType::NONE
+            },
+            Op::Binary(op, lhs, rhs) => {
+                let operand_type = self.value_type(lhs);
+                self.expect(rhs, operand_type)?;
+                let allowed = match op {
+                    BinOp::Add => operand_type != VarType::File,
+                    BinOp::Mul => operand_type == VarType::INT || operand_type == VarType::BOOL,
+                    BinOp::Sub | BinOp::Div => operand_type == VarType::INT,
+                    BinOp::Eq => true,
+                };
+                if !allowed || operand_type == VarType::NONE {
+                    return Err(format!("operands can't be {}", type_name(operand_type)));
+                }
+                if op == BinOp::Eq { VarType::BOOL } else { operand_type }
+            },
+            Op::Not(value) => {
+                self.expect(value, VarType::BOOL)?;
+                VarType::BOOL
+            },
+            Op::Convert(value) => {
+                let from = self.value_type(value);
+                if from == result || !Token::can_convert_to(from, result) {
+                    return Err(format!("can't convert {} to {}", type_name(from), type_name(result)));
+                }
+                result
+            },
+            Op::Call(ref id, ref args) => {
+                // functions of earlier pieces of a fragment aren't known
+                match self.module.functions.iter().skip(1).find(|func| func.id == *id) {
+                    Some(callee) => {
+                        self.args(args, &callee.params)?;
+                        callee.ret_type
+                    },
+                    None => result,
+                }
+            },
+            Op::Builtin(builtin, ref args) => {
+                let (params, ret_type) = builtin.signature(args.len());
+                self.args(args, &params)?;
+                ret_type
+            },
+            Op::Print(value) => {
+                if self.value_type(value) == VarType::NONE {
+                    return Err(String::from("nothing to print"));
+                }
+                VarType::NONE
+            },
+            Op::PrintChar(_) => VarType::NONE,
+            Op::Define(ref id, index) => {
+                if index == 0 || index >= self.module.functions.len() || self.module.functions[index].id != *id {
+                    return Err(format!("no function {} at {}", id, index));
+                }
+                VarType::NONE
+            },
+        };
+        if result != expected {
+            return Err(format!("result is {}, expected {}", type_name(result), type_name(expected)));
+        }
+        Ok(())
+    }
+
+    fn args(&self, args: &[Value], params: &[VarType]) -> Result<(), String> {
+        if args.len() != params.len() {
+            return Err(format!("{} arguments, expected {}", args.len(), params.len()));
+        }
+        for (&arg, &param) in args.iter().zip(params) {
+            self.expect(arg, param)?;
+        }
+        Ok(())
+    }
+
+    fn term(&self, term: &Term) -> Result<(), String> {
+        match *term {
+            Term::Jump(_) => Ok(()),
+            Term::Branch(cond, ..) => self.expect(cond, VarType::BOOL),
+            Term::Return(value) => {
+                let ret_type = self.func.ret_type;
+                match value {
+                    Some(value) => self.expect(value, ret_type),
+                    None if ret_type == VarType::NONE => Ok(()),
+                    None => Err(format!("must return {}", type_name(ret_type))),
+                }
+            },
+            Term::Exit(status) => self.expect(status, VarType::INT),
+        }
+    }
+}
+
+// The immediate dominator of every block, the entry being its own, using
+// "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy. Fails if
+// a block can't be reached.
+fn dominators(func: &Function) -> Result<Vec<usize>, String> {
+    let count = func.blocks.len();
+    let order: Vec<usize> = func.reverse_postorder().iter().map(|block| block.0).collect();
+    if let Some(block) = (0..count).find(|block| !order.contains(block)) {
+        return Err(format!("b{} is unreachable", block));
+    }
+    let mut rpo_index = vec![0; count];
+    for (i, &block) in order.iter().enumerate() {
+        rpo_index[block] = i;
+    }
+
+    let preds = func.predecessors();
+    let mut idom: Vec<Option<usize>> = vec![None; count];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in order.iter().skip(1) {
+            let mut new_idom = None;
+            for pred in &preds[block] {
+                if idom[pred.0].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred.0,
+                    Some(other) => intersect(&idom, &rpo_index, pred.0, other),
+                });
+            }
+            if new_idom.is_some() && idom[block] != new_idom {
+                idom[block] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    Ok(idom.into_iter().map(|dom| dom.unwrap()).collect())
+}
+
+fn intersect(idom: &[Option<usize>], rpo_index: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
@@ -0,0 +1,316 @@
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::tokenizer::VarType;
+use super::ast::Builtin;
+
+// What running a program needs besides the program itself: its arguments,
+// its streams and open files, and the builtins that work with them. The
+// interpreter and the bytecode VM share it, so both behave like the compiled
+// executable: numbers are unsigned 64 bit values that wrap around, booleans
+// are 0 or 1, files are handles where 0 means "no file", and runtime errors
+// print the same message and exit with the same status.
+pub struct Machine<'a> {
+    args: &'a [String],
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    errors: &'a mut dyn Write,
+    // file handle n is files[n - 1], None once closed
+    files: Vec<Option<Handle>>,
+    rand_state: u64
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(u64),
+    Str(Rc<[u8]>)
+}
+
+enum Handle {
+    Read(BufReader<File>),
+    Write(BufWriter<File>)
+}
+
+// Why a program stopped before reaching its end
+pub enum Stop {
+    // 🚪 with this status
+    Exit(i32),
+    // a runtime error, already reported, and the status it exits with
+    Error(i32)
+}
+
+pub type Run<T> = Result<T, Stop>;
+
+impl Value {
+    pub fn default(var_type: VarType) -> Value {
+        match var_type {
+            VarType::STR => Value::Str(Rc::from(&b""[..])),
+            _ => Value::Int(0),
+        }
+    }
+
+    pub fn int(&self) -> u64 {
+        match *self {
+            Value::Int(value) => value,
+            Value::Str(_) => panic!("Internal error: expected a number, found a string"),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match *self {
+            Value::Str(ref value) => value,
+            Value::Int(_) => panic!("Internal error: expected a string, found a number"),
+        }
+    }
+}
+
+pub fn string(bytes: &[u8]) -> Value {
+    Value::Str(Rc::from(bytes))
+}
+
+pub fn bool_str(value: u64) -> &'static str {
+    if value != 0 { "👍" } else { "👎" }
+}
+
+// strtol in base 10: leading white space, an optional sign and digits, with
+// values too large for a signed number saturating
+pub fn parse_int(text: &[u8]) -> u64 {
+    let mut pos = 0;
+    while pos < text.len() && (text[pos] == b' ' || (b'\t'..=b'\r').contains(&text[pos])) {
+        pos += 1;
+    }
+    let negative = pos < text.len() && text[pos] == b'-';
+    if pos < text.len() && (text[pos] == b'-' || text[pos] == b'+') {
+        pos += 1;
+    }
+    let mut value: u64 = 0;
+    let mut overflow = false;
+    while pos < text.len() && text[pos].is_ascii_digit() {
+        let digit = (text[pos] - b'0') as u64;
+        match value.checked_mul(10).and_then(|v| v.checked_add(digit)) {
+            Some(v) => value = v,
+            None => overflow = true,
+        }
+        pos += 1;
+    }
+    if negative {
+        if overflow || value > i64::MAX as u64 + 1 {
+            i64::MIN as u64
+        } else {
+            value.wrapping_neg()
+        }
+    } else if overflow || value > i64::MAX as u64 {
+        i64::MAX as u64
+    } else {
+        value
+    }
+}
+
+// Reads a whole line with its newline, None at the end of the input
+fn read_line(reader: &mut dyn BufRead) -> Option<Vec<u8>> {
+    let mut line = Vec::new();
+    match reader.read_until(b'\n', &mut line) {
+        Ok(read) if read > 0 => Some(line),
+        _ => None,
+    }
+}
+
+// A line without its newline, or the empty string at the end of the input
+fn read_str(reader: &mut dyn BufRead) -> Value {
+    match read_line(reader) {
+        Some(mut line) => {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            string(&line)
+        },
+        None => string(b""),
+    }
+}
+
+fn at_eof(reader: &mut dyn BufRead) -> bool {
+    match reader.fill_buf() {
+        Ok(buf) => buf.is_empty(),
+        Err(_) => true,
+    }
+}
+
+impl<'a> Machine<'a> {
+    // `args` are the program's arguments, not including the program name
+    pub fn new(args: &'a [String], input: &'a mut dyn BufRead, output: &'a mut dyn Write,
+               errors: &'a mut dyn Write) -> Machine<'a> {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Machine {
+            args,
+            input,
+            output,
+            errors,
+            files: Vec::new(),
+            rand_state: seed
+        }
+    }
+
+    // The streams the program reads and writes, for sharing them with the REPL
+    pub fn streams(&mut self) -> (&mut dyn BufRead, &mut dyn Write, &mut dyn Write) {
+        (&mut *self.input, &mut *self.output, &mut *self.errors)
+    }
+
+    // Output that is still buffered is written, like the C library does at
+    // exit
+    pub fn flush(&mut self) {
+        let _ = self.output.flush();
+        for handle in self.files.iter_mut() {
+            if let Some(Handle::Write(ref mut writer)) = *handle {
+                let _ = writer.flush();
+            }
+        }
+    }
+
+    pub fn runtime_error(&mut self, message: &str, status: i32) -> Stop {
+        let _ = self.output.flush();
+        let _ = writeln!(self.errors, "🚨 Runtime error: {}", message);
+        Stop::Error(status)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        let _ = self.output.write_all(bytes);
+    }
+
+    pub fn print(&mut self, value: &Value, var_type: VarType) {
+        match var_type {
+            VarType::STR => self.write(value.bytes()),
+            VarType::BOOL => self.write(bool_str(value.int()).as_bytes()),
+            _ => {
+                let text = value.int().to_string();
+                self.write(text.as_bytes());
+            },
+        }
+    }
+
+    pub fn builtin(&mut self, builtin: Builtin, args: Vec<Value>) -> Run<Value> {
+        let value = match builtin {
+            Builtin::Rand => {
+                let limit = args[0].int();
+                if limit == 0 {
+                    return Err(self.runtime_error("division by zero", 4));
+                }
+                Value::Int(self.rand() % limit)
+            },
+            Builtin::ReadStr => {
+                // show any pending prompt before waiting for input
+                let _ = self.output.flush();
+                read_str(self.input)
+            },
+            Builtin::ReadInt => {
+                let _ = self.output.flush();
+                Value::Int(parse_int(read_str(self.input).bytes()))
+            },
+            Builtin::Eof => {
+                if args.is_empty() {
+                    let _ = self.output.flush();
+                    Value::Int(at_eof(self.input) as u64)
+                } else {
+                    let eof = match self.file(&args[0]) {
+                        Some(&mut Handle::Read(ref mut reader)) => at_eof(reader),
+                        Some(&mut Handle::Write(ref mut writer)) => {
+                            let _ = writer.flush();
+                            true
+                        },
+                        None => true,
+                    };
+                    Value::Int(eof as u64)
+                }
+            },
+            Builtin::ArgCount => Value::Int(self.args.len() as u64),
+            Builtin::Arg => {
+                let index = args[0].int();
+                if index >= self.args.len() as u64 {
+                    return Err(self.runtime_error("argument index out of bounds", 3));
+                }
+                string(self.args[index as usize].as_bytes())
+            },
+            Builtin::Parse => Value::Int(parse_int(args[0].bytes())),
+            Builtin::Open => Value::Int(self.open(args[0].bytes(), args[1].bytes())),
+            Builtin::ReadLine => match self.file(&args[0]) {
+                Some(&mut Handle::Read(ref mut reader)) => read_str(reader),
+                // the C library writes out what is buffered before failing
+                Some(&mut Handle::Write(ref mut writer)) => {
+                    let _ = writer.flush();
+                    string(b"")
+                },
+                None => string(b""),
+            },
+            Builtin::Write => {
+                let written = match self.file(&args[0]) {
+                    Some(&mut Handle::Write(ref mut writer)) => {
+                        writer.write_all(args[1].bytes()).and_then(|_| writer.write_all(b"\n")).is_ok()
+                    },
+                    _ => false,
+                };
+                Value::Int(written as u64)
+            },
+            Builtin::Close => {
+                let handle = args[0].int() as usize;
+                let closed = match self.files.get_mut(handle.wrapping_sub(1)).and_then(Option::take) {
+                    Some(Handle::Write(mut writer)) => writer.flush().is_ok(),
+                    Some(Handle::Read(_)) => true,
+                    None => false,
+                };
+                Value::Int(closed as u64)
+            },
+        };
+        Ok(value)
+    }
+
+    // A 64 bit linear congruential generator, the same one the bundled
+    // runtime uses
+    fn rand(&mut self) -> u64 {
+        self.rand_state = self.rand_state.wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.rand_state >> 33
+    }
+
+    fn file(&mut self, handle: &Value) -> Option<&mut Handle> {
+        let handle = handle.int() as usize;
+        match self.files.get_mut(handle.wrapping_sub(1)) {
+            Some(&mut Some(ref mut file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    // Opens `path` for 📖 reading, 📝 writing or 📎 appending, returning the
+    // handle or 0 on failure
+    fn open(&mut self, path: &[u8], mode: &[u8]) -> u64 {
+        let path = OsStr::from_bytes(path);
+        let handle = if mode == "📖".as_bytes() {
+            File::open(path).map(|file| Handle::Read(BufReader::new(file)))
+        } else if mode == "📝".as_bytes() {
+            File::create(path).map(|file| Handle::Write(BufWriter::new(file)))
+        } else if mode == "📎".as_bytes() {
+            OpenOptions::new().append(true).create(true).open(path)
+                .map(|file| Handle::Write(BufWriter::new(file)))
+        } else {
+            return 0;
+        };
+        match handle {
+            Ok(handle) => {
+                self.files.push(Some(handle));
+                self.files.len() as u64
+            },
+            Err(_) => 0,
+        }
+    }
+}
+
+pub fn convert(value: Value, from: VarType, to: VarType) -> Value {
+    match (from, to) {
+        (VarType::INT, VarType::STR) => string(value.int().to_string().as_bytes()),
+        (VarType::BOOL, VarType::STR) => string(bool_str(value.int()).as_bytes()),
+        // booleans are always 0 or 1
+        (_, VarType::BOOL) => Value::Int((value.int() != 0) as u64),
+        _ => value,
+    }
+}
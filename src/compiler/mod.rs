@@ -1,800 +1,201 @@
 mod tokenizer;
+mod ast;
+mod parser;
+mod checker;
+mod dead;
+mod ir;
+mod opt;
+mod codegen;
+mod cgen;
+mod wasm;
+mod assembler;
+mod bytecode;
+mod machine;
+mod interpreter;
+mod repl;
+use std::io::{BufRead, Write};
 use self::tokenizer::Tokenizer;
-use std::io::prelude::*;
-use std::error::Error;
-use std::fs::File;
-use self::tokenizer::TokenType;
-use self::tokenizer::VarType;
 use self::tokenizer::Token;
-use std::collections::HashMap;
+use self::ast::Program;
+use self::parser::Parser;
+use self::checker::Checker;
+use self::codegen::{CodeGen, Target, X86_64, Aarch64, Riscv64};
+use self::cgen::CGen;
+use self::wasm::WasmGen;
+use self::interpreter::Interpreter;
+pub use self::repl::Repl;
+pub use self::dead::Warning;
 
 pub struct Compiler {
     tokens: Vec<Token>,
-    file: File,
-    pos: usize
+    program: Program,
+    // about the code check_syntax removed
+    warnings: Vec<Warning>,
+    // how much gen_ir optimizes, see opt::optimize
+    opt_level: u8,
+    // the source file named in debug information, None for none
+    debug_file: Option<String>
 }
 
 impl Compiler {
-    pub fn new(program: String, file: File) -> Compiler {
+    pub fn new(program: String) -> Compiler {
         let mut tokenizer = Tokenizer::new(program);
         let tokens = tokenizer.tokenize();
-        // println!("{:?}", tokens);
+        let program = Parser::new(&tokens).parse();
 
         Compiler {
             tokens: tokens,
-            file: file,
-            pos: 0
+            program: program,
+            warnings: Vec::new(),
+            opt_level: 0,
+            debug_file: None
         }
     }
 
-    fn consume(&mut self) {
-        self.pos += 1;
-        // println!("{:?}", self.tokens[self.pos]);
-    }
-
-    fn peek(&self) -> TokenType {
-        self.tokens[self.pos].kind
-    }
-
-    fn current(&self) -> Token {
-        self.tokens[self.pos].clone()
-    }
-
-    fn debug_str(&self) -> String {
-        let mut content = format!("token at pos {}: ", self.pos);
-        let mut count = 0;
-        while count < 5 && self.pos+count < self.tokens.len(){
-            content.push_str(&self.tokens[self.pos + count].to_string());
-            count += 1;
+    // One token per line, for --emit tokens
+    pub fn dump_tokens(&self) -> String {
+        let mut content = String::new();
+        for tkn in &self.tokens {
+            content.push_str(&format!("{:?}\n", tkn));
         }
         content
     }
 
-    fn annotate_type(&mut self, var_type: VarType) {
-        self.tokens[self.pos].var_type = var_type;
-    }
-
-    fn annotate_func(&mut self, args: &Vec<VarType>) {
-        self.tokens[self.pos].kind = TokenType::FUNID;
-        self.tokens[self.pos].copy_arg_types(&args);
-    }
-
-    fn reset(&mut self) {
-        self.pos = 0;
+    // The (possibly checked) syntax tree, for --emit ast
+    pub fn dump_ast(&self) -> String {
+        format!("{:#?}\n", self.program)
     }
 
-    fn write(&mut self, data: &str) {
-        if let Err(why) = self.file.write_all(data.as_bytes()) {
-            panic!("couldn't write to file: {}", why.description());
-        }
-    }
-
-    fn write_print_str(&mut self) {
-        self.write("\t\tpush %rax\n\
-                    \t\tmovq $0, %rax\n\
-                    \t\tmovq $Format_strings, %rdi\n\
-                    \t\tcall printf\n\
-                    \t\tpop %rax\n");
+    // Type checks the program, annotating every expression with its type,
+    // and removes the code that does nothing
+    pub fn check_syntax(&mut self) {
+        Checker::new(&self.tokens).check(&mut self.program);
+        self.warnings = dead::eliminate(&mut self.program, &self.tokens);
     }
 
-    fn write_print_int(&mut self) {
-        self.write("\t\tpush %rax\n\
-                    \t\tmovq $0, %rax\n\
-                    \t\tmovq $Format_ints, %rdi\n\
-                    \t\tcall printf\n\
-                    \t\tpop %rax\n");
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
     }
 
-    pub fn gen_data(&mut self) -> (HashMap<String, VarType>, HashMap<String, Vec<VarType>>) {
-        let mut content = String::from(".data\n\
-                        \t\targc_: .quad 0\n\
-                        \t\tFormat_ints: .byte '%', 'l', 'u', 10, 0\n\
-                        \t\tFormat_strings: .byte '%', 's', 10, 0\n\
-                        \t\tFuncTable: .quad 0\n\
-                        \t\tFuncCall: .quad 0\n");
-
-        let mut vars: HashMap<String, VarType> = HashMap::new();
-        let mut funcs: HashMap<String, Vec<VarType>> = HashMap::new();
-
-        while self.peek() != TokenType::END {
-            match self.peek(){
-                TokenType::NEW => {
-                    self.consume();
-
-                    // type token
-                    let var_type = match self.peek() {
-                        TokenType::BOOL => VarType::BOOL,
-                        TokenType::INT => VarType::INT,
-                        TokenType::STR => VarType::STR,
-                        _ => panic!("Bad instantiation. Found NEW keyword without type: {}", 
-                            self.debug_str()),
-                    };
-                    self.consume();
-
-                    // id token
-                    match self.peek() {
-                        TokenType::ID => {
-                            let id = self.current().value_str;
-                            if var_type == VarType::INT || var_type == VarType::BOOL {
-                                content.push_str(&format!("\t\t{}: .quad 0\n", id));
-                            } else {
-                                // skip over LEND, ID again, and EQ
-                                self.consume();
-                                self.consume();
-                                self.consume();
-                                self.consume();
-                                let s = self.current().value_str;
-                                content.push_str(&format!("\t\t{}: .string \"{}\"\n", id, s));
-                            }
-                            vars.insert(id, var_type);
-                        },
-                        _ => panic!("Bad instantiation. No variable name provided: {}", 
-                            self.debug_str()),
-                    }
-                },
-                TokenType::FUN => {
-                    self.consume();
-                    match self.peek() {
-                        TokenType::ID => {
-                            let id = self.current().value_str;
-                            content.push_str(&format!("\t\t{}: .quad 0\n", id));
-                            self.consume();
-                            if self.peek() != TokenType::LPAREN {
-                                panic!("Function declaration missing parentheses: {}", self.debug_str());
-                            }
-                            self.consume();
-                            let mut arg_count = 0;
-                            let mut args: Vec<VarType> = Vec::new();
-                            while self.peek() != TokenType::RPAREN {
-                                // argument type
-                                let var_type = match self.peek() {
-                                    TokenType::BOOL => VarType::BOOL,
-                                    TokenType::INT => VarType::INT,
-                                    TokenType::STR => VarType::STR,
-                                    _ => panic!("Must declare type of argument: {}", 
-                                        self.debug_str()),
-                                };
-                                self.consume();
-                                args.push(var_type);
-
-                                // argument name
-                                if self.peek() != TokenType::ID {
-                                    panic!("Arguments must have names: {}", self.debug_str());
-                                }
-                                self.consume();
-
-                                // check argument count
-                                arg_count += 1;
-                                if arg_count > 6 {
-                                    panic!("Limited to six arguments: {}", self.debug_str());
-                                }
-
-                                // consume delimiter
-                                if self.peek() == TokenType::DELIM {
-                                    self.consume();
-                                }
-                            }
-
-                            funcs.insert(id, args);
-                        },
-                        _ => panic!("Bad function declaration. No function name provided: {}", 
-                            self.debug_str()),
-                    }
-                },
-                _ => {
-                    self.consume();
-                }
-            }
-        }
-
-        self.write(&content);
-
-        self.reset();
-
-        // println!("{:?}", vars);
-
-        (vars, funcs)
+    pub fn set_opt_level(&mut self, level: u8) {
+        self.opt_level = level;
     }
 
-    pub fn gen_annotations(&mut self, vars: HashMap<String, VarType>, funcs: HashMap<String, Vec<VarType>>) {
-        // annotate all id types
-        let mut declaring_fun = false;
-        while self.peek() != TokenType::END {
-            match self.peek() {
-                TokenType::ID => {
-                    let id = self.current().value_str;
-                    // println!("{:?}", id);
-                    if !declaring_fun {
-                        match vars.get(&id) {
-                            Some(var_type) => self.annotate_type(*var_type),
-                            None => {
-                                match funcs.get(&id) {
-                                    Some(args) => self.annotate_func(args),
-                                    None => panic!("Variable or function never declared: {}", self.debug_str()),
-                                }
-                            }
-                        }
-                    }
-                    else {
-                        match vars.get(&id) {
-                            Some(_) => panic!("Argument cannot share name with global variable: {}",
-                                                     self.debug_str()),
-                            None => {},
-                        }
-                    }
-                    self.consume();
-                },
-                TokenType::FUN => {
-                    declaring_fun = true;
-                    self.consume();
-                },
-                TokenType::RPAREN => {
-                    if declaring_fun {
-                        declaring_fun = false;
-                    }
-                    self.consume();
-                }
-                _ => {
-                    self.consume();
-                }
-            }
-            
-        }
-
-        self.reset();
+    // Makes the native targets keep every variable in memory and describe
+    // the code and its variables to debuggers, as coming from `file`
+    pub fn set_debug_info(&mut self, file: &str) {
+        self.debug_file = Some(file.to_string());
     }
 
-    pub fn check_syntax(&mut self) {
-        // self.check_syntax_seq();
-        while self.peek() != TokenType::END {
-            self.check_statement_syntax();
+    // The checked program in the intermediate representation the native
+    // backends are generated from, optimized and verified to be well formed
+    pub fn gen_ir(&self) -> ir::Module {
+        let mut module = if self.debug_file.is_some() {
+            ir::lower_debug(&self.program)
+        } else {
+            ir::lower(&self.program)
+        };
+        opt::optimize(&mut module, self.opt_level);
+        if let Err(message) = ir::verify(&module) {
+            panic!("Invalid IR: {}\n{}", message, module);
         }
-        self.reset();
+        module
     }
 
-    fn check_syntax_seq(&mut self) {
-        while self.peek() != TokenType::RBRACE {
-            self.check_statement_syntax();
-        }
+    // The intermediate representation as text, for --emit ir
+    pub fn dump_ir(&self) -> String {
+        self.gen_ir().to_string()
     }
 
-    fn check_statement_syntax(&mut self) {
-        match self.peek() {
-            TokenType::LBRACE => {
-                self.consume();
-                self.check_syntax_seq();
-                if self.peek() != TokenType::RBRACE {
-                    panic!("Missing a closing brace: {}", self.debug_str());
-                }
-                self.consume();
-            },
-            TokenType::NEW => {
-                self.consume();
-                // type token
-                self.consume();
-                // id token
-                self.consume();
-                // enforce end punctuation
-                if self.peek() != TokenType::LEND {
-                    panic!("Missing line end punctuation: {}", 
-                        self.debug_str());
-                }
-                self.consume();
-            },
-            TokenType::ID => {
-                // consume var name token
-                let var_type = self.current().var_type;
-                self.consume();
-
-                // consume EQ token
-                if self.peek() != TokenType::EQ {
-                    panic!("Bad instantiation. Must assign value to new variable: {}", 
-                        self.debug_str());
-                }
-                self.consume();
-
-                // check expression validity and check if assignment types match
-                let expr_type = self.check_expr_syntax();
-                if !(Token::can_convert_to(expr_type, var_type)){
-                    panic!("Illegal assignment. Cannot convert to {}: {}",
-                        match var_type {
-                            VarType::BOOL => "bool",
-                            VarType::INT => "int",
-                            VarType::STR => "string",
-                            _ => ""
-                        },
-                        self.debug_str());
-                }
-
-                // enforce end punctuation
-                if self.peek() != TokenType::LEND {
-                    panic!("Missing line end punctuation: {}", 
-                        self.debug_str());
-                }
-                self.consume();
-            },
-            TokenType::IF => {
-                self.consume();            
-                let expr_type = self.check_expr_syntax();
-                if !(Token::can_convert_to(expr_type, VarType::BOOL)) {
-                    panic!("Condition must evaluate to boolean: {}")
-                }
-                self.check_statement_syntax();
-                let mut ended = false;
-                while self.peek() == TokenType::ELSE {
-                    self.consume();
-                    if ended {
-                        panic!("Misplaced 'else': {}", self.debug_str());
-                    }
-                    if self.peek() == TokenType::IF {
-                        self.consume();
-                        let expr_type = self.check_expr_syntax();
-                        if !(Token::can_convert_to(expr_type, VarType::BOOL)) {
-                            panic!("Condition must evaluate to boolean: {}")
-                        }
-                        self.check_statement_syntax();
-                    }
-                    else {
-                        ended = true;
-                        self.check_statement_syntax();
-                    }
-                }
-            },
-            TokenType::WHILE => {
-                self.consume();
-                let expr_type = self.check_expr_syntax();
-                if !(Token::can_convert_to(expr_type, VarType::BOOL)) {
-                    panic!("Condition must evaluate to boolean: {}")
-                }
-                self.check_statement_syntax();
-            },
-            TokenType::PRINT => {
-                self.consume();
-                self.check_expr_syntax();
-
-                // enforce end punctuation
-                if self.peek() != TokenType::LEND {
-                    panic!("Missing line end punctuation: {}", 
-                        self.debug_str());
-                }
-                self.consume();
-            },
-            TokenType::FUN => {
-                self.consume();
-                while self.peek() != TokenType::RPAREN {
-                    self.consume();
-                }
-                self.consume();
-
-                self.check_statement_syntax();
-            },
-            TokenType::CALL => {
-                self.consume();
-
-                // FUNID token
-                if self.peek() != TokenType::FUNID {
-                    panic!("Cannot call undeclared function: {}", self.debug_str());
-                }
-                let args = self.current().arg_types;
-                self.consume();
-
-                // LPAREN token
-                if self.peek() != TokenType::LPAREN {
-                    panic!("Function call requires parentheses: {}", self.debug_str());
-                }
-                self.consume();
-
-                // check arg types
-                for arg_type in &args {
-                    let vt = self.check_expr_syntax();
-                    if !(Token::can_convert_to(vt, *arg_type)) {
-                        panic!("Mismatched types: expected {:?}, found {:?}: {}", arg_type, vt,
-                            self.debug_str());
-                    }
-                    // consume delimiter
-                    if self.peek() == TokenType::DELIM {
-                        self.consume();
-                    }
-                }
-
-                // RPAREN token
-                if self.peek() != TokenType::RPAREN {
-                    panic!("Missing closing parenthesis: {}", self.debug_str());
-                }
-                self.consume();
-
-                // enforce end punctuation
-                if self.peek() != TokenType::LEND {
-                    panic!("Missing line end punctuation: {}", 
-                        self.debug_str());
-                }
-                self.consume();
-            },
-            TokenType::RBRACE => {
-                return;
-            },
-            _ => {
-                // panic!("Unexpected token: {}", self.debug_str());
-            }
-        }
+    pub fn gen_code(&self) -> String {
+        self.gen_native(X86_64)
     }
 
-    fn check_expr_syntax(&mut self) -> VarType {
-        let mut vt1 = self.check_e3_syntax();
-        while self.peek() == TokenType::EQEQ {
-            self.consume();
-            let vt2 = self.check_e3_syntax();
-            if !(vt1 == vt2){
-                panic!("Cannot check equality of mismatched types: {}", self.debug_str());
-            }
-            vt1 = VarType::BOOL;
-        }
-        vt1
+    // AArch64 assembly for Linux, which a cross compiler assembles and links
+    pub fn gen_aarch64(&self) -> String {
+        self.gen_native(Aarch64)
     }
 
-    fn check_e3_syntax(&mut self) -> VarType {
-        let mut vt1 = self.check_e2_syntax();
-        while self.peek() == TokenType::PLUS || self.peek() == TokenType::MINUS {
-            let op_type = self.peek();
-            self.consume();
-            let vt2 = self.check_e2_syntax();
-
-            match op_type {
-                TokenType::PLUS => {
-                    if (vt1==VarType::STR) || (vt2==VarType::STR) {
-                        vt1 = VarType::STR;
-                    }
-                    else if (vt1==VarType::INT) || (vt2==VarType::INT) {
-                        vt1 = VarType::INT;
-                    }
-                },
-                TokenType::MINUS => {
-                    if (vt1==VarType::STR) || (vt2==VarType::STR) {
-                        panic!("Subtraction not defined for strings: {}", self.debug_str());
-                    }
-                    else {
-                        vt1 = VarType::INT;
-                    }
-                },
-                _ => panic!("Internal error: {}", self.debug_str()),
-            };
-        }
-        vt1
+    // RISC-V 64 assembly for Linux, which a cross compiler assembles and links
+    pub fn gen_riscv64(&self) -> String {
+        self.gen_native(Riscv64)
     }
 
-    fn check_e2_syntax(&mut self) -> VarType {
-        let mut vt1 = self.check_e1_syntax();
-        while (self.peek() == TokenType::MUL) || (self.peek() == TokenType::DIV) {
-            let op_type = self.peek();
-            self.consume();
-            let vt2 = self.check_e1_syntax();
-
-            match op_type {
-                TokenType::MUL => {
-                    if (vt1==VarType::STR) || (vt2==VarType::STR) {
-                        panic!("Multiplication not defined for strings: {}", self.debug_str());
-                    }
-                    else if (vt1==VarType::INT) || (vt2==VarType::INT) {
-                        vt1 = VarType::INT;
-                    }
-                },
-                TokenType::DIV => {
-                    if (vt1==VarType::STR) || (vt2==VarType::STR) {
-                        panic!("Division not defined for strings: {}", self.debug_str());
-                    }
-                    else {
-                        vt1 = VarType::INT;
-                    }
-                },
-                _ => panic!("Internal error: {}", self.debug_str()),
-            }
+    fn gen_native<T: Target>(&self, target: T) -> String {
+        let mut codegen = CodeGen::new(target);
+        if let Some(ref file) = self.debug_file {
+            let positions = self.tokens.iter().map(|token| (token.line, token.column)).collect();
+            codegen = codegen.with_debug_info(file, positions);
         }
-        vt1
+        codegen.gen(&self.gen_ir())
     }
 
-    fn check_e1_syntax(&mut self) -> VarType {
-        let vt1;
-        match self.peek() {
-            TokenType::VAL => {
-                vt1 = self.current().var_type;
-                self.consume();
-            },
-            TokenType::LPAREN => {
-                self.consume();
-                vt1 = self.check_expr_syntax();
-                if self.peek() != TokenType::RPAREN {
-                    panic!("Missing closing parenthesis: {}", self.debug_str());
-                }
-                self.consume();
-            },
-            TokenType::ID => {
-                vt1 = self.current().var_type;
-                self.consume();
-            },
-            TokenType::RAND => {
-                self.consume();
-
-                // LPAREN token
-                if self.peek() != TokenType::LPAREN {
-                    panic!("Function call requires parentheses: {}", self.debug_str());
-                }
-                self.consume();
-
-                // check arg
-                let arg_type = self.check_expr_syntax();
-                if !(Token::can_convert_to(arg_type, VarType::INT)) {
-                    panic!("Mismatched types: expected INT, found {:?}: {}", arg_type,
-                        self.debug_str());
-                }
-
-                // RPAREN token
-                if self.peek() != TokenType::RPAREN {
-                    panic!("Missing closing parenthesis: {}", self.debug_str());
-                }
-                self.consume();
-
-                vt1 = VarType::INT;
-            },
-            _ => {
-                panic!("Value not found: {}", self.debug_str());
-            }
-        }
-        vt1
+    // Portable C99 source, runtime included
+    pub fn gen_c(&self) -> String {
+        CGen::new().gen(&self.program)
     }
 
-    pub fn gen_code(&mut self) {
-        // let mut tokenizer = &self.tokenizer;
-        let content = "\n\n.text\n\
-                        .global main\n\
-                        .extern printf\n\
-                        .extern malloc\n\
-                        main:\n\
-                        \t\tmovq %rdi, argc_\n\
-                        \t\tmovq $16000, %rdi\n\
-                        \t\tcall malloc\n\
-                        \t\tmovq %rax, FuncTable\n";
-
-        // Write initialization data to .s file
-        self.write(content);
-
-        // Write code to .s file
-        self.program();
-
-        // Wrap up .s file
-        self.write("\t\tmovq $0, %rax\n\
-                    \t\tretq\n");
-
+    // WebAssembly in the text format, for reading
+    pub fn gen_wat(&self) -> String {
+        WasmGen::new().gen_wat(&self.program)
     }
 
-    fn program(&mut self) {
-        while self.statement() {}
+    // A WebAssembly module for browsers, importing its I/O from the host
+    pub fn gen_wasm(&self) -> Vec<u8> {
+        WasmGen::new().gen_wasm(&self.program)
     }
 
-    fn statement(&mut self) -> bool {
-        // println!("{:?}", self.current());
-        match self.peek() {
-            TokenType::CALL => {
-                self.consume();
-                let id = self.current().value_str;
-                self.write(&format!("\t\tcall *{}\n", id));
-                self.consume();
-            },
-            TokenType::FUN => {
-                let curr_pos = self.pos;
-                self.consume();
-                
-                // ID token
-                let id = self.current().value_str;
-                self.consume();
-
-                self.write(&format!("\t\tlea func_{}, %rax\n", curr_pos));
-                self.write(&format!("\t\tmovq %rax, {}\n", id));
-                self.write(&format!("\t\tjmp finish_define_func_{}\n", curr_pos));
-                self.write(&format!("func_{}:\n", curr_pos));
-                self.statement();
-                self.write("\t\tret\n");
-                self.write(&format!("finish_define_func_{}:\n", curr_pos));
-            },
-            TokenType::LBRACE => {
-                self.consume();
-                self.program();
-                    
-                // RBRACE token
-                self.consume();
-            },
-            TokenType::IF => {
-                self.consume();
-                self.expression();
-                self.write("\t\tcmp $0, %rax\n");
-                let curr_pos = self.pos;
-                self.write(&format!("\t\tje if_{}\n", curr_pos));
-                self.statement();
-                self.write(&format!("\t\tjmp done_if_{}\n", curr_pos));
-                self.write(&format!("if_{}:\n", curr_pos));
-
-                // println!("{:?}", self.current());
-                if self.peek() == TokenType::ELSE {
-                    self.consume();
-                    self.statement();
-                }
-
-
-                self.write(&format!("done_if_{}:\n", curr_pos));
-            },
-            TokenType::WHILE => {
-                self.consume();
-                let curr_pos = self.pos;
-                self.write(&format!("while_{}:\n", curr_pos));
-                self.expression();
-                self.write("\t\tcmp $0, %rax\n");
-                self.write(&format!("\t\tje while_done_{}\n", curr_pos));
-                self.statement();
-                self.write(&format!("\t\tjmp while_{}\n", curr_pos));
-                self.write(&format!("while_done_{}:\n", curr_pos));
-            },
-            TokenType::NEW => {
-                self.consume();
-                // type token
-                self.consume();
-                // id token
-                self.consume();
-                // end punctuation
-                self.consume();
-            },
-            TokenType::ID => {
-                let id = self.current().value_str;
-                let v_type = self.current().var_type;
-                self.consume();
-
-                // EQ token
-                self.consume();
-
-                // assign value to variable
-                if v_type != VarType::STR {
-                    self.expression();
-                    self.write(&format!("\t\tmovq %rax, {}\n", id));
-                }
-                else {
-                    self.expression_no_print();
-                }
-            },
-            TokenType::PRINT => {
-                self.consume();
-
-                let format_strings = self.current().var_type == VarType::STR;
-                self.write("# printing:\n");
-                self.expression();
-                self.write("\t\tmovq %rax, %rsi\n");
-                if format_strings {
-                    self.write_print_str();
-                } else {
-                    self.write_print_int();
-                }
-                self.write("# finished printing\n");
-                
-                // skip over LEND
-                self.consume();
-            },
-            TokenType::END | TokenType::RBRACE => {
-                return false;
-            },
-            _ => {
-                self.consume();
-                // println!("LSKDFJSLKDFJ");
-            }
-        };
-        return true;
+    // A relocatable object built without an external assembler. It needs the
+    // C library, unless `bundled_runtime` puts a minimal one inside, in which
+    // case it links on its own with `ld`.
+    pub fn gen_object(&self, bundled_runtime: bool) -> Vec<u8> {
+        let object = assembler::assemble(&self.gen_code_with(bundled_runtime));
+        assembler::write_object(&object)
     }
 
-    fn expression(&mut self) {
-        self.e4(true);
+    // A static executable that needs no toolchain at all
+    pub fn gen_executable(&self) -> Vec<u8> {
+        let object = assembler::assemble(&self.gen_code_with(true));
+        assembler::write_executable(&object, "_start")
     }
 
-    fn expression_no_print(&mut self){
-        self.e4(false);
+    // Runs the checked program without compiling it, returning its exit status
+    pub fn interpret(&self, args: &[String], input: &mut dyn BufRead, output: &mut dyn Write,
+                     errors: &mut dyn Write) -> i32 {
+        Interpreter::new(args, input, output, errors).run(&self.program)
     }
 
-    fn e4(&mut self, print: bool) {
-        self.e3(print);
-        while self.peek() == TokenType::EQEQ {
-            self.consume();
-            if print {
-                self.write("\t\tpush %rax\n");
-            }
-            self.e3(print);
-            if print {
-                self.write("\t\tpop %r15\n\
-                        \t\tsubq %r15, %rax\n\
-                        \t\tsete %al\n\
-                        \t\tmovzbq %al, %rax\n");
-            }
-        }
+    // Portable bytecode, which run_bytecode runs anywhere
+    pub fn gen_bytecode(&self) -> Vec<u8> {
+        bytecode::compile(&self.program).to_bytes()
     }
 
-    fn e3(&mut self, print: bool) {
-        self.e2(print);
-        while self.peek() == TokenType::PLUS || self.peek() == TokenType::MINUS {
-            let t_type = self.peek();
-            self.consume();
-            if print {
-                self.write("\t\tpush %rax\n");
-            }
-            self.e2(print);
-
-            if print {
-                match t_type {
-                    TokenType::PLUS => self.write("\t\tpop %r15\n\
-                                                    \t\taddq %r15, %rax\n"),
-                    TokenType::MINUS => self.write("\t\tmovq %rax, %r15\n\
-                                                    \t\tpop %rax\n\
-                                                    \t\tsubq %r15, %rax\n"),
-                    _ => panic!("Compile error at {}", self.pos)
-                };
-            }
+    fn gen_code_with(&self, bundled_runtime: bool) -> String {
+        let mut code = self.gen_code();
+        if bundled_runtime {
+            code.push_str(include_str!("assembler/libc.s"));
         }
+        code
     }
+}
 
-    fn e2(&mut self, print: bool) {
-        self.e1(print);
-        while self.peek() == TokenType::MUL || self.peek() == TokenType::DIV {
-            let t_type = self.peek();
-            self.consume();
-            if print {
-                self.write("\t\tpush %rax\n");
-            }
-            self.e1(print);
-
-            if print {
-                match t_type {
-                    TokenType::MUL => self.write("\t\tpop %r15\n\
-                                                  \t\tmul %r15\n"),
-                    TokenType::DIV => self.write("\t\tpop %r15\n\
-                                                  \t\tdiv %r15\n\
-                                                  \t\tmovq %rdx, %rax\n"),
-                    _ => panic!("Compile error: {}", self.debug_str())
-                };
-            }
-        }
-        if print {
-            self.write("\t\tmovq $0, %r15\n");
-        }
-    }
+// Whether `bytes` look like the output of gen_bytecode rather than a program
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(bytecode::MAGIC)
+}
 
-    fn e1(&mut self, print: bool) {
-        match self.peek() {
-            TokenType::VAL => {
-                if print {
-                    let curr = self.current();
-                    if curr.var_type == VarType::INT || curr.var_type == VarType::BOOL {
-                        self.write(&format!("\t\tmovq ${}, %rax\n", curr.value_int));
-                    } else {
-                        self.write(&format!("\t\tmovq ${}, %rax\n", curr.value_str));
-                    }
-                }
-                self.consume();
-            },
-            TokenType::LPAREN => {
-                self.consume();
-                self.e4(print);
-                self.consume();
-            },
-            TokenType::ID => {
-                if print {
-                    let curr = self.current();
-                    let id = curr.value_str;
+// Runs bytecode from gen_bytecode, returning the program's exit status, or an
+// error if the bytecode is invalid
+pub fn run_bytecode(bytes: &[u8], args: &[String], input: &mut dyn BufRead,
+                    output: &mut dyn Write, errors: &mut dyn Write) -> Result<i32, String> {
+    let module = bytecode::Module::from_bytes(bytes)?;
+    bytecode::Vm::new(args, input, output, errors).run(&module)
+}
 
-                    if curr.var_type == VarType::STR {
-                        self.write(&format!("\t\tmovq ${}, %rax\n", id));
-                    } else {
-                        self.write(&format!("\t\tmovq {}, %rax\n", id));
-                    }
-                }
-                self.consume();
-            },
-            _ => {
-                println!("needs to be implemented");
-                self.consume();
-            }
-        }
-    }
-}
\ No newline at end of file
+// Runs a module from gen_wasm with a built-in interpreter standing in for the
+// browser, returning the program's exit status
+#[cfg(test)]
+pub fn run_wasm(bytes: &[u8], args: &[String], input: &mut dyn BufRead, output: &mut dyn Write,
+                errors: &mut dyn Write) -> i32 {
+    wasm::interp::run(bytes, args, input, output, errors)
+}
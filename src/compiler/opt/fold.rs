@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+use std::mem;
+use super::super::tokenizer::VarType;
+use super::super::ast::BinOp;
+use super::super::machine::bool_str;
+use super::super::ir::{Function, Inst, Op, Term, BlockId};
+
+// Constant folding and propagation, after "Constant Propagation with
+// Conditional Branches" by Wegman and Zadeck, but sweeping over the blocks
+// until nothing changes instead of keeping work lists. Values start out
+// unknown and can only become a constant, then varying, so the sweeps end.
+// Branches are only followed where their condition allows, which finds
+// constants going around loops and drops the code a constant condition
+// never reaches.
+pub fn fold(func: &mut Function) {
+    let mut folder = Folder {
+        values: vec![Lattice::Unknown; func.types.len()],
+        reached: vec![false; func.blocks.len()],
+        edges: HashSet::new()
+    };
+    folder.propagate(func);
+    folder.rewrite(func);
+    func.tidy();
+    func.merge_blocks();
+    func.tidy();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lattice {
+    // nothing has reached it yet
+    Unknown,
+    Int(u64),
+    Str(String),
+    // not always the same
+    Varying
+}
+
+fn meet(a: &Lattice, b: &Lattice) -> Lattice {
+    match (a, b) {
+        (&Lattice::Unknown, _) => b.clone(),
+        (_, &Lattice::Unknown) => a.clone(),
+        _ if a == b => a.clone(),
+        _ => Lattice::Varying,
+    }
+}
+
+struct Folder {
+    values: Vec<Lattice>,
+    reached: Vec<bool>,
+    // the edges control can take, from and to
+    edges: HashSet<(BlockId, BlockId)>
+}
+
+impl Folder {
+    fn propagate(&mut self, func: &Function) {
+        self.reached[0] = true;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, block) in func.blocks.iter().enumerate() {
+                if !self.reached[i] {
+                    continue;
+                }
+                for inst in &block.insts {
+                    if let Some(value) = inst.value {
+                        let result = self.evaluate(func, BlockId(i), inst);
+                        if result != self.values[value.0] {
+                            self.values[value.0] = result;
+                            changed = true;
+                        }
+                    }
+                }
+                for succ in self.successors(&block.term) {
+                    if self.edges.insert((BlockId(i), succ)) {
+                        self.reached[succ.0] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    // Where control can go from a block, as far as is known
+    fn successors(&self, term: &Term) -> Vec<BlockId> {
+        match *term {
+            Term::Branch(cond, then, otherwise) => match self.values[cond.0] {
+                Lattice::Unknown => vec![],
+                Lattice::Int(0) => vec![otherwise],
+                Lattice::Int(_) => vec![then],
+                _ => vec![then, otherwise],
+            },
+            _ => term.successors(),
+        }
+    }
+
+    fn evaluate(&self, func: &Function, block: BlockId, inst: &Inst) -> Lattice {
+        let result_type = func.types[inst.value.unwrap().0];
+        match inst.op {
+            Op::Const(value) => Lattice::Int(value),
+            Op::Str(ref value) => Lattice::Str(value.clone()),
+            // only what comes in over edges control can take
+            Op::Phi(ref incoming) => incoming.iter()
+                .filter(|&&(pred, _)| self.edges.contains(&(pred, block)))
+                .fold(Lattice::Unknown, |merged, &(_, value)| meet(&merged, &self.values[value.0])),
+            Op::Binary(op, lhs, rhs) => match (&self.values[lhs.0], &self.values[rhs.0]) {
+                (&Lattice::Varying, _) | (_, &Lattice::Varying) => Lattice::Varying,
+                (&Lattice::Unknown, _) | (_, &Lattice::Unknown) => Lattice::Unknown,
+                (lhs, rhs) => binary(op, lhs.clone(), rhs.clone(), result_type),
+            },
+            Op::Not(value) => match self.values[value.0] {
+                Lattice::Int(value) => Lattice::Int((value == 0) as u64),
+                ref other => other.clone(),
+            },
+            Op::Convert(from) => match self.values[from.0] {
+                Lattice::Int(value) => convert(value, func.types[from.0], result_type),
+                ref other => other.clone(),
+            },
+            // parameters, memory, calls and input
+            _ => Lattice::Varying,
+        }
+    }
+
+    // Turns values found to be constant into constants in the entry block,
+    // which dominates all their uses, and branches on constants into jumps
+    fn rewrite(&self, func: &mut Function) {
+        let mut constants = Vec::new();
+        for (i, block) in func.blocks.iter_mut().enumerate() {
+            for mut inst in mem::take(&mut block.insts) {
+                let constant = match inst.value.map(|value| self.values[value.0].clone()) {
+                    Some(Lattice::Int(value)) => Some(Op::Const(value)),
+                    Some(Lattice::Str(value)) => Some(Op::Str(value)),
+                    _ => None,
+                };
+                match constant {
+                    Some(op) => {
+                        inst.op = op;
+                        constants.push(inst);
+                    },
+                    None => {
+                        // phis forget the edges control never takes
+                        if let Op::Phi(ref mut incoming) = inst.op {
+                            incoming.retain(|&(pred, _)| self.edges.contains(&(pred, BlockId(i))));
+                        }
+                        block.insts.push(inst);
+                    },
+                }
+            }
+            if let Term::Branch(cond, then, otherwise) = block.term {
+                if let Lattice::Int(value) = self.values[cond.0] {
+                    block.term = Term::Jump(if value != 0 { then } else { otherwise });
+                }
+            }
+        }
+        constants.append(&mut func.blocks[0].insts);
+        func.blocks[0].insts = constants;
+    }
+}
+
+// `op` on two constants, or Varying where it has to stop the program
+fn binary(op: BinOp, lhs: Lattice, rhs: Lattice, result_type: VarType) -> Lattice {
+    match (lhs, rhs) {
+        (Lattice::Str(lhs), Lattice::Str(rhs)) => match op {
+            BinOp::Add => Lattice::Str(format!("{}{}", lhs, rhs)),
+            BinOp::Eq => Lattice::Int((lhs == rhs) as u64),
+            _ => Lattice::Varying,
+        },
+        (Lattice::Int(lhs), Lattice::Int(rhs)) => Lattice::Int(match op {
+            // ➕ and ✖️ on booleans are "or" and "and"
+            BinOp::Add if result_type == VarType::BOOL => lhs | rhs,
+            BinOp::Add => lhs.wrapping_add(rhs),
+            BinOp::Sub => lhs.wrapping_sub(rhs),
+            BinOp::Mul if result_type == VarType::BOOL => lhs & rhs,
+            BinOp::Mul => lhs.wrapping_mul(rhs),
+            // division by zero stays a runtime error
+            BinOp::Div if rhs == 0 => return Lattice::Varying,
+            BinOp::Div => lhs / rhs,
+            BinOp::Eq => (lhs == rhs) as u64,
+        }),
+        _ => Lattice::Varying,
+    }
+}
+
+// Like machine::convert, on constants
+fn convert(value: u64, from: VarType, to: VarType) -> Lattice {
+    match (from, to) {
+        (VarType::INT, VarType::STR) => Lattice::Str(value.to_string()),
+        (VarType::BOOL, VarType::STR) => Lattice::Str(bool_str(value).to_string()),
+        // booleans are always 0 or 1
+        (_, VarType::BOOL) => Lattice::Int((value != 0) as u64),
+        _ => Lattice::Int(value),
+    }
+}
@@ -0,0 +1,166 @@
+use std::mem;
+use super::super::ir::{Module, Function, Block, Inst, Op, Term, Value, BlockId};
+
+// Functions of at most this many instructions are inlined
+const INLINE_LIMIT: usize = 40;
+// and no more once the caller has grown to this many
+const GROWTH_LIMIT: usize = 1000;
+
+// Replaces calls to small functions that can't call themselves, directly or
+// through others, with a copy of their body. The call and its return go
+// away, and folding afterwards sees the arguments. Calls copied in with a
+// body are inlined in turn.
+pub fn inline(module: &mut Module) {
+    let recursive: Vec<bool> = (0..module.functions.len()).map(|i| reaches(module, i, i)).collect();
+    for caller in 0..module.functions.len() {
+        let mut changed = false;
+        while let Some((block, index, callee)) = call_site(module, caller, &recursive) {
+            let callee = module.functions[callee].clone();
+            inline_call(&mut module.functions[caller], block, index, &callee);
+            changed = true;
+        }
+        if changed {
+            let func = &mut module.functions[caller];
+            func.tidy();
+            func.merge_blocks();
+            func.tidy();
+        }
+    }
+}
+
+fn size(func: &Function) -> usize {
+    func.blocks.iter().map(|block| block.insts.len() + 1).sum()
+}
+
+fn index_of(module: &Module, id: &str) -> Option<usize> {
+    module.functions.iter().position(|func| func.id == id)
+}
+
+// The functions `func` calls
+fn callees(module: &Module, func: usize) -> Vec<usize> {
+    let mut callees = Vec::new();
+    for block in &module.functions[func].blocks {
+        for inst in &block.insts {
+            if let Op::Call(ref id, _) = inst.op {
+                if let Some(callee) = index_of(module, id) {
+                    callees.push(callee);
+                }
+            }
+        }
+    }
+    callees
+}
+
+// Whether calling `from` can lead to a call of `to`
+fn reaches(module: &Module, from: usize, to: usize) -> bool {
+    let mut visited = vec![false; module.functions.len()];
+    let mut stack = callees(module, from);
+    while let Some(func) = stack.pop() {
+        if func == to {
+            return true;
+        }
+        if !visited[func] {
+            visited[func] = true;
+            stack.extend(callees(module, func));
+        }
+    }
+    false
+}
+
+// The block and index of a call in `caller` to inline, and the callee
+fn call_site(module: &Module, caller: usize, recursive: &[bool]) -> Option<(BlockId, usize, usize)> {
+    if size(&module.functions[caller]) > GROWTH_LIMIT {
+        return None;
+    }
+    for (i, block) in module.functions[caller].blocks.iter().enumerate() {
+        for (index, inst) in block.insts.iter().enumerate() {
+            if let Op::Call(ref id, _) = inst.op {
+                match index_of(module, id) {
+                    // variables kept in the frame belong to one function
+                    Some(callee) if !recursive[callee] && size(&module.functions[callee]) <= INLINE_LIMIT &&
+                                    module.functions[callee].locals.is_empty() =>
+                        return Some((BlockId(i), index, callee)),
+                    _ => {},
+                }
+            }
+        }
+    }
+    None
+}
+
+// Splits the block at the call, with the code after it moving to a new
+// block, and puts the callee's blocks in between. Its parameters become the
+// arguments, and its returns jump to the new block, where a phi of the
+// returned values takes the place of the call's result.
+fn inline_call(func: &mut Function, at: BlockId, index: usize, callee: &Function) {
+    let rest = func.blocks[at.0].insts.split_off(index + 1);
+    let call = func.blocks[at.0].insts.pop().unwrap();
+    let args = match call.op {
+        Op::Call(_, args) => args,
+        _ => unreachable!(),
+    };
+    let block_base = func.blocks.len();
+    let after = BlockId(block_base + callee.blocks.len());
+    let term = mem::replace(&mut func.blocks[at.0].term, Term::Jump(BlockId(block_base)));
+    // the successors now come from the new block
+    for succ in term.successors() {
+        for inst in func.blocks[succ.0].insts.iter_mut() {
+            if let Op::Phi(ref mut incoming) = inst.op {
+                for pair in incoming.iter_mut().filter(|pair| pair.0 == at) {
+                    pair.0 = after;
+                }
+            }
+        }
+    }
+
+    // the callee's values are numbered after the caller's
+    let value_base = func.types.len();
+    func.types.extend(callee.types.iter().cloned());
+    let mut params: Vec<Option<Value>> = vec![None; callee.types.len()];
+    for inst in &callee.blocks[0].insts {
+        if let Op::Param(i) = inst.op {
+            params[inst.value.unwrap().0] = Some(args[i]);
+        }
+    }
+    let map = |value: Value| params[value.0].unwrap_or(Value(value.0 + value_base));
+    let block = |block: BlockId| BlockId(block.0 + block_base);
+
+    let mut returns = Vec::new();
+    for (i, callee_block) in callee.blocks.iter().enumerate() {
+        let mut insts = Vec::new();
+        for inst in &callee_block.insts {
+            if let Op::Param(_) = inst.op {
+                continue;
+            }
+            let mut inst = inst.clone();
+            inst.value = inst.value.map(map);
+            inst.op.map_operands(map);
+            if let Op::Phi(ref mut incoming) = inst.op {
+                for pair in incoming.iter_mut() {
+                    pair.0 = block(pair.0);
+                }
+            }
+            insts.push(inst);
+        }
+        let mut term = callee_block.term.clone();
+        term.map_operands(map);
+        let term = match term {
+            Term::Jump(target) => Term::Jump(block(target)),
+            Term::Branch(cond, then, otherwise) => Term::Branch(cond, block(then), block(otherwise)),
+            Term::Return(value) => {
+                returns.push((BlockId(block_base + i), value));
+                Term::Jump(after)
+            },
+            Term::Exit(value) => Term::Exit(value),
+        };
+        func.blocks.push(Block { insts, term });
+    }
+
+    let mut insts = Vec::new();
+    if let Some(result) = call.value {
+        let incoming = returns.iter().map(|&(block, value)| (block, value.unwrap())).collect();
+        insts.push(Inst { value: Some(result), op: Op::Phi(incoming), pos: call.pos });
+    }
+    insts.extend(rest);
+    func.blocks.push(Block { insts, term });
+}
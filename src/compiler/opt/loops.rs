@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::mem;
+use super::super::tokenizer::VarType;
+use super::super::ast::BinOp;
+use super::super::ir::{Function, Block, Inst, Op, Term, Value, BlockId};
+
+// Loop invariant code motion and strength reduction of induction variables.
+// A loop is found by the jump back to its header, and gets a preheader: a
+// block that jumps to the header from outside and nowhere else. Inner loops
+// go first, so what they hoist can leave the outer loops too.
+pub fn optimize_loops(func: &mut Function) {
+    func.tidy();
+    // with the blocks in reverse postorder, jumps back go to a loop header
+    let mut headers: Vec<(BlockId, Vec<BlockId>)> = Vec::new();
+    for (i, block) in func.blocks.iter().enumerate() {
+        for succ in block.term.successors() {
+            if succ.0 <= i {
+                match headers.iter().position(|header| header.0 == succ) {
+                    Some(index) => headers[index].1.push(BlockId(i)),
+                    None => headers.push((succ, vec![BlockId(i)])),
+                }
+            }
+        }
+    }
+    headers.sort_by_key(|header| header.0);
+    for (header, latches) in headers.into_iter().rev() {
+        let mut body = body(func, header, &latches);
+        let preheader = preheader(func, header, &body);
+        body.resize(func.blocks.len(), false);
+        hoist_invariants(func, &body, preheader);
+        if latches.len() == 1 {
+            reduce_strength(func, header, latches[0], &body, preheader);
+        }
+    }
+    func.tidy();
+    func.merge_blocks();
+    func.tidy();
+}
+
+// Whether each block is in the loop: the header, and the blocks that get to
+// a latch without going through it
+fn body(func: &Function, header: BlockId, latches: &[BlockId]) -> Vec<bool> {
+    let preds = func.predecessors();
+    let mut body = vec![false; func.blocks.len()];
+    body[header.0] = true;
+    let mut stack = latches.to_vec();
+    while let Some(block) = stack.pop() {
+        if !body[block.0] {
+            body[block.0] = true;
+            stack.extend(preds[block.0].iter().cloned());
+        }
+    }
+    body
+}
+
+// The block before the loop, added if control can enter the loop from more
+// than one place or from a branch
+fn preheader(func: &mut Function, header: BlockId, body: &[bool]) -> BlockId {
+    let outside: Vec<BlockId> = func.predecessors()[header.0].iter().cloned()
+        .filter(|pred| !body[pred.0])
+        .collect();
+    if outside.len() == 1 && func.blocks[outside[0].0].term == Term::Jump(header) {
+        return outside[0];
+    }
+    let preheader = BlockId(func.blocks.len());
+    let mut insts = Vec::new();
+    for &pred in &outside {
+        match func.blocks[pred.0].term {
+            Term::Jump(ref mut target) => *target = preheader,
+            Term::Branch(_, ref mut then, ref mut otherwise) => {
+                if *then == header {
+                    *then = preheader;
+                }
+                if *otherwise == header {
+                    *otherwise = preheader;
+                }
+            },
+            _ => {},
+        }
+    }
+    // the header's phis take what comes from outside from the preheader,
+    // merged there if it isn't the same value from everywhere
+    for index in 0..func.blocks[header.0].insts.len() {
+        let (value, incoming) = match func.blocks[header.0].insts[index] {
+            Inst { value: Some(value), op: Op::Phi(ref mut incoming), .. } => (value, mem::take(incoming)),
+            _ => break,
+        };
+        let (entering, mut staying): (Vec<_>, Vec<_>) = incoming.into_iter()
+            .partition(|pair| outside.contains(&pair.0));
+        let merged = if entering.iter().all(|pair| pair.1 == entering[0].1) {
+            entering[0].1
+        } else {
+            let merged = Value(func.types.len());
+            func.types.push(func.types[value.0]);
+            let pos = func.blocks[header.0].insts[index].pos;
+            insts.push(Inst { value: Some(merged), op: Op::Phi(entering), pos });
+            merged
+        };
+        staying.insert(0, (preheader, merged));
+        func.blocks[header.0].insts[index].op = Op::Phi(staying);
+    }
+    func.blocks.push(Block { insts, term: Term::Jump(header) });
+    preheader
+}
+
+// The block of every value defined by an instruction, if any
+fn definitions(func: &Function) -> Vec<Option<BlockId>> {
+    let mut defs = vec![None; func.types.len()];
+    for (i, block) in func.blocks.iter().enumerate() {
+        for inst in &block.insts {
+            if let Some(value) = inst.value {
+                defs[value.0] = Some(BlockId(i));
+            }
+        }
+    }
+    defs
+}
+
+// Moves instructions whose operands don't change in the loop to the
+// preheader. Only those that can't stop the program, print or read memory
+// qualify, since the loop may not run at all.
+fn hoist_invariants(func: &mut Function, body: &[bool], preheader: BlockId) {
+    let defs = definitions(func);
+    let mut invariant = vec![false; func.types.len()];
+    let mut hoisted = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, block) in func.blocks.iter_mut().enumerate() {
+            if !body[i] {
+                continue;
+            }
+            let mut kept = Vec::new();
+            for inst in mem::take(&mut block.insts) {
+                let pure = match inst.op {
+                    Op::Binary(BinOp::Div, ..) => false,
+                    Op::Binary(..) | Op::Not(_) | Op::Convert(_) => true,
+                    _ => false,
+                };
+                let operands_invariant = inst.op.operands().iter()
+                    .all(|value| invariant[value.0] || defs[value.0].filter(|def| body[def.0]).is_none());
+                if pure && operands_invariant {
+                    invariant[inst.value.unwrap().0] = true;
+                    hoisted.push(inst);
+                    changed = true;
+                } else {
+                    kept.push(inst);
+                }
+            }
+            block.insts = kept;
+        }
+    }
+    func.blocks[preheader.0].insts.extend(hoisted);
+}
+
+// Turns multiplications of an induction variable by something the loop
+// doesn't change into a variable of its own, which goes up by the product
+// of the step and the factor every time around. Induction variables are the
+// header's integer phis that the latch gives back plus or minus a step the
+// loop doesn't change.
+fn reduce_strength(func: &mut Function, header: BlockId, latch: BlockId, body: &[bool], preheader: BlockId) {
+    let defs = definitions(func);
+    let outside = |value: Value| defs[value.0].filter(|def| body[def.0]).is_none();
+    // the phi, its initial value, the step, its operator and the value the
+    // latch gives back
+    let mut variables = Vec::new();
+    for inst in &func.blocks[header.0].insts {
+        let incoming = match inst.op {
+            Op::Phi(ref incoming) if incoming.len() == 2 => incoming,
+            Op::Phi(_) => continue,
+            _ => break,
+        };
+        let phi = inst.value.unwrap();
+        let (init, next) = match (incoming[0], incoming[1]) {
+            ((from, init), (back, next)) | ((back, next), (from, init)) if from == preheader && back == latch =>
+                (init, next),
+            _ => continue,
+        };
+        if func.types[phi.0] != VarType::INT {
+            continue;
+        }
+        if defs[next.0].is_none() {
+            continue;
+        }
+        let step = match find_inst(func, next).op {
+            Op::Binary(BinOp::Add, lhs, step) if lhs == phi && outside(step) => (BinOp::Add, step),
+            Op::Binary(BinOp::Add, step, rhs) if rhs == phi && outside(step) => (BinOp::Add, step),
+            Op::Binary(BinOp::Sub, lhs, step) if lhs == phi && outside(step) => (BinOp::Sub, step),
+            _ => continue,
+        };
+        variables.push((phi, init, step, next));
+    }
+
+    // the multiplications to replace, with their variable and factor
+    let mut products = Vec::new();
+    for (i, block) in func.blocks.iter().enumerate() {
+        if !body[i] {
+            continue;
+        }
+        for inst in &block.insts {
+            if let Op::Binary(BinOp::Mul, lhs, rhs) = inst.op {
+                if func.types[lhs.0] != VarType::INT {
+                    continue;
+                }
+                let found = variables.iter().find(|variable| variable.0 == lhs && outside(rhs)).map(|variable| (variable, rhs))
+                    .or_else(|| variables.iter().find(|variable| variable.0 == rhs && outside(lhs)).map(|variable| (variable, lhs)));
+                if let Some((&variable, factor)) = found {
+                    products.push((inst.value.unwrap(), variable, factor));
+                }
+            }
+        }
+    }
+
+    let mut replace = HashMap::new();
+    for (product, (_, init, (op, step), next), factor) in products {
+        let pos = find_inst(func, product).pos;
+        let mut new_value = || {
+            func.types.push(VarType::INT);
+            Value(func.types.len() - 1)
+        };
+        let (start, increment, phi, following) = (new_value(), new_value(), new_value(), new_value());
+        func.blocks[preheader.0].insts.extend(vec![
+            Inst { value: Some(start), op: Op::Binary(BinOp::Mul, init, factor), pos },
+            Inst { value: Some(increment), op: Op::Binary(BinOp::Mul, step, factor), pos },
+        ]);
+        func.blocks[header.0].insts.insert(0, Inst {
+            value: Some(phi),
+            op: Op::Phi(vec![(preheader, start), (latch, following)]),
+            pos
+        });
+        // the product goes up right where the variable does
+        let block = &mut func.blocks[defs[next.0].unwrap().0];
+        let index = block.insts.iter().position(|inst| inst.value == Some(next)).unwrap();
+        block.insts.insert(index + 1, Inst { value: Some(following), op: Op::Binary(op, phi, increment), pos });
+        replace.insert(product, phi);
+    }
+
+    for block in func.blocks.iter_mut() {
+        block.insts.retain(|inst| inst.value.filter(|value| replace.contains_key(value)).is_none());
+        for inst in block.insts.iter_mut() {
+            inst.op.map_operands(|value| *replace.get(&value).unwrap_or(&value));
+        }
+        block.term.map_operands(|value| *replace.get(&value).unwrap_or(&value));
+    }
+}
+
+fn find_inst(func: &Function, value: Value) -> &Inst {
+    func.blocks.iter().flat_map(|block| block.insts.iter())
+        .find(|inst| inst.value == Some(value))
+        .unwrap()
+}
@@ -0,0 +1,286 @@
+mod fold;
+mod inline;
+mod loops;
+mod tail;
+use super::ir::Module;
+
+// Optimizations of the IR, which the native backends are generated from.
+// Calls a function ends with to itself become jumps at every level, since
+// deep recursion needs them to run at all. Apart from that, level 0 leaves
+// the code as it was lowered; -O (level 1) folds constants,
+// moves what doesn't change out of loops and turns multiplications by loop
+// counters into additions; -O2 inlines small functions before all that.
+pub fn optimize(module: &mut Module, level: u8) {
+    for func in module.functions.iter_mut() {
+        tail::tail_calls(func);
+    }
+    if level == 0 {
+        return;
+    }
+    if level >= 2 {
+        inline::inline(module);
+    }
+    for func in module.functions.iter_mut() {
+        fold::fold(func);
+        loops::optimize_loops(func);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tokenizer::Tokenizer;
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+    use std::collections::HashMap;
+    use super::super::tokenizer::VarType;
+    use super::super::ast::BinOp;
+    use super::super::machine::bool_str;
+    use super::super::ir::{self, Function, Op, Term, Value, BlockId};
+
+    fn optimized(program: &str) -> String {
+        optimized_at(program, 1)
+    }
+
+    fn lowered(program: &str) -> Module {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        ir::lower(&ast)
+    }
+
+    fn optimized_at(program: &str, level: u8) -> String {
+        let mut module = lowered(program);
+        optimize(&mut module, level);
+        assert_eq!(ir::verify(&module), Ok(()));
+        module.to_string()
+    }
+
+    #[derive(Clone)]
+    enum Data {
+        Int(u64),
+        Str(String)
+    }
+
+    // Runs a module without input or files, counting the instructions it
+    // goes through and the multiplications among them
+    struct Run {
+        output: String,
+        insts: usize,
+        muls: usize,
+        memory: HashMap<String, Data>
+    }
+
+    impl Run {
+        fn new(module: &Module) -> Run {
+            let mut run = Run { output: String::new(), insts: 0, muls: 0, memory: HashMap::new() };
+            run.call(module, &module.functions[0], Vec::new());
+            run
+        }
+
+        fn call(&mut self, module: &Module, func: &Function, args: Vec<Data>) -> Option<Data> {
+            let mut values = vec![Data::Int(0); func.types.len()];
+            let (mut block, mut from) = (0, 0);
+            loop {
+                // phis all read what they merge before any of them changes
+                let phis: Vec<(Value, Data)> = func.blocks[block].insts.iter()
+                    .filter_map(|inst| match inst.op {
+                        Op::Phi(ref incoming) => {
+                            let value = incoming.iter().find(|pair| pair.0 == BlockId(from)).unwrap().1;
+                            Some((inst.value.unwrap(), values[value.0].clone()))
+                        },
+                        _ => None,
+                    })
+                    .collect();
+                for (value, data) in phis {
+                    values[value.0] = data;
+                }
+                for inst in &func.blocks[block].insts {
+                    self.insts += 1;
+                    let int = |value: Value| match values[value.0] {
+                        Data::Int(value) => value,
+                        Data::Str(_) => panic!("not an int"),
+                    };
+                    let result = match inst.op {
+                        Op::Phi(_) | Op::Define(..) => continue,
+                        Op::Const(value) => Data::Int(value),
+                        Op::Str(ref value) => Data::Str(value.clone()),
+                        Op::Param(index) => args[index].clone(),
+                        Op::Load(ref id) => self.memory.get(id).cloned().unwrap_or(Data::Int(0)),
+                        Op::Store(ref id, value) => {
+                            self.memory.insert(id.clone(), values[value.0].clone());
+                            continue;
+                        },
+                        Op::Binary(op, lhs, rhs) => match (values[lhs.0].clone(), values[rhs.0].clone()) {
+                            (Data::Str(lhs), Data::Str(rhs)) => match op {
+                                BinOp::Add => Data::Str(format!("{}{}", lhs, rhs)),
+                                _ => Data::Int((lhs == rhs) as u64),
+                            },
+                            _ => {
+                                let (lhs, rhs) = (int(lhs), int(rhs));
+                                let is_bool = func.types[inst.value.unwrap().0] == VarType::BOOL;
+                                Data::Int(match op {
+                                    BinOp::Add if is_bool => lhs | rhs,
+                                    BinOp::Add => lhs.wrapping_add(rhs),
+                                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                                    BinOp::Mul if is_bool => lhs & rhs,
+                                    BinOp::Mul => {
+                                        self.muls += 1;
+                                        lhs.wrapping_mul(rhs)
+                                    },
+                                    BinOp::Div => lhs / rhs,
+                                    BinOp::Eq => (lhs == rhs) as u64,
+                                })
+                            },
+                        },
+                        Op::Not(value) => Data::Int((int(value) == 0) as u64),
+                        Op::Convert(value) => match func.types[inst.value.unwrap().0] {
+                            VarType::STR if func.types[value.0] == VarType::BOOL => Data::Str(bool_str(int(value)).to_string()),
+                            VarType::STR => Data::Str(int(value).to_string()),
+                            VarType::BOOL => Data::Int((int(value) != 0) as u64),
+                            _ => values[value.0].clone(),
+                        },
+                        Op::Call(ref id, ref args) => {
+                            let callee = module.functions.iter().find(|func| func.id == *id).unwrap();
+                            let args = args.iter().map(|arg| values[arg.0].clone()).collect();
+                            match self.call(module, callee, args) {
+                                Some(data) => data,
+                                None => continue,
+                            }
+                        },
+                        Op::Print(value) => {
+                            match values[value.0] {
+                                Data::Int(int) if func.types[value.0] == VarType::BOOL => self.output.push_str(bool_str(int)),
+                                Data::Int(int) => self.output.push_str(&int.to_string()),
+                                Data::Str(ref value) => self.output.push_str(value),
+                            }
+                            continue;
+                        },
+                        Op::PrintChar(ch) => {
+                            self.output.push(ch);
+                            continue;
+                        },
+                        Op::Builtin(..) => panic!("builtins aren't supported"),
+                    };
+                    values[inst.value.unwrap().0] = result;
+                }
+                self.insts += 1;
+                from = block;
+                match func.blocks[block].term {
+                    Term::Jump(target) => block = target.0,
+                    Term::Branch(cond, then, otherwise) => {
+                        block = if let Data::Int(0) = values[cond.0] { otherwise.0 } else { then.0 };
+                    },
+                    Term::Return(value) => return value.map(|value| values[value.0].clone()),
+                    Term::Exit(_) => panic!("exit isn't supported"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_and_conversions_fold() {
+        assert_eq!(optimized("🆕🔢😀🔚 😀⬅️3️⃣🔚 📄😀✖️4️⃣➗️3️⃣◾0️⃣➖1️⃣◾🚫🌜😀↔️3️⃣🌛➕👍🔚 \
+                              📄🖼🌜💬🔢☯️💬◾😀◾😀↔️2️⃣🌛➕💬🐶💬🔚"),
+                   "\nfn main() {\nb0:\n    v0: int = const 0\n    v1: int = const 3\n\
+                    \x20   v2: int = const 4\n    v3: int = const 12\n    v4: int = const 3\n\
+                    \x20   v5: int = const 4\n    v6: int = const 0\n    v7: int = const 1\n\
+                    \x20   v8: int = const 18446744073709551615\n    v9: int = const 3\n\
+                    \x20   v10: bool = const 1\n    v11: bool = const 0\n    v12: bool = const 1\n\
+                    \x20   v13: bool = const 1\n    v14: string = str \"\"\n    v15: string = str \"3\"\n\
+                    \x20   v16: string = str \"3\"\n    v17: int = const 2\n    v18: bool = const 0\n\
+                    \x20   v19: string = str \"👎\"\n    v20: string = str \"3👎\"\n\
+                    \x20   v21: string = str \"🐶\"\n    v22: string = str \"3👎🐶\"\n\
+                    \x20   print v5\n    print ' '\n    print v8\n    print ' '\n    print v13\n\
+                    \x20   print '\\n'\n    print v22\n    print '\\n'\n    ret\n}\n");
+    }
+
+    #[test]
+    fn constant_conditions_remove_branches() {
+        // 😀 is 0 or 1 in the loop, so it stays a variable
+        assert_eq!(optimized("🆕☯️😍🔚 ❓😍 📄1️⃣🔚 ❌ 📄2️⃣🔚 \
+                              🆕🔢😀🔚 🔁😀↔️0️⃣ 🌘 😀⬅️1️⃣🔚 🌒 📄😀🔚"),
+                   "\nfn main() {\nb0:\n    v0: bool = const 0\n    v1: int = const 0\n\
+                    \x20   v2: int = const 2\n    v3: int = const 0\n    v4: int = const 1\n\
+                    \x20   print v2\n    print '\\n'\n    jump b1\n\
+                    b1:  # from b0, b2\n    v5: int = phi [b0: v1] [b2: v4]\n    v6: bool = eq v5, v3\n\
+                    \x20   branch v6, b2, b3\n\
+                    b2:  # from b1\n    jump b1\n\
+                    b3:  # from b1\n    print v5\n    print '\\n'\n    ret\n}\n");
+
+        let text = optimized("🆕🔢😀🔚 🔁🚫🌜😀↔️0️⃣🌛 🌘 📄😀🔚 🌒 📄2️⃣🔚");
+        assert!(!text.contains("branch") && !text.contains("b1:"), "{}", text);
+    }
+
+    #[test]
+    fn runtime_errors_and_input_are_left_alone() {
+        let text = optimized("🆕🔢😀🔚 📄1️⃣➗️😀🔚 📄📨🌜🌛➕1️⃣🔚");
+        assert!(text.contains("div v1, v0\n"), "{}", text);
+        assert!(text.contains("readint()\n"), "{}", text);
+    }
+
+    #[test]
+    fn small_functions_are_inlined() {
+        // 🐕 folds away once inlined, 🦊 calls itself and stays a call
+        let program = "🤪🔢🐕🌜🔢🍖🌛 🌘 ❓🍖↔️0️⃣ ↩️1️⃣🔚 ↩️🍖➕1️⃣🔚 🌒 \
+                       🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+                       📄📞🐕🌜4️⃣🌛🔚 📄📞🦊🌜📞🐕🌜2️⃣🌛🌛🔚";
+        let text = optimized_at(program, 2);
+        assert!(!text.contains("call 🐕") && text.contains("call 🦊(v"), "{}", text);
+        assert!(text.contains("const 5\n"), "{}", text);
+        assert!(optimized_at(program, 1).contains("call 🐕"));
+    }
+
+    // The module with constants folded, and with the loops optimized too
+    fn loops_optimized(program: &str) -> (Module, Module) {
+        let mut folded = lowered(program);
+        for func in folded.functions.iter_mut() {
+            fold::fold(func);
+        }
+        let mut optimized = folded.clone();
+        for func in optimized.functions.iter_mut() {
+            loops::optimize_loops(func);
+        }
+        assert_eq!(ir::verify(&optimized), Ok(()));
+        (folded, optimized)
+    }
+
+    #[test]
+    fn loops_do_less_work() {
+        // 🐭✖️🐹 leaves both loops, 🦁✖️🐭 and 🐻✖️3️⃣ become sums
+        let (folded, optimized) = loops_optimized(
+            "🤪🔢🐙🌜🔢🐭◾🔢🐹🌛 🌘 🆕🔢🐯🔚 🆕🔢🦁🔚 🔁🚫🌜🦁↔️🐹🌛 🌘 \
+                 🆕🔢🐻🔚 🐻⬅️0️⃣🔚 🔁🚫🌜🐻↔️🐭🌛 🌘 \
+                     🐯⬅️🐯➕🌜🐭✖️🐹🌛➕🌜🦁✖️🐭🌛➕🌜🐻✖️3️⃣🌛🔚 🐻⬅️🐻➕1️⃣🔚 🌒 \
+                 🦁⬅️🦁➕1️⃣🔚 🌒 ↩️🐯🔚 🌒 \
+             📄📞🐙🌜7️⃣◾2️⃣0️⃣🌛🔚 📄📞🐙🌜0️⃣◾0️⃣🌛🔚");
+        let (before, after) = (Run::new(&folded), Run::new(&optimized));
+        assert_eq!(before.output, "30170\n0\n");
+        assert_eq!(after.output, before.output);
+        assert!(after.insts < before.insts, "{} instructions, before {}", after.insts, before.insts);
+        assert!(after.muls * 4 < before.muls, "{} multiplications, before {}", after.muls, before.muls);
+    }
+
+    #[test]
+    fn loops_that_never_run_stay_harmless() {
+        // hoisted, the division would stop the program
+        let (_, optimized) = loops_optimized(
+            "🤪🔢🐙🌜🔢🐭◾🔢🐹🌛 🌘 🆕🔢🐯🔚 🔁🚫🌜🐯↔️🐹🌛 🌘 🐯⬅️🐯➕1️⃣➗️🐭🔚 🌒 ↩️🐯🔚 🌒 \
+             📄📞🐙🌜0️⃣◾0️⃣🌛🔚");
+        assert_eq!(Run::new(&optimized).output, "0\n");
+        let text = optimized.to_string();
+        assert!(text.contains("b2:  # from b1\n    v7: int = div"), "{}", text);
+    }
+
+    #[test]
+    fn tail_calls_become_jumps() {
+        let text = optimized_at("🤪🔢🦊🌜🔢🐾◾🔢🐝🌛 🌘 ❓🐾↔️0️⃣ ↩️🐝🔚 ↩️📞🦊🌜🐾➖1️⃣◾🐝✖️🐾🌛🔚 🌒 \
+                                 📄📞🦊🌜5️⃣◾1️⃣🌛🔚", 0);
+        // only main calls it
+        assert!(text.matches("call 🦊").count() == 1 && text.contains("phi"), "{}", text);
+        // the result is multiplied after the call returns
+        let text = optimized_at("🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+                                 📄📞🦊🌜5️⃣🌛🔚", 0);
+        assert_eq!(text.matches("call 🦊").count(), 2, "{}", text);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::mem;
+use super::super::ir::{Function, Block, Inst, Op, Term, Value, BlockId};
+
+// Turns calls of a function to itself whose result it returns straight away
+// into jumps back to its start, so recursion that deep doesn't use up the
+// stack. The entry block keeps the parameters and jumps to a copy of the
+// rest, where a phi of every parameter merges the arguments of the calls.
+pub fn tail_calls(func: &mut Function) {
+    let tail_blocks: Vec<usize> = (0..func.blocks.len()).filter(|&i| is_tail_call(func, i)).collect();
+    if tail_blocks.is_empty() {
+        return;
+    }
+
+    // the entry's code moves to a block of its own, which control now also
+    // comes back to
+    let start = BlockId(func.blocks.len());
+    let (params, rest): (Vec<Inst>, Vec<Inst>) = mem::take(&mut func.blocks[0].insts).into_iter()
+        .partition(|inst| matches!(inst.op, Op::Param(_)));
+    let term = mem::replace(&mut func.blocks[0].term, Term::Jump(start));
+    for block in func.blocks.iter_mut().skip(1) {
+        retarget(&mut block.term, BlockId(0), start);
+    }
+    for succ in term.successors() {
+        for inst in func.blocks[succ.0].insts.iter_mut() {
+            if let Op::Phi(ref mut incoming) = inst.op {
+                for pair in incoming.iter_mut().filter(|pair| pair.0 == BlockId(0)) {
+                    pair.0 = start;
+                }
+            }
+        }
+    }
+    let mut start_block = Block { insts: rest, term };
+    retarget(&mut start_block.term, BlockId(0), start);
+
+    // the parameters' phis, which take their place everywhere else
+    let mut replace = HashMap::new();
+    let mut phis = Vec::new();
+    for inst in &params {
+        let (param, index) = match (inst.value, &inst.op) {
+            (Some(param), &Op::Param(index)) => (param, index),
+            _ => unreachable!(),
+        };
+        let phi = Value(func.types.len());
+        func.types.push(func.types[param.0]);
+        replace.insert(param, phi);
+        phis.push((index, Inst { value: Some(phi), op: Op::Phi(vec![(BlockId(0), param)]), pos: inst.pos }));
+    }
+    func.blocks[0].insts = params;
+    func.blocks.push(start_block);
+    for block in func.blocks.iter_mut().skip(1) {
+        for inst in block.insts.iter_mut() {
+            inst.op.map_operands(|value| *replace.get(&value).unwrap_or(&value));
+        }
+        block.term.map_operands(|value| *replace.get(&value).unwrap_or(&value));
+    }
+
+    // each tail call passes its arguments back to the start
+    for &i in &tail_blocks {
+        let i = if i == 0 { start.0 } else { i };
+        let call = func.blocks[i].insts.pop().unwrap();
+        let args = match call.op {
+            Op::Call(_, args) => args,
+            _ => unreachable!(),
+        };
+        for &mut (index, ref mut phi) in phis.iter_mut() {
+            if let Op::Phi(ref mut incoming) = phi.op {
+                incoming.push((BlockId(i), args[index]));
+            }
+        }
+        func.blocks[i].term = Term::Jump(start);
+    }
+    let mut insts: Vec<Inst> = phis.into_iter().map(|(_, phi)| phi).collect();
+    insts.append(&mut func.blocks[start.0].insts);
+    func.blocks[start.0].insts = insts;
+    func.tidy();
+}
+
+// Whether block `i` ends by calling the function and returning the result
+fn is_tail_call(func: &Function, i: usize) -> bool {
+    let block = &func.blocks[i];
+    match (block.insts.last(), &block.term) {
+        (Some(&Inst { value, op: Op::Call(ref id, _), .. }), &Term::Return(result)) =>
+            *id == func.id && value == result,
+        _ => false,
+    }
+}
+
+fn retarget(term: &mut Term, from: BlockId, to: BlockId) {
+    match *term {
+        Term::Jump(ref mut target) => {
+            if *target == from {
+                *target = to;
+            }
+        },
+        Term::Branch(_, ref mut then, ref mut otherwise) => {
+            if *then == from {
+                *then = to;
+            }
+            if *otherwise == from {
+                *otherwise = to;
+            }
+        },
+        Term::Return(_) | Term::Exit(_) => {},
+    }
+}
@@ -0,0 +1,401 @@
+use super::tokenizer::TokenType;
+use super::tokenizer::VarType;
+use super::tokenizer::Token;
+use super::ast::*;
+
+// Splits a format string literal at its placeholders (🔢 number, ☯️ boolean,
+// 🔤 string). There is always one more segment than there are placeholders.
+fn format_segments(format: &str) -> (Vec<String>, Vec<VarType>) {
+    let mut segments = vec![String::new()];
+    let mut placeholders = Vec::new();
+    let mut chars = format.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let placeholder = match ch {
+            '🔢' => VarType::INT,
+            '☯' => {
+                if chars.peek() == Some(&'\u{fe0f}') {
+                    chars.next();
+                }
+                VarType::BOOL
+            },
+            '🔤' => VarType::STR,
+            _ => {
+                segments.last_mut().unwrap().push(ch);
+                continue;
+            }
+        };
+        placeholders.push(placeholder);
+        segments.push(String::new());
+    }
+    (segments, placeholders)
+}
+
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    in_function: bool
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token]) -> Parser<'a> {
+        Parser {
+            tokens,
+            pos: 0,
+            in_function: false
+        }
+    }
+
+    pub fn parse(mut self) -> Program {
+        let mut statements = Vec::new();
+        while self.peek() != TokenType::END {
+            statements.push(self.statement());
+        }
+
+        Program {
+            globals: Vec::new(),
+            statements
+        }
+    }
+
+    // A lone expression that uses up all the tokens, as typed into the REPL
+    pub fn parse_expression(mut self) -> Expr {
+        let expr = self.expression();
+        if self.peek() != TokenType::END {
+            panic!("Unexpected token after expression: {}", self.debug_str());
+        }
+        expr
+    }
+
+    fn consume(&mut self) {
+        self.pos += 1;
+    }
+
+    fn peek(&self) -> TokenType {
+        self.tokens[self.pos].kind
+    }
+
+    fn current(&self) -> Token {
+        self.tokens[self.pos].clone()
+    }
+
+    fn debug_str(&self) -> String {
+        let mut content = format!("token at pos {}: ", self.pos);
+        let mut count = 0;
+        while count < 5 && self.pos+count < self.tokens.len(){
+            content.push_str(&self.tokens[self.pos + count].to_string());
+            count += 1;
+        }
+        content
+    }
+
+    fn expect(&mut self, kind: TokenType, message: &str) {
+        if self.peek() != kind {
+            panic!("{}: {}", message, self.debug_str());
+        }
+        self.consume();
+    }
+
+    fn expect_lend(&mut self) {
+        self.expect(TokenType::LEND, "Missing line end punctuation");
+    }
+
+    fn var_type(&mut self, message: &str) -> VarType {
+        let var_type = match self.peek() {
+            TokenType::BOOL => VarType::BOOL,
+            TokenType::INT => VarType::INT,
+            TokenType::STR => VarType::STR,
+            TokenType::File => VarType::File,
+            _ => panic!("{}: {}", message, self.debug_str()),
+        };
+        self.consume();
+        var_type
+    }
+
+    fn id(&mut self, message: &str) -> String {
+        if self.peek() != TokenType::ID {
+            panic!("{}: {}", message, self.debug_str());
+        }
+        let id = self.current().value_str;
+        self.consume();
+        id
+    }
+
+    fn statement(&mut self) -> Stmt {
+        let pos = self.pos;
+        let kind = match self.peek() {
+            TokenType::NEW => {
+                self.consume();
+                let var_type = self.var_type("Bad instantiation. Found NEW keyword without type");
+                let id = self.id("Bad instantiation. No variable name provided");
+                self.expect_lend();
+                StmtKind::Declare(var_type, id)
+            },
+            TokenType::ID => {
+                let id = self.id("Expected a variable");
+                self.expect(TokenType::EQ, "Bad instantiation. Must assign value to new variable");
+                let value = self.expression();
+                self.expect_lend();
+                StmtKind::Assign(id, value)
+            },
+            TokenType::IF => {
+                self.consume();
+                let cond = self.expression();
+                let then = self.statement();
+                let otherwise = if self.peek() == TokenType::ELSE {
+                    self.consume();
+                    Some(Box::new(self.statement()))
+                } else {
+                    None
+                };
+                StmtKind::If(cond, Box::new(then), otherwise)
+            },
+            TokenType::ELSE => {
+                panic!("Misplaced 'else': {}", self.debug_str());
+            },
+            TokenType::WHILE => {
+                self.consume();
+                let cond = self.expression();
+                let body = self.statement();
+                StmtKind::While(cond, Box::new(body))
+            },
+            TokenType::LBRACE => {
+                self.consume();
+                let mut statements = Vec::new();
+                while self.peek() != TokenType::RBRACE {
+                    if self.peek() == TokenType::END {
+                        panic!("Missing a closing brace: {}", self.debug_str());
+                    }
+                    statements.push(self.statement());
+                }
+                self.consume();
+                StmtKind::Block(statements)
+            },
+            TokenType::PRINT | TokenType::Put => {
+                let newline = self.peek() == TokenType::PRINT;
+                self.consume();
+
+                // a bare 📄 just prints a newline
+                let mut args = Vec::new();
+                if self.peek() != TokenType::LEND {
+                    args.push(self.expression());
+                    while self.peek() == TokenType::DELIM {
+                        self.consume();
+                        args.push(self.expression());
+                    }
+                }
+                self.expect_lend();
+                StmtKind::Print(args, newline)
+            },
+            TokenType::FUN => {
+                StmtKind::Function(self.function())
+            },
+            TokenType::Return => {
+                self.consume();
+                let value = if self.peek() != TokenType::LEND {
+                    Some(self.expression())
+                } else {
+                    None
+                };
+                self.expect_lend();
+                StmtKind::Return(value)
+            },
+            TokenType::Exit => {
+                self.consume();
+                self.expect(TokenType::LPAREN, "Function call requires parentheses");
+                let status = self.expression();
+                self.expect(TokenType::RPAREN, "Missing closing parenthesis");
+                self.expect_lend();
+                StmtKind::Exit(status)
+            },
+            TokenType::CALL | TokenType::RAND | TokenType::ReadStr | TokenType::ReadInt |
+            TokenType::Eof | TokenType::Argc | TokenType::Argv | TokenType::Parse |
+            TokenType::FOpen | TokenType::FRead | TokenType::FWrite | TokenType::FClose |
+            TokenType::Format => {
+                let call = self.e1();
+                self.expect_lend();
+                StmtKind::Expr(call)
+            },
+            _ => panic!("Unexpected token: {}", self.debug_str()),
+        };
+        Stmt {
+            kind,
+            pos,
+            end: self.pos
+        }
+    }
+
+    fn function(&mut self) -> Function {
+        if self.in_function {
+            panic!("Cannot declare a function inside another function: {}", self.debug_str());
+        }
+        // FUN token
+        self.consume();
+
+        // optional return type
+        let ret_type = match self.peek() {
+            TokenType::ID => VarType::NONE,
+            _ => self.var_type("Bad function declaration. No function name provided"),
+        };
+        let id = self.id("Bad function declaration. No function name provided");
+
+        self.expect(TokenType::LPAREN, "Function declaration missing parentheses");
+        let mut params = Vec::new();
+        while self.peek() != TokenType::RPAREN {
+            let var_type = self.var_type("Must declare type of argument");
+            let name = self.id("Arguments must have names");
+            params.push((name, var_type));
+
+            // check argument count
+            if params.len() > 6 {
+                panic!("Limited to six arguments: {}", self.debug_str());
+            }
+
+            // consume delimiter
+            if self.peek() == TokenType::DELIM {
+                self.consume();
+            }
+        }
+        self.consume();
+
+        self.in_function = true;
+        let body = self.statement();
+        self.in_function = false;
+
+        Function {
+            id,
+            ret_type,
+            params,
+            locals: Vec::new(),
+            body: Box::new(body)
+        }
+    }
+
+    fn expression(&mut self) -> Expr {
+        let mut lhs = self.e3();
+        while self.peek() == TokenType::EQEQ {
+            let pos = self.pos;
+            self.consume();
+            let rhs = self.e3();
+            lhs = Expr::new(ExprKind::Binary(BinOp::Eq, Box::new(lhs), Box::new(rhs)), pos);
+        }
+        lhs
+    }
+
+    fn e3(&mut self) -> Expr {
+        let mut lhs = self.e2();
+        while self.peek() == TokenType::PLUS || self.peek() == TokenType::MINUS {
+            let pos = self.pos;
+            let op = match self.peek() {
+                TokenType::PLUS => BinOp::Add,
+                _ => BinOp::Sub,
+            };
+            self.consume();
+            let rhs = self.e2();
+            lhs = Expr::new(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), pos);
+        }
+        lhs
+    }
+
+    fn e2(&mut self) -> Expr {
+        let mut lhs = self.e1();
+        while self.peek() == TokenType::MUL || self.peek() == TokenType::DIV {
+            let pos = self.pos;
+            let op = match self.peek() {
+                TokenType::MUL => BinOp::Mul,
+                _ => BinOp::Div,
+            };
+            self.consume();
+            let rhs = self.e1();
+            lhs = Expr::new(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), pos);
+        }
+        lhs
+    }
+
+    fn e1(&mut self) -> Expr {
+        let pos = self.pos;
+        let kind = match self.peek() {
+            TokenType::VAL => {
+                let curr = self.current();
+                self.consume();
+                match curr.var_type {
+                    VarType::INT => ExprKind::Int(curr.value_int),
+                    VarType::BOOL => ExprKind::Bool(curr.value_int != 0),
+                    _ => ExprKind::Str(curr.value_str),
+                }
+            },
+            TokenType::LPAREN => {
+                self.consume();
+                let expr = self.expression();
+                self.expect(TokenType::RPAREN, "Missing closing parenthesis");
+                return expr;
+            },
+            TokenType::ID => {
+                ExprKind::Var(self.id("Expected a variable"))
+            },
+            TokenType::NOT => {
+                self.consume();
+                ExprKind::Not(Box::new(self.e1()))
+            },
+            TokenType::CALL => {
+                self.consume();
+                let id = self.id("Cannot call undeclared function");
+                ExprKind::Call(id, self.call_args())
+            },
+            TokenType::Format => {
+                self.consume();
+                self.expect(TokenType::LPAREN, "Function call requires parentheses");
+                if self.peek() != TokenType::VAL || self.current().var_type != VarType::STR {
+                    panic!("Format string must be a string literal: {}", self.debug_str());
+                }
+                let (segments, placeholders) = format_segments(&self.current().value_str);
+                self.consume();
+
+                // one argument per placeholder
+                let mut args = Vec::new();
+                for _ in &placeholders {
+                    self.expect(TokenType::DELIM, "Missing argument for format placeholder");
+                    args.push(self.expression());
+                }
+                self.expect(TokenType::RPAREN, "Too many arguments for format string");
+                ExprKind::Format(segments, placeholders, args)
+            },
+            _ => {
+                let builtin = match self.peek() {
+                    TokenType::RAND => Builtin::Rand,
+                    TokenType::ReadStr => Builtin::ReadStr,
+                    TokenType::ReadInt => Builtin::ReadInt,
+                    TokenType::Eof => Builtin::Eof,
+                    TokenType::Argc => Builtin::ArgCount,
+                    TokenType::Argv => Builtin::Arg,
+                    TokenType::Parse => Builtin::Parse,
+                    TokenType::FOpen => Builtin::Open,
+                    TokenType::FRead => Builtin::ReadLine,
+                    TokenType::FWrite => Builtin::Write,
+                    TokenType::FClose => Builtin::Close,
+                    _ => panic!("Value not found: {}", self.debug_str()),
+                };
+                self.consume();
+                ExprKind::Builtin(builtin, self.call_args())
+            }
+        };
+        Expr::new(kind, pos)
+    }
+
+    fn call_args(&mut self) -> Vec<Expr> {
+        self.expect(TokenType::LPAREN, "Function call requires parentheses");
+        let mut args = Vec::new();
+        while self.peek() != TokenType::RPAREN {
+            if self.peek() == TokenType::END {
+                panic!("Missing closing parenthesis: {}", self.debug_str());
+            }
+            args.push(self.expression());
+
+            // consume delimiter
+            if self.peek() == TokenType::DELIM {
+                self.consume();
+            }
+        }
+        self.consume();
+        args
+    }
+}
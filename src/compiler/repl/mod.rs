@@ -0,0 +1,306 @@
+use std::cell::Cell;
+use std::io::{BufRead, Write};
+use std::panic;
+use std::sync::Once;
+use super::tokenizer::{Tokenizer, TokenType, VarType};
+use super::parser::Parser;
+use super::checker::{Checker, Scope, type_name};
+use super::codegen::{CodeGen, X86_64};
+use super::ir;
+use super::interpreter::Interpreter;
+use super::machine::Stop;
+use super::ast::*;
+
+const PROMPT: &str = "🐮 ";
+const MORE_PROMPT: &str = ".. ";
+
+const HELP: &str = "\
+Type statements to run them, or an expression without 🔚 to see its value.
+Variables and functions stay declared from one input to the next, and a
+line that opens a 🌘 block continues until it is closed with 🌒.
+
+    :type <expression>  show the type of an expression
+    :tokens <code>      show the tokens of some code
+    :asm <code>         show the assembly some code compiles to
+    :help               show this message
+    :quit               leave (so does the end of the input)
+";
+
+// Compile errors are panics, which the REPL reports and survives. The
+// default panic message is only silenced while it is doing so.
+thread_local!(static CATCHING: Cell<bool> = const { Cell::new(false) });
+static QUIET_HOOK: Once = Once::new();
+
+fn catch_errors<T, F: FnOnce() -> T>(f: F) -> Result<T, String> {
+    QUIET_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.with(|catching| catching.get()) {
+                default_hook(info);
+            }
+        }));
+    });
+    CATCHING.with(|catching| catching.set(true));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(false));
+    result.map_err(|payload| {
+        if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else {
+            String::from("unknown error")
+        }
+    })
+}
+
+// How many more 🌘 than 🌒 there are outside of string literals
+fn open_blocks(text: &str) -> i64 {
+    let mut open = 0;
+    let mut in_string = false;
+    for ch in text.chars() {
+        match ch {
+            '💬' => in_string = !in_string,
+            '🌘' if !in_string => open += 1,
+            '🌒' if !in_string => open -= 1,
+            _ => {}
+        }
+    }
+    open
+}
+
+// An input that went through the checker
+struct Checked {
+    program: Program,
+    scope: Scope,
+    // the type of the input, if it is an expression
+    expr_type: Option<VarType>
+}
+
+// Reads inputs one at a time and runs them with the interpreter
+pub struct Repl<'a> {
+    interpreter: Interpreter<'a>,
+    // what the inputs so far have declared
+    scope: Scope
+}
+
+impl<'a> Repl<'a> {
+    // `args` are what 🗣 and 👥 see. Inputs are read from `input`, which the
+    // program's own reads share.
+    pub fn new(args: &'a [String], input: &'a mut dyn BufRead, output: &'a mut dyn Write,
+               errors: &'a mut dyn Write) -> Repl<'a> {
+        Repl {
+            interpreter: Interpreter::new(args, input, output, errors),
+            scope: Scope::default()
+        }
+    }
+
+    // Runs inputs until the input ends or the program exits, returning the
+    // exit status
+    pub fn run(mut self) -> i32 {
+        let status = loop {
+            let text = match self.read() {
+                Some(text) => text,
+                None => break 0,
+            };
+            if let Some(status) = self.handle(text.trim()) {
+                break status;
+            }
+        };
+        self.interpreter.flush();
+        status
+    }
+
+    fn write(&mut self, text: &str) {
+        let (_, output, _) = self.interpreter.streams();
+        let _ = output.write_all(text.as_bytes());
+    }
+
+    fn error(&mut self, message: &str) {
+        let (_, output, errors) = self.interpreter.streams();
+        let _ = output.flush();
+        let _ = writeln!(errors, "error: {}", message);
+    }
+
+    // Reads one input, taking more lines while a 🌘 block is still open. None
+    // at the end of the input.
+    fn read(&mut self) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            let (input, output, _) = self.interpreter.streams();
+            let prompt = if text.is_empty() { PROMPT } else { MORE_PROMPT };
+            let _ = output.write_all(prompt.as_bytes());
+            let _ = output.flush();
+
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) if text.is_empty() => return None,
+                // an unfinished block still gets its error message
+                Ok(0) | Err(_) => return Some(text),
+                Ok(_) => {},
+            }
+            text.push_str(&line);
+            if open_blocks(&text) <= 0 {
+                return Some(text);
+            }
+        }
+    }
+
+    // Handles one input, returning the exit status if the program exits
+    fn handle(&mut self, text: &str) -> Option<i32> {
+        if text.is_empty() {
+            return None;
+        }
+        if !text.starts_with(':') {
+            return self.execute(text);
+        }
+        let (command, code) = match text.find(char::is_whitespace) {
+            Some(end) => (&text[..end], text[end..].trim()),
+            None => (text, ""),
+        };
+        match command {
+            ":help" => self.write(HELP),
+            ":quit" => return Some(0),
+            ":tokens" => {
+                let tokens = catch_errors(|| Tokenizer::new(code.to_string()).tokenize());
+                match tokens {
+                    Ok(tokens) => {
+                        for tkn in &tokens {
+                            self.write(&format!("{:?}\n", tkn));
+                        }
+                    },
+                    Err(message) => self.error(&message),
+                }
+            },
+            ":type" => match self.check(code) {
+                Ok(Checked { expr_type: Some(var_type), .. }) => {
+                    self.write(&format!("{}\n", type_name(var_type)));
+                },
+                Ok(_) => self.error(":type needs an expression, without 🔚"),
+                Err(message) => self.error(&message),
+            },
+            ":asm" => match self.check(code) {
+                Ok(checked) => {
+                    let code = CodeGen::new(X86_64).gen_fragment(&ir::lower_fragment(&checked.program));
+                    self.write(&code);
+                },
+                Err(message) => self.error(&message),
+            },
+            _ => self.error(&format!("unknown command '{}', try :help", command)),
+        }
+        None
+    }
+
+    fn execute(&mut self, code: &str) -> Option<i32> {
+        let checked = match self.check(code) {
+            Ok(checked) => checked,
+            Err(message) => {
+                self.error(&message);
+                return None;
+            },
+        };
+        self.scope = checked.scope;
+        let result = self.interpreter.execute(&checked.program);
+        self.interpreter.flush();
+        match result {
+            Ok(()) => None,
+            Err(Stop::Exit(status)) => Some(status),
+            // already reported, and the REPL carries on
+            Err(Stop::Error(_)) => None,
+        }
+    }
+
+    // Parses and checks `code` against the declarations so far. Code that
+    // doesn't end its last statement with 🔚 or 🌒 is an expression, which
+    // is printed when it has a value.
+    fn check(&self, code: &str) -> Result<Checked, String> {
+        let scope = self.scope.clone();
+        catch_errors(move || {
+            let tokens = Tokenizer::new(code.to_string()).tokenize();
+            let last = tokens.len().checked_sub(2).map(|last| tokens[last].kind);
+            let is_expr = !matches!(last, Some(TokenType::LEND) | Some(TokenType::RBRACE));
+            let mut program = if is_expr {
+                let expr = Parser::new(&tokens).parse_expression();
+                let pos = expr.pos;
+                Program {
+                    globals: Vec::new(),
+                    statements: vec![Stmt { kind: StmtKind::Expr(expr), pos, end: tokens.len() - 1 }]
+                }
+            } else {
+                Parser::new(&tokens).parse()
+            };
+
+            let mut checker = Checker::with_scope(&tokens, scope);
+            checker.check(&mut program);
+            let scope = checker.into_scope();
+
+            let mut expr_type = None;
+            if is_expr {
+                let stmt = &mut program.statements[0];
+                if let StmtKind::Expr(ref expr) = stmt.kind {
+                    expr_type = Some(expr.var_type);
+                    if expr.var_type != VarType::NONE {
+                        stmt.kind = StmtKind::Print(vec![expr.clone()], true);
+                    }
+                }
+            }
+            Checked {
+                program,
+                scope,
+                expr_type
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+
+    // Feeds `script` to a REPL, returning its exit status, output without the
+    // prompts, and errors
+    fn session(script: &str) -> (i32, String, String) {
+        let args = vec![String::from("🐑")];
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let status = Repl::new(&args, &mut script.as_bytes(), &mut output, &mut errors).run();
+        let output = String::from_utf8(output).unwrap().replace("🐮 ", "").replace(".. ", "");
+        (status, output, String::from_utf8(errors).unwrap())
+    }
+
+    #[test]
+    fn declarations_last_between_inputs() {
+        let (status, output, errors) = session("🆕🔢😀🔚\n😀⬅️4️⃣🔚\n\
+            🤪🔢🐝🌜🔢🐾🌛 ↩️🐾✖️🐾🔚\n📞🐝🌜😀🌛\n😀↔️4️⃣\n🗣🌜0️⃣🌛\n");
+        assert_eq!((status, errors.as_str()), (0, ""));
+        assert_eq!(output, "16\n👍\n🐑\n");
+    }
+
+    #[test]
+    fn blocks_continue_until_closed() {
+        let (_, output, _) = session("🆕🔢😀🔚\n🔁🚫🌜😀↔️2️⃣🌛 🌘\n😀⬅️😀➕1️⃣🔚\n📄😀🔚\n🌒\n");
+        assert_eq!(output, "1\n2\n");
+    }
+
+    #[test]
+    fn program_input_is_shared() {
+        let (_, output, _) = session("📥🌜🌛➕💬🐶💬\n🐱\n📭🌜🌛\n");
+        assert_eq!(output, "🐱🐶\n👍\n");
+    }
+
+    #[test]
+    fn meta_commands() {
+        let (_, output, errors) = session(":type 1️⃣↔️2️⃣\n:tokens 7️⃣\n:asm 📄1️⃣🔚\n:nope\n");
+        assert!(output.starts_with("bool\nToken { kind: VAL, value_int: 7,"));
+        assert!(output.contains("\t\tmovq $1, %rax\n\t\tmovq %rax, %rdi\n\t\tcall print_int_\n"));
+        assert_eq!(errors, "error: unknown command ':nope', try :help\n");
+    }
+
+    #[test]
+    fn errors_do_not_end_the_session() {
+        let (status, output, errors) = session("📄🐱🔚\n1️⃣➗️0️⃣\n🆕🔤🐱🔚\n🐱➕1️⃣\n🚪🌜3️⃣🌛🔚\n📄2️⃣🔚\n");
+        assert_eq!(status, 3);
+        assert_eq!(output, "1\n");
+        assert!(errors.starts_with("error: Variable or function never declared"));
+        assert!(errors.ends_with("🚨 Runtime error: division by zero\n"));
+    }
+}
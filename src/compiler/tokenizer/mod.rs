@@ -8,16 +8,25 @@ use self::tokenizer_util::is_variant_selector;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum TokenType {
+    Argc,
+    Argv,
     BOOL,
     CALL,
     DELIM,
     DIV,
     ELSE,
     END,
+    Eof,
     EQ,
     EQEQ,
+    Exit,
+    FClose,
+    File,
+    FOpen,
+    Format,
+    FRead,
     FUN,
-    FUNID,
+    FWrite,
     ID,
     IF,
     INT,
@@ -29,10 +38,15 @@ pub enum TokenType {
     NEW,
     NONE,
     NOT,
+    Parse,
     PLUS,
     PRINT,
+    Put,
     RAND,
     RBRACE,
+    ReadInt,
+    ReadStr,
+    Return,
     RPAREN,
     STR,
     VAL,
@@ -42,6 +56,7 @@ pub enum TokenType {
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum VarType {
     BOOL,
+    File,
     INT,
     STR,
     NONE,
@@ -53,7 +68,10 @@ pub struct Token {
     pub value_int: u64,
     pub value_str: String,
     pub var_type: VarType,
-    pub arg_types: Vec<VarType>
+    // where it is in the source, counting from 1, and the column just after it
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize
 }
 
 impl Clone for Token {
@@ -63,7 +81,9 @@ impl Clone for Token {
             value_int: self.value_int,
             value_str: self.value_str.clone(),
             var_type: self.var_type,
-            arg_types: self.arg_types.clone()
+            line: self.line,
+            column: self.column,
+            end_column: self.end_column
         }
     }
 }
@@ -75,7 +95,9 @@ impl Token {
             value_int: 0,
             value_str: String::from(""),
             var_type: VarType::NONE,
-            arg_types: Vec::new()
+            line: 0,
+            column: 0,
+            end_column: 0
         }
     }
 
@@ -88,18 +110,13 @@ impl Token {
             panic!("Internal error: cannot convert to NONE.");
         }
         match from_type {
-            VarType::BOOL => true,
-            VarType::INT => true,
+            VarType::BOOL => to_type != VarType::File,
+            VarType::INT => to_type != VarType::File,
             VarType::STR => to_type == VarType::STR,
+            VarType::File => to_type == VarType::File || to_type == VarType::BOOL,
             _ => panic!("Internal error: cannot convert from NONE."),
         }
     }
-
-    pub fn copy_arg_types(&mut self, args: &Vec<VarType>) {
-        for vt in args {
-            self.arg_types.push(*vt);
-        }
-    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -112,7 +129,11 @@ enum TokenizerState {
 pub struct Tokenizer {
     program: Vec<char>,
     state: TokenizerState,
-    pos: usize
+    pos: usize,
+    // the line counted up to `scanned`, and where it starts
+    line: usize,
+    line_start: usize,
+    scanned: usize
 }
 
 impl Tokenizer {
@@ -120,7 +141,10 @@ impl Tokenizer {
         Tokenizer {
             program: prog.chars().collect(),
             state: TokenizerState::NONE,
-            pos: 0
+            pos: 0,
+            line: 1,
+            line_start: 0,
+            scanned: 0
         }
     }
 
@@ -144,6 +168,9 @@ impl Tokenizer {
 
         let mut pos = self.pos;
         pos = self.fast_forward_whitespace(pos);
+        self.locate(pos);
+        tkn.line = self.line;
+        tkn.column = pos - self.line_start + 1;
 
         if pos < self.program.len() {
             let prog = &self.program;
@@ -172,8 +199,56 @@ impl Tokenizer {
                 '🔚' => TokenType::LEND,
                 '🔁' => TokenType::WHILE,
                 '📄' => TokenType::PRINT,
+                '🖨' => TokenType::Put,
+                '🖼' => {
+                    tkn.var_type = VarType::STR;
+                    TokenType::Format
+                    },
                 '📞' => TokenType::CALL,
                 '🤪' => TokenType::FUN,
+                '📥' => {
+                    tkn.var_type = VarType::STR;
+                    TokenType::ReadStr
+                    },
+                '📨' => {
+                    tkn.var_type = VarType::INT;
+                    TokenType::ReadInt
+                    },
+                '📭' => {
+                    tkn.var_type = VarType::BOOL;
+                    TokenType::Eof
+                    },
+                '👥' => {
+                    tkn.var_type = VarType::INT;
+                    TokenType::Argc
+                    },
+                '🗣' => {
+                    tkn.var_type = VarType::STR;
+                    TokenType::Argv
+                    },
+                '🚪' => TokenType::Exit,
+                '↩' => TokenType::Return,
+                '📁' => TokenType::File,
+                '📂' => {
+                    tkn.var_type = VarType::File;
+                    TokenType::FOpen
+                    },
+                '📃' => {
+                    tkn.var_type = VarType::STR;
+                    TokenType::FRead
+                    },
+                '✍' => {
+                    tkn.var_type = VarType::BOOL;
+                    TokenType::FWrite
+                    },
+                '🔒' => {
+                    tkn.var_type = VarType::BOOL;
+                    TokenType::FClose
+                    },
+                '🔄' => {
+                    tkn.var_type = VarType::INT;
+                    TokenType::Parse
+                    },
                 '👍' => {
                     tkn.value_int = 1;
                     tkn.var_type = VarType::BOOL;
@@ -194,8 +269,8 @@ impl Tokenizer {
                         TokenType::ID
                     } else { // Handle value creation
                         // Make make digit (each keycap is 3 codepoints wide)
-                        if pos + 3 < prog.len() && is_keycap(&prog[pos..pos+3]) {
-                            while pos + 3 < prog.len() && is_keycap(&prog[pos..pos+3]) {
+                        if pos + 3 <= prog.len() && is_keycap(&prog[pos..pos+3]) {
+                            while pos + 3 <= prog.len() && is_keycap(&prog[pos..pos+3]) {
                                 tkn.value_int *= 10;
                                 tkn.value_int += get_keycap_val(prog[pos]);
                                 pos += 3;
@@ -230,16 +305,28 @@ impl Tokenizer {
         }
 
         self.normalize_pos_and_state(tkn.kind);
+        tkn.end_column = self.pos.max(pos).min(self.program.len()) - self.line_start + 1;
         // println!("{:?}, {}", tkn, self.pos);
         tkn
     }
 
+    // Counts the lines up to `pos`
+    fn locate(&mut self, pos: usize) {
+        while self.scanned < pos {
+            if self.program[self.scanned] == '\n' {
+                self.line += 1;
+                self.line_start = self.scanned + 1;
+            }
+            self.scanned += 1;
+        }
+    }
+
     fn normalize_pos_and_state(&mut self, kind: TokenType) {
         if kind == TokenType::EQ || kind == TokenType::EQEQ || kind == TokenType::MUL || kind == TokenType::BOOL {
             self.pos += 1;
         }
 
-        if kind == TokenType::BOOL || kind == TokenType::INT || kind == TokenType::STR {
+        if kind == TokenType::BOOL || kind == TokenType::INT || kind == TokenType::STR || kind == TokenType::File {
             self.state = TokenizerState::DEFINING;
         }
 
@@ -265,7 +352,7 @@ impl Tokenizer {
 
     fn create_id(&self, mut start: usize) -> (usize, String) {
         let mut id = String::new();
-        while is_emoji(self.program[start]) && self.program[start] != '🔚' {
+        while start < self.program.len() && is_emoji(self.program[start]) && !is_keyword(self.program[start]) && self.program[start] != '🔚' {
             id.push(self.program[start]);
             start +=1;
         }
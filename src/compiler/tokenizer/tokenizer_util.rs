@@ -119,7 +119,10 @@ pub fn is_keyword(ch: char) -> bool {
         '🤪' | '🌘' | '🌒' | '📄' | '➕' | 
         '➖' | '✖' | '➗' | '🚫' | '⬅' | 
         '↔' | '🔀' | '🎲' | '📞' | '❓' |
-        '🌜' | '🌛' | '❌' | '🔁' => true,
+        '🌜' | '🌛' | '❌' | '🔁' | '📥' |
+        '📨' | '📭' | '👥' | '🗣' | '🔄' |
+        '🚪' | '📁' | '📂' | '📃' | '✍' |
+        '🔒' | '◾' | '💬' | '🖨' | '🖼' | '↩' => true,
         _ => false
     }
 }
\ No newline at end of file
@@ -0,0 +1,510 @@
+use std::io::{BufRead, Write};
+use super::super::ast::Builtin;
+use super::super::machine::{Machine, Value, Stop, Run, string};
+use super::module::{Instr, PAGE_SIZE};
+
+// A small WebAssembly interpreter for testing the backend without a browser.
+// It only understands the modules WasmGen produces and provides the host
+// functions on top of the machine the other interpreters use. Invalid modules
+// make it panic.
+pub fn run(bytes: &[u8], args: &[String], input: &mut dyn BufRead, output: &mut dyn Write,
+           errors: &mut dyn Write) -> i32 {
+    let module = decode(bytes);
+    let mut interp = Interp {
+        machine: Machine::new(args, input, output, errors),
+        args: args,
+        memory: vec![0; (module.memory_pages * PAGE_SIZE) as usize],
+        globals: module.globals.clone(),
+        line: Vec::new()
+    };
+    let start = module.data_start as usize;
+    interp.memory[start..start + module.data.len()].copy_from_slice(&module.data);
+    let main = module.exports.iter().find(|export| export.0 == "main").expect("no main export").1;
+    let status = match interp.call(&module, main, Vec::new()) {
+        Ok(_) => 0,
+        Err(Stop::Exit(status)) | Err(Stop::Error(status)) => status,
+    };
+    interp.machine.flush();
+    status
+}
+
+struct Decoded {
+    types: Vec<(usize, usize)>,
+    // type indices of the imports, then of the functions
+    imports: Vec<(String, u32)>,
+    funcs: Vec<u32>,
+    code: Vec<Code>,
+    globals: Vec<u64>,
+    exports: Vec<(String, u32)>,
+    memory_pages: u32,
+    data_start: u32,
+    data: Vec<u8>
+}
+
+struct Code {
+    locals: usize,
+    body: Vec<Instr>,
+    // for every block, loop and if, where its else and end are
+    matches: Vec<(usize, usize)>
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> u8 {
+        let byte = self.bytes[self.pos];
+        self.pos += 1;
+        byte
+    }
+
+    fn u32(&mut self) -> u32 {
+        let (mut value, mut shift) = (0, 0);
+        loop {
+            let byte = self.byte();
+            value |= ((byte & 0x7f) as u32) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    fn i64(&mut self) -> i64 {
+        let (mut value, mut shift) = (0i64, 0);
+        loop {
+            let byte = self.byte();
+            value |= ((byte & 0x7f) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return value;
+            }
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let len = self.u32() as usize;
+        self.pos += len;
+        String::from_utf8_lossy(&self.bytes[self.pos - len..self.pos]).into_owned()
+    }
+
+    fn instr(&mut self) -> Instr {
+        match self.byte() {
+            0x00 => Instr::Unreachable,
+            0x02 => { self.byte(); Instr::Block },
+            0x03 => { self.byte(); Instr::Loop },
+            0x04 => { self.byte(); Instr::If },
+            0x05 => Instr::Else,
+            0x0b => Instr::End,
+            0x0c => Instr::Br(self.u32()),
+            0x0d => Instr::BrIf(self.u32()),
+            0x0f => Instr::Return,
+            0x10 => Instr::Call(self.u32()),
+            0x1a => Instr::Drop,
+            0x1b => Instr::Select,
+            0x20 => Instr::LocalGet(self.u32()),
+            0x21 => Instr::LocalSet(self.u32()),
+            0x23 => Instr::GlobalGet(self.u32()),
+            0x24 => Instr::GlobalSet(self.u32()),
+            0x2d => { self.u32(); self.u32(); Instr::I32Load8U },
+            0x3a => { self.u32(); self.u32(); Instr::I32Store8 },
+            0x3f => { self.byte(); Instr::MemorySize },
+            0x40 => { self.byte(); Instr::MemoryGrow },
+            0x41 => Instr::I32Const(self.i64() as i32),
+            0x42 => Instr::I64Const(self.i64()),
+            0x45 => Instr::I32Eqz,
+            0x46 => Instr::I32Eq,
+            0x47 => Instr::I32Ne,
+            0x50 => Instr::I64Eqz,
+            0x51 => Instr::I64Eq,
+            0x52 => Instr::I64Ne,
+            0x56 => Instr::I64GtU,
+            0x58 => Instr::I64LeU,
+            0x5a => Instr::I64GeU,
+            0x7c => Instr::I64Add,
+            0x7d => Instr::I64Sub,
+            0x7e => Instr::I64Mul,
+            0x80 => Instr::I64DivU,
+            0x82 => Instr::I64RemU,
+            0x83 => Instr::I64And,
+            0x84 => Instr::I64Or,
+            0x86 => Instr::I64Shl,
+            0xa7 => Instr::I32WrapI64,
+            0xad => Instr::I64ExtendI32U,
+            op => panic!("unsupported opcode {:#x}", op),
+        }
+    }
+
+    // A constant expression, as used for globals and the data segment
+    fn init(&mut self) -> u64 {
+        let value = match self.instr() {
+            Instr::I32Const(value) => value as u32 as u64,
+            Instr::I64Const(value) => value as u64,
+            instr => panic!("unsupported initializer {:?}", instr),
+        };
+        assert_eq!(self.instr(), Instr::End);
+        value
+    }
+}
+
+fn decode(bytes: &[u8]) -> Decoded {
+    assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0", "not a WebAssembly module");
+    let mut module = Decoded {
+        types: Vec::new(),
+        imports: Vec::new(),
+        funcs: Vec::new(),
+        code: Vec::new(),
+        globals: Vec::new(),
+        exports: Vec::new(),
+        memory_pages: 0,
+        data_start: 0,
+        data: Vec::new()
+    };
+    let mut r = Reader { bytes: bytes, pos: 8 };
+    while r.pos < bytes.len() {
+        let id = r.byte();
+        let size = r.u32() as usize;
+        let end = r.pos + size;
+        let count = r.u32();
+        for _ in 0..count {
+            match id {
+                1 => {
+                    assert_eq!(r.byte(), 0x60);
+                    let params = r.u32() as usize;
+                    r.pos += params;
+                    let results = r.u32() as usize;
+                    r.pos += results;
+                    module.types.push((params, results));
+                },
+                2 => {
+                    r.name();
+                    let name = r.name();
+                    assert_eq!(r.byte(), 0x00, "only functions are imported");
+                    let type_index = r.u32();
+                    module.imports.push((name, type_index));
+                },
+                3 => module.funcs.push(r.u32()),
+                5 => {
+                    r.byte();
+                    module.memory_pages = r.u32();
+                },
+                6 => {
+                    r.pos += 2;
+                    let value = r.init();
+                    module.globals.push(value);
+                },
+                7 => {
+                    let name = r.name();
+                    let kind = r.byte();
+                    let index = r.u32();
+                    if kind == 0x00 {
+                        module.exports.push((name, index));
+                    }
+                },
+                10 => {
+                    let code_end = r.u32() as usize + r.pos;
+                    let mut locals = 0;
+                    for _ in 0..r.u32() {
+                        locals += r.u32() as usize;
+                        r.byte();
+                    }
+                    let mut body = Vec::new();
+                    while r.pos < code_end {
+                        body.push(r.instr());
+                    }
+                    let matches = match_blocks(&body);
+                    module.code.push(Code { locals: locals, body: body, matches: matches });
+                },
+                11 => {
+                    r.u32();
+                    module.data_start = r.init() as u32;
+                    let len = r.u32() as usize;
+                    module.data = bytes[r.pos..r.pos + len].to_vec();
+                    r.pos += len;
+                },
+                _ => panic!("unsupported section {}", id),
+            }
+        }
+        assert_eq!(r.pos, end, "section {} has the wrong size", id);
+    }
+    module
+}
+
+// Where the else and end of each block are, indexed by position
+fn match_blocks(body: &[Instr]) -> Vec<(usize, usize)> {
+    let mut matches = vec![(0, 0); body.len()];
+    let mut open = Vec::new();
+    for (i, instr) in body.iter().enumerate() {
+        match *instr {
+            Instr::Block | Instr::Loop | Instr::If => open.push(i),
+            Instr::Else => {
+                let start = *open.last().expect("else outside a block");
+                matches[start].0 = i;
+            },
+            // the function body's own end has nothing to match
+            Instr::End => {
+                if let Some(start) = open.pop() {
+                    matches[start].1 = i;
+                }
+            },
+            _ => {},
+        }
+    }
+    matches
+}
+
+struct Interp<'a> {
+    machine: Machine<'a>,
+    args: &'a [String],
+    memory: Vec<u8>,
+    globals: Vec<u64>,
+    // the line read_line read last, until line_copy wants it
+    line: Vec<u8>
+}
+
+// A block being executed, and how deep the stack was when it started
+struct Label {
+    is_loop: bool,
+    start: usize,
+    end: usize,
+    height: usize
+}
+
+impl<'a> Interp<'a> {
+    fn trap(&mut self, message: &str) -> Stop {
+        // what a segmentation fault would give a native program
+        self.machine.runtime_error(&format!("wasm trap: {}", message), 139)
+    }
+
+    fn c_string(&self, address: u64) -> Vec<u8> {
+        let start = address as u32 as usize;
+        let len = self.memory[start..].iter().position(|&b| b == 0).expect("unterminated string");
+        self.memory[start..start + len].to_vec()
+    }
+
+    fn call(&mut self, module: &Decoded, index: u32, args: Vec<u64>) -> Run<Option<u64>> {
+        let index = index as usize;
+        if index < module.imports.len() {
+            return self.host(&module.imports[index].0, &args);
+        }
+        let func = index - module.imports.len();
+        let code = &module.code[func];
+        let (_, results) = module.types[module.funcs[func] as usize];
+        let mut locals = args;
+        locals.resize(locals.len() + code.locals, 0);
+
+        let mut stack: Vec<u64> = Vec::new();
+        let mut labels: Vec<Label> = Vec::new();
+        let mut pc = 0;
+        while pc < code.body.len() {
+            let instr = code.body[pc];
+            pc += 1;
+            // the target of a branch, counting outwards from the innermost
+            // block
+            let mut branch = None;
+            match instr {
+                Instr::Unreachable => return Err(self.trap("unreachable")),
+                Instr::Block | Instr::Loop => labels.push(Label {
+                    is_loop: instr == Instr::Loop,
+                    start: pc - 1,
+                    end: code.matches[pc - 1].1,
+                    height: stack.len()
+                }),
+                Instr::If => {
+                    let cond = stack.pop().unwrap();
+                    let (otherwise, end) = code.matches[pc - 1];
+                    labels.push(Label { is_loop: false, start: pc - 1, end: end, height: stack.len() });
+                    if cond as u32 == 0 {
+                        if otherwise != 0 {
+                            pc = otherwise + 1;
+                        } else {
+                            pc = end;
+                        }
+                    }
+                },
+                // the end of the then branch
+                Instr::Else => branch = Some(0),
+                Instr::End => {
+                    labels.pop();
+                },
+                Instr::Br(depth) => branch = Some(depth),
+                Instr::BrIf(depth) => {
+                    if stack.pop().unwrap() as u32 != 0 {
+                        branch = Some(depth);
+                    }
+                },
+                Instr::Return => break,
+                Instr::Call(callee) => {
+                    let (params, _) = module.types[self.type_index(module, callee) as usize];
+                    let args = stack.split_off(stack.len() - params);
+                    if let Some(value) = self.call(module, callee, args)? {
+                        stack.push(value);
+                    }
+                },
+                Instr::Drop => {
+                    stack.pop();
+                },
+                Instr::Select => {
+                    let cond = stack.pop().unwrap();
+                    let otherwise = stack.pop().unwrap();
+                    if cond as u32 == 0 {
+                        *stack.last_mut().unwrap() = otherwise;
+                    }
+                },
+                Instr::LocalGet(n) => stack.push(locals[n as usize]),
+                Instr::LocalSet(n) => locals[n as usize] = stack.pop().unwrap(),
+                Instr::GlobalGet(n) => stack.push(self.globals[n as usize]),
+                Instr::GlobalSet(n) => self.globals[n as usize] = stack.pop().unwrap(),
+                Instr::I32Load8U => {
+                    let address = stack.pop().unwrap() as u32 as usize;
+                    match self.memory.get(address) {
+                        Some(&byte) => stack.push(byte as u64),
+                        None => return Err(self.trap("out of bounds memory access")),
+                    }
+                },
+                Instr::I32Store8 => {
+                    let value = stack.pop().unwrap();
+                    let address = stack.pop().unwrap() as u32 as usize;
+                    match self.memory.get_mut(address) {
+                        Some(byte) => *byte = value as u8,
+                        None => return Err(self.trap("out of bounds memory access")),
+                    }
+                },
+                Instr::MemorySize => stack.push((self.memory.len() / PAGE_SIZE as usize) as u64),
+                Instr::MemoryGrow => {
+                    let pages = stack.pop().unwrap() as usize;
+                    let old = self.memory.len() / PAGE_SIZE as usize;
+                    self.memory.resize((old + pages) * PAGE_SIZE as usize, 0);
+                    stack.push(old as u64);
+                },
+                Instr::I32Const(value) => stack.push(value as u32 as u64),
+                Instr::I64Const(value) => stack.push(value as u64),
+                Instr::I32Eqz | Instr::I64Eqz => {
+                    let value = stack.pop().unwrap();
+                    stack.push((value == 0) as u64);
+                },
+                Instr::I32WrapI64 => {
+                    let value = stack.pop().unwrap();
+                    stack.push(value as u32 as u64);
+                },
+                // i32 values are kept zero-extended already
+                Instr::I64ExtendI32U => {},
+                _ => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    let value = match instr {
+                        Instr::I32Eq | Instr::I64Eq => (left == right) as u64,
+                        Instr::I32Ne | Instr::I64Ne => (left != right) as u64,
+                        Instr::I64GtU => (left > right) as u64,
+                        Instr::I64LeU => (left <= right) as u64,
+                        Instr::I64GeU => (left >= right) as u64,
+                        Instr::I64Add => left.wrapping_add(right),
+                        Instr::I64Sub => left.wrapping_sub(right),
+                        Instr::I64Mul => left.wrapping_mul(right),
+                        Instr::I64DivU | Instr::I64RemU if right == 0 => {
+                            return Err(self.trap("integer divide by zero"));
+                        },
+                        Instr::I64DivU => left / right,
+                        Instr::I64RemU => left % right,
+                        Instr::I64And => left & right,
+                        Instr::I64Or => left | right,
+                        Instr::I64Shl => left << (right & 63),
+                        _ => unreachable!(),
+                    };
+                    stack.push(value);
+                },
+            }
+
+            if let Some(depth) = branch {
+                let target = labels.len() - 1 - depth as usize;
+                let label = &labels[target];
+                stack.truncate(label.height);
+                if label.is_loop {
+                    pc = label.start + 1;
+                    labels.truncate(target + 1);
+                } else {
+                    pc = label.end + 1;
+                    labels.truncate(target);
+                }
+            }
+        }
+        Ok(if results > 0 { stack.pop() } else { None })
+    }
+
+    fn type_index(&self, module: &Decoded, index: u32) -> u32 {
+        let index = index as usize;
+        if index < module.imports.len() {
+            module.imports[index].1
+        } else {
+            module.funcs[index - module.imports.len()]
+        }
+    }
+
+    // The functions a browser would provide
+    fn host(&mut self, name: &str, args: &[u64]) -> Run<Option<u64>> {
+        // file -1 is standard input
+        let file = |args: &[u64]| if args[0] == !0 { None } else { Some(Value::Int(args[0])) };
+        let result = match name {
+            "print" => {
+                let text = self.c_string(args[0]);
+                self.machine.write(&text);
+                None
+            },
+            "print_int" => {
+                self.machine.write(args[0].to_string().as_bytes());
+                None
+            },
+            "print_err" => {
+                let text = self.c_string(args[0]);
+                let (_, output, errors) = self.machine.streams();
+                let _ = output.flush();
+                let _ = errors.write_all(&text);
+                None
+            },
+            "exit" => return Err(Stop::Exit(args[0] as i32)),
+            "rand" => Some(self.machine.builtin(Builtin::Rand, vec![Value::Int(1 << 31)])?.int()),
+            "read_line" => {
+                let line = match file(args) {
+                    None => self.machine.builtin(Builtin::ReadStr, Vec::new())?,
+                    Some(f) => self.machine.builtin(Builtin::ReadLine, vec![f])?,
+                };
+                self.line = line.bytes().to_vec();
+                Some(self.line.len() as u64)
+            },
+            "line_copy" => {
+                let start = args[0] as usize;
+                self.memory[start..start + self.line.len()].copy_from_slice(&self.line);
+                None
+            },
+            "eof" => {
+                let args = file(args).into_iter().collect();
+                Some(self.machine.builtin(Builtin::Eof, args)?.int())
+            },
+            "arg_count" => Some(self.args.len() as u64),
+            "arg_len" => Some(self.args[args[0] as usize].len() as u64),
+            "arg_copy" => {
+                let arg = self.args[args[0] as usize].as_bytes();
+                let start = args[1] as usize;
+                self.memory[start..start + arg.len()].copy_from_slice(arg);
+                None
+            },
+            "open" => {
+                let path = string(&self.c_string(args[0]));
+                let mode = string(&self.c_string(args[1]));
+                Some(self.machine.builtin(Builtin::Open, vec![path, mode])?.int())
+            },
+            "write" => {
+                let text = string(&self.c_string(args[1]));
+                Some(self.machine.builtin(Builtin::Write, vec![Value::Int(args[0]), text])?.int())
+            },
+            "close" => Some(self.machine.builtin(Builtin::Close, vec![Value::Int(args[0])])?.int()),
+            _ => panic!("unknown import {}", name),
+        };
+        Ok(result)
+    }
+}
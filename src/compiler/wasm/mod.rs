@@ -0,0 +1,532 @@
+mod module;
+mod runtime;
+#[cfg(test)]
+pub mod interp;
+use std::collections::HashMap;
+use super::tokenizer::VarType;
+use super::ast::{self, *};
+use self::module::{Module, Func, FuncType, Global, Instr, ValType, PAGE_SIZE};
+use self::runtime::{IMPORTS, HELPERS};
+
+// WebAssembly code generation, for running programs in a browser. Every value
+// is an i64: numbers and booleans as themselves, strings as the address of
+// their NUL-terminated bytes in the exported memory, and files as handles the
+// host hands out, with 0 meaning "no file".
+//
+// The module exports "memory" and "main", which runs the program. The host
+// provides these functions in the "env" module; strings are passed as
+// addresses, and file -1 is standard input:
+//
+//   print(str: i32)                     write to standard output
+//   print_int(value: i64)               write an unsigned number in decimal
+//   print_err(str: i32)                 write to standard error
+//   exit(status: i32)                   stop the program, never returning
+//   rand() -> i32                       a random number from 0 to 2^31 - 1
+//   read_line(file: i64) -> i32         read the next line, returning its
+//                                       length without the newline (0 at the
+//                                       end of the file)
+//   line_copy(buf: i32)                 copy the line read last to buf
+//   eof(file: i64) -> i32               1 if there is nothing left to read
+//   arg_count() -> i64                  number of program arguments
+//   arg_len(index: i64) -> i32          length of an argument
+//   arg_copy(index: i64, buf: i32)      copy an argument to buf
+//   open(path: i32, mode: i32) -> i64   open a file with mode 📖, 📝 or 📎
+//   write(file: i64, str: i32) -> i32   write a string and a newline, 1 if
+//                                       it worked
+//   close(file: i64) -> i32             close a file, 1 if it worked
+//
+// Runtime errors print the same message as the other backends and exit with
+// the same status. Functions can be called before their definition, like in
+// native code.
+pub struct WasmGen {
+    module: Module,
+    funcs: HashMap<String, u32>,
+    globals: HashMap<String, u32>,
+    // addresses of the strings in the data segment
+    strings: HashMap<Vec<u8>, u32>,
+    // the function being generated
+    body: Vec<Instr>,
+    // local indices of the emooji function being generated, if any
+    locals: Option<HashMap<String, u32>>,
+    // where that function calls itself as the last thing it does, which
+    // branches back to the loop around its body, and how many blocks deep
+    // in that loop the code being generated is
+    tail_calls: Vec<usize>,
+    depth: u32
+}
+
+// Where the data segment goes, leaving 0 free so it is never an address
+const DATA_START: u32 = 8;
+
+fn func_name(id: &str) -> String {
+    let mut name = String::from("f");
+    for ch in id.chars() {
+        name.push_str(&format!("_{:x}", ch as u32));
+    }
+    name
+}
+
+fn var_name(id: &str) -> String {
+    let mut name = String::from("v");
+    for ch in id.chars() {
+        name.push_str(&format!("_{:x}", ch as u32));
+    }
+    name
+}
+
+// The flag set once the definition of function `id` has run
+
+fn i64s(count: usize) -> Vec<ValType> {
+    vec![ValType::I64; count]
+}
+
+// Finds every function definition, wherever it is in the main program
+fn collect_functions<'a>(stmt: &'a Stmt, functions: &mut Vec<&'a ast::Function>) {
+    match stmt.kind {
+        StmtKind::Function(ref func) => functions.push(func),
+        StmtKind::Block(ref statements) => {
+            for s in statements {
+                collect_functions(s, functions);
+            }
+        },
+        StmtKind::If(_, ref then, ref otherwise) => {
+            collect_functions(then, functions);
+            if let Some(ref s) = *otherwise {
+                collect_functions(s, functions);
+            }
+        },
+        StmtKind::While(_, ref body) => collect_functions(body, functions),
+        _ => {}
+    }
+}
+
+impl WasmGen {
+    pub fn new() -> WasmGen {
+        WasmGen {
+            module: Module {
+                imports: Vec::new(),
+                funcs: Vec::new(),
+                globals: Vec::new(),
+                memory_pages: 1,
+                data: Vec::new(),
+                data_start: DATA_START
+            },
+            funcs: HashMap::new(),
+            globals: HashMap::new(),
+            strings: HashMap::new(),
+            body: Vec::new(),
+            locals: None,
+            tail_calls: Vec::new(),
+            depth: 0
+        }
+    }
+
+    // Generates the module for a checked program, in the text format
+    pub fn gen_wat(self, program: &Program) -> String {
+        self.gen(program).to_wat()
+    }
+
+    // Generates the module for a checked program, in the binary format
+    pub fn gen_wasm(self, program: &Program) -> Vec<u8> {
+        self.gen(program).to_binary()
+    }
+
+    fn gen(mut self, program: &Program) -> Module {
+        let mut functions = Vec::new();
+        for stmt in &program.statements {
+            collect_functions(stmt, &mut functions);
+        }
+
+        // every function and global gets its index up front, so code can
+        // refer to them before they are generated
+        self.module.imports = IMPORTS.iter().map(|&(name, params, results)| module::Import {
+            name,
+            func_type: FuncType { params: params.to_vec(), results: results.to_vec() }
+        }).collect();
+        let names = HELPERS.iter().map(|name| name.to_string())
+            .chain(functions.iter().map(|func| func_name(&func.id)))
+            .chain(Some(String::from("main")));
+        self.funcs = self.module.func_indices();
+        for name in names {
+            let index = self.funcs.len() as u32;
+            self.funcs.insert(name, index);
+        }
+        self.add_global("heap", ValType::I64, 0);
+        for &(ref id, var_type) in &program.globals {
+            let init = if var_type == VarType::STR { self.string(b"") as i64 } else { 0 };
+            self.add_global(&var_name(id), ValType::I64, init);
+        }
+
+        self.gen_runtime();
+        for func in &functions {
+            self.function(func);
+        }
+        for stmt in &program.statements {
+            self.statement(stmt);
+        }
+        self.add_func("main", FuncType { params: vec![], results: vec![] }, vec![], true);
+
+        // the heap starts after the data, and the memory has room for both
+        let heap = self.module.data_start + self.module.data.len() as u32;
+        let heap = (heap + 7) & !7;
+        self.module.globals[0].init = heap as i64;
+        self.module.memory_pages = heap / PAGE_SIZE + 1;
+        self.module
+    }
+
+    fn add_global(&mut self, name: &str, val_type: ValType, init: i64) {
+        self.globals.insert(name.to_string(), self.module.globals.len() as u32);
+        self.module.globals.push(Global {
+            name: name.to_string(),
+            val_type,
+            init
+        });
+    }
+
+    // Finishes the function being generated as `name`, which has to be the
+    // next one in index order
+    fn add_func(&mut self, name: &str, func_type: FuncType, locals: Vec<ValType>, export: bool) {
+        let index = self.module.imports.len() + self.module.funcs.len();
+        assert_eq!(self.funcs[name] as usize, index, "Internal error: {} out of order", name);
+        let body = std::mem::take(&mut self.body);
+        self.module.funcs.push(Func {
+            name: name.to_string(),
+            func_type,
+            locals,
+            body,
+            export
+        });
+    }
+
+    fn emit(&mut self, instrs: &[Instr]) {
+        self.body.extend_from_slice(instrs);
+    }
+
+    fn call(&mut self, name: &str) {
+        let index = self.funcs[name];
+        self.body.push(Instr::Call(index));
+    }
+
+    fn global(&self, name: &str) -> u32 {
+        self.globals[name]
+    }
+
+    // The address of a NUL-terminated copy of `value` in the data segment
+    fn string(&mut self, value: &[u8]) -> u32 {
+        if let Some(&address) = self.strings.get(value) {
+            return address;
+        }
+        let address = self.module.data_start + self.module.data.len() as u32;
+        self.module.data.extend_from_slice(value);
+        self.module.data.push(0);
+        self.strings.insert(value.to_vec(), address);
+        address
+    }
+
+    fn push_string(&mut self, value: &[u8]) {
+        let address = self.string(value);
+        self.emit(&[Instr::I64Const(address as i64)]);
+    }
+
+    fn function(&mut self, func: &ast::Function) {
+        // parameters come first, then every other local
+        let mut locals = HashMap::new();
+        let mut kinds = Vec::new();
+        for &(ref id, var_type) in func.params.iter().chain(func.locals.iter()) {
+            if !locals.contains_key(id) {
+                locals.insert(id.clone(), kinds.len() as u32);
+                kinds.push(var_type);
+            }
+        }
+        self.tail_calls = func.tail_calls();
+        let looping = !self.tail_calls.is_empty();
+        if looping {
+            self.emit(&[Instr::Loop]);
+        }
+        for (i, &var_type) in kinds.iter().enumerate().skip(func.params.len()) {
+            if var_type == VarType::STR {
+                self.push_string(b"");
+                self.emit(&[Instr::LocalSet(i as u32)]);
+            } else if looping {
+                // locals start over on every pass
+                self.emit(&[Instr::I64Const(0), Instr::LocalSet(i as u32)]);
+            }
+        }
+
+        self.locals = Some(locals);
+        self.depth = 0;
+        self.statement(&func.body);
+        self.locals = None;
+        if looping {
+            self.emit(&[Instr::End]);
+        }
+        self.tail_calls = Vec::new();
+
+        // falling off the end returns the default value
+        if func.ret_type == VarType::STR {
+            self.push_string(b"");
+        } else {
+            self.emit(&[Instr::I64Const(0)]);
+        }
+        let func_type = FuncType { params: i64s(func.params.len()), results: i64s(1) };
+        let extra = i64s(kinds.len() - func.params.len());
+        self.add_func(&func_name(&func.id), func_type, extra, false);
+    }
+
+    fn load(&mut self, id: &str) {
+        if let Some(ref locals) = self.locals {
+            if let Some(&index) = locals.get(id) {
+                self.body.push(Instr::LocalGet(index));
+                return;
+            }
+        }
+        let global = self.global(&var_name(id));
+        self.emit(&[Instr::GlobalGet(global)]);
+    }
+
+    fn store(&mut self, id: &str) {
+        if let Some(ref locals) = self.locals {
+            if let Some(&index) = locals.get(id) {
+                self.body.push(Instr::LocalSet(index));
+                return;
+            }
+        }
+        let global = self.global(&var_name(id));
+        self.emit(&[Instr::GlobalSet(global)]);
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt.kind {
+            StmtKind::Declare(..) => {},
+            StmtKind::Assign(ref id, ref value) => {
+                self.expression(value);
+                self.store(id);
+            },
+            StmtKind::If(ref cond, ref then, ref otherwise) => {
+                self.expression(cond);
+                self.emit(&[Instr::I32WrapI64, Instr::If]);
+                self.depth += 1;
+                self.statement(then);
+                if let Some(ref s) = *otherwise {
+                    self.emit(&[Instr::Else]);
+                    self.statement(s);
+                }
+                self.depth -= 1;
+                self.emit(&[Instr::End]);
+            },
+            StmtKind::While(ref cond, ref body) => {
+                self.emit(&[Instr::Block, Instr::Loop]);
+                self.expression(cond);
+                self.emit(&[Instr::I64Eqz, Instr::BrIf(1)]);
+                self.depth += 2;
+                self.statement(body);
+                self.depth -= 2;
+                self.emit(&[Instr::Br(0), Instr::End, Instr::End]);
+            },
+            StmtKind::Block(ref statements) => {
+                for s in statements {
+                    self.statement(s);
+                }
+            },
+            StmtKind::Print(ref args, newline) => {
+                for (i, arg) in args.iter().enumerate() {
+                    // arguments are separated by a space
+                    if i > 0 {
+                        self.push_string(b" ");
+                        self.call("print_str_");
+                    }
+                    self.expression(arg);
+                    match arg.var_type {
+                        VarType::STR => self.call("print_str_"),
+                        VarType::BOOL => self.call("print_bool_"),
+                        _ => self.call("print_int"),
+                    }
+                }
+                if newline {
+                    self.push_string(b"\n");
+                    self.call("print_str_");
+                }
+            },
+            // functions exist from the start, generated separately
+            StmtKind::Function(_) => {},
+            StmtKind::Return(Some(ref call)) | StmtKind::Expr(ref call) if self.tail_calls.contains(&stmt.pos) => {
+                let args = match call.kind {
+                    ExprKind::Call(_, ref args) => args,
+                    _ => unreachable!(),
+                };
+                for arg in args {
+                    self.expression(arg);
+                }
+                // the parameters are the first locals, the last one on top
+                for i in (0..args.len()).rev() {
+                    self.emit(&[Instr::LocalSet(i as u32)]);
+                }
+                self.emit(&[Instr::Br(self.depth)]);
+            },
+            StmtKind::Return(ref value) => {
+                match *value {
+                    Some(ref v) => self.expression(v),
+                    None => self.emit(&[Instr::I64Const(0)]),
+                }
+                self.emit(&[Instr::Return]);
+            },
+            StmtKind::Exit(ref status) => {
+                // only the low byte reaches the parent process
+                self.expression(status);
+                self.emit(&[Instr::I64Const(0xff), Instr::I64And, Instr::I32WrapI64]);
+                self.call("exit");
+                self.emit(&[Instr::Unreachable]);
+            },
+            StmtKind::Expr(ref expr) => {
+                // result is ignored when used as a statement
+                self.expression(expr);
+                self.emit(&[Instr::Drop]);
+            },
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr.kind {
+            ExprKind::Int(value) => self.emit(&[Instr::I64Const(value as i64)]),
+            ExprKind::Bool(value) => self.emit(&[Instr::I64Const(value as i64)]),
+            ExprKind::Str(ref value) => self.push_string(value.as_bytes()),
+            ExprKind::Var(ref id) => self.load(id),
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+                match (op, expr.var_type) {
+                    (BinOp::Add, VarType::STR) => self.call("concat_"),
+                    // ➕ on booleans is "or" and ✖️ is "and"
+                    (BinOp::Add, VarType::BOOL) => self.emit(&[Instr::I64Or]),
+                    (BinOp::Mul, VarType::BOOL) => self.emit(&[Instr::I64And]),
+                    (BinOp::Add, _) => self.emit(&[Instr::I64Add]),
+                    (BinOp::Sub, _) => self.emit(&[Instr::I64Sub]),
+                    (BinOp::Mul, _) => self.emit(&[Instr::I64Mul]),
+                    (BinOp::Div, _) => self.call("div_"),
+                    (BinOp::Eq, _) if lhs.var_type == VarType::STR => self.call("str_eq_"),
+                    (BinOp::Eq, _) => self.emit(&[Instr::I64Eq, Instr::I64ExtendI32U]),
+                }
+            },
+            ExprKind::Not(ref operand) => {
+                self.expression(operand);
+                self.emit(&[Instr::I64Eqz, Instr::I64ExtendI32U]);
+            },
+            ExprKind::Call(ref id, ref args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.call(&func_name(id));
+            },
+            ExprKind::Builtin(builtin, ref args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let helper = match builtin {
+                    Builtin::Rand => "rand_",
+                    Builtin::ReadStr => "read_str_",
+                    Builtin::ReadInt => "read_int_",
+                    Builtin::Eof => {
+                        if args.is_empty() {
+                            "eof_"
+                        } else {
+                            "file_eof_"
+                        }
+                    },
+                    Builtin::ArgCount => "arg_count",
+                    Builtin::Arg => "arg_",
+                    Builtin::Parse => "parse_int_",
+                    Builtin::Open => "file_open_",
+                    Builtin::ReadLine => "read_line_",
+                    Builtin::Write => "file_write_",
+                    Builtin::Close => "file_close_",
+                };
+                self.call(helper);
+            },
+            ExprKind::Format(ref segments, _, ref args) => {
+                // arguments were converted to strings by the checker
+                self.push_string(segments[0].as_bytes());
+                for (i, arg) in args.iter().enumerate() {
+                    self.expression(arg);
+                    self.call("concat_");
+                    if !segments[i + 1].is_empty() {
+                        self.push_string(segments[i + 1].as_bytes());
+                        self.call("concat_");
+                    }
+                }
+            },
+            ExprKind::Convert(ref inner) => {
+                self.expression(inner);
+                match (inner.var_type, expr.var_type) {
+                    (VarType::INT, VarType::STR) => self.call("int_str_"),
+                    (VarType::BOOL, VarType::STR) => self.call("bool_str_"),
+                    // booleans are always 0 or 1
+                    (_, VarType::BOOL) => {
+                        self.emit(&[Instr::I64Const(0), Instr::I64Ne, Instr::I64ExtendI32U]);
+                    },
+                    _ => {},
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WasmGen;
+    use super::module::{write_u32, write_i64};
+    use super::interp;
+    use super::super::tokenizer::Tokenizer;
+    use super::super::parser::Parser;
+    use super::super::checker::Checker;
+    use super::super::ast::Program;
+
+    fn check(program: &str) -> Program {
+        let tokens = Tokenizer::new(String::from(program)).tokenize();
+        let mut ast = Parser::new(&tokens).parse();
+        Checker::new(&tokens).check(&mut ast);
+        ast
+    }
+
+    // Exit status, stdout and stderr of running the program
+    fn run(program: &str, stdin: &str) -> (i32, String, String) {
+        let bytes = WasmGen::new().gen_wasm(&check(program));
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let status = interp::run(&bytes, &[], &mut stdin.as_bytes(), &mut stdout, &mut stderr);
+        (status, String::from_utf8(stdout).unwrap(), String::from_utf8(stderr).unwrap())
+    }
+
+    #[test]
+    fn leb128() {
+        let mut bytes = Vec::new();
+        write_u32(&mut bytes, 624485);
+        write_i64(&mut bytes, -123456);
+        write_i64(&mut bytes, 64);
+        write_i64(&mut bytes, -1);
+        assert_eq!(bytes, [0xe5, 0x8e, 0x26, 0xc0, 0xbb, 0x78, 0xc0, 0x00, 0x7f]);
+    }
+
+    #[test]
+    fn text_format_names_everything() {
+        let wat = WasmGen::new().gen_wat(&check("🆕🔢😀🔚 😀⬅️📨🌜🌛🔚 📄😀➕1️⃣🔚"));
+        assert!(wat.starts_with("(module\n  (import \"env\" \"print\" (func $print (param i32)))\n"));
+        assert!(wat.contains("  (global $v_1f600 (mut i64) (i64.const 0))\n"));
+        assert!(wat.contains("  (func $main (export \"main\")\n    call $read_int_\n    global.set $v_1f600\n"));
+    }
+
+    #[test]
+    fn strings_numbers_and_input() {
+        let program = "🆕🔤👽🔚 👽⬅️📥🌜🌛🔚 📄👽➕💬🐶💬◾👽↔️💬👾💬◾📨🌜🌛➗️3️⃣🔚 \
+            📄🔄🌜📥🌜🌛🌛◾🔄🌜📥🌜🌛🌛◾📭🌜🌛🔚";
+        assert_eq!(run(program, "👾\n100\n  -12x\n99999999999999999999\n"),
+                   (0, String::from("👾🐶 👍 33\n18446744073709551604 9223372036854775807 👍\n"), String::new()));
+    }
+
+    #[test]
+    fn runtime_errors_and_traps() {
+        assert_eq!(run("🖨️1️⃣🔚 📄🎲🌜0️⃣🌛🔚", ""),
+                   (4, String::from("1"), String::from("🚨 Runtime error: division by zero\n")));
+        // a function called before its definition, like in native code
+        let (status, stdout, _) = run("📄1️⃣🔚 📄📞🦊🌜🌛🔚 🤪🔢🦊🌜🌛 🌘 ↩️2️⃣🔚 🌒", "");
+        assert_eq!((status, stdout.as_str()), (0, "1\n2\n"));
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+
+pub const PAGE_SIZE: u32 = 65536;
+
+// A WebAssembly module, written out either as text (WAT) or in the binary
+// format. Only what the backend needs is supported: functions, mutable
+// globals, one memory with data at fixed addresses, and imported functions.
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ValType {
+    I32,
+    I64
+}
+
+// Instructions, with blocks, loops and ifs always producing no value
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    // loads and stores of single bytes
+    I32Load8U,
+    I32Store8,
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64GtU,
+    I64LeU,
+    I64GeU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivU,
+    I64RemU,
+    I64And,
+    I64Or,
+    I64Shl,
+    I32WrapI64,
+    I64ExtendI32U
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>
+}
+
+// A function the host provides, from the "env" module
+pub struct Import {
+    pub name: &'static str,
+    pub func_type: FuncType
+}
+
+pub struct Func {
+    pub name: String,
+    pub func_type: FuncType,
+    // locals besides the parameters
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+    pub export: bool
+}
+
+pub struct Global {
+    pub name: String,
+    pub val_type: ValType,
+    pub init: i64
+}
+
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub funcs: Vec<Func>,
+    pub globals: Vec<Global>,
+    // initial size of the memory, in 64 KiB pages
+    pub memory_pages: u32,
+    // placed at `data_start` in the memory
+    pub data: Vec<u8>,
+    pub data_start: u32
+}
+
+// What the instructions are called in the text format
+fn mnemonic(instr: &Instr) -> &'static str {
+    match *instr {
+        Instr::Unreachable => "unreachable",
+        Instr::Block => "block",
+        Instr::Loop => "loop",
+        Instr::If => "if",
+        Instr::Else => "else",
+        Instr::End => "end",
+        Instr::Br(_) => "br",
+        Instr::BrIf(_) => "br_if",
+        Instr::Return => "return",
+        Instr::Call(_) => "call",
+        Instr::Drop => "drop",
+        Instr::Select => "select",
+        Instr::LocalGet(_) => "local.get",
+        Instr::LocalSet(_) => "local.set",
+        Instr::GlobalGet(_) => "global.get",
+        Instr::GlobalSet(_) => "global.set",
+        Instr::I32Load8U => "i32.load8_u",
+        Instr::I32Store8 => "i32.store8",
+        Instr::MemorySize => "memory.size",
+        Instr::MemoryGrow => "memory.grow",
+        Instr::I32Const(_) => "i32.const",
+        Instr::I64Const(_) => "i64.const",
+        Instr::I32Eqz => "i32.eqz",
+        Instr::I32Eq => "i32.eq",
+        Instr::I32Ne => "i32.ne",
+        Instr::I64Eqz => "i64.eqz",
+        Instr::I64Eq => "i64.eq",
+        Instr::I64Ne => "i64.ne",
+        Instr::I64GtU => "i64.gt_u",
+        Instr::I64LeU => "i64.le_u",
+        Instr::I64GeU => "i64.ge_u",
+        Instr::I64Add => "i64.add",
+        Instr::I64Sub => "i64.sub",
+        Instr::I64Mul => "i64.mul",
+        Instr::I64DivU => "i64.div_u",
+        Instr::I64RemU => "i64.rem_u",
+        Instr::I64And => "i64.and",
+        Instr::I64Or => "i64.or",
+        Instr::I64Shl => "i64.shl",
+        Instr::I32WrapI64 => "i32.wrap_i64",
+        Instr::I64ExtendI32U => "i64.extend_i32_u",
+    }
+}
+
+fn opcode(instr: &Instr) -> u8 {
+    match *instr {
+        Instr::Unreachable => 0x00,
+        Instr::Block => 0x02,
+        Instr::Loop => 0x03,
+        Instr::If => 0x04,
+        Instr::Else => 0x05,
+        Instr::End => 0x0b,
+        Instr::Br(_) => 0x0c,
+        Instr::BrIf(_) => 0x0d,
+        Instr::Return => 0x0f,
+        Instr::Call(_) => 0x10,
+        Instr::Drop => 0x1a,
+        Instr::Select => 0x1b,
+        Instr::LocalGet(_) => 0x20,
+        Instr::LocalSet(_) => 0x21,
+        Instr::GlobalGet(_) => 0x23,
+        Instr::GlobalSet(_) => 0x24,
+        Instr::I32Load8U => 0x2d,
+        Instr::I32Store8 => 0x3a,
+        Instr::MemorySize => 0x3f,
+        Instr::MemoryGrow => 0x40,
+        Instr::I32Const(_) => 0x41,
+        Instr::I64Const(_) => 0x42,
+        Instr::I32Eqz => 0x45,
+        Instr::I32Eq => 0x46,
+        Instr::I32Ne => 0x47,
+        Instr::I64Eqz => 0x50,
+        Instr::I64Eq => 0x51,
+        Instr::I64Ne => 0x52,
+        Instr::I64GtU => 0x56,
+        Instr::I64LeU => 0x58,
+        Instr::I64GeU => 0x5a,
+        Instr::I64Add => 0x7c,
+        Instr::I64Sub => 0x7d,
+        Instr::I64Mul => 0x7e,
+        Instr::I64DivU => 0x80,
+        Instr::I64RemU => 0x82,
+        Instr::I64And => 0x83,
+        Instr::I64Or => 0x84,
+        Instr::I64Shl => 0x86,
+        Instr::I32WrapI64 => 0xa7,
+        Instr::I64ExtendI32U => 0xad,
+    }
+}
+
+pub fn write_u32(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+pub fn write_i64(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // done once the rest is just the sign bit repeated
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn val_type_byte(val_type: ValType) -> u8 {
+    match val_type {
+        ValType::I32 => 0x7f,
+        ValType::I64 => 0x7e,
+    }
+}
+
+fn val_type_name(val_type: ValType) -> &'static str {
+    match val_type {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+    }
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_u32(bytes, name.len() as u32);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn write_section(bytes: &mut Vec<u8>, id: u8, count: usize, content: &[u8]) {
+    let mut section = Vec::new();
+    write_u32(&mut section, count as u32);
+    section.extend_from_slice(content);
+    bytes.push(id);
+    write_u32(bytes, section.len() as u32);
+    bytes.extend_from_slice(&section);
+}
+
+fn write_func_type(bytes: &mut Vec<u8>, func_type: &FuncType) {
+    bytes.push(0x60);
+    write_u32(bytes, func_type.params.len() as u32);
+    bytes.extend(func_type.params.iter().map(|&t| val_type_byte(t)));
+    write_u32(bytes, func_type.results.len() as u32);
+    bytes.extend(func_type.results.iter().map(|&t| val_type_byte(t)));
+}
+
+// A string for the text format, with everything but plain ASCII escaped
+fn wat_string(bytes: &[u8]) -> String {
+    let mut content = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => content.push_str(&format!("\\{}", byte as char)),
+            b' '..=b'~' => content.push(byte as char),
+            _ => content.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    content.push('"');
+    content
+}
+
+impl Instr {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(opcode(self));
+        match *self {
+            // blocks have no result
+            Instr::Block | Instr::Loop | Instr::If => bytes.push(0x40),
+            Instr::Br(n) | Instr::BrIf(n) | Instr::Call(n) | Instr::LocalGet(n)
+                | Instr::LocalSet(n) | Instr::GlobalGet(n) | Instr::GlobalSet(n) => {
+                write_u32(bytes, n);
+            },
+            // alignment and offset
+            Instr::I32Load8U | Instr::I32Store8 => bytes.extend_from_slice(&[0, 0]),
+            // memory index
+            Instr::MemorySize | Instr::MemoryGrow => bytes.push(0),
+            Instr::I32Const(value) => write_i64(bytes, value as i64),
+            Instr::I64Const(value) => write_i64(bytes, value),
+            _ => {},
+        }
+    }
+}
+
+impl Module {
+    // Index of the function called `name`, counting the imports first
+    pub fn func_indices(&self) -> HashMap<String, u32> {
+        let mut indices = HashMap::new();
+        let names = self.imports.iter().map(|import| import.name.to_string())
+            .chain(self.funcs.iter().map(|func| func.name.clone()));
+        for (i, name) in names.enumerate() {
+            indices.insert(name, i as u32);
+        }
+        indices
+    }
+
+    pub fn to_wat(&self) -> String {
+        let mut func_names: Vec<String> = self.imports.iter().map(|i| i.name.to_string()).collect();
+        func_names.extend(self.funcs.iter().map(|f| f.name.clone()));
+        let signature = |func_type: &FuncType| {
+            let mut content = String::new();
+            for &param in &func_type.params {
+                content.push_str(&format!(" (param {})", val_type_name(param)));
+            }
+            for &result in &func_type.results {
+                content.push_str(&format!(" (result {})", val_type_name(result)));
+            }
+            content
+        };
+
+        let mut content = String::from("(module\n");
+        for import in &self.imports {
+            content.push_str(&format!("  (import \"env\" \"{}\" (func ${}{}))\n",
+                import.name, import.name, signature(&import.func_type)));
+        }
+        content.push_str(&format!("  (memory (export \"memory\") {})\n", self.memory_pages));
+        for global in &self.globals {
+            let name = val_type_name(global.val_type);
+            content.push_str(&format!("  (global ${} (mut {}) ({}.const {}))\n",
+                global.name, name, name, global.init));
+        }
+        content.push_str(&format!("  (data (i32.const {}) {})\n", self.data_start,
+            wat_string(&self.data)));
+
+        for func in &self.funcs {
+            let export = if func.export { format!(" (export \"{}\")", func.name) } else { String::new() };
+            content.push_str(&format!("  (func ${}{}{}", func.name, export, signature(&func.func_type)));
+            for &local in &func.locals {
+                content.push_str(&format!(" (local {})", val_type_name(local)));
+            }
+            content.push('\n');
+            let mut depth = 2;
+            for instr in &func.body {
+                if let Instr::Else | Instr::End = *instr {
+                    depth -= 1;
+                }
+                content.push_str(&"  ".repeat(depth));
+                content.push_str(mnemonic(instr));
+                match *instr {
+                    Instr::Call(n) => content.push_str(&format!(" ${}", func_names[n as usize])),
+                    Instr::GlobalGet(n) | Instr::GlobalSet(n) => {
+                        content.push_str(&format!(" ${}", self.globals[n as usize].name));
+                    },
+                    Instr::Br(n) | Instr::BrIf(n) | Instr::LocalGet(n) | Instr::LocalSet(n) => {
+                        content.push_str(&format!(" {}", n));
+                    },
+                    Instr::I32Const(value) => content.push_str(&format!(" {}", value)),
+                    Instr::I64Const(value) => content.push_str(&format!(" {}", value)),
+                    _ => {},
+                }
+                content.push('\n');
+                if let Instr::Block | Instr::Loop | Instr::If | Instr::Else = *instr {
+                    depth += 1;
+                }
+            }
+            content.push_str("  )\n");
+        }
+        content.push_str(")\n");
+        content
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+
+        // every distinct signature once
+        let mut types: Vec<&FuncType> = Vec::new();
+        let signatures = self.imports.iter().map(|i| &i.func_type)
+            .chain(self.funcs.iter().map(|f| &f.func_type));
+        for func_type in signatures {
+            if !types.contains(&func_type) {
+                types.push(func_type);
+            }
+        }
+        let type_index = |func_type: &FuncType| types.iter().position(|&t| *t == *func_type).unwrap() as u32;
+        let mut section = Vec::new();
+        for func_type in &types {
+            write_func_type(&mut section, func_type);
+        }
+        write_section(&mut bytes, 1, types.len(), &section);
+
+        let mut section = Vec::new();
+        for import in &self.imports {
+            write_name(&mut section, "env");
+            write_name(&mut section, import.name);
+            section.push(0x00);
+            write_u32(&mut section, type_index(&import.func_type));
+        }
+        write_section(&mut bytes, 2, self.imports.len(), &section);
+
+        let mut section = Vec::new();
+        for func in &self.funcs {
+            write_u32(&mut section, type_index(&func.func_type));
+        }
+        write_section(&mut bytes, 3, self.funcs.len(), &section);
+
+        let mut section = vec![0x00];
+        write_u32(&mut section, self.memory_pages);
+        write_section(&mut bytes, 5, 1, &section);
+
+        let mut section = Vec::new();
+        for global in &self.globals {
+            section.extend_from_slice(&[val_type_byte(global.val_type), 0x01]);
+            match global.val_type {
+                ValType::I32 => Instr::I32Const(global.init as i32).encode(&mut section),
+                ValType::I64 => Instr::I64Const(global.init).encode(&mut section),
+            }
+            Instr::End.encode(&mut section);
+        }
+        write_section(&mut bytes, 6, self.globals.len(), &section);
+
+        let mut section = Vec::new();
+        write_name(&mut section, "memory");
+        section.extend_from_slice(&[0x02, 0x00]);
+        let mut exports = 1;
+        for (i, func) in self.funcs.iter().enumerate() {
+            if func.export {
+                write_name(&mut section, &func.name);
+                section.push(0x00);
+                write_u32(&mut section, (self.imports.len() + i) as u32);
+                exports += 1;
+            }
+        }
+        write_section(&mut bytes, 7, exports, &section);
+
+        let mut section = Vec::new();
+        for func in &self.funcs {
+            let mut code = Vec::new();
+            // locals as runs of the same type
+            let mut runs: Vec<(u32, ValType)> = Vec::new();
+            for &local in &func.locals {
+                match runs.last_mut() {
+                    Some(&mut (ref mut count, val_type)) if val_type == local => *count += 1,
+                    _ => runs.push((1, local)),
+                }
+            }
+            write_u32(&mut code, runs.len() as u32);
+            for &(count, val_type) in &runs {
+                write_u32(&mut code, count);
+                code.push(val_type_byte(val_type));
+            }
+            for instr in &func.body {
+                instr.encode(&mut code);
+            }
+            Instr::End.encode(&mut code);
+            write_u32(&mut section, code.len() as u32);
+            section.extend_from_slice(&code);
+        }
+        write_section(&mut bytes, 10, self.funcs.len(), &section);
+
+        let mut section = vec![0x00];
+        Instr::I32Const(self.data_start as i32).encode(&mut section);
+        Instr::End.encode(&mut section);
+        write_u32(&mut section, self.data.len() as u32);
+        section.extend_from_slice(&self.data);
+        write_section(&mut bytes, 11, 1, &section);
+        bytes
+    }
+}
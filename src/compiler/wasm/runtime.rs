@@ -0,0 +1,270 @@
+use super::WasmGen;
+use super::module::{FuncType, ValType};
+use super::module::Instr::*;
+use super::module::ValType::{I32, I64};
+
+// Functions the host provides, with their parameters and results. See the
+// top of mod.rs for what they do.
+pub const IMPORTS: [(&str, &[ValType], &[ValType]); 14] = [
+    ("print", &[I32], &[]),
+    ("print_int", &[I64], &[]),
+    ("print_err", &[I32], &[]),
+    ("exit", &[I32], &[]),
+    ("rand", &[], &[I32]),
+    ("read_line", &[I64], &[I32]),
+    ("line_copy", &[I32], &[]),
+    ("eof", &[I64], &[I32]),
+    ("arg_count", &[], &[I64]),
+    ("arg_len", &[I64], &[I32]),
+    ("arg_copy", &[I64, I32], &[]),
+    ("open", &[I32, I32], &[I64]),
+    ("write", &[I64, I32], &[I32]),
+    ("close", &[I64], &[I32])
+];
+
+// Runtime helpers, in the order gen_runtime generates them
+pub const HELPERS: [&str; 23] = [
+    "alloc_", "strlen_", "copy_", "concat_", "str_eq_", "int_str_", "bool_str_",
+    "div_zero_error_", "bounds_error_", "div_", "rand_", "parse_int_", "read_line_",
+    "read_str_", "read_int_", "file_eof_", "eof_", "arg_", "file_open_", "file_write_",
+    "file_close_", "print_str_", "print_bool_"
+];
+
+fn func_type(params: usize, results: usize) -> FuncType {
+    FuncType {
+        params: vec![I64; params],
+        results: vec![I64; results]
+    }
+}
+
+impl WasmGen {
+    // Runtime helpers called from generated code. Like the host functions,
+    // runtime errors print a message to stderr and exit with their own code:
+    //   3 - argument index out of bounds
+    //   4 - division by zero
+    pub fn gen_runtime(&mut self) {
+        let heap = self.global("heap");
+
+        // alloc_: `size` bytes of new memory, growing it as needed. Nothing
+        // is ever freed.
+        self.emit(&[GlobalGet(heap), LocalSet(1),
+                    GlobalGet(heap), LocalGet(0), I64Add, GlobalSet(heap),
+                    Block, Loop,
+                    GlobalGet(heap), MemorySize, I64ExtendI32U, I64Const(16), I64Shl, I64LeU, BrIf(1),
+                    I32Const(1), MemoryGrow, I32Const(-1), I32Eq, If, Unreachable, End,
+                    Br(0), End, End,
+                    LocalGet(1)]);
+        self.add_func("alloc_", func_type(1, 1), vec![I64], false);
+
+        // strlen_: length of string `s`
+        self.emit(&[Block, Loop,
+                    LocalGet(0), LocalGet(1), I64Add, I32WrapI64, I32Load8U, I32Eqz, BrIf(1),
+                    LocalGet(1), I64Const(1), I64Add, LocalSet(1),
+                    Br(0), End, End,
+                    LocalGet(1)]);
+        self.add_func("strlen_", func_type(1, 1), vec![I64], false);
+
+        // copy_: copy `n` bytes from `src` to `dst`
+        self.emit(&[Block, Loop,
+                    LocalGet(2), I64Eqz, BrIf(1),
+                    LocalGet(0), I32WrapI64, LocalGet(1), I32WrapI64, I32Load8U, I32Store8,
+                    LocalGet(0), I64Const(1), I64Add, LocalSet(0),
+                    LocalGet(1), I64Const(1), I64Add, LocalSet(1),
+                    LocalGet(2), I64Const(1), I64Sub, LocalSet(2),
+                    Br(0), End, End]);
+        self.add_func("copy_", func_type(3, 0), vec![], false);
+
+        // concat_: new string with `b` appended to `a`
+        self.emit(&[LocalGet(0)]);
+        self.call("strlen_");
+        self.emit(&[LocalSet(2), LocalGet(1)]);
+        self.call("strlen_");
+        self.emit(&[LocalSet(3),
+                    LocalGet(2), LocalGet(3), I64Add, I64Const(1), I64Add]);
+        self.call("alloc_");
+        self.emit(&[LocalSet(4), LocalGet(4), LocalGet(0), LocalGet(2)]);
+        self.call("copy_");
+        // the terminating NUL comes along with `b`
+        self.emit(&[LocalGet(4), LocalGet(2), I64Add, LocalGet(1), LocalGet(3), I64Const(1), I64Add]);
+        self.call("copy_");
+        self.emit(&[LocalGet(4)]);
+        self.add_func("concat_", func_type(2, 1), vec![I64, I64, I64], false);
+
+        // str_eq_: 1 if strings `a` and `b` have the same contents
+        self.emit(&[Block, Loop,
+                    LocalGet(0), I32WrapI64, I32Load8U, LocalGet(1), I32WrapI64, I32Load8U, I32Ne,
+                    If, I64Const(0), Return, End,
+                    LocalGet(0), I32WrapI64, I32Load8U, I32Eqz, BrIf(1),
+                    LocalGet(0), I64Const(1), I64Add, LocalSet(0),
+                    LocalGet(1), I64Const(1), I64Add, LocalSet(1),
+                    Br(0), End, End,
+                    I64Const(1)]);
+        self.add_func("str_eq_", func_type(2, 1), vec![], false);
+
+        // int_str_: number `n` as a new string, written from its last digit
+        self.emit(&[I64Const(21)]);
+        self.call("alloc_");
+        self.emit(&[I64Const(20), I64Add, LocalSet(1),
+                    LocalGet(1), I32WrapI64, I32Const(0), I32Store8,
+                    Loop,
+                    LocalGet(1), I64Const(1), I64Sub, LocalSet(1),
+                    LocalGet(1), I32WrapI64, LocalGet(0), I64Const(10), I64RemU,
+                    I64Const(48), I64Add, I32WrapI64, I32Store8,
+                    LocalGet(0), I64Const(10), I64DivU, LocalSet(0),
+                    LocalGet(0), I64Eqz, I32Eqz, BrIf(0),
+                    End,
+                    LocalGet(1)]);
+        self.add_func("int_str_", func_type(1, 1), vec![I64], false);
+
+        // bool_str_: 👍 or 👎 for boolean `b`
+        self.push_string("👍".as_bytes());
+        self.push_string("👎".as_bytes());
+        self.emit(&[LocalGet(0), I64Eqz, I32Eqz, Select]);
+        self.add_func("bool_str_", func_type(1, 1), vec![], false);
+
+        for &(name, message, status) in [
+            ("div_zero_error_", "division by zero", 4),
+            ("bounds_error_", "argument index out of bounds", 3),
+        ].iter() {
+            let message = format!("🚨 Runtime error: {}\n", message);
+            let address = self.string(message.as_bytes());
+            self.emit(&[I32Const(address as i32)]);
+            self.call("print_err");
+            self.emit(&[I32Const(status)]);
+            self.call("exit");
+            self.emit(&[Unreachable]);
+            self.add_func(name, func_type(0, 0), vec![], false);
+        }
+
+        // div_: `a` divided by `b`
+        self.emit(&[LocalGet(1), I64Eqz, If]);
+        self.call("div_zero_error_");
+        self.emit(&[End, LocalGet(0), LocalGet(1), I64DivU]);
+        self.add_func("div_", func_type(2, 1), vec![], false);
+
+        // rand_: random number below `limit`
+        self.emit(&[LocalGet(0), I64Eqz, If]);
+        self.call("div_zero_error_");
+        self.emit(&[End]);
+        self.call("rand");
+        self.emit(&[I64ExtendI32U, LocalGet(0), I64RemU]);
+        self.add_func("rand_", func_type(1, 1), vec![], false);
+
+        // parse_int_: like strtol in base 10, leading white space, an
+        // optional sign and digits, saturating at the limits of a signed
+        // number. Locals: 1 character, 2 negative, 3 value, 4 limit.
+        self.emit(&[Block, Loop,
+                    LocalGet(0), I32WrapI64, I32Load8U, I64ExtendI32U, LocalSet(1),
+                    // a space, or \t to \r
+                    LocalGet(1), I64Const(32), I64Eq,
+                    LocalGet(1), I64Const(9), I64Sub, I64Const(4), I64LeU,
+                    I32Ne, I32Eqz, BrIf(1),
+                    LocalGet(0), I64Const(1), I64Add, LocalSet(0),
+                    Br(0), End, End,
+                    LocalGet(1), I64Const(45), I64Eq, If,
+                    I64Const(1), LocalSet(2),
+                    End,
+                    LocalGet(1), I64Const(45), I64Eq, LocalGet(1), I64Const(43), I64Eq, I32Ne, If,
+                    LocalGet(0), I64Const(1), I64Add, LocalSet(0),
+                    End,
+                    I64Const(i64::MAX), LocalGet(2), I64Add, LocalSet(4),
+                    Block, Loop,
+                    LocalGet(0), I32WrapI64, I32Load8U, I64ExtendI32U, I64Const(48), I64Sub, LocalSet(1),
+                    LocalGet(1), I64Const(9), I64GtU, BrIf(1),
+                    // value * 10 + digit > limit, without overflowing
+                    LocalGet(3), LocalGet(4), LocalGet(1), I64Sub, I64Const(10), I64DivU, I64GtU,
+                    If,
+                    LocalGet(4), LocalSet(3),
+                    Else,
+                    LocalGet(3), I64Const(10), I64Mul, LocalGet(1), I64Add, LocalSet(3),
+                    End,
+                    LocalGet(0), I64Const(1), I64Add, LocalSet(0),
+                    Br(0), End, End,
+                    I64Const(0), LocalGet(3), I64Sub, LocalGet(3), LocalGet(2), I32WrapI64, Select]);
+        self.add_func("parse_int_", func_type(1, 1), vec![I64, I64, I64, I64], false);
+
+        // read_line_: a line from file `f` without its newline, or the empty
+        // string at the end of the file. Every call gets a fresh buffer so
+        // strings can be kept.
+        let empty = self.string(b"") as i64;
+        self.emit(&[LocalGet(0), I64Eqz, If, I64Const(empty), Return, End,
+                    LocalGet(0)]);
+        self.call("read_line");
+        self.emit(&[I64ExtendI32U, LocalSet(1),
+                    LocalGet(1), I64Eqz, If, I64Const(empty), Return, End,
+                    LocalGet(1), I64Const(1), I64Add]);
+        self.call("alloc_");
+        self.emit(&[LocalSet(2), LocalGet(2), I32WrapI64]);
+        self.call("line_copy");
+        self.emit(&[LocalGet(2), LocalGet(1), I64Add, I32WrapI64, I32Const(0), I32Store8,
+                    LocalGet(2)]);
+        self.add_func("read_line_", func_type(1, 1), vec![I64, I64], false);
+
+        // read_str_: a line from stdin
+        self.emit(&[I64Const(-1)]);
+        self.call("read_line_");
+        self.add_func("read_str_", func_type(0, 1), vec![], false);
+
+        // read_int_: a line from stdin, parsed as a number
+        self.call("read_str_");
+        self.call("parse_int_");
+        self.add_func("read_int_", func_type(0, 1), vec![], false);
+
+        // file_eof_: 1 if file `f` has no more input
+        self.emit(&[LocalGet(0), I64Eqz, If, I64Const(1), Return, End,
+                    LocalGet(0)]);
+        self.call("eof");
+        self.emit(&[I64ExtendI32U]);
+        self.add_func("file_eof_", func_type(1, 1), vec![], false);
+
+        // eof_: 1 if stdin has no more input
+        self.emit(&[I64Const(-1)]);
+        self.call("file_eof_");
+        self.add_func("eof_", func_type(0, 1), vec![], false);
+
+        // arg_: argument `i` (0 is the first one after the program name)
+        self.emit(&[LocalGet(0)]);
+        self.call("arg_count");
+        self.emit(&[I64GeU, If]);
+        self.call("bounds_error_");
+        self.emit(&[End, LocalGet(0)]);
+        self.call("arg_len");
+        self.emit(&[I64ExtendI32U, LocalSet(1),
+                    LocalGet(1), I64Const(1), I64Add]);
+        self.call("alloc_");
+        self.emit(&[LocalSet(2), LocalGet(0), LocalGet(2), I32WrapI64]);
+        self.call("arg_copy");
+        self.emit(&[LocalGet(2), LocalGet(1), I64Add, I32WrapI64, I32Const(0), I32Store8,
+                    LocalGet(2)]);
+        self.add_func("arg_", func_type(1, 1), vec![I64, I64], false);
+
+        // file_open_: open path `p` with mode `m`, 0 if that fails
+        self.emit(&[LocalGet(0), I32WrapI64, LocalGet(1), I32WrapI64]);
+        self.call("open");
+        self.add_func("file_open_", func_type(2, 1), vec![], false);
+
+        // file_write_: write string `s` and a newline to file `f`, 1 on success
+        self.emit(&[LocalGet(0), I64Eqz, If, I64Const(0), Return, End,
+                    LocalGet(0), LocalGet(1), I32WrapI64]);
+        self.call("write");
+        self.emit(&[I64ExtendI32U]);
+        self.add_func("file_write_", func_type(2, 1), vec![], false);
+
+        // file_close_: close file `f`, 1 on success
+        self.emit(&[LocalGet(0), I64Eqz, If, I64Const(0), Return, End,
+                    LocalGet(0)]);
+        self.call("close");
+        self.emit(&[I64ExtendI32U]);
+        self.add_func("file_close_", func_type(1, 1), vec![], false);
+
+        // print_str_, print_bool_: print `value` without a newline
+        self.emit(&[LocalGet(0), I32WrapI64]);
+        self.call("print");
+        self.add_func("print_str_", func_type(1, 0), vec![], false);
+
+        self.emit(&[LocalGet(0)]);
+        self.call("bool_str_");
+        self.call("print_str_");
+        self.add_func("print_bool_", func_type(1, 0), vec![], false);
+    }
+}
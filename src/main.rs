@@ -1,70 +1,1084 @@
-use std::error::Error;
+use std::io;
 use std::io::prelude::*;
+use std::fs;
 use std::fs::File;
-use std::path::Path;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::env;
+use std::process;
 use std::process::Command;
+use std::thread;
 
 mod compiler;
+mod options;
 use compiler::Compiler;
+use compiler::Repl;
+use options::Emit;
+use options::Options;
+use options::Target;
+
+const INTERPRETER_STACK: usize = 1 << 30;
 
 fn main() {
     // parse arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Incorrect number of arguments.");
-    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match options::parse(&args) {
+        Ok(options::Command::Help) => {
+            print!("{}", options::USAGE);
+            return;
+        },
+        Ok(options::Command::Version) => {
+            println!("emooji {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Ok(options::Command::Build(options)) => options,
+        Ok(options::Command::Interpret(input, program_args)) => {
+            process::exit(interpret(&input, program_args));
+        },
+        Ok(options::Command::Repl(program_args)) => process::exit(repl(program_args)),
+        Err(why) => {
+            eprintln!("emooji: {}\n\n{}", why, options::USAGE);
+            process::exit(2);
+        },
+    };
 
-    // read from .moo file
-    let path_str = format!("{}{}", &args[1], ".moo");
-    let path = Path::new(&path_str);
+    process::exit(build(&options));
+}
 
-    let mut file = match File::open(&path) {
-        Err(why) => panic!("Couldn't open .moo file: {}",
-                            why.description()),
+fn read_program(input: &Path) -> String {
+    let mut file = match File::open(input) {
+        Err(why) => panic!("Couldn't open .moo file \'{}\': {}",
+                            input.display(), why),
         Ok(file) => file,
     };
 
     let mut program_contents = String::with_capacity(500);
-    match file.read_to_string(&mut program_contents) {
-        Err(why) => panic!("Couldn't read .moo file \'{}\': {}",
-                            path_str, why.description()),
-        Ok(_) => {},
+    if let Err(why) = file.read_to_string(&mut program_contents) {
+        panic!("Couldn't read .moo file \'{}\': {}",
+                input.display(), why);
+    }
+    program_contents
+}
+
+// Checks and interprets `input`, or runs it if it is bytecode, returning the
+// program's exit status
+fn interpret(input: &Path, program_args: Vec<String>) -> i32 {
+    let bytes = match fs::read(input) {
+        Err(why) => panic!("Couldn't read \'{}\': {}", input.display(), why),
+        Ok(bytes) => bytes,
+    };
+    if compiler::is_bytecode(&bytes) {
+        let stdin = io::stdin();
+        let mut output = io::BufWriter::new(io::stdout());
+        let result = compiler::run_bytecode(&bytes, &program_args, &mut stdin.lock(),
+                                            &mut output, &mut io::stderr());
+        return match result {
+            Ok(status) => status,
+            Err(why) => {
+                eprintln!("emooji: invalid bytecode in '{}': {}", input.display(), why);
+                1
+            },
+        };
+    }
+    let program_contents = match String::from_utf8(bytes) {
+        Err(why) => panic!("Couldn't read .moo file \'{}\': {}", input.display(), why),
+        Ok(program_contents) => program_contents,
     };
 
+    let mut compiler = Compiler::new(program_contents);
+    compiler.check_syntax();
+    print_warnings(input, &compiler);
+
+    on_big_stack(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let mut output = io::BufWriter::new(io::stdout());
+        let mut errors = io::stderr();
+        compiler.interpret(&program_args, &mut input, &mut output, &mut errors)
+    })
+}
+
+// Reads, checks and interprets statements from stdin until it ends
+fn repl(program_args: Vec<String>) -> i32 {
+    on_big_stack(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let mut output = io::BufWriter::new(io::stdout());
+        let mut errors = io::stderr();
+        Repl::new(&program_args, &mut input, &mut output, &mut errors).run()
+    })
+}
+
+// Runs the interpreter in `f` on its own thread. Deep recursion in the
+// program is deep recursion in the interpreter, so it gets far more stack
+// than the main thread has.
+fn on_big_stack<F>(f: F) -> i32 where F: FnOnce() -> i32 + Send + 'static {
+    match thread::Builder::new().stack_size(INTERPRETER_STACK).spawn(f).map(|handle| handle.join()) {
+        Ok(Ok(status)) => status,
+        Ok(Err(_)) => 101,
+        Err(why) => panic!("Couldn't start the interpreter: {}", why),
+    }
+}
 
-    // create assembly file
-    let path = format!("{}{}", &args[1], ".s");
-    let path = Path::new(&path);
-    let file = match File::create(&path) {
-        Err(why) => panic!("Couldn't create .s file: {}",
-                            why.description()),
+fn print_warnings(input: &Path, compiler: &Compiler) {
+    for warning in compiler.warnings() {
+        eprintln!("{}:{}", input.display(), warning);
+    }
+}
+
+// Runs the compiler as far as `options` asks, returning the exit status
+fn build(options: &Options) -> i32 {
+    let program_contents = read_program(&options.input);
+    let output = options.output_path();
+    if output.as_ref() == Some(&options.input) {
+        panic!("Output would overwrite the input file \'{}\'", options.input.display());
+    }
+
+    let mut compiler = Compiler::new(program_contents);
+    if options.emit == Emit::Tokens {
+        write_output(&output, &compiler.dump_tokens());
+        return 0;
+    }
+
+    compiler.check_syntax();
+    print_warnings(&options.input, &compiler);
+    compiler.set_opt_level(options.opt_level);
+    if options.debug {
+        // debuggers look for the source where the debug information says
+        let source = fs::canonicalize(&options.input).unwrap_or_else(|_| options.input.clone());
+        compiler.set_debug_info(&source.to_string_lossy());
+    }
+    if options.check {
+        return 0;
+    }
+
+    match options.emit {
+        Emit::Ast => {
+            write_output(&output, &compiler.dump_ast());
+            0
+        },
+        Emit::Ir => {
+            write_output(&output, &compiler.dump_ir());
+            0
+        },
+        Emit::Asm => {
+            write_output(&output, &gen_source(options, &compiler));
+            0
+        },
+        Emit::Bytecode => {
+            write_file(&output.unwrap(), &compiler.gen_bytecode());
+            0
+        },
+        // the module is all there is, no C compiler needed
+        _ if options.target == Target::Wasm => {
+            let output = output.unwrap();
+            if options.keep_asm {
+                write_file(&output.with_extension("wat"), compiler.gen_wat().as_bytes());
+            }
+            write_file(&output, &compiler.gen_wasm());
+            0
+        },
+        _ => build_binary(options, &compiler, &output.unwrap()),
+    }
+}
+
+// The code for --target, which the C compiler turns into an object or executable
+fn gen_source(options: &Options, compiler: &Compiler) -> String {
+    match options.target {
+        Target::X86_64 => compiler.gen_code(),
+        Target::Aarch64 => compiler.gen_aarch64(),
+        Target::Riscv64 => compiler.gen_riscv64(),
+        Target::C => compiler.gen_c(),
+        Target::Wasm => compiler.gen_wat(),
+    }
+}
+
+// Builds an object file or executable at `output`, returning the exit status
+fn build_binary(options: &Options, compiler: &Compiler, output: &Path) -> i32 {
+    let asm = output.with_extension(options.target.source_extension());
+    let code = gen_source(options, compiler);
+    if options.keep_asm {
+        write_file(&asm, code.as_bytes());
+    }
+
+    // a stale file from an earlier build must not pass for success
+    let _ = fs::remove_file(output);
+    if options.static_runtime {
+        let bytes = match options.emit {
+            Emit::Obj => compiler.gen_object(true),
+            _ => compiler.gen_executable(),
+        };
+        write_file(output, &bytes);
+        if options.emit == Emit::Exe {
+            make_executable(output);
+        }
+    } else if options.builtin_as && options.emit == Emit::Obj {
+        write_file(output, &compiler.gen_object(false));
+    } else {
+        // the C compiler assembles the code, or just links our own object
+        let input = if options.builtin_as {
+            let object = output.with_extension("o");
+            write_file(&object, &compiler.gen_object(false));
+            object
+        } else {
+            write_file(&asm, code.as_bytes());
+            asm.clone()
+        };
+
+        let cc = options.c_compiler(env::var("CC").ok());
+        let result = assemble(&cc, &input, output, options.emit);
+        if !options.keep_asm || input != asm {
+            let _ = fs::remove_file(&input);
+        }
+        match result {
+            Err(why) => {
+                eprintln!("emooji: could not run '{}': {}", cc, why);
+                return 1;
+            },
+            Ok(Some(why)) => {
+                eprintln!("{}", diagnose(&why, &options.input, &input, &code));
+                return 1;
+            },
+            Ok(None) => {},
+        }
+        if !output.exists() {
+            eprintln!("emooji: '{}' reported success but did not create '{}'",
+                cc, output.display());
+            return 1;
+        }
+    }
+
+    if options.emit == Emit::Exe {
+        if options.run {
+            return run(output, &options.program_args);
+        }
+        println!("\n\n\n\n\n\t🎉🎈🎉🎈 Success! Generated executable \'{}\', run with command \'{}\' 🎈🎉🎈🎉\n\n\n\n\n",
+            output.display(), runnable(output).display());
+    }
+    0
+}
+
+fn write_file(path: &Path, content: &[u8]) {
+    let mut file = match File::create(path) {
+        Err(why) => panic!("Couldn't create \'{}\': {}", path.display(), why),
         Ok(file) => file,
     };
+    if let Err(why) = file.write_all(content) {
+        panic!("couldn't write to file: {}", why);
+    }
+}
+
+fn make_executable(path: &Path) {
+    if let Err(why) = fs::set_permissions(path, fs::Permissions::from_mode(0o755)) {
+        panic!("Couldn't make \'{}\' executable: {}", path.display(), why);
+    }
+}
+
+// Writes `content` to `path`, or to stdout if there is none
+fn write_output(path: &Option<PathBuf>, content: &str) {
+    match *path {
+        Some(ref path) => write_file(path, content.as_bytes()),
+        None => print!("{}", content),
+    }
+}
+
+// Builds an object file or executable from the assembly in `asm` with the C
+// compiler `cc`, returning its complaints if it fails
+fn assemble(cc: &str, asm: &Path, output: &Path, emit: Emit) -> io::Result<Option<String>> {
+    let mut command = Command::new(cc);
+    command.arg("-no-pie")
+        .arg("-fno-pie")
+        .arg("-g");
+    if emit == Emit::Obj {
+        command.arg("-c");
+    }
+    let out = command.arg(asm)
+        .arg("-o")
+        .arg(output)
+        .output()?;
+    if out.status.success() {
+        return Ok(None);
+    }
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let status = match out.status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => String::from("a signal"),
+    };
+    let mut why = format!("'{}' failed with {}", cc, status);
+    if !stderr.trim().is_empty() {
+        why.push('\n');
+        why.push_str(stderr.trim_end());
+    }
+    Ok(Some(why))
+}
 
-    // create Tokenizer
-    let mut compiler = Compiler::new(program_contents, file);
+// Turns an assembler/linker failure into a compiler diagnostic. The generated
+// assembly is never the user's fault, so the lines the assembler points at are
+// quoted from `code` to make the bug report useful even without --keep-asm.
+fn diagnose(why: &str, input: &Path, asm: &Path, code: &str) -> String {
+    let mut content = format!("emooji: internal error: could not build '{}'\n  {}",
+        input.display(), why.replace("\n", "\n  "));
 
-    // start compilation
-    let (vars, funcs) = compiler.gen_data();
-    compiler.gen_annotations(vars, funcs);
-    compiler.check_syntax();
-    compiler.gen_code();
-
-    // compile binary
-    Command::new("gcc")
-    		.arg("-no-pie")
-    		.arg("-fno-pie")
-            .arg("-g")
-    		.arg(format!("{}{}", &args[1], ".s"))
-            .arg("-o")
-            .arg(&args[1])
-    		.spawn()
-    		.expect("could not assemble .s file");
-
-    println!("\n\n\n\n\n\t🎉🎈🎉🎈 Success! Generated executable \'{}\', run with command \'./{}\' 🎈🎉🎈🎉\n\n\n\n\n", &args[1], &args[1]);
-
-    // Command::new(format!("{}{}", "./", &args[1]))
-    // 		.spawn()
-    // 		.expect("could not run binary");
-}
\ No newline at end of file
+    let asm_name = asm.to_string_lossy();
+    let lines: Vec<&str> = code.lines().collect();
+    for line in why.lines() {
+        // gas reports "<file>.s:<line>: Error: ..."
+        let rest = match line.find(&*asm_name) {
+            Some(start) => &line[start + asm_name.len()..],
+            None => continue,
+        };
+        let number: String = rest.trim_start_matches(':').chars()
+            .take_while(|ch| ch.is_ascii_digit()).collect();
+        if let Ok(number) = number.parse::<usize>() {
+            if number > 0 && number <= lines.len() {
+                content.push_str(&format!("\n  {:>6} | {}", number, lines[number - 1].trim()));
+            }
+        }
+    }
+    content.push_str("\nThis is a bug in the compiler, not in your program.");
+    content
+}
+
+// A bare file name would be looked up in PATH, so point it at the current directory
+fn runnable(exe: &Path) -> PathBuf {
+    if exe.components().count() == 1 {
+        Path::new(".").join(exe)
+    } else {
+        exe.to_path_buf()
+    }
+}
+
+// Runs the executable, passing on its exit status
+fn run(exe: &Path, args: &[String]) -> i32 {
+    let status = Command::new(runnable(exe))
+        .args(args)
+        .status()
+        .expect("could not run binary");
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::io::Write;
+    use std::process::{Command, Output, Stdio};
+    use compiler;
+    use compiler::Compiler;
+    use options::{Emit, Options, Target};
+    use super::{build, diagnose, read_program};
+
+    fn test_dir() -> PathBuf {
+        let dir = env::temp_dir().join("emooji_tests");
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Options for building the source file `name`.moo in the test directory
+    fn options(name: &str, program: &str) -> Options {
+        let input = test_dir().join(format!("{}.moo", name));
+        fs::write(&input, program).unwrap();
+        Options {
+            input: input,
+            output: None,
+            emit: Emit::Exe,
+            target: Target::X86_64,
+            check: false,
+            run: false,
+            keep_asm: false,
+            builtin_as: false,
+            static_runtime: false,
+            cc: None,
+            opt_level: 0,
+            debug: false,
+            program_args: Vec::new()
+        }
+    }
+
+    // Builds `program` into an executable named `name` and runs it with `args`
+    fn run(name: &str, program: &str, args: &[&str]) -> Output {
+        let options = options(name, program);
+        assert_eq!(build(&options), 0);
+        Command::new(options.output_path().unwrap()).args(args).output().unwrap()
+    }
+
+    #[test]
+    fn exits_with_zero_by_default() {
+        let output = run("exit_default", "📄1️⃣🔚", &[]);
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    #[test]
+    fn exit_builtin_sets_status() {
+        let output = run("exit_builtin", "📄1️⃣🔚 🚪🌜4️⃣2️⃣🌛🔚 📄2️⃣🔚", &[]);
+        assert_eq!(output.status.code(), Some(42));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    }
+
+    #[test]
+    fn argument_count_leaves_out_the_program() {
+        let program = "📄👥🌜🌛🔚";
+        assert_eq!(String::from_utf8_lossy(&run("arg_count_none", program, &[]).stdout), "0\n");
+        assert_eq!(String::from_utf8_lossy(&run("arg_count", program, &["a", "", "c"]).stdout), "3\n");
+    }
+
+    #[test]
+    fn arguments_are_strings_in_order() {
+        let program = "📄🗣🌜0️⃣🌛◾🗣🌜1️⃣🌛➕💬🐽💬◾🗣🌜👥🌜🌛➖1️⃣🌛🔚";
+        let output = run("args", program, &["🐶", "", "🐱 🐭"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "🐶 🐽 🐱 🐭\n");
+    }
+
+    #[test]
+    fn parsing_reads_leading_digits() {
+        let program = "📄🔄🌜🗣🌜0️⃣🌛🌛➕1️⃣◾🔄🌜🗣🌜1️⃣🌛🌛◾🔄🌜🗣🌜2️⃣🌛🌛◾🔄🌜🗣🌜3️⃣🌛🌛◾🔄🌜💬💬🌛🔚";
+        let output = run("parse", program, &["41", "12🐶", "🐶12", " -0"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "42 12 0 0 0\n");
+    }
+
+    #[test]
+    fn negative_argument_index_exits_with_3() {
+        let output = run("exit_negative_index", "📄🗣🌜0️⃣➖1️⃣🌛🔚", &["a"]);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    }
+
+    #[test]
+    fn parsing_a_missing_argument_exits_with_3() {
+        let output = run("exit_parse_missing", "📄🔄🌜🗣🌜0️⃣🌛🌛🔚", &[]);
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn exit_status_can_be_computed() {
+        let output = run("exit_computed", "🆕🔢😀🔚 😀⬅️👥🌜🌛🔚 🚪🌜😀➕1️⃣🌛🔚", &["a", "b"]);
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn argument_out_of_bounds_exits_with_3() {
+        let output = run("exit_bounds", "📄🗣🌜0️⃣🌛🔚 📄🗣🌜1️⃣🌛🔚", &["a"]);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "a\n");
+    }
+
+    #[test]
+    fn division_by_zero_exits_with_4() {
+        let output = run("exit_div_zero", "🆕🔢😀🔚 📄1️⃣➗️😀🔚", &[]);
+        assert_eq!(output.status.code(), Some(4));
+    }
+
+    #[test]
+    fn file_io_round_trip() {
+        let path = env::temp_dir().join("emooji_tests").join("file_io.txt");
+        let missing = env::temp_dir().join("emooji_tests").join("missing").join("file.txt");
+        let program = "🆕📁📒🔚\
+            📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📝💬🌛🔚\
+            ✍️🌜📒◾💬🐶💬🌛🔚\
+            🔒🌜📒🌛🔚\
+            📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📎💬🌛🔚\
+            ✍️🌜📒◾💬🐱💬🌛🔚\
+            🔒🌜📒🌛🔚\
+            📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📖💬🌛🔚\
+            🔁📭🌜📒🌛 ↔️ 👎 📄📃🌜📒🌛🔚\
+            🔒🌜📒🌛🔚\
+            📒⬅️📂🌜🗣🌜1️⃣🌛◾💬📖💬🌛🔚\
+            ❓📒 📄1️⃣🔚 ❌ 📄0️⃣🔚\
+            📄✍️🌜📒◾💬🐶💬🌛🔚";
+        let output = run("file_io", program,
+            &[path.to_str().unwrap(), missing.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "🐶\n🐱\n0\n👎\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "🐶\n🐱\n");
+    }
+
+    #[test]
+    fn end_of_input_takes_an_optional_file() {
+        let path = env::temp_dir().join("emooji_tests").join("eof.txt");
+        fs::write(&path, "🐶\n").unwrap();
+        // the file argument may be any expression, parentheses included
+        let program = "🆕📁📒🔚 📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📖💬🌛🔚 \
+            ❓📭🌜🌜📒🌛🌛 📄1️⃣🔚 ❌ 📄2️⃣🔚 \
+            ❓📭🌜🌛 📄3️⃣🔚 ❌ 📄4️⃣🔚";
+        let output = run("eof_file", program, &[path.to_str().unwrap()]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n3\n");
+    }
+
+    #[test]
+    fn print_forms() {
+        let program = "🆕🔤👽🔚 👽⬅️💬👾💬🔚\
+            🖨️1️⃣🔚 🖨️👽🔚 📄🔚\
+            📄2️⃣◾👽◾3️⃣➕4️⃣🔚\
+            📄🖼🌜💬🔢🐶🔢☯️🔤☯️💬◾1️⃣0️⃣◾2️⃣➖1️⃣◾👍◾👽◾1️⃣↔️2️⃣🌛🔚";
+        let output = run("print_forms", program, &[]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout),
+            "1👾\n2 👾 7\n10🐶1👍👾👎\n");
+    }
+
+    #[test]
+    fn print_booleans() {
+        let program = "🆕☯️😍🔚 😍⬅️👍🔚 📄😍🔚 😍⬅️👎🔚 📄😍🔚 📄1️⃣↔️1️⃣◾📭🌜🌛🔚";
+        let output = run("print_bools", program, &[]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "👍\n👎\n👍 👍\n");
+    }
+
+    #[test]
+    fn division_divides_left_by_right() {
+        let output = run("div", "📄8️⃣➗️2️⃣🔚 📄3️⃣✖️4️⃣➗️3️⃣🔚", &[]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "4\n4\n");
+    }
+
+    #[test]
+    fn print_parenthesized_expressions() {
+        let program = "📄🌜💬😀💬🌛🔚 📄🌜1️⃣➕2️⃣🌛✖️3️⃣🔚 📄🚫🌜👎🌛🔚";
+        let output = run("print_parens", program, &[]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "😀\n9\n👍\n");
+    }
+
+    #[test]
+    fn print_mixed_expressions() {
+        let program = "🆕🔤🐒🔚 🐒⬅️💬🙈💬🔚 📄3️⃣➕🐒🔚 📄🐒➕👍🔚 📄🐒➕1️⃣➕2️⃣🔚";
+        let output = run("print_mixed", program, &[]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3🙈\n🙈👍\n🙈12\n");
+    }
+
+    #[test]
+    fn print_builtin_calls() {
+        let program = "📄👥🌜🌛🔚 📄🗣🌜0️⃣🌛➕👥🌜🌛🔚 📄🔄🌜🗣🌜1️⃣🌛🌛➕1️⃣🔚 📄📭🌜🌛🔚";
+        let output = run("print_builtins", program, &["🐸", "41"]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n🐸2\n42\n👍\n");
+    }
+
+    #[test]
+    fn print_function_calls() {
+        let program = "🤪🔤🐕🌜🔢🍖🌛 ↩️💬🐶💬➕🍖🔚 \
+            🤪☯️🐈🌜🌛 ↩️1️⃣↔️1️⃣🔚 \
+            🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+            📄📞🐕🌜3️⃣🌛🔚 📄📞🐈🌜🌛🔚 📄📞🦊🌜5️⃣🌛🔚 📄👥🌜🌛➕📞🦊🌜3️⃣🌛🔚";
+        let output = run("print_calls", program, &[]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "🐶3\n👍\n120\n6\n");
+    }
+
+    #[test]
+    fn emit_stages() {
+        let mut options = options("emit_stages", "🆕🔢😀🔚 😀⬅️2️⃣🔚 📄😀🔚");
+        let dir = test_dir();
+
+        options.emit = Emit::Tokens;
+        options.output = Some(dir.join("emit_stages.tokens"));
+        assert_eq!(build(&options), 0);
+        let tokens = fs::read_to_string(dir.join("emit_stages.tokens")).unwrap();
+        assert!(tokens.starts_with("Token { kind: NEW"));
+
+        options.emit = Emit::Ast;
+        options.output = Some(dir.join("emit_stages.ast"));
+        assert_eq!(build(&options), 0);
+        let ast = fs::read_to_string(dir.join("emit_stages.ast")).unwrap();
+        assert!(ast.contains("Declare(") && ast.contains("var_type: INT"));
+
+        options.emit = Emit::Ir;
+        options.output = Some(dir.join("emit_stages.ir"));
+        assert_eq!(build(&options), 0);
+        let ir = fs::read_to_string(dir.join("emit_stages.ir")).unwrap();
+        assert_eq!(ir, "\nfn main() {\nb0:\n    v0: int = const 0\n    v1: int = const 2\n    print v1\n    print '\\n'\n    ret\n}\n");
+
+        options.emit = Emit::Asm;
+        options.output = None;
+        assert_eq!(build(&options), 0);
+        assert!(fs::read_to_string(dir.join("emit_stages.s")).unwrap().contains("main:"));
+
+        options.emit = Emit::Bytecode;
+        assert_eq!(build(&options), 0);
+        assert!(compiler::is_bytecode(&fs::read(dir.join("emit_stages.mooc")).unwrap()));
+
+        // any C99 compiler has to accept the C source
+        options.emit = Emit::Asm;
+        options.target = Target::C;
+        assert_eq!(build(&options), 0);
+        let status = Command::new("gcc").args(["-std=c99", "-pedantic-errors", "-Werror", "-fsyntax-only"])
+            .arg(dir.join("emit_stages.c")).status().unwrap();
+        assert!(status.success());
+
+        options.target = Target::Wasm;
+        assert_eq!(build(&options), 0);
+        assert!(fs::read_to_string(dir.join("emit_stages.wat")).unwrap().starts_with("(module"));
+        options.emit = Emit::Exe;
+        assert_eq!(build(&options), 0);
+        assert!(fs::read(dir.join("emit_stages.wasm")).unwrap().starts_with(b"\0asm"));
+        options.emit = Emit::Asm;
+        options.target = Target::X86_64;
+
+        options.emit = Emit::Obj;
+        let _ = fs::remove_file(dir.join("emit_stages.s"));
+        assert_eq!(build(&options), 0);
+        assert!(dir.join("emit_stages.o").exists());
+        assert!(!dir.join("emit_stages.s").exists());
+    }
+
+    #[test]
+    fn keep_asm_leaves_assembly_next_to_output() {
+        let mut options = options("keep_asm", "📄1️⃣🔚");
+        options.output = Some(test_dir().join("keep_asm_exe"));
+        options.keep_asm = true;
+        assert_eq!(build(&options), 0);
+        assert!(test_dir().join("keep_asm_exe").exists());
+        assert!(test_dir().join("keep_asm_exe.s").exists());
+    }
+
+    #[test]
+    fn run_returns_program_status() {
+        let mut options = options("run_status", "🚪🌜👥🌜🌛➕4️⃣0️⃣🌛🔚");
+        options.run = true;
+        options.program_args = vec![String::from("a"), String::from("b")];
+        assert_eq!(build(&options), 42);
+    }
+
+    #[test]
+    fn check_only_writes_nothing() {
+        let mut options = options("check_only", "📄1️⃣🔚");
+        let _ = fs::remove_file(test_dir().join("check_only"));
+        options.check = true;
+        assert_eq!(build(&options), 0);
+        assert!(!test_dir().join("check_only").exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types")]
+    fn check_reports_type_errors() {
+        let mut options = options("check_error", "🆕🔢😀🔚 😀⬅️💬🙈💬🔚");
+        options.check = true;
+        build(&options);
+    }
+
+    #[test]
+    fn failing_compiler_is_not_success() {
+        let mut options = options("cc_false", "📄1️⃣🔚");
+        options.output = Some(test_dir().join("cc_false_exe"));
+        options.cc = Some(String::from("false"));
+        assert_eq!(build(&options), 1);
+        assert!(!test_dir().join("cc_false_exe").exists());
+
+        options.cc = Some(String::from("emooji-no-such-compiler"));
+        assert_eq!(build(&options), 1);
+    }
+
+    #[test]
+    fn missing_executable_is_not_success() {
+        let mut options = options("cc_true", "📄1️⃣🔚");
+        options.cc = Some(String::from("true"));
+        assert_eq!(build(&options), 1);
+    }
+
+    #[test]
+    fn assembler_errors_quote_generated_code() {
+        let code = "main:\n\t\tmovq %rax, %rbx\n\t\tbogus %rax\n";
+        let why = "'gcc' failed with exit status 1\n\
+                   /tmp/x.s: Assembler messages:\n\
+                   /tmp/x.s:3: Error: no such instruction: `bogus %rax'";
+        let message = diagnose(why, &PathBuf::from("x.moo"), &PathBuf::from("/tmp/x.s"), code);
+        assert!(message.contains("could not build 'x.moo'"));
+        assert!(message.contains("no such instruction"));
+        assert!(message.contains("     3 | bogus %rax"));
+    }
+
+    // Programs that touch every runtime helper, built with the C compiler,
+    // with the built-in assembler and into a static executable
+    #[test]
+    fn builtin_assembler_matches_gcc() {
+        let programs = [
+            ("same_calls", "🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+                📄📞🦊🌜5️⃣🌛🔚 📄👥🌜🌛🔚 📄🗣🌜0️⃣🌛🔚"),
+            ("same_forms", "🆕🔤👽🔚 👽⬅️💬👾💬🔚 🖨️1️⃣🔚 🖨️👽🔚 📄🔚 \
+                📄🖼🌜💬🔢🐶🔤💬◾1️⃣0️⃣◾👽🌛🔚 📄👽➕👍➕3️⃣🔚"),
+            ("same_div_zero", "🆕🔢😀🔚 📄1️⃣🔚 📄1️⃣➗️😀🔚"),
+            ("same_bounds", "📄🗣🌜1️⃣🌛🔚"),
+            ("same_exit", "📄💬🐒💬🔚 🚪🌜4️⃣2️⃣🌛🔚"),
+        ];
+        for &(name, program) in programs.iter() {
+            let mut options = options(name, program);
+            assert_eq!(build(&options), 0);
+            let expected = Command::new(options.output_path().unwrap()).arg("a").output().unwrap();
+
+            options.builtin_as = true;
+            options.output = Some(test_dir().join(format!("{}_builtin", name)));
+            assert_eq!(build(&options), 0);
+            let builtin = Command::new(options.output_path().unwrap()).arg("a").output().unwrap();
+
+            options.static_runtime = true;
+            options.output = Some(test_dir().join(format!("{}_static", name)));
+            assert_eq!(build(&options), 0);
+            let static_exe = Command::new(options.output_path().unwrap()).arg("a").output().unwrap();
+
+            for output in [&builtin, &static_exe].iter() {
+                assert_eq!(output.status.code(), expected.status.code(), "{}", name);
+                assert_eq!(output.stdout, expected.stdout, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn static_object_links_with_ld() {
+        let mut options = options("static_obj", "📄💬🐄💬🔚");
+        options.emit = Emit::Obj;
+        options.builtin_as = true;
+        options.static_runtime = true;
+        assert_eq!(build(&options), 0);
+        let object = fs::read(test_dir().join("static_obj.o")).unwrap();
+        assert_eq!(&object[..4], b"\x7fELF");
+
+        let exe = test_dir().join("static_obj_ld");
+        let status = Command::new("ld").arg(test_dir().join("static_obj.o")).arg("-o").arg(&exe)
+            .status().unwrap();
+        assert!(status.success());
+        let output = Command::new(&exe).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "🐄\n");
+    }
+
+    fn run_piped(exe: &Path, args: &[&str], stdin: &str) -> Output {
+        let mut command = Command::new(exe);
+        command.args(args);
+        run_command(command, stdin)
+    }
+
+    fn run_command(mut command: Command, stdin: &str) -> Output {
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .spawn().unwrap();
+        child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    // Builds and interprets `program`, checking that the interpreter, the
+    // bytecode VM, the optimized executable and the program built from C
+    // print the same and exit with the same status as the executable
+    fn same_as_compiled(name: &str, program: &str, args: &[&str], stdin: &str) -> Output {
+        let mut options = options(name, program);
+        assert_eq!(build(&options), 0);
+        let compiled = run_piped(&options.output_path().unwrap(), args, stdin);
+
+        for level in 1..3 {
+            options.opt_level = level;
+            options.output = Some(test_dir().join(format!("{}_opt{}", name, level)));
+            assert_eq!(build(&options), 0);
+            let optimized = run_piped(&options.output_path().unwrap(), args, stdin);
+            assert_eq!(optimized.status.code(), compiled.status.code(), "{} -O{}", name, level);
+            assert_eq!(String::from_utf8_lossy(&optimized.stdout), String::from_utf8_lossy(&compiled.stdout),
+                       "{} -O{}", name, level);
+            assert_eq!(String::from_utf8_lossy(&optimized.stderr), String::from_utf8_lossy(&compiled.stderr),
+                       "{} -O{}", name, level);
+        }
+        options.opt_level = 0;
+
+        options.target = Target::C;
+        options.output = Some(test_dir().join(format!("{}_c", name)));
+        assert_eq!(build(&options), 0);
+        let from_c = run_piped(&options.output_path().unwrap(), args, stdin);
+        assert_eq!(from_c.status.code(), compiled.status.code(), "{}", name);
+        assert_eq!(String::from_utf8_lossy(&from_c.stdout), String::from_utf8_lossy(&compiled.stdout), "{}", name);
+        assert_eq!(String::from_utf8_lossy(&from_c.stderr), String::from_utf8_lossy(&compiled.stderr), "{}", name);
+
+        let mut compiler = Compiler::new(read_program(&options.input));
+        compiler.check_syntax();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let status = compiler.interpret(&args, &mut stdin.as_bytes(), &mut stdout, &mut stderr);
+        let interpreted = (Some(status), stdout, stderr);
+
+        let bytecode = compiler.gen_bytecode();
+        assert!(compiler::is_bytecode(&bytecode));
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let status = compiler::run_bytecode(&bytecode, &args, &mut stdin.as_bytes(), &mut stdout,
+                                            &mut stderr).unwrap();
+        let vm = (Some(status), stdout, stderr);
+
+        let wasm = compiler.gen_wasm();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let status = compiler::run_wasm(&wasm, &args, &mut stdin.as_bytes(), &mut stdout, &mut stderr);
+        let wasm = (Some(status), stdout, stderr);
+
+        for &&(status, ref stdout, ref stderr) in [&interpreted, &vm, &wasm].iter() {
+            assert_eq!(status, compiled.status.code(), "{}", name);
+            assert_eq!(String::from_utf8_lossy(stdout), String::from_utf8_lossy(&compiled.stdout), "{}", name);
+            assert_eq!(String::from_utf8_lossy(stderr), String::from_utf8_lossy(&compiled.stderr), "{}", name);
+        }
+        compiled
+    }
+
+    #[test]
+    fn interpreter_matches_functions_and_loops() {
+        let program = "🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+            🤪🔢🐝🌜🔢🐾🌛 🌘 🆕🔢🐜🔚 🔁👍 🌘 🐜⬅️🐜➕1️⃣🔚 ❓🐜↔️🐾 ↩️🐜✖️🐜🔚 🌒 🌒 \
+            🤪🐣🌜🔤🐾◾☯️🐛🌛 🌘 🖨️🐾🔚 ❓🐛 📄👍🔚 ❌ 📄👎🔚 🌒 \
+            ❓👍 🌘 🤪🔤🐍🌜🌛 🌘 🌒 🌒 \
+            🆕🔢😀🔚 😀⬅️3️⃣🔚 \
+            🔁🚫🌜😀↔️0️⃣🌛 🌘 📄😀◾📞🦊🌜😀🌛◾📞🐝🌜😀🌛🔚 😀⬅️😀➖1️⃣🔚 🌒 \
+            📞🐣🌜💬🐸💬◾1️⃣↔️1️⃣🌛🔚 📞🐣🌜📞🐍🌜🌛◾👎🌛🔚 📄📞🐍🌜🌛➕💬🐸💬🔚";
+        let output = same_as_compiled("interp_calls", program, &[], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout),
+            "3 6 9\n2 2 4\n1 1 1\n🐸👍\n👎\n🐸\n");
+    }
+
+    #[test]
+    fn interpreter_matches_printing_and_wrapping() {
+        let program = "🆕🔤👽🔚 👽⬅️💬👾💬🔚 🆕☯️😍🔚 \
+            🖨️1️⃣🔚 🖨️👽🔚 📄🔚 \
+            📄2️⃣◾👽◾3️⃣➕4️⃣🔚 \
+            📄🖼🌜💬🔢🐶🔢☯️🔤☯️💬◾1️⃣0️⃣◾2️⃣➖1️⃣◾👍◾👽◾1️⃣↔️2️⃣🌛🔚 \
+            📄😍◾🚫😍◾😍➕👍◾😍✖️👍◾👽↔️💬👾💬◾👽➕😍➕1️⃣🔚 \
+            📄0️⃣➖1️⃣◾🌜0️⃣➖1️⃣🌛✖️2️⃣◾7️⃣➗️2️⃣🔚 📄🎲🌜1️⃣🌛🔚";
+        let output = same_as_compiled("interp_print", program, &[], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout),
+            "1👾\n2 👾 7\n10🐶1👍👾👎\n👎 👍 👍 👎 👍 👾👎1\n\
+             18446744073709551615 18446744073709551614 3\n0\n");
+    }
+
+    #[test]
+    fn interpreter_matches_input_and_arguments() {
+        let program = "🆕🔢🐷🔚 🆕🔤🐮🔚 🐮⬅️📥🌜🌛🔚 \
+            🔁🚫📭🌜🌛 🌘 🐷⬅️🐷➕📨🌜🌛🔚 🌒 \
+            📄🐮◾🐷◾📥🌜🌛➕💬🐽💬◾👥🌜🌛🔚 \
+            📄🔄🌜🗣🌜0️⃣🌛🌛◾🔄🌜🗣🌜1️⃣🌛🌛◾🗣🌜1️⃣🌛↔️🗣🌜2️⃣🌛◾🔄🌜🗣🌜3️⃣🌛🌛🔚";
+        let output = same_as_compiled("interp_input", program,
+            &["42🐶", "  -7", "  -7", "99999999999999999999"], "🐄\n1\n 2\n+3x\n\n-1\n");
+        assert_eq!(String::from_utf8_lossy(&output.stdout),
+            "🐄 5 🐽 4\n42 18446744073709551609 👍 9223372036854775807\n");
+    }
+
+    // Filters in fixtures/ that read stdin, each with its input and output
+    const FIXTURES: [&str; 3] = ["number_lines", "sum", "empty"];
+
+    #[test]
+    fn stdin_fixtures_pipe_through() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        for name in FIXTURES.iter() {
+            let read = |extension: &str| fs::read_to_string(dir.join(format!("{}.{}", name, extension))).unwrap();
+            let output = same_as_compiled(&format!("fixture_{}", name), &read("moo"), &[], &read("in"));
+            assert_eq!(String::from_utf8_lossy(&output.stdout), read("out"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn long_lines_are_read_whole() {
+        // lines around and past the 4096 bytes the buffer starts with, the
+        // last one ending at the end of the input
+        let lines = ["x".repeat(4095), "y".repeat(10000), "z".repeat(8191)];
+        let stdin = format!("{}\n{}\n{}", lines[0], lines[1], lines[2]);
+        let expected = format!("1 {}\n2 {}\n3 {}\n", lines[0], lines[1], lines[2]);
+        let program = "🆕🔢🐷🔚 🔁🚫📭🌜🌛 🌘 🐷⬅️🐷➕1️⃣🔚 📄🐷◾📥🌜🌛🔚 🌒";
+        let output = same_as_compiled("long_lines", program, &[], &stdin);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+        let mut options = options("long_lines_static", program);
+        options.builtin_as = true;
+        options.static_runtime = true;
+        assert_eq!(build(&options), 0);
+        let output = run_piped(&options.output_path().unwrap(), &[], &stdin);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+
+    #[test]
+    fn functions_are_called_before_their_declaration() {
+        // 🐝 is defined in a branch that never runs
+        let program = "📄📞🦊🌜2️⃣🌛◾📞🐝🌜🌛🔚 🤪🔢🦊🌜🔢🐾🌛 🌘 ↩️🐾➕1️⃣🔚 🌒 \
+            ❓👎 🌘 🤪🔤🐝🌜🌛 🌘 ↩️💬🐸💬🔚 🌒 🌒";
+        let output = same_as_compiled("call_before_declaration", program, &[], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3 🐸\n");
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    #[test]
+    fn interpreter_matches_runtime_errors() {
+        same_as_compiled("interp_bounds", "📄🗣🌜0️⃣🌛🔚 📄🗣🌜1️⃣🌛🔚", &["a"], "");
+        same_as_compiled("interp_div_zero", "🆕🔢😀🔚 🖨️1️⃣🔚 📄1️⃣➗️😀🔚", &[], "");
+        same_as_compiled("interp_rand_zero", "📄🎲🌜0️⃣🌛🔚", &[], "");
+        let output = same_as_compiled("interp_exit",
+            "🤪🐚🌜🌛 🌘 🚪🌜2️⃣5️⃣7️⃣🌛🔚 🌒 📄1️⃣🔚 📞🐚🌜🌛🔚 📄2️⃣🔚", &[], "");
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    #[test]
+    fn registers_hold_values_across_calls() {
+        // more values live at once than there are registers, some across calls
+        let program = "🤪🔢🐝🌜🔢🐾🌛 🌘 ↩️🐾✖️2️⃣🔚 🌒 \
+            🤪🔤🐍🌜🔤🐾◾🔢🐛🌛 🌘 🆕🔤🐜🔚 🐜⬅️🐾➕🖼🌜💬🔢💬◾🐛🌛🔚 ↩️🐜➕🐾🔚 🌒 \
+            🆕🔢🐭🔚 🆕🔢🐹🔚 🆕🔢🐰🔚 🆕🔢🦊🔚 🆕🔢🐻🔚 🆕🔢🐼🔚 🆕🔢🐨🔚 🆕🔤🐯🔚 \
+            🐭⬅️1️⃣🔚 🐹⬅️2️⃣🔚 🐰⬅️3️⃣🔚 🦊⬅️4️⃣🔚 🐻⬅️5️⃣🔚 🐼⬅️6️⃣🔚 🐨⬅️7️⃣🔚 🐯⬅️💬🐸💬🔚 \
+            🔁🚫🌜🐭↔️4️⃣🌛 🌘 \
+                🐹⬅️📞🐝🌜🐹🌛➕🐰🔚 🐰⬅️🐰➕📞🐝🌜🦊🌛🔚 🦊⬅️🦊➕🐻➕🐼➕🐨🔚 \
+                🐻⬅️🐻✖️🐹🔚 🐼⬅️🐼➖🐭🔚 🐨⬅️🐨➗️🐭🔚 🐯⬅️📞🐍🌜🐯◾🐭🌛🔚 🐭⬅️🐭➕1️⃣🔚 \
+            🌒 \
+            📄🐭◾🐹◾🐰◾🦊◾🐻◾🐼◾🐨🔚 📄🐯🔚";
+        let output = same_as_compiled("registers_calls", program, &[], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout),
+            "4 105 193 950 91875 0 1\n🐸1🐸2🐸1🐸3🐸1🐸2🐸1🐸\n");
+        for &(name, program) in [("collatz", include_str!("../bench/collatz.moo")),
+                                 ("primes", include_str!("../bench/primes.moo"))].iter() {
+            same_as_compiled(name, program, &[], "");
+        }
+    }
+
+    #[test]
+    fn loop_optimizations_keep_output() {
+        // invariant products and strings, counters going up and down, and a
+        // loop that never runs around a division by zero
+        let program = "🆕🔢🐭🔚 🆕🔢🐹🔚 🆕🔢🐯🔚 🆕🔤🐍🔚 🆕🔢🦁🔚 🆕🔢🐻🔚 \
+            🐭⬅️📨🌜🌛🔚 🐹⬅️🐭➕2️⃣🔚 🦁⬅️🐹🔚 \
+            🔁🚫🌜🦁↔️0️⃣🌛 🌘 \
+                🐻⬅️0️⃣🔚 🔁🚫🌜🐻↔️🐭🌛 🌘 🐯⬅️🐯➕🐭✖️🐹➕🦁✖️7️⃣➖🐻✖️🐹🔚 🐻⬅️🐻➕1️⃣🔚 🌒 \
+                🐍⬅️🖼🌜💬🔢💬◾🐭✖️🐹🌛➕🖼🌜💬🔢💬◾🦁✖️🐭🌛🔚 \
+                🦁⬅️🦁➖1️⃣🔚 \
+            🌒 \
+            🔁🚫🌜🦁↔️0️⃣🌛 🌘 🐯⬅️🐯➗️🦁🔚 🦁⬅️🦁➖1️⃣🔚 🌒 \
+            📄🐯◾🐍◾🦁🔚";
+        let output = same_as_compiled("loops", program, &[], "3\n");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "465 153 0\n");
+    }
+
+    #[test]
+    fn tail_calls_recurse_a_million_times() {
+        let program = "🤪🔢🦊🌜🔢🐾◾🔢🐝🌛 🌘 ❓🐾↔️0️⃣ ↩️🐝🔚 ↩️📞🦊🌜🐾➖1️⃣◾🐝➕🐾🌛🔚 🌒 \
+            🤪🐍🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️🔚 📞🐍🌜🐾➖1️⃣🌛🔚 🌒 \
+            📄📞🦊🌜1️⃣0️⃣0️⃣0️⃣0️⃣0️⃣0️⃣◾0️⃣🌛🔚 📞🐍🌜1️⃣0️⃣0️⃣0️⃣0️⃣0️⃣0️⃣🌛🔚 📄💬🐸💬🔚";
+        let output = same_as_compiled("tail_calls", program, &[], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "500000500000\n🐸\n");
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    #[test]
+    fn debug_info_names_lines_and_variables() {
+        let program = "🆕🔢😀🔚\n🆕🔤👽🔚\n🤪🔢🦊🌜🔢🐾🌛 🌘\n    🆕🔢🐝🔚 🐝⬅️🐾✖️2️⃣🔚\n    ↩️🐝🔚\n🌒\n\
+            😀⬅️📞🦊🌜📨🌜🌛🌛🔚\n👽⬅️💬🐸💬🔚\n📄😀◾👽🔚\n";
+        let mut options = options("debug_info", program);
+        options.debug = true;
+        let gdb = installed("gdb");
+        let input = test_dir().join("debug_info.in");
+        fs::write(&input, "21\n").unwrap();
+        for level in 0..3 {
+            options.opt_level = level;
+            assert_eq!(build(&options), 0);
+            let output = run_piped(&options.output_path().unwrap(), &[], "21\n");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "42 🐸\n", "-O{}", level);
+            if gdb {
+                // stop at the last line, once 😀 holds the doubled input
+                let output = Command::new("gdb").args(["-batch", "-nx", "-ex", "break debug_info.moo:9"])
+                    .arg("-ex").arg(format!("run < {}", input.display()))
+                    .args(["-ex", "print 😀"]).arg(options.output_path().unwrap()).output().unwrap();
+                let text = String::from_utf8_lossy(&output.stdout);
+                assert!(text.contains("$1 = 42"), "-O{}: {}{}", level, text, String::from_utf8_lossy(&output.stderr));
+            }
+        }
+        if !installed("llvm-dwarfdump") {
+            return;
+        }
+        let dump = |flag: &str| {
+            let output = Command::new("llvm-dwarfdump").arg(flag).arg(options.output_path().unwrap()).output().unwrap();
+            String::from(String::from_utf8_lossy(&output.stdout))
+        };
+        let lines = dump("--debug-line");
+        assert!(lines.contains("debug_info.moo"), "{}", lines);
+        let info = dump("--debug-info");
+        for name in &["😀", "👽", "🦊", "🐾", "🐝"] {
+            // names are shown with their bytes in octal
+            let escaped: String = name.bytes().map(|byte| format!("\\{:o}", byte)).collect();
+            assert!(info.contains(&format!("(\"{}\")", escaped)), "no {} in {}", name, info);
+        }
+    }
+
+    #[test]
+    fn dead_code_is_left_out() {
+        let program = "🆕🔢😀🔚 🆕🔤👽🔚 🆕🔢😎🔚 \
+            🤪🔢🦊🌜🔢🐾🌛 🌘 ↩️🐾🔚 📄🐾🔚 🌒 \
+            😀⬅️3️⃣🔚 😀⬅️📨🌜🌛🔚 👽⬅️📥🌜🌛🔚 😎⬅️1️⃣➗️😀🔚 \
+            📄😀🔚 🚪🌜😀🌛🔚 📄👽🔚";
+        let output = same_as_compiled("dead_code", program, &[], "7\n🐶\n");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "7\n");
+        assert_eq!(output.status.code(), Some(7));
+
+        let mut compiler = Compiler::new(String::from(program));
+        compiler.check_syntax();
+        assert_eq!(compiler.warnings().len(), 5);
+        let code = compiler.gen_code();
+        assert!(!code.contains("🦊") && !code.contains("👽") && !code.contains("😎"), "{}", code);
+    }
+
+    #[test]
+    fn interpreter_matches_file_io() {
+        let dir = env::temp_dir().join("emooji_tests");
+        let path = dir.join("interp_file.txt");
+        let missing = dir.join("missing").join("file.txt");
+        let program = "🆕📁📒🔚 🆕📁📕🔚\
+            📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📝💬🌛🔚\
+            📄✍️🌜📒◾💬🐶💬🌛◾📃🌜📒🌛◾📭🌜📒🌛🔚\
+            📕⬅️📂🌜🗣🌜0️⃣🌛◾💬📎💬🌛🔚 ✍️🌜📕◾💬🐱💬🌛🔚 🔒🌜📕🌛🔚 🔒🌜📒🌛🔚\
+            📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📖💬🌛🔚\
+            📄✍️🌜📒◾💬🐶💬🌛🔚\
+            🔁🚫📭🌜📒🌛 📄📃🌜📒🌛🔚\
+            📄🔒🌜📒🌛🔚\
+            📒⬅️📂🌜🗣🌜1️⃣🌛◾💬📖💬🌛🔚 📕⬅️📂🌜🗣🌜0️⃣🌛◾💬🐶💬🌛🔚\
+            📄📃🌜📒🌛◾📭🌜📕🌛◾✍️🌜📕◾💬🐶💬🌛◾🔒🌜📒🌛🔚\
+            📒⬅️📂🌜🗣🌜0️⃣🌛◾💬📝💬🌛🔚 ✍️🌜📒◾💬🐭💬🌛🔚";
+        let output = same_as_compiled("interp_files", program,
+            &[path.to_str().unwrap(), missing.to_str().unwrap()], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout),
+            "👍  👍\n👎\n🐶\n🐱\n👍\n 👍 👎 👎\n");
+        // the last file is never closed, but still written at exit
+        assert_eq!(fs::read_to_string(&path).unwrap(), "🐭\n");
+    }
+
+    // Whether `command` is installed, for tests of optional tools, saying so
+    // when it is not so the skipped checks show up
+    fn installed(command: &str) -> bool {
+        let found = Command::new(command).arg("--version").stdout(Stdio::null()).stderr(Stdio::null())
+            .status().is_ok();
+        if !found {
+            println!("skipping checks that need {}: it is not installed", command);
+        }
+        found
+    }
+
+    // Builds a program for another architecture with the cross compiler `cc`
+    // and checks that under `qemu` it behaves like the native executable.
+    // Without the toolchain, the golden files in src/compiler/codegen/golden
+    // are all that covers the target.
+    fn cross_matches_native(name: &str, target: Target, cc: &str, qemu: &str) {
+        if !installed(cc) || !installed(qemu) {
+            return;
+        }
+        let program = "🤪🔢🦊🌜🔢🐾🌛 🌘 ❓🐾↔️0️⃣ ↩️1️⃣🔚 ↩️🐾✖️📞🦊🌜🐾➖1️⃣🌛🔚 🌒 \
+            🆕🔤🐮🔚 🐮⬅️📥🌜🌛🔚 🆕🔢🐷🔚 🔁🚫📭🌜🌛 🌘 🐷⬅️🐷➕📨🌜🌛🔚 🌒 \
+            📄🐮➕💬🐽💬◾🐷➗️2️⃣◾🐮↔️💬🐄💬◾🚫👍➕👎◾📞🦊🌜2️⃣0️⃣🌛🔚 \
+            📄🖼🌜💬🔢🐶☯️💬◾0️⃣➖1️⃣◾🐷↔️🐷🌛◾🗣🌜0️⃣🌛◾👥🌜🌛◾🎲🌜1️⃣🌛🔚 \
+            📄🗣🌜1️⃣🌛🔚";
+        let mut options = options(name, program);
+        assert_eq!(build(&options), 0);
+        let native = run_piped(&options.output_path().unwrap(), &["🐸"], "🐄\n12\n30\n");
+
+        options.target = target;
+        options.cc = Some(cc.to_string());
+        options.output = Some(test_dir().join(format!("{}_cross", name)));
+        assert_eq!(build(&options), 0);
+        let mut command = Command::new(qemu);
+        // where qemu finds the target's C library
+        command.env("QEMU_LD_PREFIX", format!("/usr/{}", cc.trim_end_matches("-gcc")));
+        command.arg(options.output_path().unwrap()).arg("🐸");
+        let cross = run_command(command, "🐄\n12\n30\n");
+        assert_eq!(cross.status.code(), native.status.code());
+        assert_eq!(String::from_utf8_lossy(&cross.stdout), String::from_utf8_lossy(&native.stdout));
+        assert_eq!(String::from_utf8_lossy(&cross.stderr), String::from_utf8_lossy(&native.stderr));
+    }
+
+    #[test]
+    fn aarch64_matches_native() {
+        cross_matches_native("cross_aarch64", Target::Aarch64, "aarch64-linux-gnu-gcc", "qemu-aarch64");
+    }
+
+    #[test]
+    fn riscv64_matches_native() {
+        cross_matches_native("cross_riscv64", Target::Riscv64, "riscv64-linux-gnu-gcc", "qemu-riscv64");
+    }
+}
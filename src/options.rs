@@ -0,0 +1,452 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: emooji [options] <file.moo> [-- <program args>...]
+       emooji run <file.moo> [-- <program args>...]
+       emooji repl [-- <program args>...]
+
+'emooji run' interprets the program directly instead of compiling it, or runs
+bytecode built with --emit bytecode, and 'emooji repl' interprets statements
+as they are typed.
+
+A function's call to itself at its end becomes a jump back to its start, with
+or without -O and in every backend, so such recursion never runs out of stack.
+
+Options:
+    -o <path>           write the output to <path>
+    --emit <stage>      stop after producing tokens, ast, ir, asm, bytecode, obj or exe
+                        (default: exe)
+    --target <target>   generate x86-64 assembly (default); aarch64 (or arm64)
+                        or riscv64 (or rv64), Linux assembly built with a
+                        cross compiler given to --cc; c, portable C99 that
+                        the C compiler builds; or wasm, a WebAssembly module
+                        (.wasm) for browsers. asm is then the C source or the
+                        WebAssembly text format
+    -O                  optimize the native code: fold constants and drop the
+                        branches they decide, compute what a loop doesn't change
+                        before it, and turn multiplications by a loop counter
+                        into additions
+    -O2                 also inline small functions that don't recurse
+    -g                  describe the code to debuggers such as gdb: the .moo lines
+                        it comes from and where its variables are, for native targets
+    --check             only type check the program
+    --run               run the executable after building it
+    --keep-asm          keep the generated .s file when building obj or exe
+    --builtin-as        assemble with the built-in assembler instead of the C compiler
+    --static            link against the bundled runtime, needing no toolchain at all
+    --cc <command>      assemble and link with <command> (default: $CC, then gcc)
+    -h, --help          print this message
+    -V, --version       print the compiler version
+";
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    // the intermediate representation of the native backends
+    Ir,
+    Asm,
+    // portable bytecode for 'emooji run'
+    Bytecode,
+    Obj,
+    Exe
+}
+
+// What the generated code is written in
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Target {
+    X86_64,
+    Aarch64,
+    Riscv64,
+    C,
+    // a module for browsers, needing no C compiler
+    Wasm
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Version,
+    Build(Options),
+    // interpret the input with these program arguments
+    Interpret(PathBuf, Vec<String>),
+    // start the REPL with these program arguments
+    Repl(Vec<String>)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub emit: Emit,
+    pub target: Target,
+    pub check: bool,
+    pub run: bool,
+    pub keep_asm: bool,
+    pub builtin_as: bool,
+    // implies builtin_as
+    pub static_runtime: bool,
+    pub cc: Option<String>,
+    // 0 without -O
+    pub opt_level: u8,
+    // -g
+    pub debug: bool,
+    // passed to the program by --run
+    pub program_args: Vec<String>
+}
+
+impl Emit {
+    fn from_str(stage: &str) -> Option<Emit> {
+        match stage {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "ir" => Some(Emit::Ir),
+            "asm" => Some(Emit::Asm),
+            "bytecode" => Some(Emit::Bytecode),
+            "obj" => Some(Emit::Obj),
+            "exe" => Some(Emit::Exe),
+            _ => None,
+        }
+    }
+
+    // Extension added to the input's stem when no -o is given, None for
+    // stages printed to stdout
+    pub fn extension(&self) -> Option<&'static str> {
+        match *self {
+            Emit::Tokens | Emit::Ast | Emit::Ir => None,
+            Emit::Asm => Some("s"),
+            Emit::Bytecode => Some("mooc"),
+            Emit::Obj => Some("o"),
+            Emit::Exe => Some(""),
+        }
+    }
+}
+
+impl Target {
+    fn from_str(target: &str) -> Option<Target> {
+        match target {
+            "x86-64" | "x86_64" => Some(Target::X86_64),
+            "aarch64" | "arm64" => Some(Target::Aarch64),
+            "riscv64" | "rv64" => Some(Target::Riscv64),
+            "c" => Some(Target::C),
+            "wasm" => Some(Target::Wasm),
+            _ => None,
+        }
+    }
+
+    // Extension of the generated source, which --emit asm stops at
+    pub fn source_extension(&self) -> &'static str {
+        match *self {
+            Target::X86_64 | Target::Aarch64 | Target::Riscv64 => "s",
+            Target::C => "c",
+            Target::Wasm => "wat",
+        }
+    }
+
+    // Extension of objects and executables, which for WebAssembly are both
+    // just the module
+    fn binary_extension(&self, emit: Emit) -> Option<&'static str> {
+        match *self {
+            Target::Wasm => Some("wasm"),
+            _ => emit.extension(),
+        }
+    }
+}
+
+impl Options {
+    // Where the result goes, None meaning stdout
+    pub fn output_path(&self) -> Option<PathBuf> {
+        if self.output.is_some() {
+            return self.output.clone();
+        }
+        let extension = match self.emit {
+            Emit::Asm => Some(self.target.source_extension()),
+            Emit::Obj | Emit::Exe => self.target.binary_extension(self.emit),
+            _ => self.emit.extension(),
+        };
+        extension.map(|ext| self.input.with_extension(ext))
+    }
+
+    // The assembler/linker to run: --cc, then the CC environment variable, then gcc
+    pub fn c_compiler(&self, env_cc: Option<String>) -> String {
+        if let Some(ref cc) = self.cc {
+            return cc.clone();
+        }
+        match env_cc {
+            Some(ref cc) if !cc.is_empty() => cc.clone(),
+            _ => String::from("gcc"),
+        }
+    }
+}
+
+// Parses the arguments following the program name
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    if args.first().map(String::as_str) == Some("run") {
+        return parse_interpret(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("repl") {
+        return parse_repl(&args[1..]);
+    }
+
+    let mut input = None;
+    let mut output = None;
+    let mut emit = Emit::Exe;
+    let mut target = Target::X86_64;
+    let mut check = false;
+    let mut run = false;
+    let mut keep_asm = false;
+    let mut builtin_as = false;
+    let mut static_runtime = false;
+    let mut cc = None;
+    let mut opt_level = 0;
+    let mut debug = false;
+    let mut program_args = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-o" => {
+                i += 1;
+                match args.get(i) {
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => return Err(String::from("-o requires a path")),
+                }
+            },
+            "--emit" => {
+                i += 1;
+                let stage = match args.get(i) {
+                    Some(stage) => stage,
+                    None => return Err(String::from("--emit requires a stage")),
+                };
+                emit = match Emit::from_str(stage) {
+                    Some(emit) => emit,
+                    None => return Err(format!("unknown --emit stage '{}'", stage)),
+                };
+            },
+            "--target" => {
+                i += 1;
+                let name = match args.get(i) {
+                    Some(name) => name,
+                    None => return Err(String::from("--target requires a target")),
+                };
+                target = match Target::from_str(name) {
+                    Some(target) => target,
+                    None => return Err(format!("unknown --target '{}'", name)),
+                };
+            },
+            "--cc" => {
+                i += 1;
+                match args.get(i) {
+                    Some(command) => cc = Some(command.clone()),
+                    None => return Err(String::from("--cc requires a command")),
+                }
+            },
+            "-O" => opt_level = 1,
+            "-O2" => opt_level = 2,
+            "-g" => debug = true,
+            "--check" => check = true,
+            "--run" => run = true,
+            "--keep-asm" => keep_asm = true,
+            "--builtin-as" => builtin_as = true,
+            "--static" => static_runtime = true,
+            "--" => {
+                program_args.extend_from_slice(&args[i + 1..]);
+                break;
+            },
+            arg if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option '{}'", arg));
+            },
+            arg => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}'", arg));
+                }
+                input = Some(PathBuf::from(arg));
+            },
+        }
+        i += 1;
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None => return Err(String::from("no input file")),
+    };
+    if run && emit != Emit::Exe {
+        return Err(String::from("--run needs an executable, not --emit of another stage"));
+    }
+    if run && check {
+        return Err(String::from("--run and --check cannot be combined"));
+    }
+    if target != Target::X86_64 && (builtin_as || static_runtime) {
+        return Err(String::from("--builtin-as and --static only assemble x86-64"));
+    }
+    if debug && (target == Target::C || target == Target::Wasm) {
+        return Err(String::from("-g only describes the native targets"));
+    }
+    if debug && (builtin_as || static_runtime) {
+        return Err(String::from("-g needs the system assembler, not --builtin-as or --static"));
+    }
+    if target == Target::Wasm && run {
+        return Err(String::from("--run cannot run WebAssembly, load it in a browser instead"));
+    }
+
+    Ok(Command::Build(Options {
+        input,
+        output,
+        emit,
+        target,
+        check,
+        run,
+        keep_asm,
+        builtin_as: builtin_as || static_runtime,
+        static_runtime,
+        cc,
+        opt_level,
+        debug,
+        program_args
+    }))
+}
+
+// Arguments of 'emooji run': the input and the program's arguments
+fn parse_interpret(args: &[String]) -> Result<Command, String> {
+    let mut input = None;
+    let mut program_args = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--" => {
+                program_args.extend_from_slice(&args[i + 1..]);
+                break;
+            },
+            arg if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option '{}' for run", arg));
+            },
+            arg => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}'", arg));
+                }
+                input = Some(PathBuf::from(arg));
+            },
+        }
+        i += 1;
+    }
+    match input {
+        Some(input) => Ok(Command::Interpret(input, program_args)),
+        None => Err(String::from("no input file")),
+    }
+}
+
+// Arguments of 'emooji repl': only the program's arguments
+fn parse_repl(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        None => Ok(Command::Repl(Vec::new())),
+        Some("-h") | Some("--help") => Ok(Command::Help),
+        Some("--") => Ok(Command::Repl(args[1..].to_vec())),
+        Some(arg) => Err(format!("unexpected argument '{}' for repl", arg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        parse(&args)
+    }
+
+    fn build(args: &[&str]) -> Options {
+        match parse_strs(args) {
+            Ok(Command::Build(options)) => options,
+            other => panic!("expected a build, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn input_is_used_as_given() {
+        let options = build(&["dir/prog.moo"]);
+        assert_eq!(options.input, PathBuf::from("dir/prog.moo"));
+        assert_eq!(options.emit, Emit::Exe);
+        assert_eq!(options.output_path(), Some(PathBuf::from("dir/prog")));
+    }
+
+    #[test]
+    fn output_depends_on_stage() {
+        assert_eq!(build(&["--emit", "asm", "a.moo"]).output_path(), Some(PathBuf::from("a.s")));
+        assert_eq!(build(&["a.moo", "--emit", "obj"]).output_path(), Some(PathBuf::from("a.o")));
+        assert_eq!(build(&["a.moo", "--emit", "bytecode"]).output_path(),
+                   Some(PathBuf::from("a.mooc")));
+        assert_eq!(build(&["--emit", "ast", "a.moo"]).output_path(), None);
+        assert_eq!(build(&["--emit", "ir", "a.moo"]).output_path(), None);
+        assert_eq!(build(&["--target", "arm64", "--emit", "asm", "a.moo"]).output_path(),
+                   Some(PathBuf::from("a.s")));
+        assert_eq!(build(&["--target", "c", "--emit", "asm", "a.moo"]).output_path(),
+                   Some(PathBuf::from("a.c")));
+        assert_eq!(build(&["--target", "wasm", "--emit", "asm", "a.moo"]).output_path(),
+                   Some(PathBuf::from("a.wat")));
+        assert_eq!(build(&["--target", "wasm", "a.moo"]).output_path(), Some(PathBuf::from("a.wasm")));
+        assert_eq!(build(&["-o", "out.txt", "--emit", "tokens", "a.moo"]).output_path(),
+            Some(PathBuf::from("out.txt")));
+    }
+
+    #[test]
+    fn flags_and_program_args() {
+        let options = build(&["--run", "--keep-asm", "a.moo", "--", "x", "--check"]);
+        assert!(options.run && options.keep_asm && !options.check);
+        assert_eq!(options.opt_level, 0);
+        assert_eq!(build(&["-O", "a.moo"]).opt_level, 1);
+        assert_eq!(build(&["-O2", "a.moo"]).opt_level, 2);
+        assert!(build(&["-g", "a.moo"]).debug && !options.debug);
+        assert_eq!(options.program_args, vec!["x", "--check"]);
+    }
+
+    #[test]
+    fn cc_flag_beats_environment() {
+        let options = build(&["--cc", "clang", "a.moo"]);
+        assert_eq!(options.c_compiler(Some(String::from("tcc"))), "clang");
+        let options = build(&["a.moo"]);
+        assert_eq!(options.c_compiler(Some(String::from("tcc"))), "tcc");
+        assert_eq!(options.c_compiler(Some(String::new())), "gcc");
+        assert_eq!(options.c_compiler(None), "gcc");
+    }
+
+    #[test]
+    fn help_and_version_win() {
+        assert_eq!(parse_strs(&["a.moo", "--help"]), Ok(Command::Help));
+        assert_eq!(parse_strs(&["-V"]), Ok(Command::Version));
+    }
+
+    #[test]
+    fn run_and_repl_subcommands() {
+        assert_eq!(parse_strs(&["run", "a.moo", "--", "x", "--check"]),
+            Ok(Command::Interpret(PathBuf::from("a.moo"), vec![String::from("x"), String::from("--check")])));
+        assert_eq!(parse_strs(&["run", "--help"]), Ok(Command::Help));
+        assert!(parse_strs(&["run"]).is_err());
+        assert!(parse_strs(&["run", "a.moo", "--emit", "asm"]).is_err());
+        assert_eq!(parse_strs(&["repl", "--", "x"]), Ok(Command::Repl(vec![String::from("x")])));
+        assert!(parse_strs(&["repl", "a.moo"]).is_err());
+        // only the first argument is a subcommand
+        assert_eq!(build(&["a.moo", "--", "run"]).program_args, vec!["run"]);
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert!(parse_strs(&[]).is_err());
+        assert!(parse_strs(&["a.moo", "b.moo"]).is_err());
+        assert!(parse_strs(&["--emit", "pdf", "a.moo"]).is_err());
+        assert!(parse_strs(&["a.moo", "-o"]).is_err());
+        assert!(parse_strs(&["a.moo", "--cc"]).is_err());
+        assert!(parse_strs(&["--frobnicate", "a.moo"]).is_err());
+        assert!(parse_strs(&["--run", "--emit", "asm", "a.moo"]).is_err());
+        assert!(parse_strs(&["--target", "vax", "a.moo"]).is_err());
+        assert!(parse_strs(&["--target", "c", "--static", "a.moo"]).is_err());
+        assert!(parse_strs(&["--target", "aarch64", "--builtin-as", "a.moo"]).is_err());
+        assert!(parse_strs(&["--target", "rv64", "--static", "a.moo"]).is_err());
+        assert!(parse_strs(&["--target", "wasm", "--run", "a.moo"]).is_err());
+        assert!(parse_strs(&["-g", "--target", "c", "a.moo"]).is_err());
+        assert!(parse_strs(&["-g", "--builtin-as", "a.moo"]).is_err());
+    }
+}